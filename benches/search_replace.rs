@@ -0,0 +1,85 @@
+//! Benchmarks for search/replace matching on large generated files
+//!
+//! Run with `cargo bench --bench search_replace`. Building the index of a file
+//! reads every line once, so `build_index` grows linearly with the file. Lookups on
+//! a built index only visit the windows anchored on the rarest line of a search
+//! block, for every tolerance level and for the closest match, so `find_exact` and
+//! `find_closest` stay flat from 10k to 1M lines.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::hint::black_box;
+use winx_code_agent::file::search_replace::ContentIndex;
+
+const SIZES: [usize; 3] = [10_000, 100_000, 1_000_000];
+
+/// Builds a lockfile-like document with many repeated lines
+fn generated_file(lines: usize) -> String {
+    (0..lines)
+        .map(|i| match i % 4 {
+            0 => format!("[[package]]\nname = \"crate-{}\"", i),
+            1 => "version = \"1.0.0\"".to_string(),
+            2 => "source = \"registry+https://github.com/rust-lang/crates.io-index\"".to_string(),
+            _ => String::new(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn lines(search: &[&str]) -> Vec<String> {
+    search.iter().map(|s| s.to_string()).collect()
+}
+
+fn bench_build_index(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build_index");
+    group.sample_size(10);
+    for size in SIZES {
+        let content = generated_file(size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &content, |b, content| {
+            b.iter(|| ContentIndex::new(black_box(content)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_find_exact(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find_exact");
+    for size in SIZES {
+        let index = ContentIndex::new(&generated_file(size));
+        let target = format!("name = \"crate-{}\"", size / 2);
+        let search = lines(&[target.as_str(), "version = \"1.0.0\""]);
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &search, |b, search| {
+            b.iter(|| {
+                let matches = index.find(black_box(search));
+                assert_eq!(matches.len(), 1);
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_find_closest(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find_closest");
+    for size in SIZES {
+        let index = ContentIndex::new(&generated_file(size));
+        let target = format!("name = \"crate-{}\"", size / 2);
+        // The version line doesn't exist, so only the closest match search finds it
+        let search = lines(&["[[package]]", target.as_str(), "version = \"9.9.9\""]);
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &search, |b, search| {
+            b.iter(|| {
+                assert!(index.find(black_box(search)).is_empty());
+                assert!(index.closest(black_box(search)).is_some());
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_build_index,
+    bench_find_exact,
+    bench_find_closest
+);
+criterion_main!(benches);
@@ -0,0 +1,139 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use winx_code_agent::config::WinxConfig;
+use winx_code_agent::security::audit::{self, AuditEntry, AuditQuery, AuditStatus};
+
+const USAGE: &str = "\
+Usage: winx-code-agent audit [OPTIONS]
+
+Query the audit log of tool calls.
+
+Options:
+  --sessions          List the recorded sessions
+  --session <ID>      Only entries of this session, `latest` for the most recent
+  --tool <NAME>       Only calls of this tool
+  --status <STATUS>   Only `success` or `error` calls
+  --denied            Only calls with a denied permission
+  --since <TIME>      Only calls after an RFC 3339 time or a duration ago (30m, 2h, 7d)
+  --limit <N>         Show the last N matching entries (default 50, 0 for all)
+  --json              Print the matching entries as JSON lines
+  -h, --help          Show this help";
+
+/// Runs `winx-code-agent audit` with the arguments following the subcommand
+pub fn run(args: &[String]) -> Result<()> {
+    let mut query = AuditQuery {
+        limit: Some(50),
+        ..Default::default()
+    };
+    let mut json = false;
+    let mut list_sessions = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{} requires a value\n\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "--sessions" => list_sessions = true,
+            "--session" => query.session = Some(value()?.clone()),
+            "--tool" => query.tool = Some(value()?.clone()),
+            "--status" => {
+                query.status = Some(match value()?.as_str() {
+                    "success" => AuditStatus::Success,
+                    "error" => AuditStatus::Error,
+                    other => bail!("Unknown status '{}', expected success or error", other),
+                })
+            }
+            "--denied" => query.denied_only = true,
+            "--since" => query.since = Some(parse_since(value()?)?),
+            "--limit" => {
+                let limit: usize = value()?.parse().context("Invalid --limit")?;
+                query.limit = (limit > 0).then_some(limit);
+            }
+            "--json" => json = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            other => bail!("Unknown option '{}'\n\n{}", other, USAGE),
+        }
+    }
+
+    let config = WinxConfig::load_or_default()?;
+    let dir = audit::audit_dir(&config.audit);
+
+    if list_sessions {
+        for session in audit::sessions(&dir)? {
+            println!("{}", session);
+        }
+        return Ok(());
+    }
+
+    let entries = audit::query(&dir, &query)?;
+    if entries.is_empty() && !json {
+        eprintln!("No matching audit entries in {}", dir.display());
+    }
+    for entry in &entries {
+        if json {
+            println!("{}", serde_json::to_string(entry)?);
+        } else {
+            println!("{}", format_entry(entry));
+        }
+    }
+    Ok(())
+}
+
+/// Absolute RFC 3339 time, or a duration before now such as `90s`, `30m`, `2h` or `7d`
+fn parse_since(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let split = value.char_indices().last().map_or(0, |(i, _)| i);
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .with_context(|| format!("Invalid --since '{}'", value))?;
+    let ago = match unit {
+        "s" => Duration::seconds(amount),
+        "m" => Duration::minutes(amount),
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        _ => bail!("Invalid --since '{}', expected a time or e.g. 30m", value),
+    };
+    Ok(Utc::now() - ago)
+}
+
+fn format_entry(entry: &AuditEntry) -> String {
+    let status = match entry.status {
+        AuditStatus::Success => "ok",
+        AuditStatus::Error => "error",
+    };
+    let mut line = format!(
+        "{}  {:<15} {:<5} {:>6}ms",
+        entry.timestamp, entry.tool, status, entry.duration_ms
+    );
+    for decision in entry.permissions.iter().filter(|d| !d.allowed) {
+        line.push_str(&format!(
+            "  denied {}{}: {}",
+            decision.action,
+            decision
+                .path
+                .as_deref()
+                .map(|path| format!(" {}", path))
+                .unwrap_or_default(),
+            decision.reason.as_deref().unwrap_or("no reason given")
+        ));
+    }
+    // A denial usually is the error, don't print it twice
+    if let Some(error) = &entry.error {
+        if !entry
+            .permissions
+            .iter()
+            .any(|d| d.reason.as_ref() == Some(error))
+        {
+            line.push_str(&format!("  {}", error));
+        }
+    }
+    line
+}
@@ -0,0 +1,637 @@
+//! Resource limits and process-group cleanup for executed commands
+//!
+//! Every command runs in its own process group, so a timeout or an interrupt kills
+//! the command together with everything it started. CPU time is limited with
+//! `RLIMIT_CPU`. Memory and process count use a per-command cgroup v2 when the
+//! agent's cgroup can create children with the `memory` and `pids` controllers,
+//! which also catches processes that left the group; otherwise memory falls back to
+//! `RLIMIT_AS` and the process limit is not enforced. Wall time and output size are
+//! enforced by [`LimitedChild::wait_with_output`].
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a running command is checked against its limits
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long output is still collected after a killed command exited, for processes
+/// that escaped the group while holding the pipes
+const KILLED_OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// Limits applied to each command, `None` leaves the resource unlimited
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    /// Wall-clock time before the command's process group is killed
+    pub wall_time_secs: Option<u64>,
+    /// CPU time per process
+    pub cpu_time_secs: Option<u64>,
+    /// Memory of the whole command with cgroup v2, address space per process otherwise
+    pub memory_bytes: Option<u64>,
+    /// Number of processes and threads, only enforced with cgroup v2
+    pub max_processes: Option<u64>,
+    /// Combined stdout and stderr size before the command is killed
+    pub max_output_bytes: Option<usize>,
+}
+
+/// Limits per mode, as read from the `limits` section of the configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModeLimits {
+    pub wcgw: ResourceLimits,
+    pub architect: ResourceLimits,
+    pub code_writer: ResourceLimits,
+}
+
+impl Default for ModeLimits {
+    fn default() -> Self {
+        let standard = ResourceLimits {
+            wall_time_secs: Some(600),
+            max_output_bytes: Some(16 * 1024 * 1024),
+            ..Default::default()
+        };
+        Self {
+            wcgw: standard.clone(),
+            // Read-only planning has no business running long jobs
+            architect: ResourceLimits {
+                wall_time_secs: Some(120),
+                cpu_time_secs: Some(60),
+                max_output_bytes: Some(4 * 1024 * 1024),
+                ..Default::default()
+            },
+            code_writer: standard,
+        }
+    }
+}
+
+/// The limit that stopped a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitHit {
+    WallTime(u64),
+    CpuTime(u64),
+    Memory(u64),
+    Processes(u64),
+    Output(usize),
+}
+
+impl fmt::Display for LimitHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitHit::WallTime(secs) => write!(f, "wall time limit of {}s exceeded", secs),
+            LimitHit::CpuTime(secs) => write!(f, "CPU time limit of {}s exceeded", secs),
+            LimitHit::Memory(bytes) => write!(f, "memory limit of {} bytes exceeded", bytes),
+            LimitHit::Processes(count) => write!(f, "process limit of {} reached", count),
+            LimitHit::Output(bytes) => write!(f, "output limit of {} bytes exceeded", bytes),
+        }
+    }
+}
+
+/// Output of a command run under limits
+#[derive(Debug)]
+pub struct LimitedOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+    pub limit_hit: Option<LimitHit>,
+}
+
+/// Shell-style exit code, `128 + signal` for commands killed by a signal
+pub fn exit_code(status: &ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Process group of a spawned command, with its cgroup when one was created
+#[derive(Debug)]
+pub struct ProcessGroup {
+    pgid: u32,
+    #[cfg(target_os = "linux")]
+    cgroup: Option<cgroup::CommandCgroup>,
+}
+
+impl ProcessGroup {
+    /// Sends `signal` to every process of the group
+    #[cfg(unix)]
+    pub fn signal(&self, signal: i32) {
+        // SAFETY: killpg has no memory-safety requirements
+        unsafe {
+            libc::killpg(self.pgid as libc::pid_t, signal);
+        }
+    }
+
+    /// Kills the group, and the processes that left it when a cgroup tracks them
+    pub fn kill(&self) {
+        #[cfg(unix)]
+        self.signal(libc::SIGKILL);
+        #[cfg(target_os = "linux")]
+        if let Some(cgroup) = &self.cgroup {
+            cgroup.kill();
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn cgroup_limit_hit(&self, limits: &ResourceLimits) -> Option<LimitHit> {
+        let cgroup = self.cgroup.as_ref()?;
+        if let Some(bytes) = limits.memory_bytes.filter(|_| cgroup.oom_killed()) {
+            return Some(LimitHit::Memory(bytes));
+        }
+        limits
+            .max_processes
+            .filter(|_| cgroup.pids_max_reached())
+            .map(LimitHit::Processes)
+    }
+}
+
+/// A command set up to run in its own process group under [`ResourceLimits`]
+pub struct LimitedCommand {
+    command: Command,
+    limits: ResourceLimits,
+    #[cfg(target_os = "linux")]
+    cgroup: Option<cgroup::CommandCgroup>,
+}
+
+impl LimitedCommand {
+    /// Registers the process group and limit hooks, before any other `pre_exec` hook
+    pub fn new(mut command: Command, limits: &ResourceLimits) -> Self {
+        #[cfg(target_os = "linux")]
+        let cgroup = cgroup::CommandCgroup::create(limits);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+
+            #[cfg(target_os = "linux")]
+            let join_cgroup = cgroup.as_ref().map(|c| c.procs_fd());
+            #[cfg(target_os = "linux")]
+            let rlimit_memory = limits.memory_bytes.filter(|_| cgroup.is_none());
+            #[cfg(not(target_os = "linux"))]
+            let rlimit_memory = limits.memory_bytes;
+            let cpu_time = limits.cpu_time_secs;
+
+            // SAFETY: the hook only makes raw syscalls on values copied into it
+            unsafe {
+                command.pre_exec(move || {
+                    #[cfg(target_os = "linux")]
+                    if let Some(fd) = join_cgroup {
+                        cgroup::join(fd)?;
+                    }
+                    if let Some(secs) = cpu_time {
+                        // SIGXCPU at the soft limit, SIGKILL one second later
+                        set_rlimit(libc::RLIMIT_CPU, secs, secs.saturating_add(1))?;
+                    }
+                    if let Some(bytes) = rlimit_memory {
+                        set_rlimit(libc::RLIMIT_AS, bytes, bytes)?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+
+        Self {
+            command,
+            limits: limits.clone(),
+            #[cfg(target_os = "linux")]
+            cgroup,
+        }
+    }
+
+    /// The underlying command, to add further configuration such as a sandbox
+    pub fn command_mut(&mut self) -> &mut Command {
+        &mut self.command
+    }
+
+    /// Spawns the command with piped output and no input, like [`Command::output`]
+    pub fn spawn(mut self) -> io::Result<LimitedChild> {
+        let child = self
+            .command
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+        let group = Arc::new(ProcessGroup {
+            pgid: child.id(),
+            #[cfg(target_os = "linux")]
+            cgroup: self.cgroup,
+        });
+        Ok(LimitedChild {
+            child,
+            group,
+            limits: self.limits,
+        })
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type RlimitResource = libc::__rlimit_resource_t;
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+type RlimitResource = libc::c_int;
+
+#[cfg(unix)]
+fn set_rlimit(resource: RlimitResource, soft: u64, hard: u64) -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: soft as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    };
+    // SAFETY: `limit` is a valid rlimit struct
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A running [`LimitedCommand`]
+pub struct LimitedChild {
+    child: Child,
+    group: Arc<ProcessGroup>,
+    limits: ResourceLimits,
+}
+
+impl LimitedChild {
+    /// Process group of the command, to kill it from another thread
+    pub fn group(&self) -> Arc<ProcessGroup> {
+        Arc::clone(&self.group)
+    }
+
+    /// Waits for the command and collects its output, killing the whole process
+    /// group when a limit is exceeded
+    ///
+    /// Output is collected until every process holding the pipes is gone, so
+    /// background processes started by the command count against its wall time.
+    pub fn wait_with_output(mut self) -> io::Result<LimitedOutput> {
+        let output_bytes = Arc::new(AtomicUsize::new(0));
+        let overflow = Arc::new(AtomicBool::new(false));
+        let readers = [
+            self.child
+                .stdout
+                .take()
+                .map(|pipe| Box::new(pipe) as Box<dyn Read + Send>),
+            self.child
+                .stderr
+                .take()
+                .map(|pipe| Box::new(pipe) as Box<dyn Read + Send>),
+        ]
+        .map(|pipe| {
+            let buffer = Arc::new(Mutex::new(Vec::new()));
+            let handle = pipe.map(|pipe| {
+                spawn_reader(
+                    pipe,
+                    Arc::clone(&buffer),
+                    Arc::clone(&output_bytes),
+                    Arc::clone(&overflow),
+                    self.limits.max_output_bytes,
+                )
+            });
+            (buffer, handle)
+        });
+
+        let deadline = self
+            .limits
+            .wall_time_secs
+            .map(|secs| Instant::now() + Duration::from_secs(secs));
+        let mut limit_hit = None;
+        let mut killed_at: Option<Instant> = None;
+        let mut status = None;
+        loop {
+            if status.is_none() {
+                status = self.child.try_wait()?;
+            }
+            let readers_done = readers
+                .iter()
+                .all(|(_, handle)| handle.as_ref().is_none_or(|h| h.is_finished()));
+            let gave_up = killed_at.is_some_and(|t| t.elapsed() >= KILLED_OUTPUT_GRACE);
+            if status.is_some() && (readers_done || gave_up) {
+                break;
+            }
+
+            if limit_hit.is_none() {
+                if overflow.load(Ordering::Relaxed) {
+                    limit_hit = self.limits.max_output_bytes.map(LimitHit::Output);
+                } else if deadline.is_some_and(|d| Instant::now() >= d) {
+                    limit_hit = self.limits.wall_time_secs.map(LimitHit::WallTime);
+                }
+                if limit_hit.is_some() {
+                    self.group.kill();
+                    killed_at = Some(Instant::now());
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        let status = match status {
+            Some(status) => status,
+            None => self.child.wait()?,
+        };
+        let [stdout, stderr] = readers.map(|(buffer, handle)| {
+            // Readers still blocked on an escaped process are left behind
+            if let Some(handle) = handle.filter(|h| h.is_finished()) {
+                let _ = handle.join();
+            }
+            let bytes = std::mem::take(&mut *buffer.lock().unwrap_or_else(|e| e.into_inner()));
+            String::from_utf8_lossy(&bytes).to_string()
+        });
+
+        Ok(LimitedOutput {
+            limit_hit: limit_hit.or_else(|| self.kernel_limit_hit(&status)),
+            status,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Limits enforced by the kernel that explain how the command ended
+    fn kernel_limit_hit(&self, status: &ExitStatus) -> Option<LimitHit> {
+        #[cfg(target_os = "linux")]
+        if let Some(hit) = self.group.cgroup_limit_hit(&self.limits) {
+            return Some(hit);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if status.signal() == Some(libc::SIGXCPU) {
+                return self.limits.cpu_time_secs.map(LimitHit::CpuTime);
+            }
+        }
+        let _ = status;
+        None
+    }
+}
+
+/// Reads `pipe` into `buffer` until EOF, flagging `overflow` past `max_output` bytes
+fn spawn_reader(
+    mut pipe: Box<dyn Read + Send>,
+    buffer: Arc<Mutex<Vec<u8>>>,
+    total: Arc<AtomicUsize>,
+    overflow: Arc<AtomicBool>,
+    max_output: Option<usize>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut chunk = [0u8; 8192];
+        loop {
+            let read = match pipe.read(&mut chunk) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => read,
+            };
+            let before = total.fetch_add(read, Ordering::Relaxed);
+            // Keep reading past the limit so the command never blocks on a full pipe
+            let keep = match max_output {
+                Some(max) if before + read > max => {
+                    overflow.store(true, Ordering::Relaxed);
+                    max.saturating_sub(before)
+                }
+                _ => read,
+            };
+            if let Ok(mut buffer) = buffer.lock() {
+                buffer.extend_from_slice(&chunk[..keep]);
+            }
+        }
+    })
+}
+
+/// Processes descending from `pid`, found by scanning `/proc`
+#[cfg(target_os = "linux")]
+pub fn descendants(pid: u32) -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let parents: Vec<(u32, u32)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let child: u32 = entry.file_name().to_str()?.parse().ok()?;
+            let stat = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+            // The command name may contain spaces, fields resume after its closing paren
+            let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+            Some((child, fields.get(1)?.parse().ok()?))
+        })
+        .collect();
+
+    let mut found = vec![pid];
+    let mut index = 0;
+    while index < found.len() {
+        let parent = found[index];
+        found.extend(
+            parents
+                .iter()
+                .filter(|(_, ppid)| *ppid == parent)
+                .map(|(child, _)| *child),
+        );
+        index += 1;
+    }
+    found.remove(0);
+    found
+}
+
+#[cfg(target_os = "linux")]
+mod cgroup {
+    use super::ResourceLimits;
+    use std::fs;
+    use std::io;
+    use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::OnceLock;
+
+    /// Directory of the agent's own cgroup v2, `None` without a cgroup v2 hierarchy
+    fn own_cgroup_dir() -> Option<&'static PathBuf> {
+        static DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+        DIR.get_or_init(|| {
+            let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+            // Fields: id parent major:minor root mount-point options ... - fstype source
+            let mount_point = mountinfo.lines().find_map(|line| {
+                let (fields, fs_fields) = line.split_once(" - ")?;
+                if !fs_fields.starts_with("cgroup2 ") {
+                    return None;
+                }
+                fields.split_whitespace().nth(4).map(PathBuf::from)
+            })?;
+            let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+            let own = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+            Some(mount_point.join(own.trim_start_matches('/')))
+        })
+        .as_ref()
+    }
+
+    /// Child cgroup holding the processes of one command
+    #[derive(Debug)]
+    pub struct CommandCgroup {
+        dir: PathBuf,
+        procs: OwnedFd,
+    }
+
+    impl CommandCgroup {
+        /// Creates a cgroup enforcing the memory and process limits, `None` when
+        /// there are no such limits or the controllers aren't delegated to the agent
+        pub fn create(limits: &ResourceLimits) -> Option<Self> {
+            if limits.memory_bytes.is_none() && limits.max_processes.is_none() {
+                return None;
+            }
+            static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+            let dir = own_cgroup_dir()?.join(format!(
+                "winx-cmd-{}-{}",
+                std::process::id(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir(&dir).ok()?;
+
+            let configure = || -> io::Result<OwnedFd> {
+                if let Some(bytes) = limits.memory_bytes {
+                    fs::write(dir.join("memory.max"), bytes.to_string())?;
+                    // The limit covers the command, not what it can push to swap
+                    let _ = fs::write(dir.join("memory.swap.max"), "0");
+                }
+                if let Some(count) = limits.max_processes {
+                    fs::write(dir.join("pids.max"), count.to_string())?;
+                }
+                let procs = fs::OpenOptions::new()
+                    .write(true)
+                    .open(dir.join("cgroup.procs"))?;
+                Ok(procs.into())
+            };
+            match configure() {
+                Ok(procs) => Some(Self { dir, procs }),
+                Err(e) => {
+                    log::debug!("cgroup limits unavailable, falling back to rlimits: {}", e);
+                    let _ = fs::remove_dir(&dir);
+                    None
+                }
+            }
+        }
+
+        /// Descriptor moving the writing process into the cgroup
+        pub fn procs_fd(&self) -> RawFd {
+            self.procs.as_raw_fd()
+        }
+
+        /// Kills every process in the cgroup (Linux 5.14+)
+        pub fn kill(&self) {
+            let _ = fs::write(self.dir.join("cgroup.kill"), "1");
+        }
+
+        fn event_count(&self, file: &str, key: &str) -> u64 {
+            fs::read_to_string(self.dir.join(file))
+                .ok()
+                .and_then(|events| {
+                    events.lines().find_map(|line| {
+                        let (name, value) = line.split_once(' ')?;
+                        (name == key).then(|| value.trim().parse().ok())?
+                    })
+                })
+                .unwrap_or(0)
+        }
+
+        pub fn oom_killed(&self) -> bool {
+            self.event_count("memory.events", "oom_kill") > 0
+        }
+
+        pub fn pids_max_reached(&self) -> bool {
+            self.event_count("pids.events", "max") > 0
+        }
+    }
+
+    impl Drop for CommandCgroup {
+        fn drop(&mut self) {
+            // Fails while processes remain, the directory is then left for the system
+            let _ = fs::remove_dir(&self.dir);
+        }
+    }
+
+    /// Moves the calling process into the cgroup behind `procs_fd`
+    pub fn join(procs_fd: RawFd) -> io::Result<()> {
+        // SAFETY: writing a static buffer to a descriptor owned by the parent
+        let written = unsafe { libc::write(procs_fd, b"0".as_ptr().cast(), 1) };
+        if written != 1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn run(script: &str, limits: &ResourceLimits) -> LimitedOutput {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(script);
+        LimitedCommand::new(cmd, limits)
+            .spawn()
+            .unwrap()
+            .wait_with_output()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_wall_timeout_stops_the_command() {
+        let limits = ResourceLimits {
+            wall_time_secs: Some(1),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let output = run("sleep 30", &limits);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(output.limit_hit, Some(LimitHit::WallTime(1)));
+    }
+
+    #[test]
+    fn test_wall_timeout_kills_the_process_group() {
+        // The background child holds the pipes, it is killed with the group
+        let limits = ResourceLimits {
+            wall_time_secs: Some(1),
+            ..Default::default()
+        };
+        let output = run("sleep 30 & echo $!", &limits);
+        assert_eq!(output.limit_hit, Some(LimitHit::WallTime(1)));
+
+        let pid = output.stdout.trim();
+        let running = || {
+            std::fs::read_to_string(format!("/proc/{}/stat", pid))
+                .is_ok_and(|stat| !stat.rsplit(") ").next().unwrap_or("").starts_with('Z'))
+        };
+        let started = Instant::now();
+        while running() && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!running());
+    }
+
+    #[test]
+    fn test_output_cap_stops_the_command() {
+        let limits = ResourceLimits {
+            max_output_bytes: Some(1000),
+            ..Default::default()
+        };
+        let output = run("yes", &limits);
+        assert_eq!(output.stdout.len(), 1000);
+        assert_eq!(output.limit_hit, Some(LimitHit::Output(1000)));
+    }
+
+    #[test]
+    fn test_cpu_rlimit_stops_the_command() {
+        let limits = ResourceLimits {
+            cpu_time_secs: Some(1),
+            ..Default::default()
+        };
+        let output = run("while :; do :; done", &limits);
+        assert_eq!(output.limit_hit, Some(LimitHit::CpuTime(1)));
+        assert_eq!(exit_code(&output.status), 128 + libc::SIGXCPU);
+    }
+
+    #[test]
+    fn test_commands_within_limits_keep_their_exit_code() {
+        let output = run("exit 3", &ResourceLimits::default());
+        assert_eq!(exit_code(&output.status), 3);
+        assert_eq!(output.limit_hit, None);
+    }
+}
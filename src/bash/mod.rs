@@ -1,4 +1,8 @@
+pub mod limits;
+#[cfg(target_os = "linux")]
+pub mod proxy;
 pub mod runner;
+pub mod sandbox;
 pub mod screen_manager;
 pub mod security;
 pub mod state;
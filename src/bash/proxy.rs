@@ -0,0 +1,302 @@
+//! HTTP proxy admitting only the allowed hosts of sandboxed commands
+//!
+//! A sandboxed command gets a network namespace of its own with nothing but
+//! loopback, so this proxy is its only way out. The listening socket is created
+//! inside the namespace and passed back to the agent, which serves `CONNECT`
+//! tunnels and plain `http://` requests to hosts in `allowed_hosts` and answers
+//! everything else with `403 Forbidden`.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Port the proxy listens on inside the command's network namespace
+pub const PROXY_PORT: u16 = 3128;
+
+/// Longest request head read before giving up on a client
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+
+/// Time a client has to send its request head
+const HEAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether `host` is one of `allowed_hosts`, ignoring case and a trailing dot
+pub fn host_allowed(allowed_hosts: &[String], host: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    allowed_hosts
+        .iter()
+        .any(|allowed| allowed.trim_end_matches('.').eq_ignore_ascii_case(host))
+}
+
+/// Serves connections accepted on `listener` on a background thread
+///
+/// The proxy stops once `lifetime` reaches end of file, which happens when every
+/// process holding its other end has exited.
+pub fn spawn(listener: TcpListener, allowed_hosts: Vec<String>, lifetime: UnixStream) {
+    let allowed_hosts = Arc::new(allowed_hosts);
+    thread::spawn(move || {
+        if let Err(e) = serve(&listener, &allowed_hosts, &lifetime) {
+            log::warn!("Sandbox network proxy stopped: {}", e);
+        }
+    });
+}
+
+fn serve(
+    listener: &TcpListener,
+    allowed_hosts: &Arc<Vec<String>>,
+    lifetime: &UnixStream,
+) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let mut fds = [
+        libc::pollfd {
+            fd: listener.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: lifetime.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    loop {
+        // SAFETY: `fds` is a valid array of two pollfd structs
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+        if ready < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(error);
+        }
+        if fds[1].revents != 0 {
+            return Ok(());
+        }
+        match listener.accept() {
+            Ok((client, _)) => {
+                let allowed_hosts = Arc::clone(allowed_hosts);
+                thread::spawn(move || {
+                    if let Err(e) = handle(client, &allowed_hosts) {
+                        log::debug!("Sandbox network proxy connection failed: {}", e);
+                    }
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Target of a proxied request
+#[derive(Debug, PartialEq)]
+struct ProxyRequest {
+    host: String,
+    port: u16,
+    /// Request line to send upstream, `None` for a `CONNECT` tunnel
+    origin_line: Option<String>,
+}
+
+impl ProxyRequest {
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace();
+        let (method, target, version) = (parts.next()?, parts.next()?, parts.next()?);
+        if method.eq_ignore_ascii_case("CONNECT") {
+            let (host, port) = split_authority(target, None)?;
+            return Some(Self {
+                host,
+                port,
+                origin_line: None,
+            });
+        }
+
+        let rest = target
+            .get(..7)
+            .filter(|scheme| scheme.eq_ignore_ascii_case("http://"))
+            .map(|_| &target[7..])?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = split_authority(authority, Some(80))?;
+        Some(Self {
+            host,
+            port,
+            origin_line: Some(format!("{} {} {}", method, path, version)),
+        })
+    }
+}
+
+/// Host and port of `host:port` or `[v6]:port`, `default_port` when it has none
+fn split_authority(authority: &str, default_port: Option<u16>) -> Option<(String, u16)> {
+    let authority = authority.rsplit('@').next()?;
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(v6) => {
+            let (host, rest) = v6.split_once(']')?;
+            (host, rest.strip_prefix(':'))
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => default_port?,
+    };
+    (!host.is_empty()).then(|| (host.to_string(), port))
+}
+
+fn handle(mut client: TcpStream, allowed_hosts: &[String]) -> io::Result<()> {
+    client.set_read_timeout(Some(HEAD_TIMEOUT))?;
+    let (head, body) = read_head(&mut client)?;
+    let head = String::from_utf8_lossy(&head);
+    let (line, headers) = head.split_once("\r\n").unwrap_or((&head, ""));
+
+    let Some(request) = ProxyRequest::parse(line) else {
+        return respond(
+            &mut client,
+            "400 Bad Request",
+            "Expected CONNECT or an http:// URL",
+        );
+    };
+    if !host_allowed(allowed_hosts, &request.host) {
+        log::info!(
+            "Sandbox network proxy denied {}:{}",
+            request.host,
+            request.port
+        );
+        return respond(
+            &mut client,
+            "403 Forbidden",
+            &format!(
+                "{} is not in allowed_hosts, sandboxed commands can't reach it",
+                request.host
+            ),
+        );
+    }
+    let mut upstream = match connect(&request.host, request.port) {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            return respond(
+                &mut client,
+                "502 Bad Gateway",
+                &format!(
+                    "Failed to connect to {}:{}: {}",
+                    request.host, request.port, e
+                ),
+            )
+        }
+    };
+
+    match &request.origin_line {
+        None => client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?,
+        Some(origin_line) => {
+            upstream.write_all(format!("{}\r\n{}", origin_line, headers).as_bytes())?;
+        }
+    }
+    upstream.write_all(&body)?;
+    client.set_read_timeout(None)?;
+    relay(client, upstream)
+}
+
+/// Request head up to the blank line, and whatever was read past it
+fn read_head(client: &mut TcpStream) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            let body = buffer.split_off(end + 4);
+            return Ok((buffer, body));
+        }
+        if buffer.len() > MAX_HEAD_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request head too long",
+            ));
+        }
+        let read = client.read(&mut chunk)?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+fn respond(client: &mut TcpStream, status: &str, message: &str) -> io::Result<()> {
+    write!(
+        client,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+        status,
+        message.len() + 1,
+        message
+    )
+}
+
+fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no address found");
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Copies both directions until each side has closed
+fn relay(client: TcpStream, upstream: TcpStream) -> io::Result<()> {
+    let (mut client_read, mut upstream_write) = (client.try_clone()?, upstream.try_clone()?);
+    let uploads = thread::spawn(move || {
+        let _ = io::copy(&mut client_read, &mut upstream_write);
+        let _ = upstream_write.shutdown(Shutdown::Write);
+    });
+    let (mut upstream_read, mut client_write) = (upstream, client);
+    let _ = io::copy(&mut upstream_read, &mut client_write);
+    let _ = client_write.shutdown(Shutdown::Write);
+    let _ = uploads.join();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proxy_requests() {
+        assert_eq!(
+            ProxyRequest::parse("CONNECT crates.io:443 HTTP/1.1"),
+            Some(ProxyRequest {
+                host: "crates.io".to_string(),
+                port: 443,
+                origin_line: None,
+            })
+        );
+        assert_eq!(
+            ProxyRequest::parse("GET http://[::1]:8080/index?q=1 HTTP/1.1"),
+            Some(ProxyRequest {
+                host: "::1".to_string(),
+                port: 8080,
+                origin_line: Some("GET /index?q=1 HTTP/1.1".to_string()),
+            })
+        );
+        assert_eq!(
+            ProxyRequest::parse("GET http://example.com HTTP/1.0").map(|r| (r.port, r.origin_line)),
+            Some((80, Some("GET / HTTP/1.0".to_string())))
+        );
+        assert!(ProxyRequest::parse("GET /relative HTTP/1.1").is_none());
+        assert!(ProxyRequest::parse("CONNECT crates.io HTTP/1.1").is_none());
+    }
+
+    #[test]
+    fn test_host_allowed_ignores_case_and_trailing_dot() {
+        let allowed = vec!["Crates.io".to_string()];
+        assert!(host_allowed(&allowed, "crates.io."));
+        assert!(!host_allowed(&allowed, "static.crates.io"));
+        assert!(!host_allowed(&[], "crates.io"));
+    }
+}
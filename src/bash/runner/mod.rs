@@ -1,3 +1,7 @@
+use crate::bash::limits::{
+    self, LimitHit, LimitedCommand, LimitedOutput, ProcessGroup, ResourceLimits,
+};
+use crate::bash::sandbox::{self, SandboxPolicy};
 use crate::bash::screen_manager::ScreenManager;
 use crate::error::{WinxError, WinxResult};
 use std::io::{BufRead, BufReader, Write};
@@ -32,6 +36,16 @@ pub struct CommandRunner {
     tx_input: Option<Sender<String>>,
     tx_ctrl: Option<Sender<i32>>,
     screen_session: Arc<Mutex<Option<String>>>,
+    /// Confinement applied to every spawned process, set in sandboxed mode
+    sandbox: Option<SandboxPolicy>,
+    /// Limits of the commands run directly, set from the current mode
+    limits: ResourceLimits,
+    /// Process group of the last command run directly, killed on interrupt
+    active_group: Arc<Mutex<Option<Arc<ProcessGroup>>>>,
+    /// Limit that stopped the last command, if any
+    last_limit_hit: Arc<Mutex<Option<LimitHit>>>,
+    /// Pid of the persistent shell, whose descendants are killed on interrupt
+    shell_pid: Arc<Mutex<Option<u32>>>,
 }
 
 impl Clone for CommandRunner {
@@ -46,6 +60,11 @@ impl Clone for CommandRunner {
             tx_input: self.tx_input.clone(),
             tx_ctrl: self.tx_ctrl.clone(),
             screen_session: Arc::clone(&self.screen_session),
+            sandbox: self.sandbox.clone(),
+            limits: self.limits.clone(),
+            active_group: Arc::clone(&self.active_group),
+            last_limit_hit: Arc::clone(&self.last_limit_hit),
+            shell_pid: Arc::clone(&self.shell_pid),
         }
     }
 }
@@ -83,9 +102,52 @@ impl CommandRunner {
             tx_input: None,
             tx_ctrl: None,
             screen_session: Arc::new(Mutex::new(None)),
+            sandbox: None,
+            limits: ResourceLimits::default(),
+            active_group: Arc::new(Mutex::new(None)),
+            last_limit_hit: Arc::new(Mutex::new(None)),
+            shell_pid: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// Sets the limits of commands run from now on
+    pub fn set_limits(&mut self, limits: ResourceLimits) {
+        self.limits = limits;
+    }
+
+    /// Sets the sandbox applied to processes spawned from now on
+    pub fn set_sandbox(&mut self, policy: Option<SandboxPolicy>) {
+        self.sandbox = policy;
+    }
+
+    fn confine(&self, cmd: &mut Command) -> WinxResult<()> {
+        match &self.sandbox {
+            Some(policy) => sandbox::apply(cmd, policy),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs `cmd` in its own process group under the runner's limits and sandbox
+    fn run_limited(&self, cmd: Command) -> WinxResult<LimitedOutput> {
+        let mut limited = LimitedCommand::new(cmd, &self.limits);
+        // After the limit hooks, which must run outside the sandbox's namespaces
+        self.confine(limited.command_mut())?;
+        let child = limited
+            .spawn()
+            .map_err(|e| WinxError::bash_error(format!("Failed to execute command: {}", e)))?;
+        *self.active_group.lock().unwrap() = Some(child.group());
+
+        let mut output = child
+            .wait_with_output()
+            .map_err(|e| WinxError::bash_error(format!("Failed to wait for command: {}", e)))?;
+        if let Some(hit) = output.limit_hit {
+            log::warn!("Command stopped: {}", hit);
+            output.stderr += &format!("\nCommand stopped: {}\n", hit);
+        }
+        *self.last_limit_hit.lock().unwrap() = output.limit_hit;
+        Ok(output)
+    }
+
     /// Get the current screen session name
     pub fn get_screen_session(&self) -> Option<String> {
         self.screen_session.lock().unwrap().clone()
@@ -187,9 +249,14 @@ impl CommandRunner {
             }
         };
 
+        // Its own group, so signals aimed at the agent's group don't reach it
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+        self.confine(&mut cmd)?;
         let mut child = cmd
             .spawn()
             .map_err(|e| WinxError::bash_error(format!("Failed to spawn shell process: {}", e)))?;
+        *self.shell_pid.lock().unwrap() = Some(child.id());
 
         let stdout = child.stdout.take().unwrap();
         let stderr = child.stderr.take().unwrap();
@@ -284,6 +351,7 @@ impl CommandRunner {
     pub async fn execute(&self, command: &str) -> WinxResult<()> {
         // Log the command for diagnostic purposes
         log::info!("Executing bash command: {}", command);
+        *self.last_limit_hit.lock().unwrap() = None;
 
         // Enhanced logging for debugging
         log::debug!("Command execution - Current directory: {}", self.get_cwd());
@@ -316,6 +384,17 @@ impl CommandRunner {
             return direct_result;
         }
 
+        // The persistent shell and screen sessions can't enforce limits, so with limits
+        // configured the command is retried in a limited `bash -c` instead. A failed
+        // `cd` is final, there is nothing to retry
+        if self.limits != ResourceLimits::default() {
+            if command.trim().starts_with("cd ") {
+                return direct_result;
+            }
+            log::debug!("Direct command execution failed, retrying in a limited shell");
+            return self.execute_shell_command(command);
+        }
+
         log::debug!("Direct command execution failed, falling back to shell execution");
 
         if self.tx_input.is_none() {
@@ -476,95 +555,92 @@ impl CommandRunner {
         Ok(())
     }
 
-    /// Executes a command directly using std::process::Command instead of interactive shell
-    /// This works around the "Must be connected to a terminal" problem
-    fn execute_direct_command(&self, command: &str) -> WinxResult<()> {
-        log::info!("Attempting direct command execution for: {}", command);
-
-        // Check if the command is compound (contains && or ||)
-        if command.contains("&&") || command.contains("||") || command.contains(";") {
-            // Let's execute in a shell to use compound operators
-            log::info!("Detected compound command, using shell execution");
-
-            // Get current working directory
-            let cwd = self.cwd.lock().unwrap().clone();
+    /// Runs `command` with `bash -c` under the runner's limits and sandbox
+    fn execute_shell_command(&self, command: &str) -> WinxResult<()> {
+        // Get current working directory
+        let cwd = self.cwd.lock().unwrap().clone();
 
-            // Execute the command in a shell
-            let output = std::process::Command::new("bash")
-                .arg("-c")
-                .arg(command)
-                .current_dir(&cwd)
-                .env(
-                    "PATH",
-                    std::env::var("PATH").unwrap_or_else(|_| {
-                        "/usr/local/bin:/usr/bin:/bin:/usr/sbin:/sbin".to_string()
-                    }),
-                )
-                .env(
-                    "HOME",
-                    dirs::home_dir()
-                        .unwrap_or_else(|| std::path::PathBuf::from("/tmp"))
-                        .to_string_lossy()
-                        .to_string(),
-                )
-                .output()
-                .map_err(|e| {
-                    WinxError::bash_error(format!("Failed to execute compound command: {}", e))
-                })?;
-
-            // Process output
-            let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-
-            // Update buffers
-            {
-                let mut stdout_buf = self.stdout_buffer.lock().unwrap();
-                *stdout_buf = stdout;
-            }
+        // Execute the command in a shell
+        let mut cmd = Command::new("bash");
+        cmd.arg("-c")
+            .arg(command)
+            .current_dir(&cwd)
+            .env(
+                "PATH",
+                std::env::var("PATH")
+                    .unwrap_or_else(|_| "/usr/local/bin:/usr/bin:/bin:/usr/sbin:/sbin".to_string()),
+            )
+            .env(
+                "HOME",
+                dirs::home_dir()
+                    .unwrap_or_else(|| std::path::PathBuf::from("/tmp"))
+                    .to_string_lossy()
+                    .to_string(),
+            );
+        let output = self.run_limited(cmd)?;
+        let (stdout, stderr) = (output.stdout, output.stderr);
 
-            {
-                let mut stderr_buf = self.stderr_buffer.lock().unwrap();
-                *stderr_buf = stderr;
-            }
+        // Update buffers
+        {
+            let mut stdout_buf = self.stdout_buffer.lock().unwrap();
+            *stdout_buf = stdout;
+        }
 
-            // Update status
-            {
-                let mut status = self.status.lock().unwrap();
-                *status = ProcessStatus::Exited(output.status.code().unwrap_or(0));
-            }
+        {
+            let mut stderr_buf = self.stderr_buffer.lock().unwrap();
+            *stderr_buf = stderr;
+        }
 
-            // Store the command
-            {
-                let mut last_cmd = self.last_command.lock().unwrap();
-                *last_cmd = command.to_string();
-            }
+        // Update status
+        {
+            let mut status = self.status.lock().unwrap();
+            *status = ProcessStatus::Exited(limits::exit_code(&output.status));
+        }
 
-            // If it's a cd command, we need to update the directory after execution
-            if command.contains("cd ") {
-                // Run pwd to discover the current directory
-                let pwd_output = std::process::Command::new("bash")
-                    .arg("-c")
-                    .arg("pwd")
-                    .current_dir(&cwd)
-                    .output();
+        // Store the command
+        {
+            let mut last_cmd = self.last_command.lock().unwrap();
+            *last_cmd = command.to_string();
+        }
 
-                if let Ok(pwd_output) = pwd_output {
-                    let new_dir = String::from_utf8_lossy(&pwd_output.stdout)
-                        .trim()
-                        .to_string();
-                    if !new_dir.is_empty() {
-                        self.update_cwd(new_dir);
-                        log::info!("Updated working directory to: {}", self.get_cwd());
+        // If it's a cd command, we need to update the directory after execution
+        if command.contains("cd ") {
+            // Run pwd to discover the current directory
+            let pwd_output = std::process::Command::new("bash")
+                .arg("-c")
+                .arg("pwd")
+                .current_dir(&cwd)
+                .output();
+
+            if let Ok(pwd_output) = pwd_output {
+                let new_dir = String::from_utf8_lossy(&pwd_output.stdout)
+                    .trim()
+                    .to_string();
+                if !new_dir.is_empty() {
+                    self.update_cwd(new_dir);
+                    log::info!("Updated working directory to: {}", self.get_cwd());
 
-                        // Update output to show directory change
-                        let mut stdout_buf = self.stdout_buffer.lock().unwrap();
-                        *stdout_buf =
-                            format!("Changed directory to: {}\n{}", self.get_cwd(), stdout_buf);
-                    }
+                    // Update output to show directory change
+                    let mut stdout_buf = self.stdout_buffer.lock().unwrap();
+                    *stdout_buf =
+                        format!("Changed directory to: {}\n{}", self.get_cwd(), stdout_buf);
                 }
             }
+        }
 
-            return Ok(());
+        Ok(())
+    }
+
+    /// Executes a command directly using std::process::Command instead of interactive shell
+    /// This works around the "Must be connected to a terminal" problem
+    fn execute_direct_command(&self, command: &str) -> WinxResult<()> {
+        log::info!("Attempting direct command execution for: {}", command);
+
+        // Check if the command is compound (contains && or ||)
+        if command.contains("&&") || command.contains("||") || command.contains(";") {
+            // Let's execute in a shell to use compound operators
+            log::info!("Detected compound command, using shell execution");
+            return self.execute_shell_command(command);
         }
 
         // Special handling for cd commands - update internal state only
@@ -659,13 +735,8 @@ impl CommandRunner {
             );
 
         // Execute and capture output
-        let output = cmd
-            .output()
-            .map_err(|e| WinxError::bash_error(format!("Failed to execute command: {}", e)))?;
-
-        // Process output
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        let output = self.run_limited(cmd)?;
+        let (stdout, stderr) = (output.stdout, output.stderr);
 
         log::debug!("Direct command stdout: {}", stdout);
         if !stderr.is_empty() {
@@ -686,7 +757,7 @@ impl CommandRunner {
         // Update status
         {
             let mut status = self.status.lock().unwrap();
-            *status = ProcessStatus::Exited(output.status.code().unwrap_or(0));
+            *status = ProcessStatus::Exited(limits::exit_code(&output.status));
         }
 
         // Store the command
@@ -718,6 +789,26 @@ impl CommandRunner {
             return Err(WinxError::ShellNotStarted);
         }
 
+        // Kill the last direct command with everything it started
+        if let Some(group) = self.active_group.lock().unwrap().take() {
+            group.kill();
+        }
+
+        // Commands run through the shell share its group, kill its descendants instead
+        #[cfg(target_os = "linux")]
+        if let Some(shell_pid) = *self.shell_pid.lock().unwrap() {
+            for pid in limits::descendants(shell_pid) {
+                // SAFETY: kill has no memory-safety requirements
+                unsafe {
+                    let pgid = libc::getpgid(pid as libc::pid_t);
+                    if pgid > 0 && pgid as u32 != shell_pid {
+                        libc::killpg(pgid, libc::SIGKILL);
+                    }
+                    libc::kill(pid as libc::pid_t, libc::SIGKILL);
+                }
+            }
+        }
+
         let tx = self.tx_ctrl.as_ref().unwrap();
         tx.send(2).await.map_err(|e| {
             WinxError::bash_error(format!("Failed to send interrupt to shell: {}", e))
@@ -807,6 +898,10 @@ impl CommandRunner {
         let status = self.status.lock().unwrap().clone();
         let cwd = self.get_cwd();
 
+        if let Some(hit) = *self.last_limit_hit.lock().unwrap() {
+            return format!("status = stopped: {}\ncwd = {}\n", hit, cwd);
+        }
+
         match status {
             ProcessStatus::Running => format!("status = still running\ncwd = {}\n", cwd),
             ProcessStatus::Exited(code) => format!(
@@ -834,3 +929,26 @@ impl CommandRunner {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shell_builtin_fallback_keeps_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut runner = CommandRunner::new(&dir.path().to_string_lossy());
+        runner.set_limits(ResourceLimits {
+            wall_time_secs: Some(1),
+            ..Default::default()
+        });
+
+        // `exec` is a shell builtin, direct execution fails and the shell retry must
+        // still be stopped by the wall time limit
+        runner.execute("exec sleep 30").await.unwrap();
+        assert_eq!(
+            *runner.last_limit_hit.lock().unwrap(),
+            Some(LimitHit::WallTime(1))
+        );
+    }
+}
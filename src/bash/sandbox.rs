@@ -0,0 +1,591 @@
+//! Process sandbox for shell commands in sandboxed mode
+//!
+//! On Linux every process spawned by the command runner is confined before it execs:
+//!
+//! - a user namespace (so no privileges are needed) with private mount and network
+//!   namespaces. The network namespace only has loopback, where a proxy admitting
+//!   the `allowed_hosts` listens when there are any (see [`super::proxy`]),
+//! - a Landlock ruleset making the filesystem read-only outside the workspace, the
+//!   `allowed_paths`, `/dev` and, unless `sandbox.writable_temp_dir` is off, the
+//!   temporary directory,
+//! - a seccomp filter rejecting syscalls that could undo the confinement (mounts,
+//!   namespaces, ptrace, kernel modules, ...) and, without allowed hosts, IP sockets.
+//!
+//! Each layer is probed once. Layers the kernel doesn't support are skipped with a
+//! warning, commands still run with the remaining ones.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::config::SandboxConfig;
+use crate::error::WinxResult;
+
+/// What a sandboxed command is allowed to do
+#[derive(Debug, Clone, PartialEq)]
+pub struct SandboxPolicy {
+    /// Directories the command may write to, the rest of the filesystem is read-only
+    pub writable_paths: Vec<PathBuf>,
+    /// Hosts the command may reach through the proxy, no network when empty
+    pub allowed_hosts: Vec<String>,
+}
+
+impl SandboxPolicy {
+    /// Policy for the confinement roots and allowed hosts of the security manager
+    /// and its sandbox settings
+    pub fn new(
+        confinement_roots: Vec<PathBuf>,
+        allowed_hosts: Vec<String>,
+        config: &SandboxConfig,
+    ) -> Self {
+        let mut writable_paths = confinement_roots;
+        let mut extras = vec![PathBuf::from("/dev")];
+        if config.writable_temp_dir {
+            extras.push(std::env::temp_dir());
+        }
+        for extra in extras {
+            let extra = std::fs::canonicalize(&extra).unwrap_or(extra);
+            if !writable_paths.iter().any(|p| extra.starts_with(p)) {
+                writable_paths.push(extra);
+            }
+        }
+        Self {
+            writable_paths,
+            allowed_hosts,
+        }
+    }
+
+    fn existing_writable_paths(&self) -> Vec<&Path> {
+        self.writable_paths
+            .iter()
+            .map(PathBuf::as_path)
+            .filter(|p| p.exists())
+            .collect()
+    }
+}
+
+/// Confines `command` according to `policy` before it is spawned
+#[cfg(target_os = "linux")]
+pub fn apply(command: &mut Command, policy: &SandboxPolicy) -> WinxResult<()> {
+    linux::apply(command, policy)
+}
+
+/// Confines `command` according to `policy` before it is spawned
+#[cfg(not(target_os = "linux"))]
+pub fn apply(_command: &mut Command, _policy: &SandboxPolicy) -> WinxResult<()> {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    WARNED.call_once(|| {
+        log::warn!(
+            "Sandboxed mode: process sandboxing is only available on Linux, shell commands run unconfined"
+        );
+    });
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::SandboxPolicy;
+    use landlock::{
+        path_beneath_rules, AccessFs, Ruleset, RulesetAttr, RulesetCreated, RulesetCreatedAttr, ABI,
+    };
+    use seccompiler::{
+        BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+        SeccompRule, TargetArch,
+    };
+    use std::collections::BTreeMap;
+    use std::ffi::CStr;
+    use std::io;
+    use std::net::TcpListener;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+    use std::sync::{Mutex, OnceLock};
+    use std::thread;
+
+    use crate::bash::proxy;
+    use crate::error::{WinxError, WinxResult};
+
+    /// Newest Landlock ABI requested, older kernels get the subset they support
+    const LANDLOCK_ABI: ABI = ABI::V5;
+
+    /// Syscalls that could be used to escape or weaken the sandbox
+    const DENIED_SYSCALLS: &[libc::c_long] = &[
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_writev,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_userfaultfd,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_kexec_load,
+        libc::SYS_kexec_file_load,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_reboot,
+    ];
+
+    /// Sandbox layers the running kernel supports
+    #[derive(Debug, Clone, Copy)]
+    struct Support {
+        namespaces: bool,
+        landlock: bool,
+        seccomp: bool,
+    }
+
+    fn support() -> Support {
+        static SUPPORT: OnceLock<Support> = OnceLock::new();
+        *SUPPORT.get_or_init(|| {
+            let support = Support {
+                namespaces: probe(|cmd| {
+                    let maps = IdMaps::current();
+                    // SAFETY: the hook only makes raw syscalls on data owned by the closure
+                    unsafe {
+                        cmd.pre_exec(move || enter_namespaces(&maps));
+                    }
+                }),
+                landlock: landlock_abi_version() > 0,
+                seccomp: seccomp_program(false).is_some_and(|program| {
+                    probe(move |cmd| {
+                        // SAFETY: the hook only installs a filter compiled beforehand
+                        unsafe {
+                            cmd.pre_exec(move || apply_seccomp(&program));
+                        }
+                    })
+                }),
+            };
+
+            if !support.namespaces {
+                log::warn!(
+                    "Sandboxed mode: unprivileged user namespaces are unavailable, shell commands keep the host mount and network namespaces"
+                );
+            }
+            if !support.landlock {
+                log::warn!(
+                    "Sandboxed mode: Landlock is not enabled in this kernel, shell commands can write outside the workspace"
+                );
+            }
+            if !support.seccomp {
+                log::warn!(
+                    "Sandboxed mode: seccomp filtering is unavailable, shell commands can use mount, ptrace and similar syscalls"
+                );
+            }
+            log::info!("Shell command sandbox layers: {:?}", support);
+            support
+        })
+    }
+
+    /// Whether a trivial command configured by `configure` runs successfully
+    fn probe(configure: impl FnOnce(&mut Command)) -> bool {
+        let mut cmd = Command::new("true");
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        configure(&mut cmd);
+        cmd.status().is_ok_and(|status| status.success())
+    }
+
+    pub(super) fn apply(command: &mut Command, policy: &SandboxPolicy) -> WinxResult<()> {
+        let support = support();
+
+        let namespaces = support.namespaces.then(IdMaps::current);
+        let ruleset = if support.landlock {
+            Some(landlock_ruleset(policy)?)
+        } else {
+            None
+        };
+        // Allowed hosts are only reachable through the proxy in the network namespace
+        let proxy = if policy.allowed_hosts.is_empty() {
+            None
+        } else if support.namespaces {
+            Some(proxy_channel(command, policy.allowed_hosts.clone())?)
+        } else {
+            static WARNED: std::sync::Once = std::sync::Once::new();
+            WARNED.call_once(|| {
+                log::warn!(
+                    "Sandboxed mode: allowed_hosts can't be enforced without user namespaces, shell commands get no network"
+                );
+            });
+            None
+        };
+        let seccomp = if support.seccomp {
+            seccomp_program(proxy.is_none())
+        } else {
+            None
+        };
+
+        // Taken by the hook, which must be Sync
+        let ruleset = Mutex::new(ruleset);
+        // SAFETY: the hook runs between fork and exec, it only makes raw syscalls on
+        // data prepared by the parent and doesn't allocate on success
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(maps) = &namespaces {
+                    enter_namespaces(maps)?;
+                }
+                if let Some(channel) = &proxy {
+                    open_proxy_listener(channel.as_raw_fd())?;
+                }
+                let ruleset = ruleset.lock().ok().and_then(|mut r| r.take());
+                if let Some(ruleset) = ruleset {
+                    ruleset
+                        .restrict_self()
+                        .map_err(|_| io::Error::from_raw_os_error(libc::EPERM))?;
+                }
+                if let Some(program) = &seccomp {
+                    apply_seccomp(program)?;
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Identity mapping of the current user, written into the new user namespace
+    struct IdMaps {
+        uid_map: Vec<u8>,
+        gid_map: Vec<u8>,
+    }
+
+    impl IdMaps {
+        fn current() -> Self {
+            // SAFETY: getuid and getgid can't fail
+            let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+            Self {
+                uid_map: format!("{} {} 1\n", uid, uid).into_bytes(),
+                gid_map: format!("{} {} 1\n", gid, gid).into_bytes(),
+            }
+        }
+    }
+
+    fn check(result: libc::c_int) -> io::Result<()> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn write_proc_file(path: &CStr, data: &[u8]) -> io::Result<()> {
+        // SAFETY: `path` is NUL-terminated and `data` outlives the calls
+        unsafe {
+            let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            check(fd)?;
+            let written = libc::write(fd, data.as_ptr().cast(), data.len());
+            libc::close(fd);
+            if written != data.len() as isize {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the calling process into new user, mount and network namespaces
+    fn enter_namespaces(maps: &IdMaps) -> io::Result<()> {
+        // SAFETY: plain syscalls with constant arguments
+        unsafe {
+            check(libc::unshare(
+                libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWNET,
+            ))?;
+        }
+
+        write_proc_file(c"/proc/self/setgroups", b"deny")?;
+        write_proc_file(c"/proc/self/uid_map", &maps.uid_map)?;
+        write_proc_file(c"/proc/self/gid_map", &maps.gid_map)?;
+
+        // Mounts made by the command stay inside its namespace
+        // SAFETY: NUL-terminated path, null source/type/data are allowed for MS_PRIVATE
+        unsafe {
+            check(libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))
+        }
+    }
+
+    /// Channel the command sends its proxy listener over, which is served once it
+    /// arrives
+    ///
+    /// The command's processes inherit their end, so the proxy stops once all of
+    /// them have exited.
+    fn proxy_channel(command: &mut Command, allowed_hosts: Vec<String>) -> WinxResult<OwnedFd> {
+        let (agent_end, command_end) = UnixStream::pair()?;
+        thread::spawn(move || match receive_fd(&agent_end) {
+            Ok(Some(listener)) => {
+                proxy::spawn(TcpListener::from(listener), allowed_hosts, agent_end)
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!(
+                "Failed to receive the sandbox network proxy listener: {}",
+                e
+            ),
+        });
+
+        let url = format!("http://127.0.0.1:{}", proxy::PROXY_PORT);
+        for name in ["http_proxy", "https_proxy", "all_proxy"] {
+            command.env(name, &url).env(name.to_uppercase(), &url);
+        }
+        command.env_remove("no_proxy").env_remove("NO_PROXY");
+        Ok(OwnedFd::from(command_end))
+    }
+
+    /// Brings loopback up in the new network namespace, listens on the proxy port
+    /// there and sends the listener to the agent over `channel`
+    fn open_proxy_listener(channel: RawFd) -> io::Result<()> {
+        // SAFETY: raw syscalls on zero-initialized structs owned by this frame
+        unsafe {
+            let control = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+            check(control)?;
+            let mut request: libc::ifreq = std::mem::zeroed();
+            request.ifr_name[0] = b'l' as libc::c_char;
+            request.ifr_name[1] = b'o' as libc::c_char;
+            request.ifr_ifru.ifru_flags = (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+            let result = libc::ioctl(control, libc::SIOCSIFFLAGS as _, &request);
+            libc::close(control);
+            check(result)?;
+
+            let listener = libc::socket(libc::AF_INET, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0);
+            check(listener)?;
+            let mut addr: libc::sockaddr_in = std::mem::zeroed();
+            addr.sin_family = libc::AF_INET as libc::sa_family_t;
+            addr.sin_port = proxy::PROXY_PORT.to_be();
+            addr.sin_addr.s_addr = u32::from_be_bytes([127, 0, 0, 1]).to_be();
+            let result = check(libc::bind(
+                listener,
+                (&addr as *const libc::sockaddr_in).cast(),
+                std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+            ))
+            .and_then(|_| check(libc::listen(listener, 128)))
+            .and_then(|_| send_fd(channel, listener));
+            libc::close(listener);
+            result?;
+
+            // Keep the channel open in everything the command starts
+            check(libc::fcntl(channel, libc::F_SETFD, 0))
+        }
+    }
+
+    /// Control message buffer holding one file descriptor, aligned for `cmsghdr`
+    #[repr(C, align(8))]
+    struct FdControl([u8; 32]);
+
+    fn send_fd(channel: RawFd, fd: RawFd) -> io::Result<()> {
+        let mut byte = [0u8; 1];
+        let mut control = FdControl([0; 32]);
+        // SAFETY: the message points at buffers of this frame, sized with CMSG_SPACE
+        unsafe {
+            let mut iov = libc::iovec {
+                iov_base: byte.as_mut_ptr().cast(),
+                iov_len: byte.len(),
+            };
+            let mut message: libc::msghdr = std::mem::zeroed();
+            message.msg_iov = &mut iov;
+            message.msg_iovlen = 1;
+            message.msg_control = control.0.as_mut_ptr().cast();
+            message.msg_controllen = libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) as _;
+            let header = libc::CMSG_FIRSTHDR(&message);
+            (*header).cmsg_level = libc::SOL_SOCKET;
+            (*header).cmsg_type = libc::SCM_RIGHTS;
+            (*header).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(header).cast::<RawFd>(), fd);
+            if libc::sendmsg(channel, &message, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// File descriptor sent over `channel`, `None` when it closed without one
+    fn receive_fd(channel: &UnixStream) -> io::Result<Option<OwnedFd>> {
+        let mut byte = [0u8; 1];
+        let mut control = FdControl([0; 32]);
+        // SAFETY: the message points at buffers of this frame, nothing else owns the
+        // received descriptor
+        unsafe {
+            let mut iov = libc::iovec {
+                iov_base: byte.as_mut_ptr().cast(),
+                iov_len: byte.len(),
+            };
+            let mut message: libc::msghdr = std::mem::zeroed();
+            message.msg_iov = &mut iov;
+            message.msg_iovlen = 1;
+            message.msg_control = control.0.as_mut_ptr().cast();
+            message.msg_controllen = control.0.len() as _;
+            let received = libc::recvmsg(channel.as_raw_fd(), &mut message, libc::MSG_CMSG_CLOEXEC);
+            if received < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let header = libc::CMSG_FIRSTHDR(&message);
+            if received == 0
+                || header.is_null()
+                || (*header).cmsg_level != libc::SOL_SOCKET
+                || (*header).cmsg_type != libc::SCM_RIGHTS
+            {
+                return Ok(None);
+            }
+            let fd = std::ptr::read_unaligned(libc::CMSG_DATA(header).cast::<RawFd>());
+            Ok(Some(OwnedFd::from_raw_fd(fd)))
+        }
+    }
+
+    /// Landlock ABI version of the running kernel, 0 when unsupported
+    fn landlock_abi_version() -> i64 {
+        const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1;
+        // SAFETY: querying the version takes no ruleset attributes
+        let version = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<libc::c_void>(),
+                0usize,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        version.max(0)
+    }
+
+    /// Ruleset denying writes everywhere except beneath the policy's writable paths
+    fn landlock_ruleset(policy: &SandboxPolicy) -> WinxResult<RulesetCreated> {
+        let to_error = |e: landlock::RulesetError| {
+            WinxError::Other(format!("Failed to build the Landlock ruleset: {}", e))
+        };
+        Ruleset::default()
+            .handle_access(AccessFs::from_write(LANDLOCK_ABI))
+            .and_then(|ruleset| ruleset.create())
+            .and_then(|ruleset| {
+                ruleset.add_rules(path_beneath_rules(
+                    policy.existing_writable_paths(),
+                    AccessFs::from_write(LANDLOCK_ABI),
+                ))
+            })
+            .map_err(to_error)
+    }
+
+    /// Compiles the seccomp filter, `None` on architectures seccompiler doesn't support
+    fn seccomp_program(deny_ip_sockets: bool) -> Option<BpfProgram> {
+        let arch = TargetArch::try_from(std::env::consts::ARCH).ok()?;
+
+        let mut rules: BTreeMap<i64, Vec<SeccompRule>> = DENIED_SYSCALLS
+            .iter()
+            .map(|&syscall| (syscall, Vec::new()))
+            .collect();
+        if deny_ip_sockets {
+            // Unix sockets keep working, only the socket domain argument is checked
+            let socket_rules = [libc::AF_INET, libc::AF_INET6]
+                .into_iter()
+                .map(|domain| {
+                    SeccompCondition::new(
+                        0,
+                        SeccompCmpArgLen::Dword,
+                        SeccompCmpOp::Eq,
+                        domain as u64,
+                    )
+                    .and_then(|condition| SeccompRule::new(vec![condition]))
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?;
+            rules.insert(libc::SYS_socket, socket_rules);
+        }
+
+        let filter = SeccompFilter::new(
+            rules,
+            SeccompAction::Allow,
+            SeccompAction::Errno(libc::EPERM as u32),
+            arch,
+        )
+        .ok()?;
+        filter.try_into().ok()
+    }
+
+    fn apply_seccomp(program: &BpfProgram) -> io::Result<()> {
+        seccompiler::apply_filter(program).map_err(|_| io::Error::from_raw_os_error(libc::EPERM))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_sandbox_confines_writes() {
+            let dir = tempfile::tempdir().unwrap();
+            let root = std::fs::canonicalize(dir.path()).unwrap();
+            let inside = root.join("inside");
+            let outside = root.join("outside");
+            std::fs::create_dir_all(&inside).unwrap();
+            std::fs::create_dir_all(&outside).unwrap();
+
+            let policy = SandboxPolicy {
+                writable_paths: vec![inside.clone()],
+                allowed_hosts: Vec::new(),
+            };
+            let run = |script: &str| {
+                let mut cmd = Command::new("sh");
+                cmd.arg("-c").arg(script);
+                apply(&mut cmd, &policy).unwrap();
+                cmd.status().unwrap().success()
+            };
+
+            assert!(run(&format!("touch {}/ok", inside.display())));
+            if support().landlock {
+                assert!(!run(&format!("touch {}/escaped", outside.display())));
+                assert!(!outside.join("escaped").exists());
+            }
+        }
+
+        #[test]
+        fn test_sandbox_network_only_reaches_allowed_hosts() {
+            if !support().namespaces {
+                return;
+            }
+            let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = server.local_addr().unwrap().port();
+            thread::spawn(move || {
+                for mut stream in server.incoming().flatten() {
+                    let _ = io::Write::write_all(&mut stream, b"hello");
+                }
+            });
+
+            let policy = SandboxPolicy {
+                writable_paths: Vec::new(),
+                allowed_hosts: vec!["127.0.0.1".to_string()],
+            };
+            let run = |script: String| {
+                let mut cmd = Command::new("bash");
+                cmd.arg("-c").arg(script);
+                apply(&mut cmd, &policy).unwrap();
+                String::from_utf8_lossy(&cmd.output().unwrap().stdout).into_owned()
+            };
+            let connect = |host: &str| {
+                run(format!(
+                    "exec 3<>/dev/tcp/127.0.0.1/{}; printf 'CONNECT {}:{} HTTP/1.1\\r\\n\\r\\n' >&3; cat <&3",
+                    proxy::PROXY_PORT,
+                    host,
+                    port
+                ))
+            };
+
+            let allowed = connect("127.0.0.1");
+            assert!(allowed.starts_with("HTTP/1.1 200"), "{}", allowed);
+            assert!(allowed.ends_with("hello"), "{}", allowed);
+            assert!(connect("localhost").starts_with("HTTP/1.1 403"));
+            assert_eq!(
+                run(format!(
+                    "exec 3<>/dev/tcp/127.0.0.1/{} && echo reached",
+                    port
+                )),
+                ""
+            );
+        }
+    }
+}
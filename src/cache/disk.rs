@@ -0,0 +1,300 @@
+//! Disk-backed second tier for [`super::AdvancedCache`]
+//!
+//! Values are stored content-addressed: `objects/ab/<sha256>` holds the bytes and
+//! `refs/<namespace>/<sha256 of key>` points at the object currently cached for a
+//! key. Identical values share one object, and an object whose bytes no longer hash
+//! to its name is treated as corrupt and dropped. Every file is written to a
+//! temporary file first and renamed into place, so a crash never leaves a torn entry.
+//!
+//! Object modification times record the last access and drive LRU eviction once the
+//! tier grows past its size limit.
+
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::error::{WinxError, WinxResult};
+
+/// Header written before every object, bump when the layout changes
+const OBJECT_MAGIC: &[u8; 8] = b"WINXCAC1";
+
+/// Default size limit of the whole tier
+pub const DEFAULT_MAX_DISK_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Default size limit of a single entry, larger values stay memory-only
+pub const DEFAULT_MAX_ENTRY_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Eviction frees space down to this fraction of the limit, to avoid evicting on every write
+const EVICTION_TARGET_PERCENT: u64 = 90;
+
+fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    format!("{:x}", hasher.finalize())
+}
+
+fn io_error(e: std::io::Error, path: &Path) -> WinxError {
+    WinxError::io_error(e, Some(path))
+}
+
+/// Writes `data` to `path` atomically through a temporary file in `tmp_dir`
+fn write_atomic(tmp_dir: &Path, path: &Path, chunks: &[&[u8]]) -> WinxResult<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| io_error(e, parent))?;
+    }
+    let mut tmp = tempfile::NamedTempFile::new_in(tmp_dir).map_err(|e| io_error(e, tmp_dir))?;
+    for chunk in chunks {
+        tmp.write_all(chunk).map_err(|e| io_error(e, tmp.path()))?;
+    }
+    tmp.as_file()
+        .sync_all()
+        .map_err(|e| io_error(e, tmp.path()))?;
+    tmp.persist(path).map_err(|e| io_error(e.error, path))?;
+    Ok(())
+}
+
+/// Persistent content-addressed cache under a directory
+pub struct DiskCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_entry_bytes: u64,
+    /// Approximate size of all objects, recomputed on eviction
+    total_bytes: Mutex<u64>,
+}
+
+impl DiskCache {
+    /// Opens (creating if needed) the cache stored in `dir`
+    pub fn open(dir: impl Into<PathBuf>, max_bytes: u64, max_entry_bytes: u64) -> WinxResult<Self> {
+        let dir = dir.into();
+        for sub in ["objects", "refs", "tmp"] {
+            let path = dir.join(sub);
+            fs::create_dir_all(&path).map_err(|e| io_error(e, &path))?;
+        }
+
+        let cache = Self {
+            dir,
+            max_bytes,
+            max_entry_bytes,
+            total_bytes: Mutex::new(0),
+        };
+        let total = cache.objects().iter().map(|(_, size, _)| size).sum();
+        *cache.total_bytes.lock().map_err(|e| {
+            WinxError::lock_error(format!("Failed to acquire disk cache lock: {}", e))
+        })? = total;
+        Ok(cache)
+    }
+
+    /// Default location of the tier, under the user's cache directory
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|d| d.join("winx-code-agent").join("advanced"))
+    }
+
+    fn ref_path(&self, namespace: &str, key: &str) -> PathBuf {
+        self.dir
+            .join("refs")
+            .join(namespace)
+            .join(sha256_hex(key.as_bytes()))
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.dir.join("objects").join(&hash[..2]).join(hash)
+    }
+
+    /// Returns the bytes cached for `key`, dropping the entry if it is corrupt
+    pub fn get(&self, namespace: &str, key: &str) -> Option<Vec<u8>> {
+        let ref_path = self.ref_path(namespace, key);
+        let hash = fs::read_to_string(&ref_path).ok()?;
+        let hash = hash.trim();
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            let _ = fs::remove_file(&ref_path);
+            return None;
+        }
+
+        let object_path = self.object_path(hash);
+        let Ok(bytes) = fs::read(&object_path) else {
+            // Object evicted, the reference is dangling
+            let _ = fs::remove_file(&ref_path);
+            return None;
+        };
+
+        let valid =
+            bytes.starts_with(OBJECT_MAGIC) && sha256_hex(&bytes[OBJECT_MAGIC.len()..]) == hash;
+        if !valid {
+            log::warn!(
+                "Dropping corrupt disk cache entry {}",
+                object_path.display()
+            );
+            let _ = fs::remove_file(&object_path);
+            let _ = fs::remove_file(&ref_path);
+            return None;
+        }
+
+        // Record the access for LRU eviction
+        if let Ok(file) = fs::File::options().append(true).open(&object_path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        Some(bytes[OBJECT_MAGIC.len()..].to_vec())
+    }
+
+    /// Stores `data` for `key`, evicting least recently used entries if over the limit
+    pub fn put(&self, namespace: &str, key: &str, data: &[u8]) -> WinxResult<()> {
+        let ref_path = self.ref_path(namespace, key);
+        if data.len() as u64 > self.max_entry_bytes {
+            // Don't leave an older value behind for this key
+            let _ = fs::remove_file(&ref_path);
+            return Ok(());
+        }
+
+        let tmp_dir = self.dir.join("tmp");
+        let hash = sha256_hex(data);
+        let object_path = self.object_path(&hash);
+        if object_path.exists() {
+            if let Ok(file) = fs::File::options().append(true).open(&object_path) {
+                let _ = file.set_modified(SystemTime::now());
+            }
+        } else {
+            write_atomic(&tmp_dir, &object_path, &[OBJECT_MAGIC, data])?;
+            let mut total = self.total_bytes.lock().map_err(|e| {
+                WinxError::lock_error(format!("Failed to acquire disk cache lock: {}", e))
+            })?;
+            *total += (OBJECT_MAGIC.len() + data.len()) as u64;
+        }
+
+        write_atomic(&tmp_dir, &ref_path, &[hash.as_bytes()])?;
+
+        let over_limit = self.total_bytes.lock().map(|t| *t > self.max_bytes);
+        if over_limit.unwrap_or(false) {
+            self.evict();
+        }
+        Ok(())
+    }
+
+    /// Forgets the entry of `key`; its object stays until evicted, it may be shared
+    pub fn remove(&self, namespace: &str, key: &str) {
+        let _ = fs::remove_file(self.ref_path(namespace, key));
+    }
+
+    /// Removes every entry of the tier
+    pub fn clear(&self) -> WinxResult<()> {
+        for sub in ["objects", "refs", "tmp"] {
+            let path = self.dir.join(sub);
+            if path.exists() {
+                fs::remove_dir_all(&path).map_err(|e| io_error(e, &path))?;
+            }
+            fs::create_dir_all(&path).map_err(|e| io_error(e, &path))?;
+        }
+        if let Ok(mut total) = self.total_bytes.lock() {
+            *total = 0;
+        }
+        Ok(())
+    }
+
+    /// Every object as (path, size, last access)
+    fn objects(&self) -> Vec<(PathBuf, u64, SystemTime)> {
+        let Ok(shards) = fs::read_dir(self.dir.join("objects")) else {
+            return Vec::new();
+        };
+        shards
+            .flatten()
+            .filter_map(|shard| fs::read_dir(shard.path()).ok())
+            .flat_map(|entries| entries.flatten())
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                let accessed = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                Some((entry.path(), metadata.len(), accessed))
+            })
+            .collect()
+    }
+
+    /// Deletes least recently used objects until the tier is under its target size
+    fn evict(&self) {
+        let mut objects = self.objects();
+        objects.sort_by_key(|(_, _, accessed)| *accessed);
+
+        let target = self.max_bytes / 100 * EVICTION_TARGET_PERCENT;
+        let mut total: u64 = objects.iter().map(|(_, size, _)| size).sum();
+        let mut evicted = 0;
+        for (path, size, _) in objects {
+            if total <= target {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                total = total.saturating_sub(size);
+                evicted += 1;
+            }
+        }
+
+        if evicted > 0 {
+            log::debug!("Evicted {} disk cache objects", evicted);
+            self.remove_dangling_refs();
+        }
+        if let Ok(mut current) = self.total_bytes.lock() {
+            *current = total;
+        }
+    }
+
+    fn remove_dangling_refs(&self) {
+        let Ok(namespaces) = fs::read_dir(self.dir.join("refs")) else {
+            return;
+        };
+        for entry in namespaces
+            .flatten()
+            .filter_map(|ns| fs::read_dir(ns.path()).ok())
+            .flat_map(|entries| entries.flatten())
+        {
+            let dangling = fs::read_to_string(entry.path())
+                .map(|hash| {
+                    let hash = hash.trim();
+                    hash.len() != 64 || !self.object_path(hash).exists()
+                })
+                .unwrap_or(true);
+            if dangling {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disk_cache_roundtrip_corruption_and_eviction() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::open(dir.path(), 100, 60).unwrap();
+
+        cache.put("query", "a", b"first value").unwrap();
+        assert_eq!(cache.get("query", "a").unwrap(), b"first value");
+        assert!(cache.get("plugin", "a").is_none());
+
+        // Oversized values are not stored
+        cache.put("query", "big", &[0u8; 61]).unwrap();
+        assert!(cache.get("query", "big").is_none());
+
+        // Corrupt objects are detected and dropped
+        let object = cache.object_path(&sha256_hex(b"first value"));
+        fs::write(&object, b"WINXCAC1tampered").unwrap();
+        assert!(cache.get("query", "a").is_none());
+        assert!(!object.exists());
+
+        // Going over the limit evicts the least recently used entry
+        cache.put("query", "old", &[1u8; 50]).unwrap();
+        let old = cache.object_path(&sha256_hex(&[1u8; 50]));
+        fs::File::options()
+            .append(true)
+            .open(&old)
+            .and_then(|f| f.set_modified(SystemTime::UNIX_EPOCH))
+            .unwrap();
+        cache.put("query", "new", &[2u8; 50]).unwrap();
+        assert!(cache.get("query", "old").is_none());
+        assert_eq!(cache.get("query", "new").unwrap(), vec![2u8; 50]);
+
+        cache.clear().unwrap();
+        assert!(cache.get("query", "new").is_none());
+    }
+}
@@ -4,11 +4,24 @@ use std::collections::HashMap;
 use std::fs;
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::config::config::CacheConfig;
 use crate::error::{WinxError, WinxResult};
 
+pub mod disk;
+pub mod watcher;
+
+use disk::DiskCache;
+
+/// How long query results computed by [`AdvancedCache::get_or_compute`] stay valid
+const QUERY_TTL: Duration = Duration::from_secs(300);
+
+/// Disk tier namespaces
+const DISK_QUERY_NAMESPACE: &str = "query";
+const DISK_PLUGIN_NAMESPACE: &str = "plugin";
+
 #[derive(Clone)]
 pub struct FileCache {
     content_cache: HashMap<PathBuf, (String, Instant)>,
@@ -94,6 +107,8 @@ pub struct AdvancedCache {
     file_cache: Arc<Mutex<LruCache<PathBuf, CacheEntry>>>,
     plugin_cache: Arc<Mutex<LruCache<String, Vec<u8>>>>,
     query_cache: Arc<Mutex<LruCache<String, QueryResult>>>,
+    /// Optional persistent tier for query results and plugin bytes
+    disk: RwLock<Option<Arc<DiskCache>>>,
 }
 
 #[derive(Clone)]
@@ -102,6 +117,32 @@ pub struct QueryResult {
     pub timestamp: Instant,
 }
 
+/// Query result as persisted in the disk tier, with a wall-clock timestamp
+#[derive(serde::Serialize, serde::Deserialize)]
+struct DiskQueryResult {
+    stored_at: u64,
+    data: serde_json::Value,
+}
+
+/// Copies a disk tier hit back to memory with the time it was stored at
+fn restore_query(
+    cache: &mut LruCache<String, QueryResult>,
+    key: &str,
+    data: serde_json::Value,
+    stored_at: Option<Instant>,
+) {
+    if let Some(timestamp) = stored_at {
+        cache.put(key.to_string(), QueryResult { data, timestamp });
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 impl AdvancedCache {
     pub fn new(
         max_file_entries: usize,
@@ -121,6 +162,59 @@ impl AdvancedCache {
                 NonZeroUsize::new(max_query_entries)
                     .unwrap_or_else(|| NonZeroUsize::new(500).unwrap()),
             ))),
+            disk: RwLock::new(None),
+        }
+    }
+
+    /// Adds a persistent tier consulted on memory misses and written through on stores
+    pub fn with_disk_tier(self, disk: DiskCache) -> Self {
+        self.set_disk_tier(Some(disk));
+        self
+    }
+
+    /// Replaces the persistent tier, `None` keeps the cache in memory only
+    pub fn set_disk_tier(&self, disk: Option<DiskCache>) {
+        if let Ok(mut current) = self.disk.write() {
+            *current = disk.map(Arc::new);
+        }
+    }
+
+    fn disk(&self) -> Option<Arc<DiskCache>> {
+        self.disk.read().ok()?.clone()
+    }
+
+    /// Reads a query result from the disk tier, if younger than `max_age` when given
+    ///
+    /// The result is dated when it was stored, so copying it back to memory doesn't
+    /// restart its TTL. It is `None` when that predates the monotonic clock.
+    fn disk_query(
+        &self,
+        key: &str,
+        max_age: Option<Duration>,
+    ) -> Option<(serde_json::Value, Option<Instant>)> {
+        let bytes = self.disk()?.get(DISK_QUERY_NAMESPACE, key)?;
+        let stored: DiskQueryResult = serde_json::from_slice(&bytes).ok()?;
+        let age = Duration::from_secs(unix_now().saturating_sub(stored.stored_at));
+        if max_age.is_some_and(|max_age| age >= max_age) {
+            return None;
+        }
+        Some((stored.data, Instant::now().checked_sub(age)))
+    }
+
+    fn store_disk_query(&self, key: &str, data: &serde_json::Value) {
+        let Some(disk) = self.disk() else {
+            return;
+        };
+        let stored = DiskQueryResult {
+            stored_at: unix_now(),
+            data: data.clone(),
+        };
+        // The disk tier is best effort, the memory tier already holds the value
+        let result = serde_json::to_vec(&stored)
+            .map_err(|e| WinxError::parse_error(format!("Cache serialization error: {}", e)))
+            .and_then(|bytes| disk.put(DISK_QUERY_NAMESPACE, key, &bytes));
+        if let Err(e) = result {
+            log::warn!("Failed to write disk cache entry: {}", e);
         }
     }
 
@@ -137,7 +231,7 @@ impl AdvancedCache {
         // Check if the result is in cache and not expired
         if let Some(result) = cache.get(key) {
             // Cache hit - check if expired (e.g., 5 minutes TTL)
-            if result.timestamp.elapsed() < Duration::from_secs(300) {
+            if result.timestamp.elapsed() < QUERY_TTL {
                 // Deserialize and return
                 return serde_json::from_value(result.data.clone()).map_err(|e| {
                     WinxError::parse_error(format!("Cache deserialization error: {}", e))
@@ -145,6 +239,14 @@ impl AdvancedCache {
             }
         }
 
+        // Memory miss - try the disk tier, e.g. a result computed before a restart
+        if let Some((data, stored_at)) = self.disk_query(key, Some(QUERY_TTL)) {
+            if let Ok(value) = serde_json::from_value::<T>(data.clone()) {
+                restore_query(&mut cache, key, data, stored_at);
+                return Ok(value);
+            }
+        }
+
         // Cache miss or expired - compute the result
         let computed = compute()?;
 
@@ -152,6 +254,7 @@ impl AdvancedCache {
         let serialized = serde_json::to_value(&computed)
             .map_err(|e| WinxError::parse_error(format!("Cache serialization error: {}", e)))?;
 
+        self.store_disk_query(key, &serialized);
         cache.put(
             key.to_string(),
             QueryResult {
@@ -163,6 +266,75 @@ impl AdvancedCache {
         Ok(computed)
     }
 
+    /// Returns a value stored with [`AdvancedCache::store_query`], regardless of its age
+    ///
+    /// Meant for callers that validate freshness themselves, e.g. indexes refreshed
+    /// incrementally from file modification times.
+    pub fn get_query<T>(&self, key: &str) -> Option<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let mut cache = self.query_cache.lock().ok()?;
+        if let Some(result) = cache.get(key) {
+            return serde_json::from_value(result.data.clone()).ok();
+        }
+
+        let (data, stored_at) = self.disk_query(key, None)?;
+        let value = serde_json::from_value(data.clone()).ok()?;
+        restore_query(&mut cache, key, data, stored_at);
+        Some(value)
+    }
+
+    /// Returns a value stored with [`AdvancedCache::store_query`] if younger than `max_age`
+    pub fn get_fresh_query<T>(&self, key: &str, max_age: Duration) -> Option<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let mut cache = self.query_cache.lock().ok()?;
+        if let Some(result) = cache.get(key) {
+            if result.timestamp.elapsed() < max_age {
+                return serde_json::from_value(result.data.clone()).ok();
+            }
+        }
+
+        let (data, stored_at) = self.disk_query(key, Some(max_age))?;
+        let value = serde_json::from_value(data.clone()).ok()?;
+        restore_query(&mut cache, key, data, stored_at);
+        Some(value)
+    }
+
+    pub fn store_query<T>(&self, key: &str, value: &T) -> WinxResult<()>
+    where
+        T: serde::Serialize,
+    {
+        let serialized = serde_json::to_value(value)
+            .map_err(|e| WinxError::parse_error(format!("Cache serialization error: {}", e)))?;
+        self.store_disk_query(key, &serialized);
+
+        let mut cache = self
+            .query_cache
+            .lock()
+            .map_err(|e| WinxError::lock_error(format!("Failed to acquire cache lock: {}", e)))?;
+        cache.put(
+            key.to_string(),
+            QueryResult {
+                data: serialized,
+                timestamp: Instant::now(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Drops a value stored with [`AdvancedCache::store_query`] from both tiers
+    pub fn remove_query(&self, key: &str) {
+        if let Ok(mut cache) = self.query_cache.lock() {
+            cache.pop(key);
+        }
+        if let Some(disk) = self.disk() {
+            disk.remove(DISK_QUERY_NAMESPACE, key);
+        }
+    }
+
     pub fn get_file(&self, path: &Path) -> Option<CacheEntry> {
         let cache = self.file_cache.lock().ok()?;
         cache.peek(path).cloned()
@@ -203,11 +375,23 @@ impl AdvancedCache {
     }
 
     pub fn get_plugin(&self, key: &str) -> Option<Vec<u8>> {
-        let cache = self.plugin_cache.lock().ok()?;
-        cache.peek(key).cloned()
+        let mut cache = self.plugin_cache.lock().ok()?;
+        if let Some(data) = cache.peek(key) {
+            return Some(data.clone());
+        }
+
+        let data = self.disk()?.get(DISK_PLUGIN_NAMESPACE, key)?;
+        cache.put(key.to_string(), data.clone());
+        Some(data)
     }
 
     pub fn store_plugin(&self, key: String, data: Vec<u8>) -> WinxResult<()> {
+        if let Some(disk) = self.disk() {
+            if let Err(e) = disk.put(DISK_PLUGIN_NAMESPACE, &key, &data) {
+                log::warn!("Failed to write disk cache entry: {}", e);
+            }
+        }
+
         let mut cache = self
             .plugin_cache
             .lock()
@@ -230,6 +414,9 @@ impl AdvancedCache {
         file_cache.clear();
         plugin_cache.clear();
         query_cache.clear();
+        if let Some(disk) = self.disk() {
+            disk.clear()?;
+        }
         Ok(())
     }
 }
@@ -242,6 +429,36 @@ lazy_static::lazy_static! {
         Arc::new(AdvancedCache::new(1000, 100, 500));
 }
 
+/// Enables or disables the disk tier of the global cache from the `cache` config section
+pub fn apply_config(config: &CacheConfig) -> WinxResult<()> {
+    let cache = get_advanced_cache();
+    if !config.disk_enabled {
+        cache.set_disk_tier(None);
+        return Ok(());
+    }
+
+    let Some(dir) = config.dir.clone().or_else(DiskCache::default_dir) else {
+        log::warn!("No cache directory available, the disk cache tier stays disabled");
+        cache.set_disk_tier(None);
+        return Ok(());
+    };
+    match DiskCache::open(&dir, config.max_disk_bytes, config.max_entry_bytes) {
+        Ok(disk) => {
+            log::info!("Disk cache tier enabled at {}", dir.display());
+            cache.set_disk_tier(Some(disk));
+        }
+        Err(e) => {
+            log::warn!(
+                "Disk cache unavailable at {}, using memory only: {}",
+                dir.display(),
+                e
+            );
+            cache.set_disk_tier(None);
+        }
+    }
+    Ok(())
+}
+
 pub fn get_file_cache() -> Arc<Mutex<FileCache>> {
     GLOBAL_FILE_CACHE.clone()
 }
@@ -274,3 +491,71 @@ pub fn invalidate_cached_file(path: &Path) {
         // File invalidated in advanced cache
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_or_compute_uses_disk_tier_across_instances() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = || {
+            AdvancedCache::new(10, 10, 10)
+                .with_disk_tier(DiskCache::open(dir.path(), 1 << 20, 1 << 20).unwrap())
+        };
+
+        let first = open();
+        let value: Vec<String> = first
+            .get_or_compute("tree", || Ok(vec!["src".to_string()]))
+            .unwrap();
+        assert_eq!(value, vec!["src"]);
+
+        // A fresh instance, as after a restart, reads the value back from disk
+        let second = open();
+        let cached: Vec<String> = second
+            .get_or_compute("tree", || panic!("should not recompute"))
+            .unwrap();
+        assert_eq!(cached, value);
+
+        second.clear_all().unwrap();
+        assert!(open().get_query::<Vec<String>>("tree").is_none());
+    }
+
+    #[test]
+    fn test_get_fresh_query_honors_max_age() {
+        let cache = AdvancedCache::new(10, 10, 10);
+        cache.store_query("answer", &42).unwrap();
+        assert_eq!(
+            cache.get_fresh_query::<i32>("answer", Duration::from_secs(60)),
+            Some(42)
+        );
+        assert_eq!(cache.get_fresh_query::<i32>("answer", Duration::ZERO), None);
+    }
+
+    #[test]
+    fn test_disk_hits_expire_when_the_stored_entry_does() {
+        let dir = tempfile::tempdir().unwrap();
+        let disk = DiskCache::open(dir.path(), 1 << 20, 1 << 20).unwrap();
+        let stored = DiskQueryResult {
+            stored_at: unix_now() - 5,
+            data: serde_json::json!(42),
+        };
+        disk.put(
+            DISK_QUERY_NAMESPACE,
+            "answer",
+            &serde_json::to_vec(&stored).unwrap(),
+        )
+        .unwrap();
+        let cache = AdvancedCache::new(10, 10, 10).with_disk_tier(disk);
+
+        assert_eq!(
+            cache.get_fresh_query::<i32>("answer", Duration::from_secs(10)),
+            Some(42)
+        );
+        // The copy in memory is as old as the entry on disk, not fresh
+        assert_eq!(
+            cache.get_fresh_query::<i32>("answer", Duration::from_secs(5)),
+            None
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Filesystem watcher keeping the caches coherent with changes made outside the agent
+//!
+//! Files changed by shell commands or editors are invalidated from the file caches as
+//! soon as inotify reports them, instead of being served until the time-based expiry.
+//! Only directories present in the workspace tree index are watched, so ignored
+//! directories such as `target/` or `node_modules/` don't consume inotify watches.
+
+use notify::event::CreateKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+
+use crate::error::{WinxError, WinxResult};
+use crate::file::repository::RepositoryExplorer;
+use crate::file::tree_index::IGNORE_DIRS;
+
+/// Maximum number of directories watched, to stay well below the inotify limit
+const MAX_WATCHED_DIRS: usize = 8192;
+
+/// Watcher over the directories of one workspace
+struct WorkspaceWatcher {
+    root: PathBuf,
+    /// Incremented for every new workspace so stale event threads can tell they are outdated
+    generation: u64,
+    watcher: RecommendedWatcher,
+    watched_dirs: usize,
+}
+
+lazy_static::lazy_static! {
+    static ref WORKSPACE_WATCHER: Mutex<Option<WorkspaceWatcher>> = Mutex::new(None);
+}
+
+/// Starts watching `root`, replacing the watcher of any previous workspace
+///
+/// When the watcher can't be created (e.g. inotify is unavailable) the caches keep
+/// relying on their time-based expiry.
+pub fn watch_workspace(root: &Path) -> WinxResult<()> {
+    // Event paths are reported under the watched path, canonical like whitelist keys
+    let root = root
+        .canonicalize()
+        .map_err(|e| WinxError::io_error(e, Some(root)))?;
+    let root = root.as_path();
+    let mut current = WORKSPACE_WATCHER
+        .lock()
+        .map_err(|e| WinxError::lock_error(format!("Failed to acquire watcher lock: {}", e)))?;
+
+    if current.as_ref().is_some_and(|w| w.root == root) {
+        return Ok(());
+    }
+    let generation = current.as_ref().map_or(1, |w| w.generation + 1);
+    // Dropping the old watcher closes its channel and ends its event thread
+    *current = None;
+
+    let index = RepositoryExplorer::new()
+        .tree_index(root)
+        .map_err(|e| WinxError::Other(format!("Failed to index workspace: {}", e)))?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)
+        .map_err(|e| WinxError::Other(format!("Failed to create file watcher: {}", e)))?;
+
+    let mut watched_dirs = 0;
+    for rel in index.directories() {
+        if watched_dirs >= MAX_WATCHED_DIRS {
+            log::warn!(
+                "Watching only the first {} directories of {}",
+                MAX_WATCHED_DIRS,
+                root.display()
+            );
+            break;
+        }
+        if watcher
+            .watch(&root.join(rel), RecursiveMode::NonRecursive)
+            .is_ok()
+        {
+            watched_dirs += 1;
+        }
+    }
+
+    let thread_root = root.to_path_buf();
+    thread::Builder::new()
+        .name("winx-fs-watcher".to_string())
+        .spawn(move || {
+            for event in receiver {
+                match event {
+                    Ok(event) => handle_event(&thread_root, generation, event),
+                    Err(e) => log::warn!("File watcher error: {}", e),
+                }
+            }
+        })
+        .map_err(|e| WinxError::Other(format!("Failed to start watcher thread: {}", e)))?;
+
+    log::info!(
+        "Watching {} directories of {} for changes",
+        watched_dirs,
+        root.display()
+    );
+    *current = Some(WorkspaceWatcher {
+        root: root.to_path_buf(),
+        generation,
+        watcher,
+        watched_dirs,
+    });
+    Ok(())
+}
+
+/// Paths touched by `event` that the caches care about
+///
+/// Access events are dropped, as are paths inside ignored directories.
+fn changed_paths<'a>(root: &Path, event: &'a Event) -> Vec<&'a PathBuf> {
+    if !matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) | EventKind::Any
+    ) {
+        return Vec::new();
+    }
+
+    event
+        .paths
+        .iter()
+        .filter(|path| {
+            path.strip_prefix(root).is_ok_and(|rel| {
+                !rel.components().any(|c| {
+                    c.as_os_str()
+                        .to_str()
+                        .is_some_and(|name| IGNORE_DIRS.contains(&name))
+                })
+            })
+        })
+        .collect()
+}
+
+fn handle_event(root: &Path, generation: u64, event: Event) {
+    let paths = changed_paths(root, &event);
+    if paths.is_empty() {
+        return;
+    }
+
+    // Directories created after the initial scan need their own watch
+    if matches!(event.kind, EventKind::Create(CreateKind::Folder)) {
+        if let Ok(mut current) = WORKSPACE_WATCHER.lock() {
+            if let Some(w) = current.as_mut().filter(|w| w.generation == generation) {
+                for path in &paths {
+                    if w.watched_dirs < MAX_WATCHED_DIRS
+                        && w.watcher.watch(path, RecursiveMode::NonRecursive).is_ok()
+                    {
+                        w.watched_dirs += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // The next listing rescans instead of trusting the cached tree
+    super::get_advanced_cache().remove_query(&RepositoryExplorer::tree_cache_key(root));
+    for path in paths {
+        super::invalidate_cached_file(path);
+        crate::tools::file_operations::mark_whitelist_stale(path);
+        crate::tools::initialize::Initialize::record_file_change(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, ModifyKind};
+
+    #[test]
+    fn test_changed_paths_skips_access_and_ignored_dirs() {
+        let root = Path::new("/work");
+        let event = Event::new(EventKind::Modify(ModifyKind::Any))
+            .add_path(root.join("src/main.rs"))
+            .add_path(root.join("target/debug/app"))
+            .add_path(PathBuf::from("/elsewhere/file.rs"));
+        assert_eq!(changed_paths(root, &event), vec![&root.join("src/main.rs")]);
+
+        let access = Event::new(EventKind::Access(AccessKind::Any)).add_path(root.join("a.rs"));
+        assert!(changed_paths(root, &access).is_empty());
+    }
+}
@@ -1,11 +1,13 @@
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
+use crate::bash::limits::ModeLimits;
 use crate::error::WinxResult;
 use crate::plugins::manager::PluginConfig;
 use crate::security::{Role, SecurityManager};
 
 #[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct WinxConfig {
     pub plugins: Vec<PluginConfig>,
     pub rl_system: RLConfig,
@@ -13,9 +15,13 @@ pub struct WinxConfig {
     pub security: SecurityConfig,
     pub transport: TransportConfig,
     pub telemetry: TelemetryConfig,
+    pub limits: ModeLimits,
+    pub audit: AuditConfig,
+    pub cache: CacheConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RLConfig {
     pub enabled: bool,
     pub learning_rate: f64,
@@ -42,12 +48,20 @@ impl Default for RLConfig {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SemanticConfig {
     pub enabled: bool,
     pub catalog: String,
     pub schema: String,
     pub cache_duration_secs: u64,
+    /// SQLite database metric queries run against, relative to the workspace
+    pub database: Option<PathBuf>,
+    /// `sqlite3` shell the queries run in
+    pub sqlite_binary: String,
+    /// Most rows a query returns per page
+    pub max_rows: usize,
+    pub query_timeout_secs: u64,
 }
 
 impl Default for SemanticConfig {
@@ -57,11 +71,16 @@ impl Default for SemanticConfig {
             catalog: "default".to_string(),
             schema: "public".to_string(),
             cache_duration_secs: 300,
+            database: None,
+            sqlite_binary: "sqlite3".to_string(),
+            max_rows: 1000,
+            query_timeout_secs: 30,
         }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SecurityConfig {
     pub verify_signatures: bool,
     pub sandboxed: bool,
@@ -69,6 +88,12 @@ pub struct SecurityConfig {
     pub allowed_hosts: Vec<String>,
     pub roles: Vec<Role>,
     pub default_role: String,
+    /// Redact secrets from command output and file contents returned to the model
+    pub redact_secrets: bool,
+    /// Extra regexes to redact, only the first capture group when there is one
+    pub redaction_patterns: Vec<String>,
+    /// Process sandbox of shell commands in sandboxed mode
+    pub sandbox: SandboxConfig,
 }
 
 impl Default for SecurityConfig {
@@ -83,11 +108,34 @@ impl Default for SecurityConfig {
                 SecurityManager::create_readonly_role(),
             ],
             default_role: "default".to_string(),
+            redact_secrets: true,
+            redaction_patterns: vec![],
+            sandbox: SandboxConfig::default(),
+        }
+    }
+}
+
+/// What sandboxed shell commands may do besides writing to the workspace and allowed paths
+///
+/// Their network is off, apart from a proxy to the `allowed_hosts`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// Let commands write to the temporary directory, which most build tools need.
+    /// `/dev` always stays writable for `/dev/null` and terminals
+    pub writable_temp_dir: bool,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            writable_temp_dir: true,
         }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TransportConfig {
     pub transport_type: TransportType,
     pub sse_port: u16,
@@ -118,6 +166,7 @@ impl Default for TransportConfig {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TelemetryConfig {
     pub enabled: bool,
     pub endpoint: Option<String>,
@@ -125,6 +174,10 @@ pub struct TelemetryConfig {
     pub flush_interval_secs: u64,
     pub include_errors: bool,
     pub include_performance: bool,
+    /// Serve the Prometheus `/metrics` endpoint on localhost
+    pub metrics_endpoint: bool,
+    /// Port of the `/metrics` endpoint
+    pub metrics_port: u16,
 }
 
 impl Default for TelemetryConfig {
@@ -136,6 +189,55 @@ impl Default for TelemetryConfig {
             flush_interval_secs: 60,
             include_errors: true,
             include_performance: true,
+            metrics_endpoint: false,
+            metrics_port: 9464,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// Directory of the session logs, defaults to the local data directory
+    pub dir: Option<PathBuf>,
+    /// Size at which a session log is rotated
+    pub max_file_bytes: u64,
+    /// Rotated files kept per session
+    pub max_files: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: None,
+            max_file_bytes: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Persist query results and plugin bytes across restarts
+    pub disk_enabled: bool,
+    /// Directory of the disk tier, defaults to the user's cache directory
+    pub dir: Option<PathBuf>,
+    /// Size limit of the disk tier, least recently used entries are evicted past it
+    pub max_disk_bytes: u64,
+    /// Larger values are kept in memory only
+    pub max_entry_bytes: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            disk_enabled: false,
+            dir: None,
+            max_disk_bytes: crate::cache::disk::DEFAULT_MAX_DISK_BYTES,
+            max_entry_bytes: crate::cache::disk::DEFAULT_MAX_ENTRY_BYTES,
         }
     }
 }
@@ -182,8 +284,37 @@ impl WinxConfig {
             .join("config.json")
     }
 
+    /// Loads the file named by `WINX_CONFIG`, or the default config file if it exists
+    ///
+    /// Missing sections and fields take their default values.
+    pub fn load_or_default() -> WinxResult<Self> {
+        let path = match std::env::var_os("WINX_CONFIG") {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let path = Self::default_config_path();
+                if !path.exists() {
+                    return Ok(Self::default());
+                }
+                path
+            }
+        };
+        log::info!("Loading configuration from {}", path.display());
+        Self::load(&path)
+    }
+
     pub fn apply(&self) -> WinxResult<()> {
-        // TODO: Apply configuration to various subsystems
+        crate::tools::initialize::Initialize::apply_security_config(&self.security)?;
+        crate::tools::initialize::Initialize::apply_command_limits(&self.limits)?;
+        crate::security::audit::init(&self.audit)?;
+        crate::telemetry::init(&self.telemetry)?;
+        crate::cache::apply_config(&self.cache)?;
+        crate::tools::semantic_layer::SemanticLayer::apply_config(&self.semantic)?;
+        if self.security.sandboxed {
+            log::info!(
+                "Sandboxed mode enabled: tools are confined to the workspace and allowed paths"
+            );
+        }
+        // TODO: Apply the remaining sections to their subsystems
         Ok(())
     }
 }
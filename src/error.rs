@@ -366,7 +366,7 @@ impl<T, E: fmt::Display> ErrorExt<T> for Result<T, E> {
             // Try to detect if it's an IO error from the error message
             if e.to_string().contains("permission denied") || e.to_string().contains("not found") {
                 // Create a new IO error from the message
-                let io_err = std::io::Error::new(std::io::ErrorKind::Other, e.to_string());
+                let io_err = std::io::Error::other(e.to_string());
                 WinxError::io_error(io_err, Some(path))
             } else {
                 WinxError::file_error(e.to_string(), path)
@@ -0,0 +1,106 @@
+use similar::TextDiff;
+use std::path::Path;
+
+/// Default number of unchanged lines shown around each hunk
+pub const DEFAULT_CONTEXT_LINES: usize = 3;
+
+/// Context lines used for the compact diff returned after an edit
+pub const COMPACT_CONTEXT_LINES: usize = 2;
+
+/// Maximum number of diff lines returned by [`compact_unified_diff`]
+pub const MAX_COMPACT_DIFF_LINES: usize = 120;
+
+/// Builds a unified diff between two versions of a file
+///
+/// The headers follow the `a/` and `b/` convention used by git so the output can be
+/// applied with `git apply` or `patch -p1`. When `original` is `None` the file is
+/// treated as newly created and the old side is reported as `/dev/null`.
+///
+/// Returns an empty string when both versions are identical.
+pub fn unified_diff(
+    path: &Path,
+    original: Option<&str>,
+    updated: &str,
+    context_lines: usize,
+) -> String {
+    let old_content = original.unwrap_or("");
+    if original.is_some() && old_content == updated {
+        return String::new();
+    }
+
+    let display_path = path.display().to_string();
+    let display_path = display_path.trim_start_matches('/');
+    let old_header = if original.is_some() {
+        format!("a/{}", display_path)
+    } else {
+        "/dev/null".to_string()
+    };
+    let new_header = format!("b/{}", display_path);
+
+    TextDiff::from_lines(old_content, updated)
+        .unified_diff()
+        .context_radius(context_lines)
+        .header(&old_header, &new_header)
+        .to_string()
+}
+
+/// Builds a short unified diff suitable for tool responses
+///
+/// Uses [`COMPACT_CONTEXT_LINES`] of context and truncates the output after
+/// [`MAX_COMPACT_DIFF_LINES`] lines, noting how many lines were omitted.
+pub fn compact_unified_diff(path: &Path, original: &str, updated: &str) -> String {
+    let diff = unified_diff(path, Some(original), updated, COMPACT_CONTEXT_LINES);
+    let total_lines = diff.lines().count();
+    if total_lines <= MAX_COMPACT_DIFF_LINES {
+        return diff;
+    }
+
+    let mut truncated: String = diff
+        .lines()
+        .take(MAX_COMPACT_DIFF_LINES)
+        .map(|line| format!("{}\n", line))
+        .collect();
+    truncated.push_str(&format!(
+        "... diff truncated ({} more lines)\n",
+        total_lines - MAX_COMPACT_DIFF_LINES
+    ));
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff_reports_changed_lines() {
+        let diff = unified_diff(
+            Path::new("/src/lib.rs"),
+            Some("a\nb\nc\n"),
+            "a\nB\nc\n",
+            DEFAULT_CONTEXT_LINES,
+        );
+
+        assert!(diff.starts_with("--- a/src/lib.rs\n+++ b/src/lib.rs\n"));
+        assert!(diff.contains("-b\n"));
+        assert!(diff.contains("+B\n"));
+    }
+
+    #[test]
+    fn test_unified_diff_for_new_and_unchanged_files() {
+        let diff = unified_diff(Path::new("new.txt"), None, "hello\n", 3);
+        assert!(diff.starts_with("--- /dev/null\n+++ b/new.txt\n"));
+        assert!(diff.contains("+hello\n"));
+
+        assert!(unified_diff(Path::new("same.txt"), Some("x\n"), "x\n", 3).is_empty());
+    }
+
+    #[test]
+    fn test_compact_unified_diff_truncates_long_diffs() {
+        let original: String = (0..300).map(|i| format!("line {}\n", i)).collect();
+        let updated: String = (0..300).map(|i| format!("LINE {}\n", i)).collect();
+
+        let diff = compact_unified_diff(Path::new("big.txt"), &original, &updated);
+        assert_eq!(diff.lines().count(), MAX_COMPACT_DIFF_LINES + 1);
+        assert!(diff.ends_with("more lines)\n"));
+    }
+}
@@ -1,4 +1,12 @@
+pub mod diff;
 pub mod operations;
 pub mod repository;
+pub mod search;
 pub mod search_replace;
 pub mod syntax_checker;
+pub mod tree_index;
+
+/// Whether a path found while walking a directory may be read
+///
+/// Directories that fail it are not descended into.
+pub type PathFilter = std::sync::Arc<dyn Fn(&std::path::Path) -> bool + Send + Sync>;
@@ -1,24 +1,15 @@
 use anyhow::Result;
-use std::collections::{HashMap, HashSet};
-use std::fs;
+use std::collections::HashMap;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 
+use crate::cache::get_advanced_cache;
+use crate::file::tree_index::{TreeEntry, TreeIndex};
+use crate::file::PathFilter;
+
 const MAX_DEPTH: usize = 5;
 const MAX_FILES_PER_DIR: usize = 20;
 const MAX_TOTAL_FILES: usize = 200;
-const IGNORE_DIRS: [&str; 10] = [
-    ".git",
-    "node_modules",
-    "target",
-    "build",
-    "dist",
-    "venv",
-    ".venv",
-    ".env",
-    "__pycache__",
-    ".DS_Store",
-];
 
 /// Represents activity metrics of a file
 #[derive(Debug, Clone, Default)]
@@ -26,12 +17,14 @@ pub struct FileActivity {
     pub read_count: usize,
     pub edit_count: usize,
     pub write_count: usize,
+    /// Changes made outside the agent, reported by the workspace watcher
+    pub external_change_count: usize,
 }
 
 impl FileActivity {
     /// Calculate activity score
     pub fn activity_score(&self) -> usize {
-        self.read_count * 2 + self.edit_count * 3 + self.write_count
+        self.read_count * 2 + self.edit_count * 3 + self.write_count + self.external_change_count
     }
 }
 
@@ -56,6 +49,7 @@ impl RepositoryExplorer {
             "read" => entry.read_count += 1,
             "edit" => entry.edit_count += 1,
             "write" => entry.write_count += 1,
+            "change" => entry.external_change_count += 1,
             _ => (),
         }
     }
@@ -68,123 +62,110 @@ impl RepositoryExplorer {
             .map(|(path, activity)| (path.clone(), activity.activity_score()))
             .collect();
 
-        files.sort_by(|a, b| b.1.cmp(&a.1));
+        files.sort_by_key(|f| std::cmp::Reverse(f.1));
         files.truncate(limit);
 
         files
     }
 
-    /// Check if path should be ignored
-    fn should_ignore(&self, path: &Path) -> bool {
-        if let Some(file_name) = path.file_name().and_then(|f| f.to_str()) {
-            // Check if directory should be ignored
-            if path.is_dir() && IGNORE_DIRS.contains(&file_name) {
-                return true;
-            }
+    /// Cache key of the tree index of `root`, the same for every spelling of the path
+    pub fn tree_cache_key(root: &Path) -> String {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        format!("repository_tree:{}", root.display())
+    }
 
-            // Check if it's a hidden file
-            if file_name.starts_with('.') && file_name != "." && file_name != ".." {
-                return true;
+    /// Get the tree index of `root`, refreshing the cached copy incrementally
+    ///
+    /// The index lives in the advanced cache so repeated `initialize` calls on a large
+    /// workspace only list directories that changed since the last call.
+    pub fn tree_index(&self, root: &Path) -> Result<TreeIndex> {
+        let cache = get_advanced_cache();
+        let key = Self::tree_cache_key(root);
+
+        let index = match cache.get_query::<TreeIndex>(&key) {
+            Some(mut index) => {
+                let relisted = index.refresh();
+                log::debug!(
+                    "Refreshed tree index of {} ({} directories listed again)",
+                    root.display(),
+                    relisted
+                );
+                index
             }
-        }
+            None => {
+                let index = TreeIndex::build(root);
+                log::debug!(
+                    "Built tree index of {} with {} entries",
+                    root.display(),
+                    index.entry_count()
+                );
+                index
+            }
+        };
 
-        false
+        cache.store_query(&key, &index)?;
+        Ok(index)
     }
 
     /// Explore workspace and get directory tree
     pub fn explore_workspace(&self, root_path: &Path) -> Result<String> {
+        let index = self.tree_index(root_path)?;
         let mut output = io::Cursor::new(Vec::new());
 
         writeln!(output, "{}", root_path.display())?;
 
-        self.explore_directory(root_path, &mut output, 0, 0, &mut HashSet::new(), &mut 0)?;
+        self.explore_directory(&index, "", &mut output, 0, 0, &mut 0)?;
+
+        if index.truncated {
+            writeln!(output, "... (workspace too large, index truncated)")?;
+        }
 
         Ok(String::from_utf8(output.into_inner())?)
     }
 
-    /// Recursively explore directory
+    /// Recursively render an indexed directory
     fn explore_directory(
         &self,
-        dir: &Path,
+        index: &TreeIndex,
+        dir: &str,
         output: &mut io::Cursor<Vec<u8>>,
         depth: usize,
         indent: usize,
-        visited: &mut HashSet<PathBuf>,
         total_files: &mut usize,
     ) -> io::Result<()> {
-        // Avoid loops
-        if visited.contains(dir) {
-            return Ok(());
-        }
-        visited.insert(dir.to_path_buf());
-
         // Check depth
         if depth > MAX_DEPTH || *total_files >= MAX_TOTAL_FILES {
             return Ok(());
         }
 
-        // List the directory
-        let entries = match fs::read_dir(dir) {
-            Ok(entries) => entries,
-            Err(e) => {
-                writeln!(
-                    output,
-                    "{}Error reading directory: {}",
-                    " ".repeat(indent),
-                    e
-                )?;
-                return Ok(());
-            }
+        let Some(entries) = index.entries(dir) else {
+            return Ok(());
         };
 
-        // Collect and sort entries (directories first, then files)
-        let mut dirs = Vec::new();
-        let mut files = Vec::new();
-
-        for entry in entries {
-            let entry = match entry {
-                Ok(entry) => entry,
-                Err(_) => continue,
-            };
-
-            let path = entry.path();
-
-            if self.should_ignore(&path) {
-                continue;
-            }
-
-            if path.is_dir() {
-                dirs.push(path);
-            } else {
-                files.push(path);
-            }
-        }
-
-        // Sort alphabetically
-        dirs.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
-        files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+        // Entries are sorted by name; show directories first, then files
+        let (dirs, files): (Vec<&TreeEntry>, Vec<&TreeEntry>) =
+            entries.iter().partition(|e| e.is_dir);
 
         // Process directories
-        for path in dirs {
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                writeln!(output, "{}{}/ (dir)", " ".repeat(indent + 2), name)?;
-                self.explore_directory(&path, output, depth + 1, indent + 4, visited, total_files)?;
-            }
+        for entry in dirs {
+            writeln!(output, "{}{}/ (dir)", " ".repeat(indent + 2), entry.name)?;
+            let child = if dir.is_empty() {
+                entry.name.clone()
+            } else {
+                format!("{}/{}", dir, entry.name)
+            };
+            self.explore_directory(index, &child, output, depth + 1, indent + 4, total_files)?;
         }
 
         // Process files (limiting the number per directory)
         let file_count = files.len();
-        let files_to_display = files.iter().take(MAX_FILES_PER_DIR).collect::<Vec<_>>();
-
-        for path in files_to_display {
+        for entry in files.iter().take(MAX_FILES_PER_DIR) {
             if *total_files >= MAX_TOTAL_FILES {
                 break;
             }
 
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                writeln!(output, "{}{}", " ".repeat(indent + 2), name)?;
-                *total_files += 1;
-            }
+            writeln!(output, "{}{}", " ".repeat(indent + 2), entry.name)?;
+            *total_files += 1;
         }
 
         // Show if there are more files that weren't displayed
@@ -203,68 +184,88 @@ impl RepositoryExplorer {
 
     /// Get the most recently modified files
     pub fn get_recent_files(&self, root_path: &Path, limit: usize) -> Result<Vec<PathBuf>> {
-        let mut files = Vec::new();
-
-        self.collect_files_recursive(root_path, &mut files, &mut HashSet::new())?;
+        let index = self.tree_index(root_path)?;
 
         // Sort by modification time (newest first)
-        files.sort_by(|a, b| {
-            let a_time = fs::metadata(a).and_then(|m| m.modified()).ok();
-            let b_time = fs::metadata(b).and_then(|m| m.modified()).ok();
-
-            match (a_time, b_time) {
-                (Some(a_time), Some(b_time)) => b_time.cmp(&a_time),
-                (Some(_), None) => std::cmp::Ordering::Less,
-                (None, Some(_)) => std::cmp::Ordering::Greater,
-                (None, None) => std::cmp::Ordering::Equal,
-            }
-        });
+        let mut files: Vec<(String, u64)> = index
+            .files()
+            .map(|(rel, entry)| (rel, entry.modified))
+            .collect();
+        files.sort_by_key(|f| std::cmp::Reverse(f.1));
 
         // Limit the number of files
         files.truncate(limit);
 
-        Ok(files)
+        Ok(files
+            .into_iter()
+            .map(|(rel, _)| root_path.join(rel))
+            .collect())
     }
 
-    /// Recursively collect files
-    fn collect_files_recursive(
+    /// List the indexed contents of `dir` down to `depth` levels
+    ///
+    /// `index_root` is the directory whose cached index is used; `dir` must lie inside
+    /// it. When `pattern` is given only entries whose name matches are returned, but
+    /// all directories are still descended. Entries `allowed` rejects are left out and
+    /// not descended. Returns the entries as paths relative to `dir`, and whether the
+    /// `limit` was hit.
+    pub fn list_directory(
         &self,
+        index_root: &Path,
         dir: &Path,
-        files: &mut Vec<PathBuf>,
-        visited: &mut HashSet<PathBuf>,
-    ) -> io::Result<()> {
-        // Avoid loops
-        if visited.contains(dir) {
-            return Ok(());
-        }
-        visited.insert(dir.to_path_buf());
-
-        // List the directory
-        let entries = match fs::read_dir(dir) {
-            Ok(entries) => entries,
-            Err(_) => return Ok(()),
-        };
-
-        for entry in entries {
-            let entry = match entry {
-                Ok(entry) => entry,
-                Err(_) => continue,
-            };
-
-            let path = entry.path();
-
-            if self.should_ignore(&path) {
+        depth: usize,
+        pattern: Option<&glob::Pattern>,
+        limit: usize,
+        allowed: &PathFilter,
+    ) -> Result<(Vec<(String, TreeEntry)>, bool)> {
+        let index = self.tree_index(index_root)?;
+        let start = dir
+            .strip_prefix(index_root)
+            .map(|rel| rel.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_default();
+
+        let mut listed = Vec::new();
+        let mut pending = vec![(start.clone(), 1)];
+        while let Some((current, level)) = pending.pop() {
+            let Some(entries) = index.entries(&current) else {
                 continue;
+            };
+            let entries: Vec<(String, &TreeEntry)> = entries
+                .iter()
+                .map(|entry| {
+                    let rel = if current.is_empty() {
+                        entry.name.clone()
+                    } else {
+                        format!("{}/{}", current, entry.name)
+                    };
+                    (rel, entry)
+                })
+                .filter(|(rel, _)| allowed(&index_root.join(rel)))
+                .collect();
+            // Push subdirectories in reverse so they are visited in name order
+            for (rel, entry) in entries.iter().rev() {
+                if entry.is_dir && level < depth {
+                    pending.push((rel.clone(), level + 1));
+                }
             }
-
-            if path.is_dir() {
-                self.collect_files_recursive(&path, files, visited)?;
-            } else {
-                files.push(path);
+            for (rel, entry) in entries {
+                if pattern.is_some_and(|p| !p.matches(&entry.name)) {
+                    continue;
+                }
+                let display = rel
+                    .strip_prefix(&start)
+                    .map(|r| r.trim_start_matches('/').to_string())
+                    .unwrap_or(rel);
+                listed.push((display, entry.clone()));
+                if listed.len() >= limit {
+                    listed.sort_by(|a, b| a.0.cmp(&b.0));
+                    return Ok((listed, true));
+                }
             }
         }
 
-        Ok(())
+        listed.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok((listed, false))
     }
 }
 
@@ -273,3 +274,29 @@ impl Default for RepositoryExplorer {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_list_directory_leaves_out_rejected_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("secrets/keys")).unwrap();
+        fs::write(root.join("secrets/keys/id_rsa"), "key").unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+
+        let denied = root.join("secrets");
+        let allowed: PathFilter = Arc::new(move |path| !path.starts_with(&denied));
+        let (entries, truncated) = RepositoryExplorer::new()
+            .list_directory(&root, &root, 3, None, 100, &allowed)
+            .unwrap();
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["src", "src/main.rs"]);
+        assert!(!truncated);
+    }
+}
@@ -0,0 +1,310 @@
+use anyhow::{anyhow, Context, Result};
+use ignore::overrides::OverrideBuilder;
+use ignore::types::TypesBuilder;
+use ignore::{WalkBuilder, WalkState};
+use regex::{Regex, RegexBuilder};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use super::PathFilter;
+
+/// Project specific ignore file, using `.gitignore` syntax
+pub const WINX_IGNORE_FILE: &str = ".winxignore";
+
+/// Default number of matches returned per page
+pub const DEFAULT_MAX_RESULTS: usize = 100;
+
+/// Files larger than this are not searched
+const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Matches kept across all pages, the first ones in path and line order
+const MAX_TOTAL_MATCHES: usize = 10_000;
+
+/// Longest line returned in results, longer lines are cut
+const MAX_LINE_LENGTH: usize = 300;
+
+/// Options controlling a workspace search
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    /// Regex, or literal text when `literal` is set
+    pub pattern: String,
+    pub literal: bool,
+    pub case_insensitive: bool,
+    /// File type names understood by ripgrep (e.g. `rust`, `py`, `js`)
+    pub file_types: Vec<String>,
+    /// Globs relative to the search root, prefix with `!` to exclude
+    pub globs: Vec<String>,
+    /// Lines of context shown before and after each match
+    pub context_lines: usize,
+    /// Maximum number of matches in one page
+    pub max_results: usize,
+    /// Index of the first match to return, taken from a previous page's cursor
+    pub offset: usize,
+}
+
+impl SearchOptions {
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self {
+            pattern: pattern.into(),
+            literal: false,
+            case_insensitive: false,
+            file_types: Vec::new(),
+            globs: Vec::new(),
+            context_lines: 0,
+            max_results: DEFAULT_MAX_RESULTS,
+            offset: 0,
+        }
+    }
+}
+
+/// A single matching line with its surrounding context
+#[derive(Debug, Clone)]
+pub struct SearchMatch {
+    pub path: PathBuf,
+    /// 1-based line number
+    pub line_number: usize,
+    pub line: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+/// One page of search results
+#[derive(Debug, Clone, Default)]
+pub struct SearchResults {
+    pub matches: Vec<SearchMatch>,
+    /// Number of matches found, capped at the scan limit
+    pub total_matches: usize,
+    pub files_searched: usize,
+    /// Offset of the next page, if there are more matches
+    pub next_offset: Option<usize>,
+    /// Whether more matches were found than are kept, later ones are dropped
+    pub scan_truncated: bool,
+}
+
+/// Searches every file under `root`, honoring `.gitignore`, `.ignore` and `.winxignore`
+///
+/// Files are walked and scanned in parallel, entries `allowed` rejects are skipped.
+/// Matches are ordered by path and line so that successive pages obtained with
+/// `offset` are stable.
+pub fn search_workspace(
+    root: &Path,
+    options: &SearchOptions,
+    allowed: PathFilter,
+) -> Result<SearchResults> {
+    let regex = build_regex(options)?;
+
+    let mut walker = WalkBuilder::new(root);
+    walker
+        .standard_filters(true)
+        .require_git(false)
+        .add_custom_ignore_filename(WINX_IGNORE_FILE)
+        .filter_entry(move |entry| allowed(entry.path()));
+
+    if !options.file_types.is_empty() {
+        let mut types = TypesBuilder::new();
+        types.add_defaults();
+        for file_type in &options.file_types {
+            types.select(file_type);
+        }
+        walker.types(types.build().context("Invalid file type filter")?);
+    }
+
+    if !options.globs.is_empty() {
+        let mut overrides = OverrideBuilder::new(root);
+        for glob in &options.globs {
+            overrides
+                .add(glob)
+                .with_context(|| format!("Invalid glob: {}", glob))?;
+        }
+        walker.overrides(overrides.build().context("Invalid glob filter")?);
+    }
+
+    let kept: Mutex<Vec<SearchMatch>> = Mutex::new(Vec::new());
+    let files_searched = AtomicUsize::new(0);
+    let total_found = AtomicUsize::new(0);
+
+    walker.build_parallel().run(|| {
+        let regex = &regex;
+        let kept = &kept;
+        let files_searched = &files_searched;
+        let total_found = &total_found;
+        Box::new(move |entry| {
+            let Ok(entry) = entry else {
+                return WalkState::Continue;
+            };
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                return WalkState::Continue;
+            }
+            if entry.metadata().is_ok_and(|m| m.len() > MAX_FILE_SIZE) {
+                return WalkState::Continue;
+            }
+
+            files_searched.fetch_add(1, Ordering::Relaxed);
+            let matches = search_file(entry.path(), regex, options.context_lines);
+            if !matches.is_empty() {
+                total_found.fetch_add(matches.len(), Ordering::Relaxed);
+                // The whole tree is scanned so the kept matches don't depend on
+                // thread scheduling, only the first ones in sort order stay in memory
+                if let Ok(mut kept) = kept.lock() {
+                    kept.extend(matches);
+                    if kept.len() > 2 * MAX_TOTAL_MATCHES {
+                        sort_matches(&mut kept);
+                        kept.truncate(MAX_TOTAL_MATCHES);
+                    }
+                }
+            }
+            WalkState::Continue
+        })
+    });
+
+    let mut matches = kept
+        .into_inner()
+        .map_err(|e| anyhow!("Failed to collect search results: {}", e))?;
+    sort_matches(&mut matches);
+
+    let scan_truncated = total_found.into_inner() > MAX_TOTAL_MATCHES;
+    matches.truncate(MAX_TOTAL_MATCHES);
+    let total_matches = matches.len();
+
+    let end = options.offset.saturating_add(options.max_results);
+    let next_offset = (end < total_matches).then_some(end);
+    let page = matches
+        .into_iter()
+        .skip(options.offset)
+        .take(options.max_results)
+        .collect();
+
+    Ok(SearchResults {
+        matches: page,
+        total_matches,
+        files_searched: files_searched.into_inner(),
+        next_offset,
+        scan_truncated,
+    })
+}
+
+fn sort_matches(matches: &mut [SearchMatch]) {
+    matches.sort_by(|a, b| a.path.cmp(&b.path).then(a.line_number.cmp(&b.line_number)));
+}
+
+fn build_regex(options: &SearchOptions) -> Result<Regex> {
+    let pattern = if options.literal {
+        regex::escape(&options.pattern)
+    } else {
+        options.pattern.clone()
+    };
+
+    RegexBuilder::new(&pattern)
+        .case_insensitive(options.case_insensitive)
+        .build()
+        .map_err(|e| anyhow!("Invalid search pattern: {}", e))
+}
+
+/// Returns the matching lines of a text file, skipping unreadable and binary files
+fn search_file(path: &Path, regex: &Regex, context_lines: usize) -> Vec<SearchMatch> {
+    let Ok(bytes) = fs::read(path) else {
+        return Vec::new();
+    };
+    // Treat files with NUL bytes near the start as binary
+    if bytes.iter().take(8192).any(|b| *b == 0) {
+        return Vec::new();
+    }
+
+    let content = String::from_utf8_lossy(&bytes);
+    if !regex.is_match(&content) {
+        return Vec::new();
+    }
+
+    let lines: Vec<&str> = content.lines().collect();
+    let clip = |line: &str| -> String {
+        if line.len() <= MAX_LINE_LENGTH {
+            return line.to_string();
+        }
+        let mut end = MAX_LINE_LENGTH;
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}...", &line[..end])
+    };
+
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| regex.is_match(line))
+        .map(|(i, line)| SearchMatch {
+            path: path.to_path_buf(),
+            line_number: i + 1,
+            line: clip(line),
+            before: lines[i.saturating_sub(context_lines)..i]
+                .iter()
+                .map(|l| clip(l))
+                .collect(),
+            after: lines[(i + 1).min(lines.len())..(i + 1 + context_lines).min(lines.len())]
+                .iter()
+                .map(|l| clip(l))
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn everything() -> PathFilter {
+        Arc::new(|_| true)
+    }
+
+    #[test]
+    fn test_search_honors_ignore_files_and_pagination() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join(".gitignore"), "ignored.rs\n").unwrap();
+        fs::write(root.join(WINX_IGNORE_FILE), "generated/\n").unwrap();
+        fs::create_dir(root.join("generated")).unwrap();
+        fs::write(root.join("generated/out.rs"), "fn target() {}\n").unwrap();
+        fs::write(root.join("ignored.rs"), "fn target() {}\n").unwrap();
+        fs::write(root.join("a.rs"), "fn target() {}\nfn other() {}\n").unwrap();
+        fs::write(root.join("b.py"), "def target(): pass\n").unwrap();
+
+        let mut options = SearchOptions::new("target");
+        options.literal = true;
+        options.max_results = 1;
+
+        let first = search_workspace(root, &options, everything()).unwrap();
+        assert_eq!(first.total_matches, 2);
+        assert_eq!(first.matches[0].path, root.join("a.rs"));
+        assert_eq!(first.next_offset, Some(1));
+
+        options.offset = 1;
+        let second = search_workspace(root, &options, everything()).unwrap();
+        assert_eq!(second.matches[0].path, root.join("b.py"));
+        assert_eq!(second.next_offset, None);
+
+        let mut typed = SearchOptions::new(r"fn \w+\(");
+        typed.file_types = vec!["rust".to_string()];
+        typed.context_lines = 1;
+        let rust_only = search_workspace(root, &typed, everything()).unwrap();
+        assert_eq!(rust_only.total_matches, 2);
+        assert_eq!(rust_only.matches[0].after, vec!["fn other() {}"]);
+    }
+
+    #[test]
+    fn test_search_skips_paths_the_filter_rejects() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("secrets")).unwrap();
+        fs::write(root.join("secrets/key.txt"), "token = target\n").unwrap();
+        fs::write(root.join("notes.txt"), "target\n").unwrap();
+        fs::write(root.join("private.txt"), "target\n").unwrap();
+
+        let denied = [root.join("secrets"), root.join("private.txt")];
+        let allowed: PathFilter = Arc::new(move |path| !denied.iter().any(|d| path.starts_with(d)));
+        let results = search_workspace(root, &SearchOptions::new("target"), allowed).unwrap();
+        let paths: Vec<&Path> = results.matches.iter().map(|m| m.path.as_path()).collect();
+        assert_eq!(paths, [root.join("notes.txt")]);
+    }
+}
@@ -0,0 +1,348 @@
+//! Hash-based line indexes used to locate search blocks in large files
+//!
+//! Instead of comparing every window of the file against the search block, lines are
+//! hashed once, with all whitespace removed, and candidate windows are anchored on the
+//! rarest line of the search block. A window matching at a stricter tolerance level
+//! also matches at that one, so every level verifies the same few candidates line by
+//! line and no level goes over the whole file again.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hasher;
+use std::sync::OnceLock;
+
+use super::ToleranceLevel;
+
+/// Words appearing on more lines than this are too common to anchor fuzzy candidates
+const MAX_WORD_POSTINGS: usize = 2048;
+
+/// Lines appearing more often than this only anchor closest match candidates when
+/// every line of the search block is that common
+const MAX_LINE_POSTINGS: usize = 2048;
+
+/// Number of best-voted windows scored with edit distance in fuzzy matching
+pub(super) const MAX_FUZZY_CANDIDATES: usize = 32;
+
+/// Hashes a line as it would look after applying the tolerance's normalization
+///
+/// The hash is fed incrementally so no normalized copy of the line is allocated.
+pub(super) fn hash_line(line: &str, tolerance: &ToleranceLevel) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    match tolerance {
+        ToleranceLevel::Exact => hasher.write(line.as_bytes()),
+        ToleranceLevel::IgnoreTrailingWhitespace => hasher.write(line.trim_end().as_bytes()),
+        ToleranceLevel::IgnoreLeadingWhitespace => hasher.write(line.trim_start().as_bytes()),
+        ToleranceLevel::IgnoreAllWhitespace => {
+            for part in line.split_whitespace() {
+                hasher.write(part.as_bytes());
+            }
+        }
+    }
+    hasher.finish()
+}
+
+/// Index of line hashes for a single tolerance level
+pub(super) struct LineIndex {
+    hashes: Vec<u64>,
+    positions: HashMap<u64, Vec<usize>>,
+}
+
+impl LineIndex {
+    /// Builds the index in a single pass over the content
+    pub(super) fn build(content_lines: &[String], tolerance: &ToleranceLevel) -> Self {
+        let hashes: Vec<u64> = content_lines
+            .iter()
+            .map(|line| hash_line(line, tolerance))
+            .collect();
+
+        let mut positions: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, hash) in hashes.iter().enumerate() {
+            positions.entry(*hash).or_default().push(i);
+        }
+
+        Self { hashes, positions }
+    }
+
+    /// Number of distinct normalized lines in the content
+    pub(super) fn unique_lines(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Lines whose hash is `hash`, in ascending order
+    fn positions(&self, hash: u64) -> &[usize] {
+        self.positions.get(&hash).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the start of every window whose line hashes equal `search_hashes`
+    ///
+    /// Windows are anchored on the search line with the fewest occurrences in the
+    /// content, so the work done is proportional to that line's frequency rather
+    /// than to the size of the file. Results are in ascending order.
+    pub(super) fn candidate_starts(&self, search_hashes: &[u64]) -> Vec<usize> {
+        if search_hashes.is_empty() || search_hashes.len() > self.hashes.len() {
+            return Vec::new();
+        }
+
+        // Pick the rarest search line as the anchor; a missing line means no match
+        let mut anchor: Option<(usize, &Vec<usize>)> = None;
+        for (offset, hash) in search_hashes.iter().enumerate() {
+            match self.positions.get(hash) {
+                None => return Vec::new(),
+                Some(found) => {
+                    if anchor.is_none_or(|(_, best)| found.len() < best.len()) {
+                        anchor = Some((offset, found));
+                    }
+                }
+            }
+        }
+
+        let Some((offset, anchor_positions)) = anchor else {
+            return Vec::new();
+        };
+
+        let last_start = self.hashes.len() - search_hashes.len();
+        anchor_positions
+            .iter()
+            .filter_map(|&pos| pos.checked_sub(offset))
+            .filter(|&start| start <= last_start)
+            .filter(|&start| self.hashes[start..start + search_hashes.len()] == *search_hashes)
+            .collect()
+    }
+}
+
+/// Lines of a file's content with the indexes search blocks are looked up in
+///
+/// Built once per content, after which finding a block costs in proportion to how
+/// often its rarest line occurs rather than to the length of the file.
+pub struct ContentIndex {
+    lines: Vec<String>,
+    /// Lines hashed with all whitespace removed, the loosest tolerance level
+    index: LineIndex,
+    /// Lines containing each word, keyed by word hash, built on first fuzzy search
+    words: OnceLock<HashMap<u64, Vec<usize>>>,
+}
+
+impl ContentIndex {
+    pub fn new(content: &str) -> Self {
+        let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+        let index = LineIndex::build(&lines, &ToleranceLevel::IgnoreAllWhitespace);
+        Self {
+            lines,
+            index,
+            words: OnceLock::new(),
+        }
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Number of distinct lines in the content, whitespace aside
+    pub(super) fn unique_lines(&self) -> usize {
+        self.index.unique_lines()
+    }
+
+    /// Start of every window that may match `search_lines` at any tolerance level
+    pub(super) fn candidate_starts(&self, search_lines: &[String]) -> Vec<usize> {
+        let search_hashes: Vec<u64> = search_lines
+            .iter()
+            .map(|line| hash_line(line, &ToleranceLevel::IgnoreAllWhitespace))
+            .collect();
+        self.index.candidate_starts(&search_hashes)
+    }
+
+    /// Counts, for window starts, how many search lines equal the content line at the
+    /// same offset once both are trimmed
+    ///
+    /// Windows are found from the search lines occurring at most
+    /// [`MAX_LINE_POSTINGS`] times, then every search line is compared in them. Only
+    /// windows sharing at least one line with the search block are returned.
+    pub(super) fn trimmed_line_votes(&self, search_lines: &[String]) -> HashMap<usize, usize> {
+        let positions: Vec<&[usize]> = search_lines
+            .iter()
+            .map(|line| {
+                self.index
+                    .positions(hash_line(line, &ToleranceLevel::IgnoreAllWhitespace))
+            })
+            .collect();
+        let mut anchors: Vec<usize> = (0..search_lines.len())
+            .filter(|&offset| positions[offset].len() <= MAX_LINE_POSTINGS)
+            .collect();
+        if anchors.iter().all(|&offset| positions[offset].is_empty()) {
+            anchors = (0..search_lines.len()).collect();
+        }
+
+        let starts: HashSet<usize> = anchors
+            .iter()
+            .flat_map(|&offset| {
+                positions[offset]
+                    .iter()
+                    .filter_map(move |&pos| pos.checked_sub(offset))
+            })
+            .collect();
+        starts
+            .into_iter()
+            .filter_map(|start| {
+                let votes = search_lines
+                    .iter()
+                    .enumerate()
+                    .filter(|(offset, search_line)| {
+                        self.lines
+                            .get(start + offset)
+                            .is_some_and(|line| line.trim() == search_line.trim())
+                    })
+                    .count();
+                (votes > 0).then_some((start, votes))
+            })
+            .collect()
+    }
+
+    /// Ranks window starts by how many words they share with the search block
+    ///
+    /// Returns at most `limit` starts, best voted first. Words present on more than
+    /// [`MAX_WORD_POSTINGS`] lines are ignored as they don't discriminate windows.
+    pub(super) fn word_vote_candidates(&self, search_lines: &[String], limit: usize) -> Vec<usize> {
+        let postings = self.words.get_or_init(|| {
+            let mut postings: HashMap<u64, Vec<usize>> = HashMap::new();
+            for (i, line) in self.lines.iter().enumerate() {
+                for word in line.split_whitespace() {
+                    let lines = postings
+                        .entry(hash_line(word, &ToleranceLevel::Exact))
+                        .or_default();
+                    if lines.last() != Some(&i) {
+                        lines.push(i);
+                    }
+                }
+            }
+            postings
+        });
+
+        let mut votes: HashMap<usize, usize> = HashMap::new();
+        for (offset, search_line) in search_lines.iter().enumerate() {
+            for word in search_line.split_whitespace() {
+                let Some(lines) = postings.get(&hash_line(word, &ToleranceLevel::Exact)) else {
+                    continue;
+                };
+                if lines.len() > MAX_WORD_POSTINGS {
+                    continue;
+                }
+                for start in lines.iter().filter_map(|&pos| pos.checked_sub(offset)) {
+                    *votes.entry(start).or_default() += 1;
+                }
+            }
+        }
+
+        let mut ranked: Vec<(usize, usize)> = votes.into_iter().collect();
+        // Most votes first, earlier windows first on ties
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked
+            .into_iter()
+            .take(limit)
+            .map(|(start, _)| start)
+            .collect()
+    }
+}
+
+/// Levenshtein distance between `a` and `b`, or `None` if it exceeds `max_distance`
+///
+/// Only the diagonal band of width `2 * max_distance + 1` is evaluated and the
+/// computation stops as soon as every cell in a row is over the bound.
+pub(super) fn bounded_levenshtein(a: &[char], b: &[char], max_distance: usize) -> Option<usize> {
+    if a.len().abs_diff(b.len()) > max_distance {
+        return None;
+    }
+    if a.is_empty() || b.is_empty() {
+        return Some(a.len().max(b.len()));
+    }
+
+    let over = max_distance + 1;
+    let mut previous: Vec<usize> = (0..=b.len()).map(|j| j.min(over)).collect();
+    let mut current = vec![over; b.len() + 1];
+
+    for i in 1..=a.len() {
+        let from = i.saturating_sub(max_distance).max(1);
+        let to = (i + max_distance).min(b.len());
+
+        current.fill(over);
+        current[0] = i.min(over);
+        let mut row_min = current[0];
+
+        for j in from..=to {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let value = (previous[j - 1] + cost)
+                .min(previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(over);
+            current[j] = value;
+            row_min = row_min.min(value);
+        }
+
+        if row_min > max_distance {
+            return None;
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    let distance = previous[b.len()];
+    (distance <= max_distance).then_some(distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_candidate_starts_anchor_on_rare_line() {
+        let content = lines("}\nfn a() {\n}\nfn b() {\n}\nfn a() {\n}");
+        let search = lines("fn a() {\n}");
+        let index = LineIndex::build(&content, &ToleranceLevel::Exact);
+        let hashes: Vec<u64> = search
+            .iter()
+            .map(|l| hash_line(l, &ToleranceLevel::Exact))
+            .collect();
+
+        assert_eq!(index.candidate_starts(&hashes), vec![1, 5]);
+        assert!(index
+            .candidate_starts(&[hash_line("missing", &ToleranceLevel::Exact)])
+            .is_empty());
+    }
+
+    #[test]
+    fn test_content_index_candidates_cover_looser_tolerances() {
+        let index = ContentIndex::new("fn a() {\n    x();   \n}\nfn b() {\n  x ( ) ;\n}");
+        assert_eq!(index.candidate_starts(&lines("x();")), vec![1, 4]);
+        assert_eq!(index.candidate_starts(&lines("fn b() {\nx();")), vec![3]);
+
+        // Windows are found from the rare line, the common ones still vote
+        let common = "}\n".repeat(MAX_LINE_POSTINGS + 1);
+        let index = ContentIndex::new(&format!("{}fn a() {{\n}}", common));
+        let votes = index.trimmed_line_votes(&lines("fn a() {\n}"));
+        assert_eq!(votes, HashMap::from([(MAX_LINE_POSTINGS + 1, 2)]));
+    }
+
+    #[test]
+    fn test_hash_line_applies_tolerance() {
+        let level = ToleranceLevel::IgnoreAllWhitespace;
+        assert_eq!(
+            hash_line("let  x = 1;", &level),
+            hash_line("letx=1;", &level)
+        );
+        assert_ne!(
+            hash_line("  foo", &ToleranceLevel::Exact),
+            hash_line("foo", &ToleranceLevel::Exact)
+        );
+    }
+
+    #[test]
+    fn test_bounded_levenshtein() {
+        let a: Vec<char> = "kitten".chars().collect();
+        let b: Vec<char> = "sitting".chars().collect();
+        assert_eq!(bounded_levenshtein(&a, &b, 3), Some(3));
+        assert_eq!(bounded_levenshtein(&a, &b, 2), None);
+        assert_eq!(bounded_levenshtein(&a, &a, 0), Some(0));
+    }
+}
@@ -1,8 +1,14 @@
 use anyhow::{anyhow, Result};
-use regex::Regex;
-use std::collections::{HashMap, HashSet};
+use regex::{Regex, RegexBuilder};
+use std::collections::HashSet;
 use std::ops::Range;
 
+mod index;
+
+use index::{bounded_levenshtein, MAX_FUZZY_CANDIDATES};
+
+pub use index::ContentIndex;
+
 // Regular expressions for detecting various search/replace block formats
 // The module supports multiple syntax formats to accommodate different user preferences
 lazy_static::lazy_static! {
@@ -11,6 +17,12 @@ lazy_static::lazy_static! {
     static ref DIVIDER_MARKER: Regex = Regex::new(r"^======*\s*$").unwrap();
     static ref REPLACE_MARKER: Regex = Regex::new(r"^>>>>>>+\s*REPLACE\s*$").unwrap();
 
+    // Anchor-based operations (insert, delete, replace between anchors)
+    static ref OPERATION_MARKER: Regex =
+        Regex::new(r"^<<<<<<+\s*(SEARCH\s+REGEX|INSERT\s+BEFORE|INSERT\s+AFTER|DELETE|REPLACE\s+BETWEEN)\s*$").unwrap();
+    static ref OPERATION_END_MARKER: Regex =
+        Regex::new(r"^>>>>>>+\s*(REPLACE|INSERT|DELETE)\s*$").unwrap();
+
     // Legacy/alternate format markers (to detect common mistakes)
     static ref ORIGINAL_MARKER: Regex = Regex::new(r"^<<<<<<+\s*ORIGINAL\s*$").unwrap();
     static ref UPDATED_MARKER: Regex = Regex::new(r"^>>>>>>+\s*UPDATED\s*$").unwrap();
@@ -36,6 +48,31 @@ pub struct SearchReplaceBlock {
     /// None means apply to all occurrences
     /// Some(0) means apply to first occurrence, Some(1) to second, etc.
     pub occurrence_index: Option<usize>,
+
+    /// Operation to perform at the matched block. For anchor-based kinds
+    /// `search_lines` holds the (start) anchor.
+    pub kind: BlockKind,
+}
+
+/// Kind of edit performed by a [`SearchReplaceBlock`]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum BlockKind {
+    /// Replace the matched block with `replace_lines`
+    #[default]
+    Replace,
+    /// Insert `replace_lines` right before the anchor
+    InsertBefore,
+    /// Insert `replace_lines` right after the anchor
+    InsertAfter,
+    /// Remove the matched block
+    Delete,
+    /// Replace the lines between the anchor and the first following match of
+    /// `end_anchor_lines`, keeping both anchors
+    ReplaceBetween { end_anchor_lines: Vec<String> },
+    /// Treat `search_lines` as a multiline regex and `replace_lines` as a template
+    /// supporting `$1`/`${name}` captures. Without an occurrence index every match
+    /// is replaced.
+    Regex,
 }
 
 /// Error types specific to search/replace operations
@@ -81,11 +118,20 @@ pub enum UniquenessCheck {
     },
 }
 
+/// Tolerance levels tried for every search block, strictest first
+const TOLERANCE_LEVELS: [ToleranceLevel; 4] = [
+    ToleranceLevel::Exact,
+    ToleranceLevel::IgnoreTrailingWhitespace,
+    ToleranceLevel::IgnoreLeadingWhitespace,
+    ToleranceLevel::IgnoreAllWhitespace,
+];
+
 /// Tolerance levels for pattern matching, with increasing flexibility
 /// These allow for successful matches despite minor formatting differences
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub enum ToleranceLevel {
     /// Exact match
+    #[default]
     Exact,
     /// Ignore trailing whitespace
     IgnoreTrailingWhitespace,
@@ -145,12 +191,6 @@ impl ToleranceLevel {
     }
 }
 
-impl Default for ToleranceLevel {
-    fn default() -> Self {
-        Self::Exact
-    }
-}
-
 /// Tolerance hit for tracking which tolerances were applied
 #[derive(Debug, Clone)]
 pub struct ToleranceHit {
@@ -166,6 +206,36 @@ pub struct MatchResult {
     pub score: f64,
 }
 
+/// Outcome of applying a set of search/replace blocks
+#[derive(Debug, Clone)]
+pub struct EditOutcome {
+    /// Content after all blocks were applied
+    pub content: String,
+    /// Deduplicated tolerance warnings raised while matching
+    pub warnings: Vec<String>,
+    /// Line range (0-based, end exclusive) of each block's replacement in `content`,
+    /// in the same order as the input blocks
+    pub replaced_ranges: Vec<Range<usize>>,
+    /// Number of matches replaced by each regex block, `None` for literal blocks
+    pub match_counts: Vec<Option<usize>>,
+}
+
+/// Shifts previously recorded ranges after `replaced` was swapped for `new_len` lines
+fn shift_replaced_ranges(ranges: &mut [Range<usize>], replaced: &Range<usize>, new_len: usize) {
+    let old_len = replaced.end - replaced.start;
+    for range in ranges.iter_mut() {
+        if range.start >= replaced.end {
+            // Entirely after the replaced block
+            range.start = range.start + new_len - old_len;
+            range.end = range.end + new_len - old_len;
+        } else if range.end > replaced.start {
+            // Overlaps the replaced block, grow it to cover the replacement
+            range.start = range.start.min(replaced.start);
+            range.end = range.end.max(replaced.end) + new_len - old_len;
+        }
+    }
+}
+
 /// Parses search/replace blocks from input text, supporting multiple syntax formats
 ///
 /// This function attempts to parse the input using different formats in order:
@@ -265,7 +335,23 @@ pub fn parse_search_replace_blocks(
              ```\n\n\
              ```\n\
              replace content\n\
-             ```\n"
+             ```\n\n\
+             4. Anchor operations:\n\
+             <<<<<<< INSERT BEFORE (or INSERT AFTER)\n\
+             anchor\n\
+             =======\n\
+             inserted lines\n\
+             >>>>>>> INSERT\n\n\
+             <<<<<<< DELETE\n\
+             lines to delete\n\
+             >>>>>>> DELETE\n\n\
+             <<<<<<< REPLACE BETWEEN\n\
+             start anchor\n\
+             =======\n\
+             end anchor\n\
+             =======\n\
+             new lines between the anchors\n\
+             >>>>>>> REPLACE\n"
                 .to_string(),
         ))
     }
@@ -276,7 +362,7 @@ pub fn parse_search_replace_blocks(
 /// This function checks whether a search pattern matches exactly one location
 /// in the content, and provides helpful diagnostics when uniqueness issues arise.
 pub fn verify_search_block_uniqueness(content: &str, search_block: &str) -> UniquenessCheck {
-    let content_lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+    let index = ContentIndex::new(content);
     let search_lines: Vec<String> = search_block.lines().map(|s| s.to_string()).collect();
 
     // Don't check empty blocks
@@ -286,20 +372,12 @@ pub fn verify_search_block_uniqueness(content: &str, search_block: &str) -> Uniq
         };
     }
 
-    // Try with different tolerance levels
-    let tolerance_levels = [
-        ToleranceLevel::Exact,
-        ToleranceLevel::IgnoreTrailingWhitespace,
-        ToleranceLevel::IgnoreLeadingWhitespace,
-        ToleranceLevel::IgnoreAllWhitespace,
-    ];
-
-    let matches = find_matches(&content_lines, &search_lines, &tolerance_levels);
+    let matches = index.find(&search_lines);
 
     match matches.len() {
         0 => {
             // No exact matches, try to find the closest one for helpful error message
-            let closest = find_closest_match(&content_lines, &search_lines);
+            let closest = index.closest(&search_lines);
             UniquenessCheck::NotFound {
                 closest_match: closest,
             }
@@ -346,10 +424,8 @@ pub fn verify_search_block_uniqueness(content: &str, search_block: &str) -> Uniq
 /// suggestions to the user about why their search pattern failed.
 ///
 /// Returns a tuple with the match result and similarity score if a close match is found
-fn find_closest_match(
-    content_lines: &[String],
-    search_lines: &[String],
-) -> Option<(MatchResult, f64)> {
+fn find_closest_match(index: &ContentIndex, search_lines: &[String]) -> Option<(MatchResult, f64)> {
+    let content_lines = index.lines();
     if content_lines.is_empty() || search_lines.is_empty() {
         return None;
     }
@@ -370,19 +446,20 @@ fn find_closest_match(
 
     // Try different matching strategies
 
-    // Strategy 1: Line-by-line exact match with tolerance for whitespace
+    // Strategy 1: Line-by-line exact match with tolerance for whitespace.
+    // Only windows sharing at least one trimmed line with the search block can score,
+    // so candidates come from a line index instead of scanning every window.
     let min_block_size = 3.min(search_lines_filtered.len());
+    let last_start = content_lines.len().saturating_sub(min_block_size);
 
-    for i in 0..content_lines.len().saturating_sub(min_block_size) {
-        let mut matching_lines = 0;
-        let max_lines = (i + search_lines_filtered.len()).min(content_lines.len());
-
-        for (search_idx, content_idx) in (0..search_lines_filtered.len()).zip(i..max_lines) {
-            if content_lines[content_idx].trim() == search_lines_filtered[search_idx].trim() {
-                matching_lines += 1;
-            }
-        }
+    let mut votes: Vec<(usize, usize)> = index
+        .trimmed_line_votes(&search_lines_filtered)
+        .into_iter()
+        .filter(|(start, _)| *start < last_start)
+        .collect();
+    votes.sort_unstable();
 
+    for (i, matching_lines) in votes {
         let score = matching_lines as f64 / search_lines_filtered.len() as f64;
         if score > best_score && score > 0.4 {
             // At least 40% match
@@ -401,46 +478,46 @@ fn find_closest_match(
         }
     }
 
-    // Strategy 2: Word-based similarity for more fuzzy matching
+    // Strategy 2: Edit distance over whitespace-normalized text for fuzzier matching.
+    // Windows sharing the most words with the search block are scored, and the
+    // distance computation gives up once it can no longer beat the best score.
     if best_score < 0.6 {
-        for start in 0..content_lines
-            .len()
-            .saturating_sub(search_lines_filtered.len())
-            + 1
-        {
-            let mut similarity = 0.0;
-
-            // Calculate similarity for the sequence
-            for (i, search_line) in search_lines_filtered.iter().enumerate() {
-                if start + i < content_lines.len() {
-                    let search_words: Vec<&str> = search_line.split_whitespace().collect();
-                    let content_words: Vec<&str> =
-                        content_lines[start + i].split_whitespace().collect();
-
-                    // Word-based similarity score
-                    let mut word_matches = 0;
-                    for search_word in &search_words {
-                        if content_words.contains(search_word) {
-                            word_matches += 1;
-                        }
-                    }
+        let normalize = |lines: &[String]| -> Vec<char> {
+            lines
+                .iter()
+                .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+                .collect::<Vec<_>>()
+                .join("\n")
+                .chars()
+                .collect()
+        };
+        let search_chars = normalize(&search_lines_filtered);
 
-                    if !search_words.is_empty() {
-                        similarity += word_matches as f64 / search_words.len() as f64;
-                    }
-                }
-            }
+        let mut candidates =
+            index.word_vote_candidates(&search_lines_filtered, MAX_FUZZY_CANDIDATES);
+        candidates.sort_unstable();
 
-            // Average similarity across all lines
-            similarity /= search_lines_filtered.len() as f64;
+        for start in candidates {
+            if start >= content_lines.len() {
+                continue;
+            }
+            let end = (start + search_lines_filtered.len()).min(content_lines.len());
+            let window_chars = normalize(&content_lines[start..end]);
+
+            let max_len = search_chars.len().max(window_chars.len()).max(1);
+            let max_distance = ((1.0 - best_score) * max_len as f64).floor() as usize;
+            let Some(distance) = bounded_levenshtein(&search_chars, &window_chars, max_distance)
+            else {
+                continue;
+            };
 
+            let similarity = 1.0 - distance as f64 / max_len as f64;
             if similarity > best_score {
                 best_score = similarity;
 
                 best_match = Some((
                     MatchResult {
-                        range: start
-                            ..start + search_lines_filtered.len().min(content_lines.len() - start),
+                        range: start..end,
                         tolerances: vec![ToleranceHit {
                             level: ToleranceLevel::IgnoreAllWhitespace,
                             count: (similarity * search_lines_filtered.len() as f64) as usize,
@@ -511,13 +588,21 @@ fn parse_standard_format(lines: &[&str]) -> Vec<SearchReplaceBlock> {
             i += 1;
         }
 
-        if i < lines.len() && SEARCH_MARKER.is_match(lines[i]) {
+        if i < lines.len() && OPERATION_MARKER.is_match(lines[i]) {
+            match parse_operation_block(lines, &mut i, occurrence_index) {
+                Some(block) => blocks.push(block),
+                None => return Vec::new(),
+            }
+        } else if i < lines.len() && SEARCH_MARKER.is_match(lines[i]) {
             let mut search_block = Vec::new();
             i += 1;
 
             // Collect search lines until divider
             while i < lines.len() && !DIVIDER_MARKER.is_match(lines[i]) {
-                if SEARCH_MARKER.is_match(lines[i]) || REPLACE_MARKER.is_match(lines[i]) {
+                if SEARCH_MARKER.is_match(lines[i])
+                    || REPLACE_MARKER.is_match(lines[i])
+                    || OPERATION_MARKER.is_match(lines[i])
+                {
                     // Invalid block, skip
                     log::warn!("Invalid syntax: Nested markers found");
                     return Vec::new();
@@ -557,6 +642,7 @@ fn parse_standard_format(lines: &[&str]) -> Vec<SearchReplaceBlock> {
                 search_lines: search_block,
                 replace_lines: replace_block,
                 occurrence_index,
+                kind: BlockKind::Replace,
             });
         } else if lines[i].trim().starts_with("#") {
             // Skip other comments
@@ -572,6 +658,115 @@ fn parse_standard_format(lines: &[&str]) -> Vec<SearchReplaceBlock> {
     blocks
 }
 
+/// Parses an anchor-based operation block starting at the opening marker at `lines[*i]`
+///
+/// Sections are separated by `=======` and the block is closed by `>>>>>>> REPLACE`,
+/// `>>>>>>> INSERT` or `>>>>>>> DELETE`:
+/// ```text
+/// <<<<<<< INSERT BEFORE      (or INSERT AFTER)
+/// anchor
+/// =======
+/// inserted lines
+/// >>>>>>> INSERT
+///
+/// <<<<<<< DELETE
+/// lines to delete
+/// >>>>>>> DELETE
+///
+/// <<<<<<< REPLACE BETWEEN
+/// start anchor
+/// =======
+/// end anchor
+/// =======
+/// new lines between the anchors
+/// >>>>>>> REPLACE
+///
+/// <<<<<<< SEARCH REGEX
+/// fn (\w+)_old\(
+/// =======
+/// fn ${1}_new(
+/// >>>>>>> REPLACE
+/// ```
+///
+/// On success `*i` points past the closing marker.
+fn parse_operation_block(
+    lines: &[&str],
+    i: &mut usize,
+    occurrence_index: Option<usize>,
+) -> Option<SearchReplaceBlock> {
+    let operation = lines[*i]
+        .trim_start_matches('<')
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    *i += 1;
+
+    // Collect the sections between dividers until the closing marker
+    let mut sections: Vec<Vec<String>> = vec![Vec::new()];
+    loop {
+        if *i >= lines.len() {
+            log::warn!("Invalid syntax: Unclosed {} block", operation);
+            return None;
+        }
+        let line = lines[*i];
+        *i += 1;
+
+        if OPERATION_END_MARKER.is_match(line) {
+            break;
+        }
+        if SEARCH_MARKER.is_match(line) || OPERATION_MARKER.is_match(line) {
+            log::warn!("Invalid syntax: Nested markers found");
+            return None;
+        }
+        if DIVIDER_MARKER.is_match(line) {
+            sections.push(Vec::new());
+        } else if let Some(section) = sections.last_mut() {
+            section.push(line.to_string());
+        }
+    }
+
+    let expected_sections = match operation.as_str() {
+        "DELETE" => 1,
+        "REPLACE BETWEEN" => 3,
+        _ => 2,
+    };
+    if sections.len() != expected_sections || sections[0].is_empty() {
+        log::warn!(
+            "Invalid syntax: {} block expects {} section(s) with a non-empty anchor",
+            operation,
+            expected_sections
+        );
+        return None;
+    }
+
+    let mut sections = sections.into_iter();
+    let search_lines = sections.next().unwrap_or_default();
+    let (kind, replace_lines) = match operation.as_str() {
+        "SEARCH REGEX" => (BlockKind::Regex, sections.next().unwrap_or_default()),
+        "INSERT BEFORE" => (BlockKind::InsertBefore, sections.next().unwrap_or_default()),
+        "INSERT AFTER" => (BlockKind::InsertAfter, sections.next().unwrap_or_default()),
+        "DELETE" => (BlockKind::Delete, Vec::new()),
+        _ => {
+            let end_anchor_lines = sections.next().unwrap_or_default();
+            if end_anchor_lines.is_empty() {
+                log::warn!("Invalid syntax: REPLACE BETWEEN block has an empty end anchor");
+                return None;
+            }
+            (
+                BlockKind::ReplaceBetween { end_anchor_lines },
+                sections.next().unwrap_or_default(),
+            )
+        }
+    };
+
+    Some(SearchReplaceBlock {
+        search_lines,
+        replace_lines,
+        occurrence_index,
+        kind,
+    })
+}
+
 /// Parses blocks using the simplified format with minimal markers
 ///
 /// Simple format example:
@@ -663,6 +858,7 @@ fn parse_simple_format(lines: &[&str]) -> Vec<SearchReplaceBlock> {
                 search_lines: search_content,
                 replace_lines: replace_content,
                 occurrence_index: None, // default to all occurrences
+                kind: BlockKind::Replace,
             });
         }
 
@@ -750,6 +946,7 @@ fn parse_markdown_format(lines: &[&str]) -> Vec<SearchReplaceBlock> {
                 search_lines: search_block,
                 replace_lines: replace_block,
                 occurrence_index: None, // default to all occurrences
+                kind: BlockKind::Replace,
             });
         } else {
             i += 1; // Skip non-marker lines
@@ -768,6 +965,7 @@ pub fn is_search_replace_content(content: &str, percentage_to_change: i32) -> bo
     let lines: Vec<&str> = content.lines().take(10).collect();
     for line in &lines {
         if SEARCH_MARKER.is_match(line)
+            || OPERATION_MARKER.is_match(line)
             || SIMPLE_SEARCH_START.is_match(line)
             || MARKDOWN_CODE_BLOCK.is_match(line)
         {
@@ -803,16 +1001,30 @@ pub fn is_search_replace_content(content: &str, percentage_to_change: i32) -> bo
     false
 }
 
+impl ContentIndex {
+    /// Every match of `search_lines`, best scored first
+    pub fn find(&self, search_lines: &[String]) -> Vec<MatchResult> {
+        find_matches(self, search_lines, &TOLERANCE_LEVELS)
+    }
+
+    /// The most similar section to `search_lines` and its similarity, for blocks
+    /// [`ContentIndex::find`] doesn't match
+    pub fn closest(&self, search_lines: &[String]) -> Option<(MatchResult, f64)> {
+        find_closest_match(self, search_lines)
+    }
+}
+
 /// Finds all possible matches for a search block using multiple tolerance levels
 ///
 /// This function tries to match the search block against the content using
 /// increasingly flexible tolerance levels, collecting all matches found.
 /// This helps handle formatting inconsistencies while still finding the right content.
 fn find_matches(
-    content_lines: &[String],
+    index: &ContentIndex,
     search_lines: &[String],
     tolerance_levels: &[ToleranceLevel],
 ) -> Vec<MatchResult> {
+    let content_lines = index.lines();
     let mut matches = Vec::new();
     // Track positions already found to avoid duplication
     let mut found_positions = HashSet::new();
@@ -836,11 +1048,7 @@ fn find_matches(
     }
 
     // First, check if we're searching for an entire file
-    let search_text = search_lines.join("\n");
-    let content_text = content_lines.join("\n");
-
-    // If the search block is the entire file, treat it as a special case
-    if search_text == content_text {
+    if search_lines == content_lines {
         log::debug!("Exact full file match detected");
         return vec![MatchResult {
             range: 0..content_lines.len(),
@@ -852,6 +1060,26 @@ fn find_matches(
         }];
     }
 
+    if search_lines.is_empty() || search_lines.len() > content_lines.len() {
+        return matches;
+    }
+
+    // Windows matching at any level match with all whitespace removed, so one lookup
+    // gives the candidates of every level
+    log::debug!(
+        "Search with {} unique line patterns in content",
+        index.unique_lines()
+    );
+    let candidates = index.candidate_starts(search_lines);
+    if candidates.is_empty() {
+        log::debug!("No candidate windows found");
+        return matches;
+    }
+    log::debug!(
+        "Found {} candidate windows anchored on the rarest search line",
+        candidates.len()
+    );
+
     // Try each tolerance level
     for tolerance in tolerance_levels {
         let processed_search_lines: Vec<String> = search_lines
@@ -859,91 +1087,41 @@ fn find_matches(
             .map(|line| tolerance.process_line(line))
             .collect();
 
-        let processed_content_lines: Vec<String> = content_lines
-            .iter()
-            .map(|line| tolerance.process_line(line))
-            .collect();
-
-        // Build a map for faster lookup
-        let mut search_positions = HashMap::new();
-        for (i, content_line) in processed_content_lines.iter().enumerate() {
-            search_positions
-                .entry(content_line.clone())
-                .or_insert_with(Vec::new)
-                .push(i);
-        }
-
-        // Log stats for debugging
-        log::debug!(
-            "Search with tolerance {:?}: {} unique line patterns in content",
-            tolerance,
-            search_positions.len()
-        );
+        for &pos in &candidates {
+            // Check if we've already found a match at this position
+            if found_positions.contains(&pos) {
+                log::debug!("Skipping already found position at line {}", pos);
+                continue;
+            }
 
-        // Skip if the first line isn't found at all - quick fail
-        if !search_positions.contains_key(&processed_search_lines[0]) {
-            log::debug!(
-                "First line of search block not found with tolerance {:?}",
-                tolerance
-            );
-            continue;
-        }
+            // Confirm the hash match line by line and count actual differences
+            let mut all_match = true;
+            let mut tolerance_count = 0;
 
-        // Find all potential matches for the first line
-        if let Some(first_line_positions) = search_positions.get(&processed_search_lines[0]) {
-            log::debug!(
-                "Found {} potential starting positions for first line",
-                first_line_positions.len()
-            );
-            for &pos in first_line_positions {
-                // Check if we've already found a match at this position
-                if found_positions.contains(&pos) {
-                    log::debug!("Skipping already found position at line {}", pos);
+            for (i, search_line) in processed_search_lines.iter().enumerate() {
+                let content_line = &content_lines[pos + i];
+                if content_line == &search_lines[i] {
                     continue;
                 }
-
-                if pos + search_lines.len() > content_lines.len() {
-                    continue;
+                if &tolerance.process_line(content_line) != search_line {
+                    all_match = false;
+                    break;
                 }
+                tolerance_count += 1;
+            }
 
-                // Check if all lines match
-                let mut all_match = true;
-                let mut tolerance_count = 0;
-
-                for (i, search_line) in processed_search_lines.iter().enumerate() {
-                    let content_pos = pos + i;
-                    if content_pos >= processed_content_lines.len()
-                        || &processed_content_lines[content_pos] != search_line
-                    {
-                        all_match = false;
-                        break;
-                    }
-
-                    // Count actual differences between original lines
-                    if content_lines[content_pos] != search_lines[i] {
-                        tolerance_count += 1;
-                    }
-                }
-
-                if all_match {
-                    // Add the position to the set of found positions
-                    found_positions.insert(pos);
-
-                    matches.push(MatchResult {
-                        range: pos..(pos + search_lines.len()),
-                        tolerances: vec![ToleranceHit {
-                            level: tolerance.clone(),
-                            count: tolerance_count,
-                        }],
-                        score: tolerance.score_multiplier() * tolerance_count as f64,
-                    });
+            if all_match {
+                // Add the position to the set of found positions
+                found_positions.insert(pos);
 
-                    // If this is the complete file content, we don't need more matches
-                    if search_lines.len() == content_lines.len() && pos == 0 {
-                        log::debug!("Found full file match - no need to continue searching");
-                        return matches;
-                    }
-                }
+                matches.push(MatchResult {
+                    range: pos..(pos + search_lines.len()),
+                    tolerances: vec![ToleranceHit {
+                        level: tolerance.clone(),
+                        count: tolerance_count,
+                    }],
+                    score: tolerance.score_multiplier() * tolerance_count as f64,
+                });
             }
         }
     }
@@ -1065,37 +1243,142 @@ fn remove_empty_boundary_lines(lines: &[String]) -> Vec<String> {
     lines[start..=end].to_vec()
 }
 
+/// Applies a [`BlockKind::Regex`] block to `content`
+///
+/// The pattern is compiled in multiline mode so `^` and `$` match at line boundaries.
+/// Returns the new content, the line range touched in the old content, the number of
+/// lines that range spans afterwards and how many matches were replaced.
+fn apply_regex_block(
+    content: &str,
+    block: &SearchReplaceBlock,
+) -> Result<(String, Range<usize>, usize, usize)> {
+    let pattern = block.search_lines.join("\n");
+    let template = block.replace_lines.join("\n");
+    let regex = RegexBuilder::new(&pattern)
+        .multi_line(true)
+        .build()
+        .map_err(|e| {
+            anyhow!(SearchReplaceError::SyntaxError(format!(
+                "Invalid regex in SEARCH REGEX block: {}",
+                e
+            )))
+        })?;
+
+    let all_captures: Vec<regex::Captures> = regex.captures_iter(content).collect();
+    if all_captures.is_empty() {
+        return Err(anyhow!(SearchReplaceError::MatchError(format!(
+            "Regex matched nothing in file content:\n```\n{}\n```\n\nRetry immediately with same \"percentage_to_change\" fixing the pattern.",
+            pattern
+        ))));
+    }
+
+    let selected: Vec<&regex::Captures> = match block.occurrence_index {
+        Some(index) => {
+            let Some(captures) = all_captures.get(index) else {
+                return Err(anyhow!(SearchReplaceError::MatchError(format!(
+                    "Requested occurrence index {} but only found {} matches. Valid indices: 0 to {}",
+                    index,
+                    all_captures.len(),
+                    all_captures.len() - 1
+                ))));
+            };
+            vec![captures]
+        }
+        None => all_captures.iter().collect(),
+    };
+
+    let mut new_content = String::with_capacity(content.len());
+    let mut last_end = 0;
+    for captures in &selected {
+        let whole = captures.get(0).expect("group 0 always participates");
+        new_content.push_str(&content[last_end..whole.start()]);
+        captures.expand(&template, &mut new_content);
+        last_end = whole.end();
+    }
+    new_content.push_str(&content[last_end..]);
+
+    // Line span covering every replaced match, before and after the edit
+    let line_of = |text: &str, offset: usize| text[..offset].matches('\n').count();
+    let first = selected[0].get(0).map_or(0, |m| m.start());
+    let last = selected[selected.len() - 1].get(0).map_or(0, |m| m.end());
+    let start_line = line_of(content, first);
+    let old_range = start_line..line_of(content, last) + 1;
+    let line_delta = line_of(&new_content, new_content.len()) as isize
+        - line_of(content, content.len()) as isize;
+    let new_len = (old_range.len() as isize + line_delta).max(0) as usize;
+
+    Ok((new_content, old_range, new_len, selected.len()))
+}
+
+/// Finds the first match of `end_anchor_lines` at or after line `from`
+///
+/// Returns the line where the end anchor starts. Used by REPLACE BETWEEN blocks, whose
+/// end anchor is always the closest one following the start anchor.
+fn find_end_anchor(
+    index: &ContentIndex,
+    from: usize,
+    end_anchor_lines: &[String],
+) -> Result<usize> {
+    index
+        .find(end_anchor_lines)
+        .iter()
+        .map(|m| m.range.start)
+        .filter(|&start| start >= from)
+        .min()
+        .ok_or_else(|| {
+            anyhow!(SearchReplaceError::MatchError(format!(
+                "End anchor not found after line {}:\n```\n{}\n```\n\nRetry immediately with same \"percentage_to_change\" fixing the end anchor.",
+                from,
+                end_anchor_lines.join("\n")
+            )))
+        })
+}
+
 /// Apply search/replace blocks to file content
 pub fn apply_search_replace(
     original_content: &str,
     blocks: &[SearchReplaceBlock],
     logger: impl Fn(&str),
 ) -> Result<(String, Vec<String>)> {
+    apply_search_replace_detailed(original_content, blocks, logger)
+        .map(|outcome| (outcome.content, outcome.warnings))
+}
+
+/// Apply search/replace blocks to file content, tracking where each replacement landed
+pub fn apply_search_replace_detailed(
+    original_content: &str,
+    blocks: &[SearchReplaceBlock],
+    logger: impl Fn(&str),
+) -> Result<EditOutcome> {
     let mut content = original_content.to_string();
     let mut warnings = Vec::new();
-
-    // Define tolerance levels to try in order
-    let tolerance_levels = vec![
-        ToleranceLevel::Exact,
-        ToleranceLevel::IgnoreTrailingWhitespace,
-        ToleranceLevel::IgnoreLeadingWhitespace,
-        ToleranceLevel::IgnoreAllWhitespace,
-    ];
+    let mut replaced_ranges: Vec<Range<usize>> = Vec::new();
+    let mut match_counts: Vec<Option<usize>> = Vec::new();
 
     // Process each block
     for block in blocks {
-        let content_lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+        if block.kind == BlockKind::Regex {
+            let (new_content, old_range, new_len, count) = apply_regex_block(&content, block)?;
+            logger(&format!("Regex block replaced {} match(es)", count));
+
+            shift_replaced_ranges(&mut replaced_ranges, &old_range, new_len);
+            replaced_ranges.push(old_range.start..old_range.start + new_len);
+            match_counts.push(Some(count));
+            content = new_content;
+            continue;
+        }
+
+        let index = ContentIndex::new(&content);
+        let content_lines = index.lines();
         let search_text = block.search_lines.join("\n");
         let replace_lines = block.replace_lines.clone();
 
         // Find all possible matches with different tolerance levels
-        let matches = find_matches(&content_lines, &block.search_lines, &tolerance_levels);
+        let matches = index.find(&block.search_lines);
 
         if matches.is_empty() {
             // No match found, try to find a similar block
-            if let Some((match_result, similarity)) =
-                find_closest_match(&content_lines, &block.search_lines)
-            {
+            if let Some((match_result, similarity)) = index.closest(&block.search_lines) {
                 // Extract the context from the match result
                 let range = match_result.range.clone();
                 let context_lines = content_lines
@@ -1205,10 +1488,30 @@ pub fn apply_search_replace(
         let adjusted_replace_lines =
             fix_indentation(&matched_lines, &block.search_lines, &replace_lines);
 
+        // Work out which lines the operation rewrites
+        let anchor = target_match.range.clone();
+        let (edit_range, new_lines) = match &block.kind {
+            // Regex blocks never reach literal matching
+            BlockKind::Replace | BlockKind::Regex => (anchor, adjusted_replace_lines),
+            BlockKind::InsertBefore => (anchor.start..anchor.start, adjusted_replace_lines),
+            BlockKind::InsertAfter => (anchor.end..anchor.end, adjusted_replace_lines),
+            BlockKind::Delete => (anchor, Vec::new()),
+            BlockKind::ReplaceBetween { end_anchor_lines } => {
+                let end_start = find_end_anchor(&index, anchor.end, end_anchor_lines)?;
+                (anchor.end..end_start, adjusted_replace_lines)
+            }
+        };
+
+        // Record where the new lines land, shifting earlier blocks accordingly
+        let new_len = new_lines.len();
+        shift_replaced_ranges(&mut replaced_ranges, &edit_range, new_len);
+        replaced_ranges.push(edit_range.start..edit_range.start + new_len);
+        match_counts.push(None);
+
         // Create new content with replacement
-        let mut new_content_lines = content_lines[..target_match.range.start].to_vec();
-        new_content_lines.extend(adjusted_replace_lines);
-        new_content_lines.extend(content_lines[target_match.range.end..].to_vec());
+        let mut new_content_lines = content_lines[..edit_range.start].to_vec();
+        new_content_lines.extend(new_lines);
+        new_content_lines.extend(content_lines[edit_range.end..].to_vec());
 
         content = new_content_lines.join("\n");
     }
@@ -1216,7 +1519,12 @@ pub fn apply_search_replace(
     // Deduplicate warnings
     let warnings: HashSet<String> = warnings.into_iter().collect();
 
-    Ok((content, warnings.into_iter().collect()))
+    Ok(EditOutcome {
+        content,
+        warnings: warnings.into_iter().collect(),
+        replaced_ranges,
+        match_counts,
+    })
 }
 
 // Fallback function for trying blocks individually if multiple blocks fail
@@ -1225,8 +1533,18 @@ pub fn apply_search_replace_with_fallback(
     blocks: &[SearchReplaceBlock],
     logger: impl Fn(&str) + Clone,
 ) -> Result<(String, Vec<String>)> {
+    apply_search_replace_with_fallback_detailed(original_content, blocks, logger)
+        .map(|outcome| (outcome.content, outcome.warnings))
+}
+
+/// Same as [`apply_search_replace_with_fallback`], also tracking where each replacement landed
+pub fn apply_search_replace_with_fallback_detailed(
+    original_content: &str,
+    blocks: &[SearchReplaceBlock],
+    logger: impl Fn(&str) + Clone,
+) -> Result<EditOutcome> {
     // Try all blocks at once first
-    match apply_search_replace(original_content, blocks, logger.clone()) {
+    match apply_search_replace_detailed(original_content, blocks, logger.clone()) {
         Ok(result) => Ok(result),
         Err(err) => {
             // If we have multiple blocks and it failed, try them one at a time
@@ -1235,12 +1553,32 @@ pub fn apply_search_replace_with_fallback(
 
                 let mut current_content = original_content.to_string();
                 let mut all_warnings = Vec::new();
+                let mut replaced_ranges: Vec<Range<usize>> = Vec::new();
+                let mut match_counts: Vec<Option<usize>> = Vec::new();
 
                 for block in blocks {
-                    match apply_search_replace(&current_content, &[block.clone()], logger.clone()) {
-                        Ok((new_content, warnings)) => {
-                            current_content = new_content;
-                            all_warnings.extend(warnings);
+                    match apply_search_replace_detailed(
+                        &current_content,
+                        std::slice::from_ref(block),
+                        logger.clone(),
+                    ) {
+                        Ok(outcome) => {
+                            // Map the block's new range back to the span it replaced so
+                            // ranges of earlier blocks can be shifted
+                            if let Some(range) = outcome.replaced_ranges.first() {
+                                let old_len = current_content.lines().count();
+                                let new_len = outcome.content.lines().count();
+                                let replaced_end = (range.end + old_len).saturating_sub(new_len);
+                                shift_replaced_ranges(
+                                    &mut replaced_ranges,
+                                    &(range.start..replaced_end),
+                                    range.len(),
+                                );
+                                replaced_ranges.push(range.clone());
+                            }
+                            match_counts.extend(outcome.match_counts);
+                            current_content = outcome.content;
+                            all_warnings.extend(outcome.warnings);
                         }
                         Err(block_err) => {
                             // If individual block fails, propagate that error with improved message
@@ -1253,7 +1591,12 @@ pub fn apply_search_replace_with_fallback(
                     }
                 }
 
-                Ok((current_content, all_warnings))
+                Ok(EditOutcome {
+                    content: current_content,
+                    warnings: all_warnings,
+                    replaced_ranges,
+                    match_counts,
+                })
             } else {
                 // Single block, just return the original error
                 Err(err)
@@ -1289,6 +1632,7 @@ bar
             search_lines: vec!["foo".to_string()],
             replace_lines: vec!["replaced".to_string()],
             occurrence_index: Some(1), // replace second occurrence
+            kind: BlockKind::Replace,
         }];
 
         let (result, _) = apply_search_replace(content, &blocks, |_| {}).unwrap();
@@ -1305,6 +1649,7 @@ bar
             search_lines: vec!["foo".to_string()],
             replace_lines: vec!["replaced".to_string()],
             occurrence_index: Some(5), // index out of bounds
+            kind: BlockKind::Replace,
         }];
 
         let result = apply_search_replace(content, &blocks, |_| {});
@@ -1330,4 +1675,128 @@ bar
         assert_eq!(blocks[0].search_lines, vec!["foo"]);
         assert_eq!(blocks[0].replace_lines, vec!["bar"]);
     }
+
+    #[test]
+    fn test_detailed_reports_replaced_ranges() {
+        let content = "a\nb\nc\nd\ne";
+        let blocks = vec![
+            SearchReplaceBlock {
+                search_lines: vec!["d".to_string()],
+                replace_lines: vec!["d1".to_string(), "d2".to_string()],
+                occurrence_index: None,
+                kind: BlockKind::Replace,
+            },
+            SearchReplaceBlock {
+                search_lines: vec!["b".to_string()],
+                replace_lines: vec!["b1".to_string(), "b2".to_string(), "b3".to_string()],
+                occurrence_index: None,
+                kind: BlockKind::Replace,
+            },
+        ];
+
+        let outcome = apply_search_replace_detailed(content, &blocks, |_| {}).unwrap();
+        assert_eq!(outcome.content, "a\nb1\nb2\nb3\nc\nd1\nd2\ne");
+        // The first block is shifted down by the two lines added above it
+        assert_eq!(outcome.replaced_ranges, vec![5..7, 1..4]);
+    }
+
+    #[test]
+    fn test_parse_anchor_operations() {
+        let input = r#"<<<<<<< INSERT AFTER
+fn a() {}
+=======
+fn b() {}
+>>>>>>> INSERT
+# occurrence: 1
+<<<<<<< DELETE
+// old
+>>>>>>> DELETE
+<<<<<<< REPLACE BETWEEN
+start
+=======
+end
+=======
+middle
+>>>>>>> REPLACE"#;
+
+        let blocks = parse_search_replace_blocks(input).unwrap();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0].kind, BlockKind::InsertAfter);
+        assert_eq!(blocks[0].search_lines, vec!["fn a() {}"]);
+        assert_eq!(blocks[0].replace_lines, vec!["fn b() {}"]);
+        assert_eq!(blocks[1].kind, BlockKind::Delete);
+        assert_eq!(blocks[1].occurrence_index, Some(1));
+        assert_eq!(
+            blocks[2].kind,
+            BlockKind::ReplaceBetween {
+                end_anchor_lines: vec!["end".to_string()]
+            }
+        );
+        assert_eq!(blocks[2].replace_lines, vec!["middle"]);
+    }
+
+    #[test]
+    fn test_apply_anchor_operations() {
+        let content = "start\nfoo\nbar\nend\nbaz";
+        let block = |kind, search: &str, replace: &[&str]| SearchReplaceBlock {
+            search_lines: vec![search.to_string()],
+            replace_lines: replace.iter().map(|s| s.to_string()).collect(),
+            occurrence_index: None,
+            kind,
+        };
+
+        let (result, _) = apply_search_replace(
+            content,
+            &[block(BlockKind::InsertBefore, "bar", &["new"])],
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(result, "start\nfoo\nnew\nbar\nend\nbaz");
+
+        let (result, _) = apply_search_replace(
+            content,
+            &[block(BlockKind::InsertAfter, "bar", &["new"])],
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(result, "start\nfoo\nbar\nnew\nend\nbaz");
+
+        let (result, _) =
+            apply_search_replace(content, &[block(BlockKind::Delete, "foo", &[])], |_| {}).unwrap();
+        assert_eq!(result, "start\nbar\nend\nbaz");
+
+        let between = BlockKind::ReplaceBetween {
+            end_anchor_lines: vec!["end".to_string()],
+        };
+        let outcome =
+            apply_search_replace_detailed(content, &[block(between, "start", &["x"])], |_| {})
+                .unwrap();
+        assert_eq!(outcome.content, "start\nx\nend\nbaz");
+        assert_eq!(outcome.replaced_ranges, vec![1..2]);
+    }
+
+    #[test]
+    fn test_regex_block_with_captures() {
+        let input = r#"<<<<<<< SEARCH REGEX
+^fn (\w+)_old\(
+=======
+fn ${1}_new(
+>>>>>>> REPLACE"#;
+        let blocks = parse_search_replace_blocks(input).unwrap();
+        assert_eq!(blocks[0].kind, BlockKind::Regex);
+
+        let content = "fn a_old() {}\nlet x = 1;\nfn b_old() {}";
+        let outcome = apply_search_replace_detailed(content, &blocks, |_| {}).unwrap();
+        assert_eq!(outcome.content, "fn a_new() {}\nlet x = 1;\nfn b_new() {}");
+        assert_eq!(outcome.match_counts, vec![Some(2)]);
+        assert_eq!(outcome.replaced_ranges, vec![0..3]);
+
+        let mut second_only = blocks.clone();
+        second_only[0].occurrence_index = Some(1);
+        let (result, _) = apply_search_replace(content, &second_only, |_| {}).unwrap();
+        assert_eq!(result, "fn a_old() {}\nlet x = 1;\nfn b_new() {}");
+
+        second_only[0].occurrence_index = Some(2);
+        assert!(apply_search_replace(content, &second_only, |_| {}).is_err());
+    }
 }
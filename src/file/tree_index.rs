@@ -0,0 +1,375 @@
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::file::search::WINX_IGNORE_FILE;
+
+/// Directories skipped even when no ignore file mentions them
+pub const IGNORE_DIRS: [&str; 10] = [
+    ".git",
+    "node_modules",
+    "target",
+    "build",
+    "dist",
+    "venv",
+    ".venv",
+    ".env",
+    "__pycache__",
+    ".DS_Store",
+];
+
+/// Ignore files whose changes require rescanning a whole subtree
+const IGNORE_FILE_NAMES: [&str; 3] = [".gitignore", ".ignore", WINX_IGNORE_FILE];
+
+/// Safety cap on the number of entries held by one index
+const MAX_INDEXED_ENTRIES: usize = 200_000;
+
+/// A file or directory inside an indexed directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch
+    pub modified: u64,
+}
+
+/// Snapshot of one directory, with the stamps used to detect changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedDir {
+    modified: u64,
+    /// Ignore files present in the directory and their modification times
+    ignore_files: Vec<(String, u64)>,
+    entries: Vec<TreeEntry>,
+}
+
+/// Workspace tree index honoring `.gitignore`, `.ignore` and `.winxignore`
+///
+/// Directories are keyed by their path relative to `root` (`""` for the root itself).
+/// The index is refreshed incrementally: only directories whose mtime or ignore files
+/// changed are listed again, the files of the others are only stat'ed again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TreeIndex {
+    pub root: PathBuf,
+    dirs: BTreeMap<String, IndexedDir>,
+    /// Whether the entry cap was hit while scanning
+    pub truncated: bool,
+}
+
+/// Modification time of `metadata` in nanoseconds since the Unix epoch
+pub fn modified_nanos(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+fn join_rel(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+/// Whether `rel` is `dir` itself or lies below it
+fn is_within(rel: &str, dir: &str) -> bool {
+    dir.is_empty()
+        || rel == dir
+        || (rel.starts_with(dir) && rel.as_bytes().get(dir.len()) == Some(&b'/'))
+}
+
+impl TreeIndex {
+    /// Builds a complete index of `root`
+    pub fn build(root: &Path) -> Self {
+        let mut index = Self {
+            root: root.to_path_buf(),
+            dirs: BTreeMap::new(),
+            truncated: false,
+        };
+        let scanned = index.scan("", None);
+        index.dirs = scanned;
+        index
+    }
+
+    /// Number of files and directories in the index
+    pub fn entry_count(&self) -> usize {
+        self.dirs.values().map(|d| d.entries.len()).sum()
+    }
+
+    /// Entries of the directory at `rel`, if it is indexed
+    pub fn entries(&self, rel: &str) -> Option<&[TreeEntry]> {
+        self.dirs.get(rel).map(|d| d.entries.as_slice())
+    }
+
+    /// Iterates over the relative paths of every indexed directory
+    pub fn directories(&self) -> impl Iterator<Item = &str> {
+        self.dirs.keys().map(String::as_str)
+    }
+
+    /// Iterates over every indexed file as (relative path, entry)
+    pub fn files(&self) -> impl Iterator<Item = (String, &TreeEntry)> {
+        self.dirs.iter().flat_map(|(rel, dir)| {
+            dir.entries
+                .iter()
+                .filter(|e| !e.is_dir)
+                .map(move |e| (join_rel(rel, &e.name), e))
+        })
+    }
+
+    /// Brings the index up to date with the filesystem
+    ///
+    /// Returns the number of directories that had to be listed again.
+    pub fn refresh(&mut self) -> usize {
+        let mut changed = Vec::new();
+        let mut removed = Vec::new();
+
+        for (rel, dir) in self.dirs.iter_mut() {
+            let path = self.root.join(rel);
+            match fs::metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => {
+                    let ignore_changed = dir.ignore_files.iter().any(|(name, modified)| {
+                        fs::metadata(path.join(name))
+                            .map(|m| modified_nanos(&m) != *modified)
+                            .unwrap_or(true)
+                    });
+                    if ignore_changed || modified_nanos(&metadata) != dir.modified {
+                        changed.push(rel.clone());
+                    } else {
+                        // Rewriting a file in place leaves its directory's mtime alone
+                        for entry in dir.entries.iter_mut().filter(|e| !e.is_dir) {
+                            if let Ok(metadata) = fs::metadata(path.join(&entry.name)) {
+                                entry.size = metadata.len();
+                                entry.modified = modified_nanos(&metadata);
+                            }
+                        }
+                    }
+                }
+                _ => removed.push(rel.clone()),
+            }
+        }
+
+        for rel in &removed {
+            self.remove_subtree(rel);
+        }
+
+        let mut relisted = 0;
+        let mut rescanned: Vec<String> = Vec::new();
+        for rel in changed {
+            // Already covered by a subtree rescan of an ancestor
+            if !self.dirs.contains_key(&rel) || rescanned.iter().any(|dir| is_within(&rel, dir)) {
+                continue;
+            }
+            relisted += 1;
+
+            let Some(listed) = self.scan(&rel, Some(1)).remove(&rel) else {
+                self.remove_subtree(&rel);
+                continue;
+            };
+            let previous = self.dirs.get(&rel).cloned();
+
+            let ignore_rules_changed = previous
+                .as_ref()
+                .is_none_or(|prev| prev.ignore_files != listed.ignore_files);
+
+            if ignore_rules_changed {
+                // Ignore rules apply to the whole subtree, rebuild it
+                self.remove_subtree(&rel);
+                let subtree = self.scan(&rel, None);
+                self.dirs.extend(subtree);
+                rescanned.push(rel);
+                continue;
+            }
+
+            let old_dirs: Vec<String> = previous
+                .map(|prev| {
+                    prev.entries
+                        .into_iter()
+                        .filter(|e| e.is_dir)
+                        .map(|e| e.name)
+                        .collect()
+                })
+                .unwrap_or_default();
+            let new_dirs: Vec<String> = listed
+                .entries
+                .iter()
+                .filter(|e| e.is_dir)
+                .map(|e| e.name.clone())
+                .collect();
+
+            for name in old_dirs.iter().filter(|n| !new_dirs.contains(n)) {
+                self.remove_subtree(&join_rel(&rel, name));
+            }
+            for name in new_dirs.iter().filter(|n| !old_dirs.contains(n)) {
+                let child = join_rel(&rel, name);
+                let subtree = self.scan(&child, None);
+                self.dirs.extend(subtree);
+            }
+
+            self.dirs.insert(rel, listed);
+        }
+
+        relisted
+    }
+
+    fn remove_subtree(&mut self, rel: &str) {
+        self.dirs.retain(|key, _| !is_within(key, rel));
+    }
+
+    /// Scans the directory at `rel`, down to `max_depth` levels when given
+    ///
+    /// Directories at the depth limit are listed as entries but not indexed.
+    fn scan(&mut self, rel: &str, max_depth: Option<usize>) -> BTreeMap<String, IndexedDir> {
+        let start = self.root.join(rel);
+        let mut dirs: BTreeMap<String, IndexedDir> = BTreeMap::new();
+        let mut budget = MAX_INDEXED_ENTRIES.saturating_sub(self.entry_count());
+
+        let walker = WalkBuilder::new(&start)
+            .standard_filters(true)
+            .require_git(false)
+            .add_custom_ignore_filename(WINX_IGNORE_FILE)
+            .max_depth(max_depth)
+            .filter_entry(|entry| {
+                entry.depth() == 0
+                    || !entry
+                        .file_name()
+                        .to_str()
+                        .is_some_and(|name| IGNORE_DIRS.contains(&name))
+            })
+            .build();
+
+        for entry in walker.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let Ok(relative) = entry.path().strip_prefix(&self.root) else {
+                continue;
+            };
+            let entry_rel = relative.to_string_lossy().replace('\\', "/");
+            let is_dir = metadata.is_dir();
+
+            if is_dir && max_depth.is_none_or(|limit| entry.depth() < limit) {
+                let path = entry.path();
+                let ignore_files = IGNORE_FILE_NAMES
+                    .iter()
+                    .filter_map(|name| {
+                        fs::metadata(path.join(name))
+                            .ok()
+                            .map(|m| (name.to_string(), modified_nanos(&m)))
+                    })
+                    .collect();
+                dirs.insert(
+                    entry_rel.clone(),
+                    IndexedDir {
+                        modified: modified_nanos(&metadata),
+                        ignore_files,
+                        entries: Vec::new(),
+                    },
+                );
+            }
+
+            if entry.depth() == 0 {
+                continue;
+            }
+            if budget == 0 {
+                self.truncated = true;
+                break;
+            }
+            budget -= 1;
+
+            let parent = entry_rel
+                .rsplit_once('/')
+                .map(|(parent, _)| parent.to_string())
+                .unwrap_or_default();
+            if let Some(dir) = dirs.get_mut(&parent) {
+                dir.entries.push(TreeEntry {
+                    name: entry.file_name().to_string_lossy().to_string(),
+                    is_dir,
+                    size: if is_dir { 0 } else { metadata.len() },
+                    modified: modified_nanos(&metadata),
+                });
+            }
+        }
+
+        for dir in dirs.values_mut() {
+            dir.entries.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        dirs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(index: &TreeIndex, rel: &str) -> Vec<String> {
+        index
+            .entries(rel)
+            .unwrap_or_default()
+            .iter()
+            .map(|e| e.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_index_honors_ignore_files_and_refreshes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::create_dir(root.join("node_modules")).unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(root.join("debug.log"), "noise").unwrap();
+
+        let mut index = TreeIndex::build(root);
+        assert_eq!(names(&index, ""), vec!["src"]);
+        assert_eq!(names(&index, "src"), vec!["main.rs"]);
+
+        // New directory with a file, and a removed file
+        fs::create_dir(root.join("src/util")).unwrap();
+        fs::write(root.join("src/util/mod.rs"), "").unwrap();
+        fs::remove_file(root.join("src/main.rs")).unwrap();
+        // Make sure the directory mtime differs on coarse-grained filesystems
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(5);
+        fs::File::open(root.join("src"))
+            .and_then(|f| f.set_modified(later))
+            .ok();
+
+        assert!(index.refresh() >= 1);
+        assert_eq!(names(&index, "src"), vec!["util"]);
+        assert_eq!(names(&index, "src/util"), vec!["mod.rs"]);
+    }
+
+    #[test]
+    fn test_refresh_restats_files_rewritten_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("notes.txt"), "short").unwrap();
+        let mut index = TreeIndex::build(root);
+        let before = modified_nanos(&fs::metadata(root).unwrap());
+
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(root.join("notes.txt"))
+            .unwrap();
+        std::io::Write::write_all(&mut &file, b"a much longer line").unwrap();
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(5);
+        file.set_modified(later).unwrap();
+        drop(file);
+        assert_eq!(modified_nanos(&fs::metadata(root).unwrap()), before);
+
+        assert_eq!(index.refresh(), 0);
+        let entry = &index.entries("").unwrap()[0];
+        assert_eq!(entry.size, 18);
+        assert_eq!(
+            entry.modified,
+            modified_nanos(&fs::metadata(root.join("notes.txt")).unwrap())
+        );
+    }
+}
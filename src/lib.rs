@@ -9,6 +9,7 @@ pub mod reinforcement;
 pub mod security;
 pub mod semantic;
 pub mod server;
+pub mod telemetry;
 pub mod tools;
 
 // Reexport error types and utilities
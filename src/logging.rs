@@ -53,17 +53,12 @@ pub fn init_logging() {
         return;
     }
 
-    // Create log file in user's home directory for debug
-    let home_dir = dirs::home_dir()
-        .unwrap_or_else(|| std::path::PathBuf::from("."))
-        .join("Library/Logs/Claude/winx-debug.log");
-
     // Log startup message
     log::info!(
         "Winx Code Agent logging initialized at level: {:?}",
         log::max_level()
     );
-    log::debug!("Debug log file location: {}", home_dir.to_string_lossy());
+    log::debug!("Logging to stderr, tool calls are recorded in the audit log");
 
     // Log environment information
     if let Ok(workspace) = std::env::var("WINX_WORKSPACE") {
@@ -2,12 +2,24 @@ use anyhow::Result;
 use rmcp::{transport::io, ServiceExt};
 use std::path::PathBuf;
 use std::process::exit;
+use winx_code_agent::config::WinxConfig;
 use winx_code_agent::server::CodeAgent;
+use winx_code_agent::telemetry;
 
+mod audit_cli;
 mod logging;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("audit") {
+        if let Err(e) = audit_cli::run(&args[1..]) {
+            eprintln!("Error: {:#}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
     // Initialize enhanced logging
     logging::init_logging();
 
@@ -19,9 +31,32 @@ async fn main() -> Result<()> {
         workspace.display()
     );
 
+    // Apply the configuration file before any tool runs
+    let config = match WinxConfig::load_or_default() {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Failed to load configuration: {}", e);
+            exit(1);
+        }
+    };
+    if let Err(e) = config.apply() {
+        log::error!("Failed to apply configuration: {}", e);
+        exit(1);
+    }
+    telemetry::spawn_metrics_endpoint(&config.telemetry);
+
     // Pre-configure environment variable for InitializeParams
     std::env::set_var("WINX_WORKSPACE", workspace.to_string_lossy().to_string());
 
+    let result = serve_stdio().await;
+
+    if let Err(e) = telemetry::flush().await {
+        log::warn!("Failed to flush telemetry: {}", e);
+    }
+    result
+}
+
+async fn serve_stdio() -> Result<()> {
     let agent = CodeAgent::new();
     let transport = io::stdio();
 
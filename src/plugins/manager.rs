@@ -30,20 +30,15 @@ pub struct PluginConfig {
     pub plugin_type: PluginType,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum PluginType {
+    #[default]
     Wasm,
     Native,
     Remote,
 }
 
-impl Default for PluginType {
-    fn default() -> Self {
-        Self::Wasm
-    }
-}
-
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct RuntimeConfig {
     pub allowed_hosts: Option<Vec<String>>,
@@ -125,7 +125,7 @@ impl AdaptiveToolSystem {
 
         // Perform experience replay occasionally (every 100 actions)
         // This helps stabilize learning by revisiting past experiences
-        if self.action_history.len() % 100 == 0 {
+        if self.action_history.len().is_multiple_of(100) {
             self.q_learning.experience_replay(10);
         }
 
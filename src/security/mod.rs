@@ -2,8 +2,21 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 
+use crate::config::config::{SandboxConfig, SecurityConfig};
 use crate::error::{WinxError, WinxResult};
 
+pub mod audit;
+pub mod paths;
+pub mod redact;
+
+use redact::Redactor;
+
+/// Canonical form of a configured path, so it compares with resolved tool paths
+fn canonical_rule(path: &Path) -> PathBuf {
+    paths::canonicalize_lenient(&paths::expand_tilde(&path.to_string_lossy()))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Action {
     ReadFile,
@@ -78,9 +91,27 @@ impl RoleBasedAccess {
         Ok(())
     }
 
-    pub fn check_permission(&self, user: &str, action: &Action, path: Option<&Path>) -> bool {
+    /// Makes `role_name` the role of users without an explicit assignment
+    pub fn set_default_role(&mut self, role_name: &str) -> WinxResult<()> {
+        if !self.roles.iter().any(|r| r.name == role_name) {
+            return Err(WinxError::invalid_argument(format!(
+                "Role '{}' does not exist",
+                role_name
+            )));
+        }
+        self.default_role = role_name.to_string();
+        Ok(())
+    }
+
+    /// Role assigned to `user`, or the default role
+    pub fn role_for(&self, user: &str) -> Option<&Role> {
         let role_name = self.user_roles.get(user).unwrap_or(&self.default_role);
-        let role = match self.roles.iter().find(|r| &r.name == role_name) {
+        self.roles.iter().find(|r| &r.name == role_name)
+    }
+
+    /// Paths must be resolved with [`paths::resolve`] beforehand
+    pub fn check_permission(&self, user: &str, action: &Action, path: Option<&Path>) -> bool {
+        let role = match self.role_for(user) {
             Some(role) => role,
             None => return false,
         };
@@ -94,7 +125,7 @@ impl RoleBasedAccess {
         if let Some(path) = path {
             // First check denied paths
             for denied in &role.denied_paths {
-                if path.starts_with(denied) {
+                if path.starts_with(canonical_rule(denied)) {
                     return false;
                 }
             }
@@ -103,7 +134,7 @@ impl RoleBasedAccess {
             if !role.allowed_paths.is_empty() {
                 let mut is_allowed = false;
                 for allowed in &role.allowed_paths {
-                    if path.starts_with(allowed) {
+                    if path.starts_with(canonical_rule(allowed)) {
                         is_allowed = true;
                         break;
                     }
@@ -126,20 +157,28 @@ impl Default for RoleBasedAccess {
 
 pub struct SecurityManager {
     verify_signatures: bool,
+    /// Canonical workspace root, always allowed when sandboxed
+    workspace: Option<PathBuf>,
     allowed_paths: Vec<PathBuf>,
     allowed_hosts: Vec<String>,
     sandboxed: bool,
     role_based_access: RoleBasedAccess,
+    /// `None` when redaction is disabled
+    redactor: Option<Redactor>,
+    sandbox: SandboxConfig,
 }
 
 impl SecurityManager {
     pub fn new() -> Self {
         Self {
             verify_signatures: true,
+            workspace: None,
             allowed_paths: vec![],
             allowed_hosts: vec![],
             sandboxed: false,
             role_based_access: RoleBasedAccess::new(),
+            redactor: Some(Redactor::default()),
+            sandbox: SandboxConfig::default(),
         }
     }
 
@@ -151,13 +190,39 @@ impl SecurityManager {
     ) -> Self {
         Self {
             verify_signatures,
+            workspace: None,
             allowed_paths,
             allowed_hosts,
             sandboxed,
             role_based_access: RoleBasedAccess::new(),
+            redactor: Some(Redactor::default()),
+            sandbox: SandboxConfig::default(),
         }
     }
 
+    /// Builds a manager from the `security` section of the configuration
+    pub fn from_config(config: &SecurityConfig) -> WinxResult<Self> {
+        let mut manager = Self::with_settings(
+            config.verify_signatures,
+            config.allowed_paths.clone(),
+            config.allowed_hosts.clone(),
+            config.sandboxed,
+        );
+        for role in &config.roles {
+            manager.role_based_access.add_role(role.clone());
+        }
+        manager
+            .role_based_access
+            .set_default_role(&config.default_role)?;
+        manager.redactor = if config.redact_secrets {
+            Some(Redactor::new(&config.redaction_patterns)?)
+        } else {
+            None
+        };
+        manager.sandbox = config.sandbox.clone();
+        Ok(manager)
+    }
+
     pub async fn verify_plugin_signature(&self, _path: &str) -> WinxResult<bool> {
         if !self.verify_signatures {
             return Ok(true);
@@ -193,27 +258,81 @@ impl SecurityManager {
 
         // Additional path checks
         if let Some(path) = path {
-            // If sandboxed, only allow access to allowed paths
-            if self.sandboxed && !self.allowed_paths.is_empty() {
-                let mut is_allowed = false;
-                for allowed in &self.allowed_paths {
-                    if path.starts_with(allowed) {
-                        is_allowed = true;
-                        break;
-                    }
-                }
-                if !is_allowed {
-                    return Err(WinxError::permission_error(format!(
-                        "Access to path '{}' is not allowed in sandboxed mode",
-                        path.display()
-                    )));
-                }
+            self.check_path(user, path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Enforces path confinement for a resolved path, independently of the action
+    ///
+    /// Paths under one of the role's `denied_paths` are always rejected. In sandboxed
+    /// mode the path must also lie in the workspace or one of the `allowed_paths`.
+    pub fn check_path(&self, user: &str, path: &Path) -> WinxResult<()> {
+        if let Some(role) = self.role_based_access.role_for(user) {
+            if let Some(denied) = role
+                .denied_paths
+                .iter()
+                .find(|denied| path.starts_with(canonical_rule(denied)))
+            {
+                return Err(WinxError::permission_error(format!(
+                    "Access to '{}' is denied: it is under '{}', denied for role '{}'",
+                    path.display(),
+                    denied.display(),
+                    role.name
+                )));
+            }
+        }
+
+        if self.sandboxed {
+            let confined = self
+                .confinement_roots()
+                .iter()
+                .any(|root| path.starts_with(root));
+            if !confined {
+                return Err(WinxError::permission_error(format!(
+                    "Access to '{}' is not allowed in sandboxed mode: it is outside the workspace and the allowed paths",
+                    path.display()
+                )));
             }
         }
 
         Ok(())
     }
 
+    /// Sets the workspace root that sandboxed paths are confined to
+    pub fn set_workspace(&mut self, workspace: &Path) {
+        self.workspace = Some(canonical_rule(workspace));
+    }
+
+    /// Canonical workspace root, once a workspace has been initialized
+    pub fn workspace(&self) -> Option<&Path> {
+        self.workspace.as_deref()
+    }
+
+    /// Canonical directories sandboxed access is confined to: the workspace and the allowed paths
+    pub fn confinement_roots(&self) -> Vec<PathBuf> {
+        self.workspace
+            .iter()
+            .cloned()
+            .chain(self.allowed_paths.iter().map(|p| canonical_rule(p)))
+            .collect()
+    }
+
+    pub fn allowed_hosts(&self) -> &[String] {
+        &self.allowed_hosts
+    }
+
+    /// Process sandbox settings of shell commands
+    pub fn sandbox_config(&self) -> &SandboxConfig {
+        &self.sandbox
+    }
+
+    /// Redactor for tool output, `None` when redaction is disabled
+    pub fn redactor(&self) -> Option<&Redactor> {
+        self.redactor.as_ref()
+    }
+
     pub fn is_host_allowed(&self, host: &str) -> bool {
         if self.allowed_hosts.is_empty() {
             return true; // No restrictions
@@ -302,3 +421,35 @@ impl Default for SecurityManager {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_path_enforces_denied_paths_and_sandbox() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        let workspace = root.join("ws");
+        std::fs::create_dir_all(workspace.join("secrets")).unwrap();
+
+        let mut manager = SecurityManager::new();
+        manager.set_workspace(&workspace);
+        let mut role = Role::default();
+        role.denied_paths.push(workspace.join("secrets"));
+        manager.get_role_based_access().add_role(role);
+
+        assert!(manager
+            .check_path("default", &workspace.join("a.rs"))
+            .is_ok());
+        assert!(manager
+            .check_path("default", &workspace.join("secrets/key.pem"))
+            .is_err());
+        assert!(manager.check_path("default", &root.join("other")).is_ok());
+
+        manager.set_sandboxed(true);
+        assert!(manager.check_path("default", &root.join("other")).is_err());
+        manager.add_allowed_path(root.join("other"));
+        assert!(manager.check_path("default", &root.join("other/x")).is_ok());
+    }
+}
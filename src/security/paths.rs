@@ -0,0 +1,136 @@
+//! Path resolution shared by every tool that touches the filesystem
+//!
+//! Tool paths are expanded (`~`), made absolute against the workspace and
+//! canonicalized before any permission check, so `..` segments and symlinks can't be
+//! used to step outside the allowed directories. Paths that don't exist yet (files
+//! about to be created) are resolved through their longest existing ancestor.
+
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Maximum number of symlinks followed while resolving one path
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Expands a leading `~` or `~/` to the user's home directory
+pub fn expand_tilde(path: &str) -> PathBuf {
+    if path == "~" || path.starts_with("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return match path.strip_prefix("~/") {
+                Some(rest) => home.join(rest),
+                None => home,
+            };
+        }
+    }
+    PathBuf::from(path)
+}
+
+/// Resolves a path given to a tool against `base`, expanding `~` and canonicalizing it
+pub fn resolve(path: &str, base: &Path) -> io::Result<PathBuf> {
+    let expanded = expand_tilde(path.trim());
+    let absolute = if expanded.is_absolute() {
+        expanded
+    } else {
+        base.join(expanded)
+    };
+    canonicalize_lenient(&absolute)
+}
+
+/// Canonicalizes `path` even when its last components don't exist yet
+///
+/// Existing components are resolved with [`fs::canonicalize`], following symlinks,
+/// dangling ones included. Missing components are appended as-is, with `.` and `..`
+/// applied lexically.
+pub fn canonicalize_lenient(path: &Path) -> io::Result<PathBuf> {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    resolve_components(&absolute, 0)
+}
+
+fn resolve_components(path: &Path, hops: usize) -> io::Result<PathBuf> {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => resolved.push(component),
+            Component::CurDir => {}
+            // `resolved` never contains symlinks, so popping is exact
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => {
+                resolved.push(name);
+                resolved = resolve_existing(resolved, hops)?;
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+/// Canonicalizes `path` if it exists, follows it if it is a dangling symlink
+fn resolve_existing(path: PathBuf, hops: usize) -> io::Result<PathBuf> {
+    if let Ok(canonical) = fs::canonicalize(&path) {
+        return Ok(canonical);
+    }
+
+    // A dangling symlink would let a write create its target anywhere
+    match fs::symlink_metadata(&path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            if hops >= MAX_SYMLINK_HOPS {
+                return Err(io::Error::other(format!(
+                    "Too many levels of symbolic links: {}",
+                    path.display()
+                )));
+            }
+            let target = fs::read_link(&path)?;
+            let parent = path.parent().unwrap_or(Path::new("/"));
+            resolve_components(&parent.join(target), hops + 1)
+        }
+        _ => Ok(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_handles_dot_dot_missing_parents_and_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let workspace = root.join("ws");
+        let outside = root.join("outside");
+        fs::create_dir_all(workspace.join("src")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+
+        // Relative paths and `..` are resolved against the workspace
+        assert_eq!(
+            resolve("src/../src/new/file.rs", &workspace).unwrap(),
+            workspace.join("src/new/file.rs")
+        );
+        assert_eq!(
+            resolve("missing/../../outside/x", &workspace).unwrap(),
+            outside.join("x")
+        );
+
+        #[cfg(unix)]
+        {
+            // Symlinks pointing out of the workspace resolve to their target
+            std::os::unix::fs::symlink(&outside, workspace.join("escape")).unwrap();
+            assert_eq!(
+                resolve("escape/secret", &workspace).unwrap(),
+                outside.join("secret")
+            );
+
+            // So do dangling ones, which a write would otherwise follow
+            std::os::unix::fs::symlink(outside.join("created"), workspace.join("dangling"))
+                .unwrap();
+            assert_eq!(
+                resolve("dangling", &workspace).unwrap(),
+                outside.join("created")
+            );
+        }
+    }
+}
@@ -1,13 +1,19 @@
+use serde::Serialize;
+use std::fmt;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+use super::lineage;
+use super::query::{self, CompiledQuery, MetricQuery};
+use super::rewrite;
+use super::sql::{self, SqlAnalysis, SqlDialect};
+use super::validate;
 use super::SemanticContext;
 use crate::error::WinxResult;
 
+#[derive(Debug)]
 pub struct SemanticAnalyzer {
-    #[allow(dead_code)]
     catalog: String,
-    #[allow(dead_code)]
     schema: String,
     context: Arc<Mutex<SemanticContext>>,
 }
@@ -27,55 +33,72 @@ impl SemanticAnalyzer {
         Ok(())
     }
 
-    pub async fn analyze_sql(&self, sql: &str) -> WinxResult<String> {
-        // TODO: Implement SQL analysis using semantic context
-        // This would involve:
-        // 1. Parsing the SQL
-        // 2. Resolving table/column references to models
-        // 3. Applying semantic relationships
-        // 4. Optimizing the query based on semantic understanding
-
-        Ok(sql.to_string()) // For now, just pass through
+    /// Resolves the tables and columns of `sql` against the models of the context
+    ///
+    /// Unqualified tables are looked up in the analyzer's catalog and schema.
+    pub async fn analyze_sql(&self, sql: &str, dialect: SqlDialect) -> WinxResult<SqlAnalysis> {
+        let context = self.context.lock().await;
+        sql::analyze(sql, dialect, &context, &self.catalog, &self.schema)
     }
 
-    pub async fn build_lineage(&self, _sql: &str) -> WinxResult<LineageGraph> {
-        // TODO: Build lineage graph from SQL
-        // This would trace data flow through the query
-
-        Ok(LineageGraph::new())
+    /// Column-level lineage of `sql`, from the output columns back to model columns
+    pub async fn build_lineage(&self, sql: &str, dialect: SqlDialect) -> WinxResult<LineageGraph> {
+        let context = self.context.lock().await;
+        lineage::build(sql, dialect, &context, &self.catalog, &self.schema)
     }
 
-    pub async fn transform_to_semantic_sql(&self, sql: &str) -> WinxResult<String> {
-        let _context = self.context.lock().await;
-
-        // TODO: Transform regular SQL to semantic SQL using MDL definitions
-        // This would involve replacing physical table/column names with logical ones
-
-        Ok(sql.to_string())
+    /// Rewrites SQL over the physical tables into models, calculated fields and metrics
+    pub async fn transform_to_semantic_sql(
+        &self,
+        sql: &str,
+        dialect: SqlDialect,
+    ) -> WinxResult<String> {
+        let context = self.context.lock().await;
+        rewrite::to_semantic(sql, dialect, &context, &self.catalog, &self.schema)
     }
 
-    pub async fn expand_metrics(&self, sql: &str) -> WinxResult<String> {
-        let _context = self.context.lock().await;
-
-        // TODO: Expand metric references in SQL to their full definitions
-        // For example, "SELECT revenue FROM orders" could expand to the actual
-        // calculation defined in the metric
+    /// Rewrites semantic SQL into SQL over the physical tables
+    pub async fn transform_to_physical_sql(
+        &self,
+        sql: &str,
+        dialect: SqlDialect,
+    ) -> WinxResult<String> {
+        let context = self.context.lock().await;
+        rewrite::to_physical(sql, dialect, &context, &self.catalog, &self.schema)
+    }
 
-        Ok(sql.to_string())
+    /// Expands metric references into their aggregations, joining their base models
+    pub async fn expand_metrics(&self, sql: &str, dialect: SqlDialect) -> WinxResult<String> {
+        let context = self.context.lock().await;
+        rewrite::expand_metrics(sql, dialect, &context, &self.catalog, &self.schema)
     }
 
-    pub async fn validate_query(&self, _sql: &str) -> WinxResult<Vec<ValidationIssue>> {
-        // TODO: Validate SQL against semantic model
-        // Check for:
-        // - References to non-existent models
-        // - Invalid relationships
-        // - Misuse of metrics
+    /// Compiles a metric query into SQL over the physical tables
+    ///
+    /// Queries with errors come back with their issues and no SQL.
+    pub async fn compile_query(
+        &self,
+        query: &MetricQuery,
+        dialect: SqlDialect,
+    ) -> WinxResult<CompiledQuery> {
+        let context = self.context.lock().await;
+        query::compile(query, dialect, &context, &self.catalog, &self.schema)
+    }
 
-        Ok(Vec::new())
+    /// Checks `sql` against the semantic model, issues are ordered by location
+    ///
+    /// SQL that doesn't parse yields a single error rather than failing.
+    pub async fn validate_query(
+        &self,
+        sql: &str,
+        dialect: SqlDialect,
+    ) -> WinxResult<Vec<ValidationIssue>> {
+        let context = self.context.lock().await;
+        validate::validate(sql, dialect, &context, &self.catalog, &self.schema)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct LineageGraph {
     pub nodes: Vec<LineageNode>,
     pub edges: Vec<LineageEdge>,
@@ -89,12 +112,22 @@ impl LineageGraph {
         }
     }
 
+    /// Adds a node unless one with the same id exists
     pub fn add_node(&mut self, node: LineageNode) {
-        self.nodes.push(node);
+        if !self.nodes.iter().any(|n| n.id == node.id) {
+            self.nodes.push(node);
+        }
     }
 
+    /// Adds an edge unless the same edge exists
     pub fn add_edge(&mut self, edge: LineageEdge) {
-        self.edges.push(edge);
+        if !self.edges.contains(&edge) {
+            self.edges.push(edge);
+        }
+    }
+
+    pub fn node(&self, id: &str) -> Option<&LineageNode> {
+        self.nodes.iter().find(|n| n.id == id)
     }
 }
 
@@ -104,14 +137,18 @@ impl Default for LineageGraph {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct LineageNode {
     pub id: String,
     pub name: String,
     pub node_type: LineageNodeType,
+    /// Table, view or transform a column belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum LineageNodeType {
     Table,
     Column,
@@ -120,14 +157,15 @@ pub enum LineageNodeType {
     Transform,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct LineageEdge {
     pub from_id: String,
     pub to_id: String,
     pub edge_type: LineageEdgeType,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum LineageEdgeType {
     DerivedFrom,
     Transform,
@@ -135,21 +173,45 @@ pub enum LineageEdgeType {
     Filter,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ValidationIssue {
     pub severity: ValidationSeverity,
     pub message: String,
     pub location: Option<SourceLocation>,
 }
 
-#[derive(Debug)]
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(
+                f,
+                "{} at {}:{}: {}",
+                self.severity, location.line, location.column, self.message
+            ),
+            None => write!(f, "{}: {}", self.severity, self.message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ValidationSeverity {
     Error,
     Warning,
     Info,
 }
 
-#[derive(Debug)]
+impl fmt::Display for ValidationSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Info => "info",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub struct SourceLocation {
     pub line: usize,
     pub column: usize,
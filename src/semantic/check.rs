@@ -0,0 +1,811 @@
+//! Deep validation of a [`WinxMDL`]
+//!
+//! Besides the names of models, checks that relationships join existing columns
+//! of comparable types, that primary keys are columns, that calculated fields,
+//! metrics and views parse and only use known columns, and that calculated
+//! fields don't depend on themselves. Every problem is collected; given the YAML
+//! the MDL was read from, each is located at its key.
+
+use sqlparser::ast::{Expr, SelectItem, SetExpr, Statement, Visit};
+use sqlparser::parser::Parser;
+use std::collections::HashSet;
+
+use super::analyzer::{SourceLocation, ValidationIssue, ValidationSeverity};
+use super::mdl::{DataType, Model, WinxMDL};
+use super::sql::{self, eq_ignore_case, model_column_name, ExprRefs, IdentifierKind, SqlDialect};
+use super::validate::{type_class, type_name};
+use super::SemanticContext;
+
+/// Step of the path to a value of the MDL document
+#[derive(Debug, Clone, Copy)]
+enum Segment {
+    Key(&'static str),
+    Index(usize),
+}
+
+use Segment::{Index, Key};
+
+/// Checks `mdl`, locating the issues in `source` when it's the YAML it was read from
+///
+/// Located issues are ordered by line.
+pub fn check(mdl: &WinxMDL, source: Option<&str>) -> Vec<ValidationIssue> {
+    let mut checker = Checker {
+        mdl,
+        source,
+        issues: Vec::new(),
+    };
+    checker.models();
+    checker.relationships();
+    checker.metrics();
+    checker.views();
+
+    let mut issues = checker.issues;
+    issues.sort_by_key(|issue| issue.location);
+    issues
+}
+
+struct Checker<'a> {
+    mdl: &'a WinxMDL,
+    source: Option<&'a str>,
+    issues: Vec<ValidationIssue>,
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, path: &[Segment], message: String) {
+        self.issues.push(ValidationIssue {
+            severity: ValidationSeverity::Error,
+            message,
+            location: self.source.and_then(|source| locate(source, path)),
+        });
+    }
+
+    fn model(&self, name: &str) -> Option<&'a Model> {
+        self.mdl.models.iter().find(|m| m.name == name)
+    }
+
+    fn models(&mut self) {
+        let mut names = HashSet::new();
+        for (i, model) in self.mdl.models.iter().enumerate() {
+            if !names.insert(&model.name) {
+                self.error(
+                    &[Key("models"), Index(i), Key("name")],
+                    format!("Duplicate model name: {}", model.name),
+                );
+            }
+
+            let mut fields = HashSet::new();
+            let field_names = model
+                .columns
+                .iter()
+                .map(|c| &c.name)
+                .chain(model.calculated_fields.iter().map(|f| &f.name));
+            for field in field_names {
+                if !fields.insert(field.to_lowercase()) {
+                    self.error(
+                        &[Key("models"), Index(i), Key("name")],
+                        format!("Model '{}' defines '{}' more than once", model.name, field),
+                    );
+                }
+            }
+
+            if let Some(primary_key) = &model.primary_key {
+                if !model
+                    .columns
+                    .iter()
+                    .any(|c| eq_ignore_case(&c.name, primary_key))
+                {
+                    self.error(
+                        &[Key("models"), Index(i), Key("primary_key")],
+                        format!(
+                            "Primary key '{}' of model '{}' is not one of its columns",
+                            primary_key, model.name
+                        ),
+                    );
+                }
+            }
+
+            self.calculated_fields(i, model);
+        }
+    }
+
+    fn calculated_fields(&mut self, i: usize, model: &Model) {
+        // Calculated fields each field refers to, for finding cycles
+        let mut uses: Vec<Vec<usize>> = vec![Vec::new(); model.calculated_fields.len()];
+
+        for (j, field) in model.calculated_fields.iter().enumerate() {
+            let path = [
+                Key("models"),
+                Index(i),
+                Key("calculated_fields"),
+                Index(j),
+                Key("expression"),
+            ];
+            let expr = match parse_expr(&field.expression) {
+                Ok(expr) => expr,
+                Err(e) => {
+                    self.error(
+                        &path,
+                        format!(
+                            "Calculated field '{}.{}' doesn't parse: {}",
+                            model.name, field.name, e
+                        ),
+                    );
+                    continue;
+                }
+            };
+            for (qualifier, column) in references(&expr) {
+                if qualifier.is_none() {
+                    if let Some(k) = model
+                        .calculated_fields
+                        .iter()
+                        .position(|f| eq_ignore_case(&f.name, &column))
+                    {
+                        uses[j].push(k);
+                        continue;
+                    }
+                }
+                if let Some(reason) =
+                    self.unknown_column(Some(model), qualifier.as_deref(), &column)
+                {
+                    self.error(
+                        &path,
+                        format!(
+                            "Calculated field '{}.{}' refers to {}",
+                            model.name, field.name, reason
+                        ),
+                    );
+                }
+            }
+        }
+
+        for cycle in cycles(&uses) {
+            let names: Vec<&str> = cycle
+                .iter()
+                .chain(cycle.first())
+                .map(|&k| model.calculated_fields[k].name.as_str())
+                .collect();
+            self.error(
+                &[
+                    Key("models"),
+                    Index(i),
+                    Key("calculated_fields"),
+                    Index(cycle[0]),
+                    Key("expression"),
+                ],
+                format!(
+                    "Calculated fields of model '{}' depend on themselves: {}",
+                    model.name,
+                    names.join(" -> ")
+                ),
+            );
+        }
+    }
+
+    /// Why `qualifier.column` can't be resolved, unqualified columns belong to `model`
+    fn unknown_column(
+        &self,
+        model: Option<&Model>,
+        qualifier: Option<&str>,
+        column: &str,
+    ) -> Option<String> {
+        let (model, name) = match qualifier {
+            Some(qualifier) => match self
+                .mdl
+                .models
+                .iter()
+                .find(|m| eq_ignore_case(&m.name, qualifier))
+            {
+                Some(model) => (model, format!("{}.{}", qualifier, column)),
+                None => return Some(format!("the unknown model '{}'", qualifier)),
+            },
+            None => match model {
+                Some(model) => (model, column.to_string()),
+                // Without a model any model's column will do
+                None => {
+                    let known = self
+                        .mdl
+                        .models
+                        .iter()
+                        .any(|m| model_column_name(m, column).is_some());
+                    return (!known).then(|| format!("'{}', which no model has", column));
+                }
+            },
+        };
+        model_column_name(model, column)
+            .is_none()
+            .then(|| format!("'{}', which model '{}' doesn't have", name, model.name))
+    }
+
+    /// Type of a column or calculated field, `None` when it doesn't exist
+    fn column_type<'m>(model: &'m Model, column: &str) -> Option<&'m DataType> {
+        model
+            .columns
+            .iter()
+            .find(|c| eq_ignore_case(&c.name, column))
+            .map(|c| &c.data_type)
+            .or_else(|| {
+                model
+                    .calculated_fields
+                    .iter()
+                    .find(|f| eq_ignore_case(&f.name, column))
+                    .map(|f| &f.data_type)
+            })
+    }
+
+    fn relationships(&mut self) {
+        for (i, relationship) in self.mdl.relationships.iter().enumerate() {
+            let mut types = Vec::new();
+            for (key, model_key, model, column) in [
+                (
+                    "from_column",
+                    "from_model",
+                    &relationship.from_model,
+                    &relationship.from_column,
+                ),
+                (
+                    "to_column",
+                    "to_model",
+                    &relationship.to_model,
+                    &relationship.to_column,
+                ),
+            ] {
+                let Some(found) = self.model(model) else {
+                    self.error(
+                        &[Key("relationships"), Index(i), Key(model_key)],
+                        format!(
+                            "Relationship '{}' references non-existent model: {}",
+                            relationship.name, model
+                        ),
+                    );
+                    continue;
+                };
+                match Self::column_type(found, column) {
+                    Some(data_type) => types.push((model, column, data_type)),
+                    None => self.error(
+                        &[Key("relationships"), Index(i), Key(key)],
+                        format!(
+                            "Relationship '{}' references non-existent column: {}.{}",
+                            relationship.name, model, column
+                        ),
+                    ),
+                }
+            }
+
+            if let [(from_model, from_column, from_type), (to_model, to_column, to_type)] =
+                types[..]
+            {
+                let classes = (type_class(from_type), type_class(to_type));
+                if matches!(classes, (Some(from), Some(to)) if from != to) {
+                    self.error(
+                        &[Key("relationships"), Index(i), Key("to_column")],
+                        format!(
+                            "Relationship '{}' joins {}.{} ({}) with {}.{} ({})",
+                            relationship.name,
+                            from_model,
+                            from_column,
+                            type_name(from_type),
+                            to_model,
+                            to_column,
+                            type_name(to_type)
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn metrics(&mut self) {
+        for (i, metric) in self.mdl.metrics.iter().enumerate() {
+            let base = match &metric.base_model {
+                Some(name) => match self.model(name) {
+                    Some(model) => Some(model),
+                    None => {
+                        self.error(
+                            &[Key("metrics"), Index(i), Key("base_model")],
+                            format!(
+                                "Metric '{}' references non-existent model: {}",
+                                metric.name, name
+                            ),
+                        );
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            let expressions = std::iter::once((
+                vec![Key("metrics"), Index(i), Key("expression")],
+                &metric.expression,
+            ))
+            .chain(metric.filters.iter().enumerate().map(|(j, filter)| {
+                (
+                    vec![
+                        Key("metrics"),
+                        Index(i),
+                        Key("filters"),
+                        Index(j),
+                        Key("expression"),
+                    ],
+                    &filter.expression,
+                )
+            }));
+            for (path, text) in expressions {
+                // `COUNT(*)` metrics aggregate `*`
+                if text.trim() == "*" && metric.aggregation.is_some() {
+                    continue;
+                }
+                let expr = match parse_expr(text) {
+                    Ok(expr) => expr,
+                    Err(e) => {
+                        self.error(
+                            &path,
+                            format!("Metric '{}' doesn't parse: {}", metric.name, e),
+                        );
+                        continue;
+                    }
+                };
+                for (qualifier, column) in references(&expr) {
+                    // Derived metrics combine other metrics
+                    let is_metric = qualifier.is_none()
+                        && self
+                            .mdl
+                            .metrics
+                            .iter()
+                            .any(|m| eq_ignore_case(&m.name, &column));
+                    let is_field = base.is_some_and(|m| model_column_name(m, &column).is_some());
+                    if is_metric && !is_field {
+                        continue;
+                    }
+                    if let Some(reason) = self.unknown_column(base, qualifier.as_deref(), &column) {
+                        self.error(
+                            &path,
+                            format!("Metric '{}' refers to {}", metric.name, reason),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn views(&mut self) {
+        let Ok(context) = SemanticContext::from_mdl(self.mdl) else {
+            return;
+        };
+        for (i, view) in self.mdl.views.iter().enumerate() {
+            let path = [Key("views"), Index(i), Key("base_query")];
+            let statements = match sql::parse(&view.base_query, SqlDialect::Ansi) {
+                Ok(statements) => statements,
+                Err(e) => {
+                    self.error(&path, format!("View '{}' doesn't parse: {}", view.name, e));
+                    continue;
+                }
+            };
+            let Ok(analysis) = sql::analyze(&view.base_query, SqlDialect::Ansi, &context, "", "")
+            else {
+                continue;
+            };
+            for unknown in &analysis.unknown {
+                // Views may be built on other views
+                let is_view = unknown.kind == IdentifierKind::Table
+                    && self.mdl.views.iter().any(|v| {
+                        unknown
+                            .name
+                            .rsplit('.')
+                            .next()
+                            .is_some_and(|name| eq_ignore_case(name, &v.name))
+                    });
+                if !is_view {
+                    let kind = match unknown.kind {
+                        IdentifierKind::Table => "table",
+                        IdentifierKind::Column => "column",
+                    };
+                    self.error(
+                        &path,
+                        format!(
+                            "View '{}' refers to the unknown {} '{}': {}",
+                            view.name, kind, unknown.name, unknown.reason
+                        ),
+                    );
+                }
+            }
+
+            // View columns are computed from what the query selects
+            let Some(mut known) = output_columns(&statements) else {
+                continue;
+            };
+            for reference in &analysis.models {
+                if let Some(model) = self.model(&reference.model) {
+                    known.extend(model.columns.iter().map(|c| c.name.to_lowercase()));
+                    known.extend(
+                        model
+                            .calculated_fields
+                            .iter()
+                            .map(|f| f.name.to_lowercase()),
+                    );
+                }
+            }
+            for (j, column) in view.columns.iter().enumerate() {
+                let path = [
+                    Key("views"),
+                    Index(i),
+                    Key("columns"),
+                    Index(j),
+                    Key("expression"),
+                ];
+                let expr = match parse_expr(&column.expression) {
+                    Ok(expr) => expr,
+                    Err(e) => {
+                        self.error(
+                            &path,
+                            format!(
+                                "Column '{}' of view '{}' doesn't parse: {}",
+                                column.name, view.name, e
+                            ),
+                        );
+                        continue;
+                    }
+                };
+                for (_, name) in references(&expr) {
+                    if !known.contains(&name.to_lowercase()) {
+                        self.error(
+                            &path,
+                            format!(
+                                "Column '{}' of view '{}' refers to '{}', which its query doesn't provide",
+                                column.name, view.name, name
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn parse_expr(text: &str) -> Result<Expr, String> {
+    let dialect = SqlDialect::Ansi.parser_dialect();
+    Parser::new(dialect.as_ref())
+        .try_with_sql(text)
+        .and_then(|mut parser| parser.parse_expr())
+        .map_err(|e| e.to_string())
+}
+
+/// `(qualifier, column)` of the column references of an expression
+fn references(expr: &Expr) -> Vec<(Option<String>, String)> {
+    let mut refs = ExprRefs::default();
+    let _ = expr.visit(&mut refs);
+    refs.columns
+        .iter()
+        .filter_map(|parts| match parts.as_slice() {
+            [column] => Some((None, column.value.clone())),
+            [.., qualifier, column] => Some((Some(qualifier.value.clone()), column.value.clone())),
+            [] => None,
+        })
+        .collect()
+}
+
+/// Lowercase names of the columns a single SELECT outputs, `None` for other queries
+///
+/// A wildcard outputs nothing by name, the columns of its models are added by the caller.
+fn output_columns(statements: &[Statement]) -> Option<HashSet<String>> {
+    let [Statement::Query(query)] = statements else {
+        return None;
+    };
+    let SetExpr::Select(select) = query.body.as_ref() else {
+        return None;
+    };
+    let mut names = HashSet::new();
+    for item in &select.projection {
+        match item {
+            SelectItem::ExprWithAlias { alias, .. } => {
+                names.insert(alias.value.to_lowercase());
+            }
+            SelectItem::UnnamedExpr(expr) => {
+                if let Some([.., column]) = sql::column_parts(expr) {
+                    names.insert(column.value.to_lowercase());
+                }
+            }
+            SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..) => {}
+        }
+    }
+    Some(names)
+}
+
+/// Cycles of a dependency graph, each reported once starting from its lowest node
+fn cycles(uses: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut cycles = Vec::new();
+    let mut reported = HashSet::new();
+    for start in 0..uses.len() {
+        // Depth-first search for paths leading back to `start` through higher nodes
+        let mut stack = vec![(start, 0)];
+        let mut path = vec![start];
+        let mut visited = HashSet::new();
+        while let Some((node, next)) = stack.last_mut() {
+            let node = *node;
+            let Some(&target) = uses[node].get(*next) else {
+                stack.pop();
+                path.pop();
+                continue;
+            };
+            *next += 1;
+            if target == start {
+                let mut members = path.clone();
+                members.sort_unstable();
+                if reported.insert(members) {
+                    cycles.push(path.clone());
+                }
+            } else if target > start && visited.insert(target) {
+                stack.push((target, 0));
+                path.push(target);
+            }
+        }
+    }
+    cycles
+}
+
+/// Line and column of the value at `path` in block-style YAML
+///
+/// Stops at the deepest step it can follow, e.g. the item of a flow sequence.
+fn locate(source: &str, path: &[Segment]) -> Option<SourceLocation> {
+    let lines: Vec<YamlLine> = source
+        .lines()
+        .enumerate()
+        .filter_map(|(number, text)| YamlLine::new(number + 1, text))
+        .collect();
+
+    let mut range = 0..lines.len();
+    let mut found = None;
+    for segment in path {
+        let block = &lines[range.clone()];
+        let (position, end, column) = match segment {
+            Key(key) => {
+                let Some(indent) = block.iter().map(|l| l.indent).min() else {
+                    break;
+                };
+                let Some(position) = block
+                    .iter()
+                    .position(|l| l.indent == indent && l.has_key(key))
+                else {
+                    break;
+                };
+                // The value is what's indented further, or a compact sequence
+                let end = block[position + 1..]
+                    .iter()
+                    .position(|l| l.indent <= indent)
+                    .map_or(block.len(), |p| position + 1 + p);
+                (position, end, block[position].indent)
+            }
+            Index(index) => {
+                let Some(first) = block.first().filter(|l| l.item) else {
+                    break;
+                };
+                let items: Vec<usize> = block
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, l)| l.item && l.dash == first.dash)
+                    .map(|(p, _)| p)
+                    .collect();
+                let Some(&position) = items.get(*index) else {
+                    break;
+                };
+                let end = items.get(index + 1).copied().unwrap_or(block.len());
+                (position, end, block[position].dash)
+            }
+        };
+        found = Some(block[position].location(column));
+        // A key's value starts on the next line, an item's on its own line
+        let start = match segment {
+            Key(_) => position + 1,
+            Index(_) => position,
+        };
+        range = range.start + start..range.start + end;
+    }
+    found
+}
+
+/// A line of YAML with its content, ignoring blank lines and comments
+struct YamlLine<'a> {
+    number: usize,
+    /// Indentation of the leading `-` of a sequence item, or of the content
+    dash: usize,
+    /// Column of the content after any `- `
+    indent: usize,
+    item: bool,
+    content: &'a str,
+}
+
+impl<'a> YamlLine<'a> {
+    fn new(number: usize, text: &'a str) -> Option<Self> {
+        let content = text.trim_start();
+        if content.is_empty() || content.starts_with('#') || content.starts_with("---") {
+            return None;
+        }
+        let dash = text.len() - content.len();
+        let mut indent = dash;
+        let mut rest = content;
+        while rest == "-" || rest.starts_with("- ") {
+            let after = &rest[1..];
+            let trimmed = after.trim_start();
+            indent += 1 + after.len() - trimmed.len();
+            rest = trimmed;
+        }
+        Some(Self {
+            number,
+            dash,
+            indent,
+            item: indent != dash,
+            content: rest,
+        })
+    }
+
+    fn has_key(&self, key: &str) -> bool {
+        [
+            key.to_string(),
+            format!("\"{}\"", key),
+            format!("'{}'", key),
+        ]
+        .iter()
+        .any(|quoted| {
+            self.content
+                .strip_prefix(quoted.as_str())
+                .and_then(|rest| rest.trim_start().strip_prefix(':'))
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+        })
+    }
+
+    fn location(&self, column: usize) -> SourceLocation {
+        SourceLocation {
+            line: self.number,
+            column: column + 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MDL: &str = "
+models:
+- name: orders
+  columns:
+  - name: id
+    data_type: Integer
+    description: null
+    nullable: false
+    primary_key: true
+  - name: customer_id
+    data_type: String
+    description: null
+    nullable: false
+    primary_key: false
+  - name: amount
+    data_type: Float
+    description: null
+    nullable: true
+    primary_key: false
+  table_reference:
+    catalog: null
+    schema: null
+    table: orders
+  primary_key: order_id
+  calculated_fields:
+  - name: gross
+    expression: net * 1.2
+    data_type: Float
+    description: null
+  - name: net
+    expression: gross - discount
+    data_type: Float
+    description: null
+  - name: doubled
+    expression: amount *
+    data_type: Float
+    description: null
+  description: null
+- name: customers
+  columns:
+  - name: id
+    data_type: Integer
+    description: null
+    nullable: false
+    primary_key: true
+  table_reference:
+    catalog: null
+    schema: null
+    table: customers
+  primary_key: id
+  calculated_fields: []
+  description: null
+relationships:
+- name: orders_customer
+  from_model: orders
+  from_column: customer_id
+  to_model: customers
+  to_column: id
+  relationship_type: ManyToOne
+- name: orders_shipment
+  from_model: orders
+  from_column: shipment_id
+  to_model: shipments
+  to_column: id
+  relationship_type: ManyToOne
+metrics:
+- name: revenue
+  expression: amount
+  description: null
+  base_model: orders
+  aggregation: Sum
+  filters:
+  - expression: status = 'paid'
+- name: order_count
+  expression: '*'
+  description: null
+  base_model: orders
+  aggregation: Count
+  filters: []
+- name: average_order
+  expression: revenue / order_count
+  description: null
+  base_model: null
+  aggregation: null
+  filters: []
+views:
+- name: big_orders
+  base_query: SELECT id, amount AS total FROM orders WHERE amount > 100
+  description: null
+  columns:
+  - name: total_with_tax
+    data_type: Float
+    expression: total * 1.2
+  - name: note
+    data_type: String
+    expression: comment
+";
+
+    #[test]
+    fn test_check_collects_located_issues() {
+        let mdl: WinxMDL = serde_yaml::from_str(MDL).unwrap();
+        let issues: Vec<String> = check(&mdl, Some(MDL))
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                "error at 24:3: Primary key 'order_id' of model 'orders' is not one of its columns",
+                "error at 27:5: Calculated fields of model 'orders' depend on themselves: gross -> net -> gross",
+                "error at 31:5: Calculated field 'orders.net' refers to 'discount', which model 'orders' doesn't have",
+                "error at 35:5: Calculated field 'orders.doubled' doesn't parse: sql parser error: Expected: an expression, found: EOF",
+                "error at 58:3: Relationship 'orders_customer' joins orders.customer_id (string) with customers.id (integer)",
+                "error at 62:3: Relationship 'orders_shipment' references non-existent column: orders.shipment_id",
+                "error at 63:3: Relationship 'orders_shipment' references non-existent model: shipments",
+                "error at 73:5: Metric 'revenue' refers to 'status', which model 'orders' doesn't have",
+                "error at 96:5: Column 'note' of view 'big_orders' refers to 'comment', which its query doesn't provide",
+            ]
+        );
+
+        // Without the source the same issues come unlocated
+        assert_eq!(check(&mdl, None).len(), issues.len());
+        assert!(check(&mdl, None).iter().all(|i| i.location.is_none()));
+    }
+
+    #[test]
+    fn test_locate_follows_compact_and_indented_sequences() {
+        let yaml = "models:\n  - name: a\n    columns:\n      - name: x\n      - name: y\n";
+        let location = locate(
+            yaml,
+            &[
+                Key("models"),
+                Index(0),
+                Key("columns"),
+                Index(1),
+                Key("name"),
+            ],
+        );
+        assert_eq!(location, Some(SourceLocation { line: 5, column: 9 }));
+        assert_eq!(
+            cycles(&[vec![1], vec![2], vec![0], vec![3]]),
+            [vec![0, 1, 2], vec![3]]
+        );
+    }
+}
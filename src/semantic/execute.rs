@@ -0,0 +1,153 @@
+//! Running queries against a SQLite database
+//!
+//! No SQLite library is linked in, so queries go through the `sqlite3` shell:
+//! the database is opened read-only in safe mode, which also refuses `ATTACH`
+//! and extensions, and rows come back as JSON.
+
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::error::{WinxError, WinxResult};
+
+#[derive(Debug, Clone)]
+pub struct SqliteRunner {
+    binary: String,
+    database: PathBuf,
+    timeout: Duration,
+}
+
+/// Rows of a query, each with a value per column
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueryRows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// Row of the shell's JSON output, keeping the order of its columns
+struct Row(Vec<(String, Value)>);
+
+impl<'de> Deserialize<'de> for Row {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RowVisitor;
+
+        impl<'de> Visitor<'de> for RowVisitor {
+            type Value = Row;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a row object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Row, A::Error> {
+                let mut row = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    row.push(entry);
+                }
+                Ok(Row(row))
+            }
+        }
+
+        deserializer.deserialize_map(RowVisitor)
+    }
+}
+
+impl SqliteRunner {
+    pub fn new(binary: impl Into<String>, database: impl Into<PathBuf>, timeout: Duration) -> Self {
+        Self {
+            binary: binary.into(),
+            database: database.into(),
+            timeout,
+        }
+    }
+
+    pub fn database(&self) -> &Path {
+        &self.database
+    }
+
+    /// Runs one SELECT, `columns` names the output when no row comes back
+    pub async fn query(&self, sql: &str, columns: &[String]) -> WinxResult<QueryRows> {
+        if !self.database.is_file() {
+            return Err(WinxError::file_error(
+                "SQLite database not found",
+                &self.database,
+            ));
+        }
+
+        let mut child = Command::new(&self.binary)
+            .args(["-readonly", "-safe", "-bail", "-json"])
+            .arg(&self.database)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| WinxError::other(format!("Failed to run '{}': {}", self.binary, e)))?;
+
+        // SQL on stdin keeps it off the command line and out of the shell's option parsing
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(sql.trim_end().as_bytes()).await?;
+            stdin.write_all(b";\n").await?;
+        }
+        let output = tokio::time::timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                WinxError::other(format!(
+                    "SQLite query timed out after {}s",
+                    self.timeout.as_secs()
+                ))
+            })??;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() || !stderr.trim().is_empty() {
+            return Err(WinxError::other(format!("SQLite error: {}", stderr.trim())));
+        }
+        parse_rows(&String::from_utf8_lossy(&output.stdout), columns)
+    }
+}
+
+/// Reads the shell's JSON output, which is empty when no row matched
+fn parse_rows(output: &str, columns: &[String]) -> WinxResult<QueryRows> {
+    if output.trim().is_empty() {
+        return Ok(QueryRows {
+            columns: columns.to_vec(),
+            rows: Vec::new(),
+        });
+    }
+    let rows: Vec<Row> = serde_json::from_str(output)?;
+    let columns = match rows.first() {
+        Some(row) => row.0.iter().map(|(name, _)| name.clone()).collect(),
+        None => columns.to_vec(),
+    };
+    Ok(QueryRows {
+        columns,
+        rows: rows
+            .into_iter()
+            .map(|row| row.0.into_iter().map(|(_, value)| value).collect())
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_rows_keeps_column_order() {
+        let output = "[{\"country\":\"NL\",\"revenue\":12.5,\"active\":null},\n\
+                      {\"country\":\"BE\",\"revenue\":3,\"active\":1}]\n";
+        let rows = parse_rows(output, &[]).unwrap();
+        assert_eq!(rows.columns, ["country", "revenue", "active"]);
+        assert_eq!(rows.rows[1], [json!("BE"), json!(3), json!(1)]);
+
+        let empty = parse_rows("", &["country".to_string()]).unwrap();
+        assert_eq!(empty.columns, ["country"]);
+        assert!(empty.rows.is_empty());
+    }
+}
@@ -0,0 +1,423 @@
+//! Cube data schema in YAML
+//!
+//! A model becomes a cube over its `sql_table`, columns and calculated fields
+//! become dimensions, metrics become measures of their base model's cube and
+//! relationships become joins of the `from_model` cube. Cube has no
+//! many-to-many joins, those relationships are left out.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::error::{WinxError, WinxResult};
+use crate::semantic::import::type_from_name;
+use crate::semantic::mdl::{
+    Aggregation, CalculatedField, Column, DataType, FilterExpression, Metric, Model, Relationship,
+    RelationshipType, TableReference, WinxMDL,
+};
+use crate::semantic::validate::type_name;
+
+lazy_static! {
+    /// `{CUBE}.customer_id = {customers}.id`
+    static ref JOIN_SQL: Regex =
+        Regex::new(r"^\s*\{(\w+)\}\.(\w+)\s*=\s*\{(\w+)\}\.(\w+)\s*$").unwrap();
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Schema {
+    #[serde(default)]
+    cubes: Vec<Cube>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Cube {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sql_table: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    joins: Vec<Join>,
+    #[serde(default)]
+    dimensions: Vec<Dimension>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    measures: Vec<Measure>,
+    #[serde(default, skip_serializing_if = "CubeMeta::is_empty")]
+    meta: CubeMeta,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CubeMeta {
+    /// Relationship names by joined cube
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    relationships: BTreeMap<String, String>,
+}
+
+impl CubeMeta {
+    fn is_empty(&self) -> bool {
+        self.relationships.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Join {
+    name: String,
+    relationship: String,
+    sql: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Dimension {
+    name: String,
+    sql: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    primary_key: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    meta: Option<DimensionMeta>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DimensionMeta {
+    data_type: String,
+    #[serde(default = "default_nullable")]
+    nullable: bool,
+    /// Set on calculated fields
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    calculated: bool,
+}
+
+fn default_nullable() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Measure {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sql: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    filters: Vec<MeasureFilter>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MeasureFilter {
+    sql: String,
+}
+
+fn dimension_type(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Integer | DataType::Float => "number",
+        DataType::Boolean => "boolean",
+        DataType::Date | DataType::Timestamp => "time",
+        _ => "string",
+    }
+}
+
+/// Cube SQL with `{CUBE}.` references made plain column names
+fn plain_sql(sql: &str) -> String {
+    sql.replace("${CUBE}.", "").replace("{CUBE}.", "")
+}
+
+pub fn export(mdl: &WinxMDL) -> WinxResult<String> {
+    let mut cubes: Vec<Cube> = mdl
+        .models
+        .iter()
+        .map(|model| {
+            let reference = &model.table_reference;
+            let sql_table = [&reference.catalog, &reference.schema]
+                .into_iter()
+                .flatten()
+                .chain(std::iter::once(&reference.table))
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(".");
+            let columns = model.columns.iter().map(|column| Dimension {
+                name: column.name.clone(),
+                sql: column.name.clone(),
+                kind: dimension_type(&column.data_type).to_string(),
+                primary_key: column.primary_key,
+                description: column.description.clone(),
+                meta: Some(DimensionMeta {
+                    data_type: type_name(&column.data_type),
+                    nullable: column.nullable,
+                    calculated: false,
+                }),
+            });
+            let calculated = model.calculated_fields.iter().map(|field| Dimension {
+                name: field.name.clone(),
+                sql: field.expression.clone(),
+                kind: dimension_type(&field.data_type).to_string(),
+                primary_key: false,
+                description: field.description.clone(),
+                meta: Some(DimensionMeta {
+                    data_type: type_name(&field.data_type),
+                    nullable: true,
+                    calculated: true,
+                }),
+            });
+            Cube {
+                name: model.name.clone(),
+                sql_table: Some(sql_table),
+                description: model.description.clone(),
+                joins: Vec::new(),
+                dimensions: columns.chain(calculated).collect(),
+                measures: Vec::new(),
+                meta: CubeMeta::default(),
+            }
+        })
+        .collect();
+
+    for relationship in &mdl.relationships {
+        let kind = match relationship.relationship_type {
+            RelationshipType::OneToOne => "one_to_one",
+            RelationshipType::OneToMany => "one_to_many",
+            RelationshipType::ManyToOne => "many_to_one",
+            RelationshipType::ManyToMany => {
+                log::warn!(
+                    "Cube has no many-to-many joins, leaving out relationship '{}'",
+                    relationship.name
+                );
+                continue;
+            }
+        };
+        let cube = cube_mut(
+            &mut cubes,
+            &relationship.from_model,
+            "Relationship",
+            &relationship.name,
+        )?;
+        cube.joins.push(Join {
+            name: relationship.to_model.clone(),
+            relationship: kind.to_string(),
+            sql: format!(
+                "{{CUBE}}.{} = {{{}}}.{}",
+                relationship.from_column, relationship.to_model, relationship.to_column
+            ),
+        });
+        cube.meta
+            .relationships
+            .insert(relationship.to_model.clone(), relationship.name.clone());
+    }
+
+    for metric in &mdl.metrics {
+        let Some(base_model) = &metric.base_model else {
+            log::warn!(
+                "Cube measures belong to a cube, leaving out metric '{}' without a base model",
+                metric.name
+            );
+            continue;
+        };
+        let (kind, sql) = match &metric.aggregation {
+            Some(Aggregation::Count) if metric.expression.trim() == "*" => ("count", None),
+            Some(Aggregation::Count) => ("count", Some(metric.expression.clone())),
+            Some(Aggregation::DistinctCount) => ("count_distinct", Some(metric.expression.clone())),
+            Some(Aggregation::Sum) => ("sum", Some(metric.expression.clone())),
+            Some(Aggregation::Average) => ("avg", Some(metric.expression.clone())),
+            Some(Aggregation::Min) => ("min", Some(metric.expression.clone())),
+            Some(Aggregation::Max) => ("max", Some(metric.expression.clone())),
+            Some(Aggregation::Custom(function)) => (
+                "number",
+                Some(format!("{}({})", function, metric.expression)),
+            ),
+            None => ("number", Some(metric.expression.clone())),
+        };
+        cube_mut(&mut cubes, base_model, "Metric", &metric.name)?
+            .measures
+            .push(Measure {
+                name: metric.name.clone(),
+                kind: kind.to_string(),
+                sql,
+                description: metric.description.clone(),
+                filters: metric
+                    .filters
+                    .iter()
+                    .map(|f| MeasureFilter {
+                        sql: f.expression.clone(),
+                    })
+                    .collect(),
+            });
+    }
+
+    Ok(serde_yaml::to_string(&Schema { cubes })?)
+}
+
+fn cube_mut<'a>(
+    cubes: &'a mut [Cube],
+    model: &str,
+    kind: &str,
+    name: &str,
+) -> WinxResult<&'a mut Cube> {
+    cubes.iter_mut().find(|c| c.name == model).ok_or_else(|| {
+        WinxError::invalid_argument(format!(
+            "{} '{}' refers to the unknown model '{}'",
+            kind, name, model
+        ))
+    })
+}
+
+pub fn import(content: &str) -> WinxResult<WinxMDL> {
+    let schema: Schema = serde_yaml::from_str(content)?;
+    let mut mdl = WinxMDL::new();
+
+    for cube in schema.cubes {
+        let parts: Vec<String> = cube
+            .sql_table
+            .as_deref()
+            .unwrap_or(&cube.name)
+            .split('.')
+            .map(str::to_string)
+            .collect();
+        let table_reference = match parts.as_slice() {
+            [catalog, schema, table] => TableReference {
+                catalog: Some(catalog.clone()),
+                schema: Some(schema.clone()),
+                table: table.clone(),
+            },
+            [schema, table] => TableReference {
+                catalog: None,
+                schema: Some(schema.clone()),
+                table: table.clone(),
+            },
+            _ => TableReference {
+                catalog: None,
+                schema: None,
+                table: parts.join("."),
+            },
+        };
+
+        let mut columns = Vec::new();
+        let mut calculated_fields = Vec::new();
+        for dimension in cube.dimensions {
+            let data_type = dimension
+                .meta
+                .as_ref()
+                .map(|meta| type_from_name(&meta.data_type))
+                .unwrap_or_else(|| match dimension.kind.as_str() {
+                    "number" => DataType::Float,
+                    "boolean" => DataType::Boolean,
+                    "time" => DataType::Timestamp,
+                    "string" => DataType::String,
+                    other => DataType::Custom(other.to_string()),
+                });
+            let sql = plain_sql(&dimension.sql);
+            let calculated = match &dimension.meta {
+                Some(meta) => meta.calculated,
+                None => sql != dimension.name,
+            };
+            if calculated {
+                calculated_fields.push(CalculatedField {
+                    name: dimension.name,
+                    expression: sql,
+                    data_type,
+                    description: dimension.description,
+                });
+            } else {
+                columns.push(Column {
+                    nullable: dimension
+                        .meta
+                        .as_ref()
+                        .map_or(!dimension.primary_key, |meta| meta.nullable),
+                    name: dimension.name,
+                    data_type,
+                    description: dimension.description,
+                    primary_key: dimension.primary_key,
+                });
+            }
+        }
+
+        for join in cube.joins {
+            let Some(captures) = JOIN_SQL.captures(&join.sql) else {
+                log::warn!(
+                    "Skipping join {} of cube {}, only `{{CUBE}}.a = {{{}}}.b` conditions are supported",
+                    join.name,
+                    cube.name,
+                    join.name
+                );
+                continue;
+            };
+            // Either side of the condition may name the joined cube
+            let (from_column, to_column) = if &captures[1] == "CUBE" || captures[1] == cube.name {
+                (captures[2].to_string(), captures[4].to_string())
+            } else {
+                (captures[4].to_string(), captures[2].to_string())
+            };
+            let relationship_type = match join.relationship.as_str() {
+                "one_to_one" | "has_one" | "hasOne" => RelationshipType::OneToOne,
+                "one_to_many" | "has_many" | "hasMany" => RelationshipType::OneToMany,
+                "many_to_one" | "belongs_to" | "belongsTo" => RelationshipType::ManyToOne,
+                other => {
+                    return Err(WinxError::parse_error(format!(
+                        "Unknown relationship '{}' in join {} of cube {}",
+                        other, join.name, cube.name
+                    )))
+                }
+            };
+            mdl.relationships.push(Relationship {
+                name: cube
+                    .meta
+                    .relationships
+                    .get(&join.name)
+                    .cloned()
+                    .unwrap_or_else(|| format!("{}_{}", cube.name, join.name)),
+                from_model: cube.name.clone(),
+                from_column,
+                to_model: join.name,
+                to_column,
+                relationship_type,
+            });
+        }
+
+        for measure in cube.measures {
+            let aggregation = match measure.kind.as_str() {
+                "count" => Some(Aggregation::Count),
+                "count_distinct" | "count_distinct_approx" => Some(Aggregation::DistinctCount),
+                "sum" => Some(Aggregation::Sum),
+                "avg" => Some(Aggregation::Average),
+                "min" => Some(Aggregation::Min),
+                "max" => Some(Aggregation::Max),
+                _ => None,
+            };
+            mdl.metrics.push(Metric {
+                name: measure.name,
+                expression: measure
+                    .sql
+                    .as_deref()
+                    .map(plain_sql)
+                    .unwrap_or_else(|| "*".to_string()),
+                description: measure.description,
+                base_model: Some(cube.name.clone()),
+                aggregation,
+                filters: measure
+                    .filters
+                    .iter()
+                    .map(|f| FilterExpression {
+                        expression: plain_sql(&f.sql),
+                    })
+                    .collect(),
+            });
+        }
+
+        let primary_key = match columns.iter().filter(|c| c.primary_key).collect::<Vec<_>>()[..] {
+            [column] => Some(column.name.clone()),
+            _ => None,
+        };
+        mdl.models.push(Model {
+            name: cube.name,
+            columns,
+            table_reference,
+            primary_key,
+            calculated_fields,
+            description: cube.description,
+        });
+    }
+    Ok(mdl)
+}
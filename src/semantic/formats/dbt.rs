@@ -0,0 +1,385 @@
+//! dbt `schema.yml`
+//!
+//! Models carry their physical table in `config`, primary keys as column
+//! constraints plus `unique`/`not_null` tests, and relationships as
+//! `relationships` tests on the referencing column. Metrics use the
+//! `calculation_method` metric spec. Calculated fields, relationship names and
+//! metric filters go in `meta`.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{WinxError, WinxResult};
+use crate::semantic::import::type_from_name;
+use crate::semantic::mdl::{
+    Aggregation, CalculatedField, Column, FilterExpression, Metric, Model, Relationship,
+    RelationshipType, TableReference, WinxMDL,
+};
+use crate::semantic::validate::type_name;
+
+lazy_static! {
+    static ref REF: Regex = Regex::new(r#"^\s*ref\(\s*['"]([^'"]+)['"]\s*\)\s*$"#).unwrap();
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Project {
+    version: u32,
+    #[serde(default)]
+    models: Vec<DbtModel>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    metrics: Vec<DbtMetric>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DbtModel {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "ModelConfig::is_empty")]
+    config: ModelConfig,
+    #[serde(default)]
+    columns: Vec<DbtColumn>,
+    #[serde(default, skip_serializing_if = "ModelMeta::is_empty")]
+    meta: ModelMeta,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ModelConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    database: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schema: Option<String>,
+    /// Physical table name when it differs from the model name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alias: Option<String>,
+}
+
+impl ModelConfig {
+    fn is_empty(&self) -> bool {
+        self.database.is_none() && self.schema.is_none() && self.alias.is_none()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ModelMeta {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    calculated_fields: Vec<CalculatedField>,
+}
+
+impl ModelMeta {
+    fn is_empty(&self) -> bool {
+        self.calculated_fields.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DbtColumn {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    constraints: Vec<Constraint>,
+    /// `tests` before dbt 1.8
+    #[serde(default, alias = "tests", skip_serializing_if = "Vec::is_empty")]
+    data_tests: Vec<Test>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Constraint {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Test {
+    Named(String),
+    Relationships { relationships: RelationshipTest },
+    Other(serde_yaml::Value),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RelationshipTest {
+    to: String,
+    field: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    config: Option<TestConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TestConfig {
+    meta: RelationshipMeta,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RelationshipMeta {
+    name: String,
+    relationship_type: RelationshipType,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DbtMetric {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    calculation_method: String,
+    expression: String,
+    #[serde(default, skip_serializing_if = "MetricMeta::is_empty")]
+    meta: MetricMeta,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MetricMeta {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    filters: Vec<String>,
+}
+
+impl MetricMeta {
+    fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+}
+
+fn reference(model: &str) -> String {
+    format!("ref('{}')", model)
+}
+
+fn referenced(text: &str) -> WinxResult<String> {
+    REF.captures(text)
+        .map(|c| c[1].to_string())
+        .ok_or_else(|| WinxError::parse_error(format!("Expected ref('model'), found '{}'", text)))
+}
+
+pub fn export(mdl: &WinxMDL) -> WinxResult<String> {
+    let mut models: Vec<DbtModel> = mdl
+        .models
+        .iter()
+        .map(|model| DbtModel {
+            name: model.name.clone(),
+            description: model.description.clone(),
+            config: ModelConfig {
+                database: model.table_reference.catalog.clone(),
+                schema: model.table_reference.schema.clone(),
+                alias: (model.table_reference.table != model.name)
+                    .then(|| model.table_reference.table.clone()),
+            },
+            columns: model
+                .columns
+                .iter()
+                .map(|column| {
+                    let mut tests = Vec::new();
+                    let mut constraints = Vec::new();
+                    if column.primary_key {
+                        constraints.push(Constraint {
+                            kind: "primary_key".to_string(),
+                        });
+                        tests.push(Test::Named("unique".to_string()));
+                    }
+                    if !column.nullable {
+                        tests.push(Test::Named("not_null".to_string()));
+                    }
+                    DbtColumn {
+                        name: column.name.clone(),
+                        description: column.description.clone(),
+                        data_type: Some(type_name(&column.data_type)),
+                        constraints,
+                        data_tests: tests,
+                    }
+                })
+                .collect(),
+            meta: ModelMeta {
+                calculated_fields: model.calculated_fields.clone(),
+            },
+        })
+        .collect();
+
+    for relationship in &mdl.relationships {
+        // The test goes on the referencing column, the "many" side of one-to-many
+        let (model, column, to, field) = match relationship.relationship_type {
+            RelationshipType::OneToMany => (
+                &relationship.to_model,
+                &relationship.to_column,
+                &relationship.from_model,
+                &relationship.from_column,
+            ),
+            _ => (
+                &relationship.from_model,
+                &relationship.from_column,
+                &relationship.to_model,
+                &relationship.to_column,
+            ),
+        };
+        let column = models
+            .iter_mut()
+            .find(|m| m.name == *model)
+            .and_then(|m| m.columns.iter_mut().find(|c| c.name == *column))
+            .ok_or_else(|| {
+                WinxError::invalid_argument(format!(
+                    "Relationship '{}' refers to the unknown column {}.{}",
+                    relationship.name, model, column
+                ))
+            })?;
+        column.data_tests.push(Test::Relationships {
+            relationships: RelationshipTest {
+                to: reference(to),
+                field: field.clone(),
+                config: Some(TestConfig {
+                    meta: RelationshipMeta {
+                        name: relationship.name.clone(),
+                        relationship_type: relationship.relationship_type.clone(),
+                    },
+                }),
+            },
+        });
+    }
+
+    let metrics = mdl
+        .metrics
+        .iter()
+        .map(|metric| {
+            let (calculation_method, expression) = match &metric.aggregation {
+                Some(Aggregation::Sum) => ("sum", metric.expression.clone()),
+                Some(Aggregation::Average) => ("average", metric.expression.clone()),
+                Some(Aggregation::Count) => ("count", metric.expression.clone()),
+                Some(Aggregation::Min) => ("min", metric.expression.clone()),
+                Some(Aggregation::Max) => ("max", metric.expression.clone()),
+                Some(Aggregation::DistinctCount) => ("count_distinct", metric.expression.clone()),
+                Some(Aggregation::Custom(function)) => {
+                    ("derived", format!("{}({})", function, metric.expression))
+                }
+                None => ("derived", metric.expression.clone()),
+            };
+            DbtMetric {
+                name: metric.name.clone(),
+                description: metric.description.clone(),
+                model: metric.base_model.as_deref().map(reference),
+                calculation_method: calculation_method.to_string(),
+                expression,
+                meta: MetricMeta {
+                    filters: metric
+                        .filters
+                        .iter()
+                        .map(|f| f.expression.clone())
+                        .collect(),
+                },
+            }
+        })
+        .collect();
+
+    Ok(serde_yaml::to_string(&Project {
+        version: 2,
+        models,
+        metrics,
+    })?)
+}
+
+pub fn import(content: &str) -> WinxResult<WinxMDL> {
+    let project: Project = serde_yaml::from_str(content)?;
+    let mut mdl = WinxMDL::new();
+
+    for model in project.models {
+        let mut columns = Vec::new();
+        for column in model.columns {
+            let primary_key = column.constraints.iter().any(|c| c.kind == "primary_key");
+            let mut nullable = !primary_key;
+            for test in &column.data_tests {
+                match test {
+                    Test::Named(name) if name == "not_null" => nullable = false,
+                    Test::Relationships { relationships } => {
+                        let to = referenced(&relationships.to)?;
+                        let (name, relationship_type) = match &relationships.config {
+                            Some(config) => (
+                                config.meta.name.clone(),
+                                config.meta.relationship_type.clone(),
+                            ),
+                            None => (
+                                format!("{}_{}", model.name, column.name),
+                                RelationshipType::ManyToOne,
+                            ),
+                        };
+                        let (from_model, from_column, to_model, to_column) = match relationship_type
+                        {
+                            RelationshipType::OneToMany => (
+                                to,
+                                relationships.field.clone(),
+                                model.name.clone(),
+                                column.name.clone(),
+                            ),
+                            _ => (
+                                model.name.clone(),
+                                column.name.clone(),
+                                to,
+                                relationships.field.clone(),
+                            ),
+                        };
+                        mdl.relationships.push(Relationship {
+                            name,
+                            from_model,
+                            from_column,
+                            to_model,
+                            to_column,
+                            relationship_type,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            columns.push(Column {
+                name: column.name,
+                data_type: type_from_name(column.data_type.as_deref().unwrap_or("any")),
+                description: column.description,
+                nullable,
+                primary_key,
+            });
+        }
+
+        let primary_key = match columns.iter().filter(|c| c.primary_key).collect::<Vec<_>>()[..] {
+            [column] => Some(column.name.clone()),
+            _ => None,
+        };
+        mdl.models.push(Model {
+            table_reference: TableReference {
+                catalog: model.config.database,
+                schema: model.config.schema,
+                table: model.config.alias.unwrap_or_else(|| model.name.clone()),
+            },
+            name: model.name,
+            columns,
+            primary_key,
+            calculated_fields: model.meta.calculated_fields,
+            description: model.description,
+        });
+    }
+
+    for metric in project.metrics {
+        let aggregation = match metric.calculation_method.as_str() {
+            "sum" => Some(Aggregation::Sum),
+            "average" => Some(Aggregation::Average),
+            "count" => Some(Aggregation::Count),
+            "count_distinct" => Some(Aggregation::DistinctCount),
+            "min" => Some(Aggregation::Min),
+            "max" => Some(Aggregation::Max),
+            "derived" | "expression" => None,
+            other => Some(Aggregation::Custom(other.to_string())),
+        };
+        mdl.metrics.push(Metric {
+            name: metric.name,
+            expression: metric.expression,
+            description: metric.description,
+            base_model: metric.model.as_deref().map(referenced).transpose()?,
+            aggregation,
+            filters: metric
+                .meta
+                .filters
+                .into_iter()
+                .map(|expression| FilterExpression { expression })
+                .collect(),
+        });
+    }
+    Ok(mdl)
+}
@@ -0,0 +1,196 @@
+//! JSON Schema of the rows of each model
+//!
+//! The document holds one schema per model under `$defs`. A row is an object
+//! whose non-nullable columns are required; the physical table, primary key and
+//! column order are kept in `x-table`, `x-primary-key` and `x-order`.
+//! Relationships, calculated fields and metrics aren't part of a row and are
+//! not exported.
+
+use serde_json::{json, Map, Value};
+
+use crate::error::{WinxError, WinxResult};
+use crate::semantic::import::type_from_name;
+use crate::semantic::mdl::{Column, DataType, Model, TableReference, WinxMDL};
+
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Schema of one row of `model`
+pub fn model_schema(model: &Model) -> Value {
+    let mut properties = Map::new();
+    for (position, column) in model.columns.iter().enumerate() {
+        let mut property = property(&column.data_type);
+        property["x-order"] = json!(position);
+        if column.nullable {
+            if let Some(kind) = property.get("type").cloned() {
+                property["type"] = json!([kind, "null"]);
+            }
+        }
+        if let Some(description) = &column.description {
+            property["description"] = json!(description);
+        }
+        properties.insert(column.name.clone(), property);
+    }
+
+    let mut schema = json!({
+        "$schema": DIALECT,
+        "title": model.name,
+        "type": "object",
+        "properties": properties,
+        "required": model
+            .columns
+            .iter()
+            .filter(|c| !c.nullable)
+            .map(|c| c.name.clone())
+            .collect::<Vec<_>>(),
+        "x-table": model.table_reference,
+    });
+    if let Some(description) = &model.description {
+        schema["description"] = json!(description);
+    }
+    if let Some(primary_key) = &model.primary_key {
+        schema["x-primary-key"] = json!(primary_key);
+    }
+    schema
+}
+
+fn property(data_type: &DataType) -> Value {
+    match data_type {
+        DataType::String => json!({ "type": "string" }),
+        DataType::Integer => json!({ "type": "integer" }),
+        DataType::Float => json!({ "type": "number" }),
+        DataType::Boolean => json!({ "type": "boolean" }),
+        DataType::Date => json!({ "type": "string", "format": "date" }),
+        DataType::Timestamp => json!({ "type": "string", "format": "date-time" }),
+        // Any JSON value
+        DataType::Json => json!({}),
+        DataType::Array(element) => json!({ "type": "array", "items": property(element) }),
+        DataType::Custom(name) => json!({ "x-sql-type": name }),
+    }
+}
+
+pub fn export(mdl: &WinxMDL) -> WinxResult<String> {
+    let defs: Map<String, Value> = mdl
+        .models
+        .iter()
+        .map(|model| (model.name.clone(), model_schema(model)))
+        .collect();
+    Ok(serde_json::to_string_pretty(&json!({
+        "$schema": DIALECT,
+        "$defs": defs,
+    }))?)
+}
+
+/// Reads the models of a document with `$defs`, or of a single model schema
+pub fn import(content: &str) -> WinxResult<WinxMDL> {
+    let document: Value = serde_json::from_str(content)?;
+    let mut mdl = WinxMDL::new();
+    match document.get("$defs").and_then(Value::as_object) {
+        Some(defs) => {
+            for (name, schema) in defs {
+                mdl.models.push(model(name, schema)?);
+            }
+        }
+        None => {
+            let name = document
+                .get("title")
+                .and_then(Value::as_str)
+                .ok_or_else(|| {
+                    WinxError::parse_error(
+                        "A JSON Schema without $defs needs a title to name its model".to_string(),
+                    )
+                })?;
+            mdl.models.push(model(name, &document)?);
+        }
+    }
+    Ok(mdl)
+}
+
+fn model(name: &str, schema: &Value) -> WinxResult<Model> {
+    let properties = schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .ok_or_else(|| {
+            WinxError::parse_error(format!("Schema of model '{}' has no properties", name))
+        })?;
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|r| r.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let primary_key = schema
+        .get("x-primary-key")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+
+    // Property order isn't kept by JSON objects
+    let mut properties: Vec<(&String, &Value)> = properties.iter().collect();
+    properties.sort_by_key(|(_, property)| {
+        property
+            .get("x-order")
+            .and_then(Value::as_u64)
+            .unwrap_or(u64::MAX)
+    });
+    let columns = properties
+        .into_iter()
+        .map(|(column, property)| Column {
+            name: column.clone(),
+            data_type: data_type(property),
+            description: property
+                .get("description")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            nullable: !required.contains(&column.as_str()),
+            primary_key: primary_key.as_deref() == Some(column.as_str()),
+        })
+        .collect();
+
+    let table_reference = match schema.get("x-table") {
+        Some(table) => serde_json::from_value(table.clone())?,
+        None => TableReference {
+            catalog: None,
+            schema: None,
+            table: name.to_string(),
+        },
+    };
+    Ok(Model {
+        name: name.to_string(),
+        columns,
+        table_reference,
+        primary_key,
+        calculated_fields: Vec::new(),
+        description: schema
+            .get("description")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+    })
+}
+
+fn data_type(property: &Value) -> DataType {
+    if let Some(name) = property.get("x-sql-type").and_then(Value::as_str) {
+        return type_from_name(name);
+    }
+    // `["integer", "null"]` is a nullable integer
+    let kind = match property.get("type") {
+        Some(Value::Array(kinds)) => kinds
+            .iter()
+            .filter_map(Value::as_str)
+            .find(|k| *k != "null"),
+        Some(kind) => kind.as_str(),
+        None => None,
+    };
+    match (kind, property.get("format").and_then(Value::as_str)) {
+        (Some("string"), Some("date")) => DataType::Date,
+        (Some("string"), Some("date-time")) => DataType::Timestamp,
+        (Some("string"), _) => DataType::String,
+        (Some("integer"), _) => DataType::Integer,
+        (Some("number"), _) => DataType::Float,
+        (Some("boolean"), _) => DataType::Boolean,
+        (Some("array"), _) => DataType::Array(Box::new(
+            property
+                .get("items")
+                .map(data_type)
+                .unwrap_or(DataType::Json),
+        )),
+        _ => DataType::Json,
+    }
+}
@@ -0,0 +1,226 @@
+//! Conversions between [`WinxMDL`] and other tools' schema formats
+//!
+//! Each format keeps what it can express natively and stores the rest in its
+//! `meta` fields, so exporting then importing gives back the same MDL. Views
+//! have no counterpart in these formats and are not exported.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use super::mdl::WinxMDL;
+use crate::error::{WinxError, WinxResult};
+
+pub mod cube;
+pub mod dbt;
+pub mod json_schema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MdlFormat {
+    /// The MDL's own YAML shape
+    #[default]
+    Winx,
+    /// dbt `schema.yml`
+    Dbt,
+    /// Cube data schema in YAML
+    Cube,
+    /// JSON Schema with a definition per model
+    JsonSchema,
+}
+
+impl FromStr for MdlFormat {
+    type Err = WinxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "winx" | "mdl" => Ok(Self::Winx),
+            "dbt" => Ok(Self::Dbt),
+            "cube" => Ok(Self::Cube),
+            "json_schema" | "json-schema" | "jsonschema" => Ok(Self::JsonSchema),
+            other => Err(WinxError::invalid_argument(format!(
+                "Unknown MDL format '{}', expected winx, dbt, cube or json_schema",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for MdlFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Winx => "winx",
+            Self::Dbt => "dbt",
+            Self::Cube => "cube",
+            Self::JsonSchema => "json_schema",
+        })
+    }
+}
+
+/// Renders `mdl` in `format`
+pub fn export(mdl: &WinxMDL, format: MdlFormat) -> WinxResult<String> {
+    match format {
+        MdlFormat::Winx => Ok(serde_yaml::to_string(mdl)?),
+        MdlFormat::Dbt => dbt::export(mdl),
+        MdlFormat::Cube => cube::export(mdl),
+        MdlFormat::JsonSchema => json_schema::export(mdl),
+    }
+}
+
+/// Reads an MDL from `content` in `format`
+pub fn import(content: &str, format: MdlFormat) -> WinxResult<WinxMDL> {
+    match format {
+        // JSON is valid YAML
+        MdlFormat::Winx => Ok(serde_yaml::from_str(content)?),
+        MdlFormat::Dbt => dbt::import(content),
+        MdlFormat::Cube => cube::import(content),
+        MdlFormat::JsonSchema => json_schema::import(content),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantic::mdl::{Column, DataType, RelationshipType};
+    use crate::semantic::test_support::shop_mdl;
+
+    /// The shop with the details formats have to carry over
+    fn mdl() -> WinxMDL {
+        let mut mdl = shop_mdl();
+        for model in &mut mdl.models {
+            for column in &mut model.columns {
+                column.description = Some(format!("The {}", column.name));
+                column.nullable = !column.primary_key && !column.name.ends_with("_id");
+            }
+        }
+        mdl.models[0].description = Some("People who order".to_string());
+        let orders = &mut mdl.models[1];
+        orders.table_reference.catalog = Some("shop".to_string());
+        orders.columns.push(Column {
+            name: "tags".to_string(),
+            data_type: DataType::Array(Box::new(DataType::String)),
+            description: None,
+            nullable: true,
+            primary_key: false,
+        });
+        mdl.metrics[0].description = Some("Revenue of paid orders".to_string());
+        // Cube measures belong to a cube, metrics of metrics have none
+        mdl.metrics.retain(|m| m.base_model.is_some());
+        mdl
+    }
+
+    /// Comparable text of the parts of an MDL a format keeps
+    fn summary(mdl: &WinxMDL, relationships_and_metrics: bool) -> String {
+        let mut mdl = mdl.clone();
+        // JSON Schema definitions are keyed by name, which loses the models' order
+        mdl.models.sort_by(|a, b| a.name.cmp(&b.name));
+        if !relationships_and_metrics {
+            mdl.relationships.clear();
+            mdl.metrics.clear();
+            for model in &mut mdl.models {
+                model.calculated_fields.clear();
+            }
+        }
+        serde_json::to_string_pretty(&mdl).unwrap()
+    }
+
+    #[test]
+    fn test_formats_round_trip() {
+        let mdl = mdl();
+        for format in [MdlFormat::Winx, MdlFormat::Dbt, MdlFormat::Cube] {
+            let exported = export(&mdl, format).unwrap();
+            let imported = import(&exported, format).unwrap();
+            assert_eq!(
+                summary(&imported, true),
+                summary(&mdl, true),
+                "{} export:\n{}",
+                format,
+                exported
+            );
+        }
+
+        let exported = export(&mdl, MdlFormat::JsonSchema).unwrap();
+        let imported = import(&exported, MdlFormat::JsonSchema).unwrap();
+        assert_eq!(summary(&imported, false), summary(&mdl, false));
+    }
+
+    #[test]
+    fn test_export_relationships_as_references() {
+        let mdl = mdl();
+        let dbt = export(&mdl, MdlFormat::Dbt).unwrap();
+        assert!(dbt.contains("to: ref('customers')"), "{}", dbt);
+        let cube = export(&mdl, MdlFormat::Cube).unwrap();
+        assert!(
+            cube.contains("sql: '{CUBE}.customer_id = {customers}.id'"),
+            "{}",
+            cube
+        );
+        assert_eq!(
+            "json-schema".parse::<MdlFormat>().unwrap(),
+            MdlFormat::JsonSchema
+        );
+    }
+
+    #[test]
+    fn test_import_hand_written_schemas() {
+        let dbt = "
+version: 2
+models:
+  - name: orders
+    columns:
+      - name: id
+        data_tests: [unique, not_null]
+      - name: customer_id
+        data_tests:
+          - relationships:
+              to: ref('customers')
+              field: id
+          - accepted_values:
+              values: [1, 2]
+  - name: customers
+    columns:
+      - name: id
+";
+        let mdl = import(dbt, MdlFormat::Dbt).unwrap();
+        assert!(!mdl.models[0].columns[0].nullable);
+        assert_eq!(mdl.relationships[0].name, "orders_customer_id");
+        assert!(matches!(
+            mdl.relationships[0].relationship_type,
+            RelationshipType::ManyToOne
+        ));
+
+        let cube = "
+cubes:
+  - name: customers
+    sql_table: public.customers
+    joins:
+      - name: orders
+        relationship: has_many
+        sql: \"{CUBE}.id = {orders}.customer_id\"
+    dimensions:
+      - name: id
+        sql: id
+        type: number
+        primary_key: true
+      - name: signup_year
+        sql: \"EXTRACT(YEAR FROM {CUBE}.created_at)\"
+        type: number
+    measures:
+      - name: count
+        type: count
+";
+        let mdl = import(cube, MdlFormat::Cube).unwrap();
+        let customers = &mdl.models[0];
+        assert_eq!(customers.table_reference.schema.as_deref(), Some("public"));
+        assert_eq!(customers.primary_key.as_deref(), Some("id"));
+        assert_eq!(
+            customers.calculated_fields[0].expression,
+            "EXTRACT(YEAR FROM created_at)"
+        );
+        assert!(matches!(
+            mdl.relationships[0].relationship_type,
+            RelationshipType::OneToMany
+        ));
+        assert_eq!(mdl.metrics[0].expression, "*");
+    }
+}
@@ -0,0 +1,663 @@
+//! Builds a [`WinxMDL`] from database schemas
+//!
+//! DDL is replayed statement by statement, so a directory of migrations gives
+//! the schema they end with. SQLite databases are read through their schema
+//! table, whose `CREATE` statements go through the same path. Foreign keys
+//! become relationships; merge the result into a hand-written MDL with
+//! [`WinxMDL::merge`].
+
+use sqlparser::ast::{
+    self, AlterColumnOperation, AlterTableOperation, ArrayElemTypeDef, ColumnDef, ColumnOption,
+    CommentObject, ObjectName, ObjectType, Statement, TableConstraint,
+};
+use sqlparser::parser::Parser;
+use std::path::{Path, PathBuf};
+
+use super::execute::SqliteRunner;
+use super::mdl::{
+    Column, DataType, Model, Relationship, RelationshipType, TableReference, WinxMDL,
+};
+use super::sql::{eq_ignore_case, SqlDialect};
+use crate::error::{WinxError, WinxResult};
+
+pub mod sqlite;
+
+/// MDL of the tables declared by `sql`
+pub fn from_ddl(sql: &str, dialect: SqlDialect) -> WinxResult<WinxMDL> {
+    let mut importer = DdlImporter::default();
+    importer.apply(sql, dialect)?;
+    Ok(importer.finish())
+}
+
+/// MDL of the `.sql` files under `dir`, applied in path order
+pub fn from_ddl_dir(dir: &Path, dialect: SqlDialect) -> WinxResult<WinxMDL> {
+    let pattern = format!("{}/**/*.sql", glob::Pattern::escape(&dir.to_string_lossy()));
+    let mut files: Vec<PathBuf> = glob::glob(&pattern)
+        .map_err(|e| WinxError::invalid_argument(format!("Invalid DDL directory: {}", e)))?
+        .filter_map(Result::ok)
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    if files.is_empty() {
+        return Err(WinxError::invalid_argument(format!(
+            "No .sql files under {}",
+            dir.display()
+        )));
+    }
+
+    let mut importer = DdlImporter::default();
+    for file in files {
+        let sql =
+            std::fs::read_to_string(&file).map_err(|e| WinxError::io_error(e, Some(&file)))?;
+        importer
+            .apply(&sql, dialect)
+            .map_err(|e| WinxError::parse_error(format!("{}: {}", file.display(), e)))?;
+    }
+    Ok(importer.finish())
+}
+
+/// MDL of the tables of the SQLite database `runner` opens
+///
+/// Internal `sqlite_` tables and statements the parser doesn't support, such as
+/// virtual tables, are skipped.
+pub async fn from_sqlite(runner: &SqliteRunner) -> WinxResult<WinxMDL> {
+    let mut importer = DdlImporter::default();
+    for entry in sqlite::read_schema(runner).await? {
+        let Some(sql) = entry.sql.filter(|_| entry.kind == "table") else {
+            continue;
+        };
+        if entry.name.starts_with("sqlite_") {
+            continue;
+        }
+        if let Err(e) = importer.apply(&sql, SqlDialect::Sqlite) {
+            log::warn!("Skipping SQLite table {}: {}", entry.name, e);
+        }
+    }
+    Ok(importer.finish())
+}
+
+/// A table as declared by the statements applied so far
+#[derive(Debug, Clone)]
+struct Table {
+    name: String,
+    catalog: Option<String>,
+    schema: Option<String>,
+    columns: Vec<TableColumn>,
+    primary_key: Vec<String>,
+    unique: Vec<Vec<String>>,
+    foreign_keys: Vec<ForeignKey>,
+    description: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct TableColumn {
+    name: String,
+    data_type: DataType,
+    nullable: bool,
+    description: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct ForeignKey {
+    columns: Vec<String>,
+    table: String,
+    /// Empty when the key refers to the primary key of `table`
+    referred: Vec<String>,
+}
+
+/// Replays DDL statements into tables, then turns them into an MDL
+#[derive(Debug, Default)]
+pub struct DdlImporter {
+    tables: Vec<Table>,
+}
+
+impl DdlImporter {
+    /// Applies the statements of `sql`, ignoring those that don't change tables
+    pub fn apply(&mut self, sql: &str, dialect: SqlDialect) -> WinxResult<()> {
+        let statements = Parser::parse_sql(dialect.parser_dialect().as_ref(), sql)
+            .map_err(|e| WinxError::parse_error(format!("Invalid {} DDL: {}", dialect, e)))?;
+        for statement in &statements {
+            self.statement(statement);
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::CreateTable(create) => {
+                if create.columns.is_empty() {
+                    log::warn!(
+                        "Skipping table {}, its columns aren't declared",
+                        create.name
+                    );
+                    return;
+                }
+                if self.index(&create.name).is_some() {
+                    if create.if_not_exists {
+                        return;
+                    }
+                    self.drop(&create.name);
+                }
+                let (catalog, schema, name) = split_name(&create.name);
+                let mut table = Table {
+                    name,
+                    catalog,
+                    schema,
+                    columns: Vec::new(),
+                    primary_key: Vec::new(),
+                    unique: Vec::new(),
+                    foreign_keys: Vec::new(),
+                    description: None,
+                };
+                for column in &create.columns {
+                    add_column(&mut table, column);
+                }
+                for constraint in &create.constraints {
+                    add_constraint(&mut table, constraint);
+                }
+                self.tables.push(table);
+            }
+            Statement::AlterTable {
+                name, operations, ..
+            } => {
+                for operation in operations {
+                    self.alter(name, operation);
+                }
+            }
+            Statement::Drop {
+                object_type: ObjectType::Table,
+                names,
+                ..
+            } => {
+                for name in names {
+                    self.drop(name);
+                }
+            }
+            Statement::Comment {
+                object_type,
+                object_name,
+                comment,
+                ..
+            } => match object_type {
+                CommentObject::Table => {
+                    if let Some(table) = self.table_mut(object_name) {
+                        table.description = comment.clone();
+                    }
+                }
+                CommentObject::Column => {
+                    let Some((column, table)) = object_name.0.split_last() else {
+                        return;
+                    };
+                    let table = ObjectName(table.to_vec());
+                    if let Some(column) = self
+                        .table_mut(&table)
+                        .and_then(|t| find_column(&mut t.columns, &column.value))
+                    {
+                        column.description = comment.clone();
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn alter(&mut self, name: &ObjectName, operation: &AlterTableOperation) {
+        let Some(index) = self.index(name) else {
+            log::warn!("ALTER TABLE of unknown table {}", name);
+            return;
+        };
+        let table_name = self.tables[index].name.clone();
+        let table = &mut self.tables[index];
+        match operation {
+            AlterTableOperation::AddColumn { column_def, .. } => add_column(table, column_def),
+            AlterTableOperation::AddConstraint(constraint) => add_constraint(table, constraint),
+            AlterTableOperation::DropColumn { column_name, .. } => {
+                let column = &column_name.value;
+                table.columns.retain(|c| !eq_ignore_case(&c.name, column));
+                table.primary_key.retain(|c| !eq_ignore_case(c, column));
+                table
+                    .unique
+                    .retain(|u| !u.iter().any(|c| eq_ignore_case(c, column)));
+                table
+                    .foreign_keys
+                    .retain(|fk| !fk.columns.iter().any(|c| eq_ignore_case(c, column)));
+            }
+            AlterTableOperation::DropPrimaryKey => table.primary_key.clear(),
+            AlterTableOperation::AlterColumn { column_name, op } => {
+                let Some(column) = find_column(&mut table.columns, &column_name.value) else {
+                    return;
+                };
+                match op {
+                    AlterColumnOperation::SetNotNull => column.nullable = false,
+                    AlterColumnOperation::DropNotNull => column.nullable = true,
+                    AlterColumnOperation::SetDataType { data_type, .. } => {
+                        column.data_type = mdl_type(data_type)
+                    }
+                    _ => {}
+                }
+            }
+            AlterTableOperation::ChangeColumn {
+                old_name,
+                new_name,
+                data_type,
+                ..
+            } => {
+                if let Some(column) = find_column(&mut table.columns, &old_name.value) {
+                    column.data_type = mdl_type(data_type);
+                }
+                self.rename_column(&table_name, &old_name.value, &new_name.value);
+            }
+            AlterTableOperation::ModifyColumn {
+                col_name,
+                data_type,
+                ..
+            } => {
+                if let Some(column) = find_column(&mut table.columns, &col_name.value) {
+                    column.data_type = mdl_type(data_type);
+                }
+            }
+            AlterTableOperation::RenameColumn {
+                old_column_name,
+                new_column_name,
+            } => self.rename_column(&table_name, &old_column_name.value, &new_column_name.value),
+            AlterTableOperation::RenameTable { table_name: to } => {
+                let (_, _, to) = split_name(to);
+                for fk in self.tables.iter_mut().flat_map(|t| &mut t.foreign_keys) {
+                    if eq_ignore_case(&fk.table, &table_name) {
+                        fk.table = to.clone();
+                    }
+                }
+                self.tables[index].name = to;
+            }
+            _ => {}
+        }
+    }
+
+    /// Renames a column along with the keys that refer to it
+    fn rename_column(&mut self, table_name: &str, from: &str, to: &str) {
+        let rename = |name: &mut String| {
+            if eq_ignore_case(name, from) {
+                *name = to.to_string();
+            }
+        };
+        for table in &mut self.tables {
+            if eq_ignore_case(&table.name, table_name) {
+                table.columns.iter_mut().for_each(|c| rename(&mut c.name));
+                table.primary_key.iter_mut().for_each(rename);
+                table.unique.iter_mut().flatten().for_each(rename);
+                table
+                    .foreign_keys
+                    .iter_mut()
+                    .flat_map(|fk| &mut fk.columns)
+                    .for_each(rename);
+            }
+            for fk in &mut table.foreign_keys {
+                if eq_ignore_case(&fk.table, table_name) {
+                    fk.referred.iter_mut().for_each(rename);
+                }
+            }
+        }
+    }
+
+    fn index(&self, name: &ObjectName) -> Option<usize> {
+        let (_, _, name) = split_name(name);
+        self.tables
+            .iter()
+            .position(|t| eq_ignore_case(&t.name, &name))
+    }
+
+    fn table_mut(&mut self, name: &ObjectName) -> Option<&mut Table> {
+        let index = self.index(name)?;
+        self.tables.get_mut(index)
+    }
+
+    fn drop(&mut self, name: &ObjectName) {
+        if let Some(index) = self.index(name) {
+            self.tables.remove(index);
+        }
+    }
+
+    /// Models of the tables, with a relationship per single-column foreign key
+    pub fn finish(self) -> WinxMDL {
+        let mut mdl = WinxMDL::new();
+        for table in &self.tables {
+            for fk in &table.foreign_keys {
+                if let Some(relationship) = self.relationship(table, fk) {
+                    mdl.relationships.push(relationship);
+                }
+            }
+        }
+        mdl.models = self
+            .tables
+            .into_iter()
+            .map(|table| Model {
+                primary_key: match table.primary_key.as_slice() {
+                    [column] => Some(column.clone()),
+                    _ => None,
+                },
+                columns: table
+                    .columns
+                    .into_iter()
+                    .map(|column| Column {
+                        primary_key: table
+                            .primary_key
+                            .iter()
+                            .any(|c| eq_ignore_case(c, &column.name)),
+                        name: column.name,
+                        data_type: column.data_type,
+                        description: column.description,
+                        nullable: column.nullable,
+                    })
+                    .collect(),
+                table_reference: TableReference {
+                    catalog: table.catalog,
+                    schema: table.schema,
+                    table: table.name.clone(),
+                },
+                name: table.name,
+                calculated_fields: Vec::new(),
+                description: table.description,
+            })
+            .collect();
+        mdl
+    }
+
+    fn relationship(&self, table: &Table, fk: &ForeignKey) -> Option<Relationship> {
+        let [column] = fk.columns.as_slice() else {
+            log::warn!(
+                "Skipping composite foreign key ({}) of {}, relationships join on one column",
+                fk.columns.join(", "),
+                table.name
+            );
+            return None;
+        };
+        let Some(target) = self
+            .tables
+            .iter()
+            .find(|t| eq_ignore_case(&t.name, &fk.table))
+        else {
+            log::warn!(
+                "Skipping foreign key {}.{}, table {} isn't declared",
+                table.name,
+                column,
+                fk.table
+            );
+            return None;
+        };
+        let to_column = match (fk.referred.as_slice(), target.primary_key.as_slice()) {
+            ([referred], _) | ([], [referred]) => referred.clone(),
+            _ => return None,
+        };
+        // A unique foreign key allows one row per target row
+        let unique = matches!(table.primary_key.as_slice(), [pk] if eq_ignore_case(pk, column))
+            || table
+                .unique
+                .iter()
+                .any(|u| matches!(u.as_slice(), [c] if eq_ignore_case(c, column)));
+        Some(Relationship {
+            name: format!("{}_{}", table.name, column),
+            from_model: table.name.clone(),
+            from_column: column.clone(),
+            to_model: target.name.clone(),
+            to_column,
+            relationship_type: if unique {
+                RelationshipType::OneToOne
+            } else {
+                RelationshipType::ManyToOne
+            },
+        })
+    }
+}
+
+fn split_name(name: &ObjectName) -> (Option<String>, Option<String>, String) {
+    let parts: Vec<String> = name.0.iter().map(|i| i.value.clone()).collect();
+    match parts.as_slice() {
+        [catalog, schema, table] => (Some(catalog.clone()), Some(schema.clone()), table.clone()),
+        [schema, table] => (None, Some(schema.clone()), table.clone()),
+        _ => (None, None, parts.last().cloned().unwrap_or_default()),
+    }
+}
+
+fn find_column<'a>(columns: &'a mut [TableColumn], name: &str) -> Option<&'a mut TableColumn> {
+    columns.iter_mut().find(|c| eq_ignore_case(&c.name, name))
+}
+
+fn add_column(table: &mut Table, column: &ColumnDef) {
+    let name = column.name.value.clone();
+    let mut imported = TableColumn {
+        name: name.clone(),
+        data_type: mdl_type(&column.data_type),
+        nullable: true,
+        description: None,
+    };
+    for option in &column.options {
+        match &option.option {
+            ColumnOption::NotNull => imported.nullable = false,
+            ColumnOption::Unique {
+                is_primary: true, ..
+            } => {
+                imported.nullable = false;
+                table.primary_key = vec![name.clone()];
+            }
+            ColumnOption::Unique { .. } => table.unique.push(vec![name.clone()]),
+            ColumnOption::ForeignKey {
+                foreign_table,
+                referred_columns,
+                ..
+            } => table.foreign_keys.push(ForeignKey {
+                columns: vec![name.clone()],
+                table: split_name(foreign_table).2,
+                referred: referred_columns.iter().map(|c| c.value.clone()).collect(),
+            }),
+            ColumnOption::Comment(comment) => imported.description = Some(comment.clone()),
+            _ => {}
+        }
+    }
+    table.columns.retain(|c| !eq_ignore_case(&c.name, &name));
+    table.columns.push(imported);
+}
+
+fn add_constraint(table: &mut Table, constraint: &TableConstraint) {
+    let names = |columns: &[ast::Ident]| -> Vec<String> {
+        columns.iter().map(|c| c.value.clone()).collect()
+    };
+    match constraint {
+        TableConstraint::PrimaryKey { columns, .. } => {
+            table.primary_key = names(columns);
+            for column in &mut table.columns {
+                if table
+                    .primary_key
+                    .iter()
+                    .any(|c| eq_ignore_case(c, &column.name))
+                {
+                    column.nullable = false;
+                }
+            }
+        }
+        TableConstraint::Unique { columns, .. } => table.unique.push(names(columns)),
+        TableConstraint::ForeignKey {
+            columns,
+            foreign_table,
+            referred_columns,
+            ..
+        } => table.foreign_keys.push(ForeignKey {
+            columns: names(columns),
+            table: split_name(foreign_table).2,
+            referred: names(referred_columns),
+        }),
+        _ => {}
+    }
+}
+
+/// MDL type of a SQL column type, using SQLite's affinity rules for unusual names
+pub(crate) fn mdl_type(sql_type: &ast::DataType) -> DataType {
+    if let ast::DataType::Array(element) = sql_type {
+        return DataType::Array(Box::new(match element {
+            ArrayElemTypeDef::AngleBracket(inner)
+            | ArrayElemTypeDef::SquareBracket(inner, _)
+            | ArrayElemTypeDef::Parenthesis(inner) => mdl_type(inner),
+            ArrayElemTypeDef::None => DataType::Custom("any".to_string()),
+        }));
+    }
+
+    type_from_name(&sql_type.to_string())
+}
+
+/// MDL type of a type name, as written in DDL or by other tools' schemas
+pub(crate) fn type_from_name(type_name: &str) -> DataType {
+    let name = type_name.trim().to_lowercase();
+    if let Some(element) = name
+        .strip_prefix("array<")
+        .and_then(|n| n.strip_suffix('>'))
+        .or_else(|| name.strip_suffix("[]"))
+    {
+        return DataType::Array(Box::new(type_from_name(element)));
+    }
+
+    let base = name.split('(').next().unwrap_or_default().trim();
+    match base {
+        "" => DataType::Custom("any".to_string()),
+        "bool" | "boolean" => DataType::Boolean,
+        "date" => DataType::Date,
+        "datetime" | "smalldatetime" => DataType::Timestamp,
+        "json" | "jsonb" => DataType::Json,
+        "interval" => DataType::Custom(type_name.trim().to_string()),
+        b if b.starts_with("timestamp") => DataType::Timestamp,
+        b if b.split_whitespace().any(is_integer_word) => DataType::Integer,
+        b if ["char", "clob", "text", "string", "uuid"]
+            .iter()
+            .any(|t| b.contains(t)) =>
+        {
+            DataType::String
+        }
+        b if ["real", "floa", "doub", "decimal", "numeric", "money"]
+            .iter()
+            .any(|t| b.contains(t)) =>
+        {
+            DataType::Float
+        }
+        _ => DataType::Custom(type_name.trim().to_string()),
+    }
+}
+
+/// `int`, `bigint`, `int8`, `uint32` or `serial`, but not `point` or `interval`
+fn is_integer_word(word: &str) -> bool {
+    let digits = |rest: &str| rest.chars().all(|c| c.is_ascii_digit());
+    let unsigned = word.strip_prefix('u').unwrap_or(word);
+    word == "integer"
+        || unsigned.strip_prefix("int").is_some_and(digits)
+        || ["tiny", "small", "medium", "big", "huge"]
+            .iter()
+            .any(|size| word == format!("{}int", size))
+        || word.ends_with("serial")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantic::mdl::{Aggregation, Metric};
+
+    #[test]
+    fn test_ddl_import_with_merge() {
+        let ddl = "
+CREATE TABLE customers (id INTEGER PRIMARY KEY, name VARCHAR(80) NOT NULL, joined_at TIMESTAMP);
+CREATE TABLE orders (
+    id BIGINT,
+    customer_id INTEGER REFERENCES customers,
+    total NUMERIC(10, 2),
+    paid BOOLEAN,
+    PRIMARY KEY (id)
+);
+CREATE TABLE invoices (id INT PRIMARY KEY, order_ref INT UNIQUE);
+ALTER TABLE invoices ADD CONSTRAINT fk_order FOREIGN KEY (order_ref) REFERENCES orders (id);
+ALTER TABLE orders RENAME COLUMN total TO amount;
+ALTER TABLE customers DROP COLUMN joined_at;
+COMMENT ON COLUMN orders.amount IS 'Order total';
+INSERT INTO customers VALUES (1, 'Ada');
+";
+        let mdl = from_ddl(ddl, SqlDialect::Postgres).unwrap();
+        let names: Vec<&str> = mdl.models.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["customers", "orders", "invoices"]);
+
+        let orders = mdl.get_model("orders").unwrap();
+        assert_eq!(orders.primary_key.as_deref(), Some("id"));
+        let amount = &orders.columns[2];
+        assert_eq!(amount.name, "amount");
+        assert!(matches!(amount.data_type, DataType::Float));
+        assert_eq!(amount.description.as_deref(), Some("Order total"));
+        assert!(matches!(orders.columns[3].data_type, DataType::Boolean));
+        assert!(!orders.columns[0].nullable && orders.columns[0].primary_key);
+        assert_eq!(mdl.get_model("customers").unwrap().columns.len(), 2);
+
+        let relationships: Vec<(&str, &str, &str, &str)> = mdl
+            .relationships
+            .iter()
+            .map(|r| {
+                (
+                    r.name.as_str(),
+                    r.to_model.as_str(),
+                    r.to_column.as_str(),
+                    match r.relationship_type {
+                        RelationshipType::OneToOne => "1:1",
+                        RelationshipType::ManyToOne => "n:1",
+                        _ => "other",
+                    },
+                )
+            })
+            .collect();
+        assert_eq!(
+            relationships,
+            vec![
+                ("orders_customer_id", "customers", "id", "n:1"),
+                ("invoices_order_ref", "orders", "id", "1:1"),
+            ]
+        );
+
+        // Merging keeps hand-written descriptions and metrics
+        let mut existing = from_ddl(
+            "CREATE TABLE orders (id BIGINT PRIMARY KEY, legacy TEXT)",
+            SqlDialect::Ansi,
+        )
+        .unwrap();
+        existing.models[0].description = Some("One row per order".to_string());
+        existing.models[0].columns[0].description = Some("Order number".to_string());
+        existing.metrics.push(Metric {
+            name: "revenue".to_string(),
+            expression: "amount".to_string(),
+            description: None,
+            base_model: Some("orders".to_string()),
+            aggregation: Some(Aggregation::Sum),
+            filters: Vec::new(),
+        });
+        existing.merge(mdl);
+        let orders = existing.get_model("orders").unwrap();
+        assert_eq!(orders.description.as_deref(), Some("One row per order"));
+        assert_eq!(
+            orders.columns[0].description.as_deref(),
+            Some("Order number")
+        );
+        assert!(orders.columns.iter().all(|c| c.name != "legacy"));
+        assert_eq!(existing.models.len(), 3);
+        assert_eq!(existing.metrics.len(), 1);
+        assert_eq!(existing.relationships.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = sqlite::tests::database(
+            &dir.path().join("shop.db"),
+            "CREATE TABLE customers (id INTEGER PRIMARY KEY, name); \
+             CREATE TABLE orders (id INTEGER PRIMARY KEY AUTOINCREMENT, customer_id INTEGER, \
+             FOREIGN KEY (customer_id) REFERENCES customers (id));",
+        );
+        let mdl = from_sqlite(&runner).await.unwrap();
+        assert_eq!(mdl.models.len(), 2);
+        assert!(matches!(
+            mdl.models[0].columns[1].data_type,
+            DataType::Custom(_)
+        ));
+        assert_eq!(mdl.relationships[0].name, "orders_customer_id");
+    }
+}
@@ -0,0 +1,101 @@
+//! Reader of the schema table of a SQLite database
+//!
+//! The table is queried through the `sqlite3` shell like metric queries are,
+//! so the file is only ever parsed by SQLite itself. Corrupt files and files
+//! that aren't databases come back as the shell's error.
+
+use serde_json::Value;
+
+use crate::error::WinxResult;
+use crate::semantic::execute::SqliteRunner;
+
+const SCHEMA_QUERY: &str = "SELECT type, name, tbl_name, sql FROM sqlite_schema ORDER BY rowid";
+
+/// A row of `sqlite_schema`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaEntry {
+    /// `table`, `index`, `view` or `trigger`
+    pub kind: String,
+    pub name: String,
+    pub table_name: String,
+    pub sql: Option<String>,
+}
+
+/// Entries of the schema table of the database `runner` opens
+pub async fn read_schema(runner: &SqliteRunner) -> WinxResult<Vec<SchemaEntry>> {
+    let rows = runner.query(SCHEMA_QUERY, &[]).await?;
+    Ok(rows
+        .rows
+        .into_iter()
+        .map(|row| {
+            let text = |i: usize| match row.get(i) {
+                Some(Value::String(text)) => Some(text.clone()),
+                _ => None,
+            };
+            SchemaEntry {
+                kind: text(0).unwrap_or_default(),
+                name: text(1).unwrap_or_default(),
+                table_name: text(2).unwrap_or_default(),
+                sql: text(3),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::process::Command;
+    use std::time::Duration;
+
+    /// Runner over a database at `path` created by running `sql`
+    pub(crate) fn database(path: &Path, sql: &str) -> SqliteRunner {
+        let status = Command::new("sqlite3").arg(path).arg(sql).status().unwrap();
+        assert!(status.success());
+        SqliteRunner::new("sqlite3", path, Duration::from_secs(10))
+    }
+
+    #[tokio::test]
+    async fn test_read_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let sql = "CREATE TABLE customers (id INTEGER PRIMARY KEY, name TEXT)";
+        let runner = database(&dir.path().join("shop.db"), sql);
+        assert_eq!(
+            read_schema(&runner).await.unwrap(),
+            vec![SchemaEntry {
+                kind: "table".to_string(),
+                name: "customers".to_string(),
+                table_name: "customers".to_string(),
+                sql: Some(sql.to_string()),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_schema_rejects_files_that_are_not_databases() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.db");
+        std::fs::write(&path, b"not a database").unwrap();
+        let runner = SqliteRunner::new("sqlite3", &path, Duration::from_secs(10));
+        assert!(read_schema(&runner).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_schema_rejects_corrupt_databases() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shop.db");
+        database(
+            &path,
+            "PRAGMA page_size = 512; CREATE TABLE customers (id INTEGER PRIMARY KEY, name TEXT);",
+        );
+        // Point the schema root page at itself as its own rightmost child
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[100] = 0x05;
+        bytes[108..112].copy_from_slice(&1u32.to_be_bytes());
+        std::fs::write(&path, bytes).unwrap();
+
+        let runner = SqliteRunner::new("sqlite3", &path, Duration::from_secs(10));
+        assert!(read_schema(&runner).await.is_err());
+    }
+}
@@ -0,0 +1,570 @@
+//! Join planning over the MDL's relationships
+//!
+//! Relationships are edges of a graph between models, walkable both ways. A
+//! step is weighed by what it does to the rows joined so far: to-one steps keep
+//! them, one-to-many steps repeat them and many-to-many steps are taken only
+//! when nothing else connects the models. Paths compare on those counts first
+//! and hops last, and paths that tie are kept so the choice can be explained.
+
+use serde::Serialize;
+use sqlparser::ast::{
+    BinaryOperator, Expr, Join, JoinConstraint, JoinOperator, ObjectName, TableFactor,
+    TableWithJoins,
+};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+
+use super::mdl::{Relationship, RelationshipType};
+use super::sql::ident;
+use super::SemanticContext;
+
+/// Equally cheap paths kept to explain a choice, the chosen one included
+const MAX_TIED_PATHS: usize = 4;
+
+/// What joining one more model does to the rows joined before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fanout {
+    /// At most one match per row
+    None,
+    /// Rows repeat once per match
+    OneToMany,
+    /// Rows repeat once per match, through a relationship without a unique side
+    ManyToMany,
+}
+
+impl Fanout {
+    fn of(relationship_type: &RelationshipType, forward: bool) -> Self {
+        match (relationship_type, forward) {
+            (RelationshipType::OneToOne, _)
+            | (RelationshipType::ManyToOne, true)
+            | (RelationshipType::OneToMany, false) => Self::None,
+            (RelationshipType::ManyToOne, false) | (RelationshipType::OneToMany, true) => {
+                Self::OneToMany
+            }
+            (RelationshipType::ManyToMany, _) => Self::ManyToMany,
+        }
+    }
+}
+
+impl fmt::Display for Fanout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "to-one",
+            Self::OneToMany => "one-to-many",
+            Self::ManyToMany => "many-to-many",
+        })
+    }
+}
+
+/// One relationship hop of a join path
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JoinStep {
+    pub relationship: String,
+    pub from_model: String,
+    pub from_column: String,
+    pub to_model: String,
+    pub to_column: String,
+    pub fanout: Fanout,
+}
+
+impl JoinStep {
+    fn along(relationship: &Relationship, forward: bool) -> Self {
+        let fanout = Fanout::of(&relationship.relationship_type, forward);
+        let (from_model, from_column, to_model, to_column) = if forward {
+            (
+                &relationship.from_model,
+                &relationship.from_column,
+                &relationship.to_model,
+                &relationship.to_column,
+            )
+        } else {
+            (
+                &relationship.to_model,
+                &relationship.to_column,
+                &relationship.from_model,
+                &relationship.from_column,
+            )
+        };
+        Self {
+            relationship: relationship.name.clone(),
+            from_model: from_model.clone(),
+            from_column: from_column.clone(),
+            to_model: to_model.clone(),
+            to_column: to_column.clone(),
+            fanout,
+        }
+    }
+
+    /// `LEFT JOIN` of the step's model, `left` naming the relation it joins from
+    pub fn to_join(&self, left: &str) -> Join {
+        let condition = Expr::BinaryOp {
+            left: Box::new(Expr::CompoundIdentifier(vec![
+                ident(left),
+                ident(&self.from_column),
+            ])),
+            op: BinaryOperator::Eq,
+            right: Box::new(Expr::CompoundIdentifier(vec![
+                ident(&self.to_model),
+                ident(&self.to_column),
+            ])),
+        };
+        // A LEFT JOIN keeps the rows of the models already joined
+        Join {
+            relation: table(&self.to_model),
+            global: false,
+            join_operator: JoinOperator::LeftOuter(JoinConstraint::On(condition)),
+        }
+    }
+}
+
+/// Cost of a path, compared field by field
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct PathCost {
+    pub many_to_many: usize,
+    pub fanouts: usize,
+    pub hops: usize,
+}
+
+impl PathCost {
+    fn then(self, fanout: Fanout) -> Self {
+        Self {
+            many_to_many: self.many_to_many + usize::from(fanout == Fanout::ManyToMany),
+            fanouts: self.fanouts + usize::from(fanout == Fanout::OneToMany),
+            hops: self.hops + 1,
+        }
+    }
+}
+
+/// The cheapest chain of joins between two sets of models
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JoinPath {
+    pub steps: Vec<JoinStep>,
+    pub cost: PathCost,
+    /// Other paths of the same cost, at most a few
+    pub alternatives: Vec<Vec<JoinStep>>,
+    /// Why the path might not be what the caller wants: repeated rows and ties
+    pub notes: Vec<String>,
+}
+
+fn fanout_notes(steps: &[JoinStep]) -> impl Iterator<Item = String> + '_ {
+    steps
+        .iter()
+        .filter(|step| step.fanout != Fanout::None)
+        .map(|step| {
+            format!(
+                "Joining '{}' along '{}' is {}, rows of '{}' repeat once per match",
+                step.to_model, step.relationship, step.fanout, step.from_model
+            )
+        })
+}
+
+fn tie_note(steps: &[JoinStep], alternatives: &[Vec<JoinStep>]) -> Option<String> {
+    if alternatives.is_empty() {
+        return None;
+    }
+    let alternatives: Vec<String> = alternatives.iter().map(|p| describe(p)).collect();
+    Some(format!(
+        "Using {}, {} other path(s) cost the same: {}",
+        describe(steps),
+        alternatives.len(),
+        alternatives.join("; ")
+    ))
+}
+
+/// Joins connecting a set of models, in the order they apply
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JoinPlan {
+    pub root: String,
+    pub steps: Vec<JoinStep>,
+    /// Models no relationship connects to the rest
+    pub unreachable: Vec<String>,
+    pub notes: Vec<String>,
+}
+
+impl JoinPlan {
+    /// FROM clause of the plan: the root, then a `LEFT JOIN` per step
+    pub fn to_from(&self) -> TableWithJoins {
+        TableWithJoins {
+            relation: table(&self.root),
+            joins: self
+                .steps
+                .iter()
+                .map(|step| step.to_join(&step.from_model))
+                .collect(),
+        }
+    }
+}
+
+pub struct JoinPlanner<'a> {
+    context: &'a SemanticContext,
+}
+
+impl<'a> JoinPlanner<'a> {
+    pub fn new(context: &'a SemanticContext) -> Self {
+        Self { context }
+    }
+
+    /// Steps along each relationship, both ways; self-joins are left out
+    fn steps(&self) -> impl Iterator<Item = JoinStep> + '_ {
+        self.context
+            .relationships
+            .iter()
+            .filter(|r| r.from_model != r.to_model)
+            .flat_map(|r| [JoinStep::along(r, true), JoinStep::along(r, false)])
+    }
+
+    /// Cheapest path from any of the `from` models to any of the `to` models
+    pub fn path(&self, from: &[String], to: &[String]) -> Option<JoinPath> {
+        if from.iter().any(|m| to.contains(m)) {
+            return Some(JoinPath {
+                steps: Vec::new(),
+                cost: PathCost::default(),
+                alternatives: Vec::new(),
+                notes: Vec::new(),
+            });
+        }
+
+        let mut costs: HashMap<String, PathCost> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        for model in from {
+            costs.insert(model.clone(), PathCost::default());
+            queue.push(Reverse((PathCost::default(), model.clone())));
+        }
+        while let Some(Reverse((cost, model))) = queue.pop() {
+            if costs.get(&model).is_some_and(|best| *best < cost) {
+                continue;
+            }
+            for step in self.steps().filter(|step| step.from_model == model) {
+                let next = cost.then(step.fanout);
+                if costs.get(&step.to_model).is_none_or(|best| next < *best) {
+                    costs.insert(step.to_model.clone(), next);
+                    queue.push(Reverse((next, step.to_model)));
+                }
+            }
+        }
+
+        let (target, cost) = to
+            .iter()
+            .filter_map(|m| costs.get(m).map(|cost| (m, *cost)))
+            .min_by_key(|(_, cost)| *cost)?;
+        let mut paths = Vec::new();
+        self.tied_paths(&costs, target, &mut Vec::new(), &mut paths);
+        let mut paths = paths.into_iter();
+        let steps = paths.next().unwrap_or_default();
+        let alternatives: Vec<Vec<JoinStep>> = paths.collect();
+        let notes = fanout_notes(&steps)
+            .chain(tie_note(&steps, &alternatives))
+            .collect();
+        Some(JoinPath {
+            steps,
+            cost,
+            alternatives,
+            notes,
+        })
+    }
+
+    /// Walks back from `model` along every step that keeps its cost optimal
+    fn tied_paths(
+        &self,
+        costs: &HashMap<String, PathCost>,
+        model: &str,
+        suffix: &mut Vec<JoinStep>,
+        paths: &mut Vec<Vec<JoinStep>>,
+    ) {
+        let Some(cost) = costs.get(model) else {
+            return;
+        };
+        if *cost == PathCost::default() {
+            paths.push(suffix.iter().rev().cloned().collect());
+            return;
+        }
+        for step in self.steps().filter(|step| step.to_model == model) {
+            if paths.len() >= MAX_TIED_PATHS {
+                return;
+            }
+            let optimal = costs
+                .get(&step.from_model)
+                .is_some_and(|before| before.then(step.fanout) == *cost);
+            if optimal {
+                let from_model = step.from_model.clone();
+                suffix.push(step);
+                self.tied_paths(costs, &from_model, suffix, paths);
+                suffix.pop();
+            }
+        }
+    }
+
+    /// Cheapest path joining `to` onto the `from` models without repeating rows of `to`
+    ///
+    /// The path is searched from `to` outwards, as if it were the root, then
+    /// turned around so it starts at one of the `from` models. Its cost and
+    /// fan-out notes are those of the search, about repeating rows of `to`.
+    pub fn path_into(&self, from: &[String], to: &str) -> Option<JoinPath> {
+        let path = self.path(&[to.to_string()], from)?;
+        let steps = self.reversed(&path.steps);
+        let alternatives: Vec<Vec<JoinStep>> = path
+            .alternatives
+            .iter()
+            .map(|steps| self.reversed(steps))
+            .collect();
+        let notes = fanout_notes(&path.steps)
+            .chain(tie_note(&steps, &alternatives))
+            .collect();
+        Some(JoinPath {
+            steps,
+            cost: path.cost,
+            alternatives,
+            notes,
+        })
+    }
+
+    fn reversed(&self, steps: &[JoinStep]) -> Vec<JoinStep> {
+        steps
+            .iter()
+            .rev()
+            .filter_map(|step| {
+                let relationship = self.context.relationships.iter().find(|r| {
+                    r.name == step.relationship
+                        && (r.from_model == step.from_model || r.to_model == step.from_model)
+                })?;
+                Some(JoinStep::along(
+                    relationship,
+                    relationship.from_model == step.to_model,
+                ))
+            })
+            .collect()
+    }
+
+    /// Path from `model` to the first of `joined` whose join repeats rows of `model`
+    ///
+    /// The path is the cheapest one, so when it fans out every path along the
+    /// relationships does: aggregating `model` in a query that also joins that
+    /// model counts its rows more than once, however the joins are written.
+    pub fn fanout_path(&self, model: &str, joined: &[String]) -> Option<JoinPath> {
+        let from = [model.to_string()];
+        joined
+            .iter()
+            .filter(|other| *other != model)
+            .filter_map(|other| self.path(&from, std::slice::from_ref(other)))
+            .find(|path| path.cost.fanouts + path.cost.many_to_many > 0)
+    }
+
+    /// Joins `models` together starting from the first, each joined model the
+    /// cheapest way from those joined so far
+    pub fn plan(&self, models: &[String]) -> Option<JoinPlan> {
+        let root = models.first()?;
+        let mut plan = JoinPlan {
+            root: root.clone(),
+            steps: Vec::new(),
+            unreachable: Vec::new(),
+            notes: Vec::new(),
+        };
+        let mut joined = vec![root.clone()];
+        for model in &models[1..] {
+            let Some(path) = self.path(&joined, std::slice::from_ref(model)) else {
+                if !plan.unreachable.contains(model) {
+                    plan.unreachable.push(model.clone());
+                }
+                continue;
+            };
+            plan.notes.extend(path.notes);
+            for step in path.steps {
+                joined.push(step.to_model.clone());
+                plan.steps.push(step);
+            }
+        }
+        Some(plan)
+    }
+}
+
+/// Cheapest chain of relationships from any of the `from` models to `to`
+pub fn join_path(context: &SemanticContext, from: &[String], to: &str) -> Option<Vec<JoinStep>> {
+    JoinPlanner::new(context)
+        .path(from, &[to.to_string()])
+        .map(|path| path.steps)
+}
+
+/// A path as `a -r-> b -s-> c`
+pub fn describe(steps: &[JoinStep]) -> String {
+    let Some(first) = steps.first() else {
+        return "no joins".to_string();
+    };
+    let mut out = first.from_model.clone();
+    for step in steps {
+        out.push_str(&format!(" -{}-> {}", step.relationship, step.to_model));
+    }
+    out
+}
+
+/// Why `metric` can't be aggregated next to the model `path` from its base model leads to
+pub fn fanout_error(metric: &str, path: &JoinPath) -> String {
+    let base = path.steps.first().map_or("", |s| s.from_model.as_str());
+    let other = path.steps.last().map_or("", |s| s.to_model.as_str());
+    format!(
+        "Metric '{}' would count rows of '{}' more than once: joining '{}' ({}) repeats them. \
+         Query it without '{}', or in a separate query",
+        metric,
+        base,
+        other,
+        describe(&path.steps),
+        other
+    )
+}
+
+pub(crate) fn table(model: &str) -> TableFactor {
+    TableFactor::Table {
+        name: ObjectName(vec![ident(model)]),
+        alias: None,
+        args: None,
+        with_hints: Vec::new(),
+        version: None,
+        with_ordinality: false,
+        partitions: Vec::new(),
+        json_path: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::semantic::test_support::{relationship, shop};
+
+    /// The shop with products and two routes from orders to their region
+    fn context() -> SemanticContext {
+        use RelationshipType::*;
+        let mut context = shop();
+        context.relationships.extend([
+            relationship(
+                "item_product",
+                ("items", "product_id"),
+                ("products", "id"),
+                ManyToOne,
+            ),
+            relationship(
+                "customer_region",
+                ("customers", "region_id"),
+                ("regions", "id"),
+                ManyToOne,
+            ),
+            relationship(
+                "order_store",
+                ("orders", "store_id"),
+                ("stores", "id"),
+                ManyToOne,
+            ),
+            relationship(
+                "store_region",
+                ("stores", "region_id"),
+                ("regions", "id"),
+                ManyToOne,
+            ),
+            relationship(
+                "customer_products",
+                ("customers", "id"),
+                ("products", "id"),
+                ManyToMany,
+            ),
+        ]);
+        context
+    }
+
+    fn names(steps: &[JoinStep]) -> Vec<&str> {
+        steps.iter().map(|s| s.relationship.as_str()).collect()
+    }
+
+    #[test]
+    fn test_path_avoids_fanout() {
+        let context = context();
+        let planner = JoinPlanner::new(&context);
+        let models = |names: &[&str]| names.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+
+        // Two fan-outs beat one many-to-many hop
+        let path = planner
+            .path(&models(&["customers"]), &models(&["products"]))
+            .unwrap();
+        assert_eq!(
+            names(&path.steps),
+            ["orders_customer", "item_order", "item_product"]
+        );
+        assert_eq!(
+            path.cost,
+            PathCost {
+                many_to_many: 0,
+                fanouts: 2,
+                hops: 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_path_explains_ties() {
+        let context = context();
+        let planner = JoinPlanner::new(&context);
+        let models = |names: &[&str]| names.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+
+        // Regions are as close through customers as through stores
+        let path = planner
+            .path(&models(&["orders"]), &models(&["regions"]))
+            .unwrap();
+        assert_eq!(names(&path.steps), ["orders_customer", "customer_region"]);
+        assert_eq!(
+            names(&path.alternatives[0]),
+            ["order_store", "store_region"]
+        );
+        assert_eq!(
+            path.notes,
+            [
+                "Using orders -orders_customer-> customers -customer_region-> regions, \
+              1 other path(s) cost the same: orders -order_store-> stores -store_region-> regions"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_joins_onto_the_root_and_reports_unreachable_models() {
+        let context = context();
+        let planner = JoinPlanner::new(&context);
+        let models = |names: &[&str]| names.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+
+        // Items are joined onto customers without repeating them
+        let path = planner.path_into(&models(&["customers"]), "items").unwrap();
+        assert_eq!(
+            describe(&path.steps),
+            "customers -orders_customer-> orders -item_order-> items"
+        );
+        assert_eq!(path.cost.fanouts, 0);
+
+        let plan = planner
+            .plan(&models(&["items", "customers", "warehouses"]))
+            .unwrap();
+        assert_eq!(plan.unreachable, ["warehouses"]);
+        assert!(plan.notes.is_empty(), "{:?}", plan.notes);
+        assert_eq!(
+            plan.to_from().to_string(),
+            "items LEFT JOIN orders ON items.order_id = orders.id \
+             LEFT JOIN customers ON orders.customer_id = customers.id"
+        );
+        assert!(join_path(&context, &models(&["orders"]), "warehouses").is_none());
+    }
+
+    #[test]
+    fn test_fanout_path_finds_models_repeating_rows() {
+        let context = context();
+        let planner = JoinPlanner::new(&context);
+        let joined = |names: &[&str]| names.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+
+        let path = planner
+            .fanout_path("orders", &joined(&["orders", "customers", "items"]))
+            .unwrap();
+        assert_eq!(names(&path.steps), ["item_order"]);
+        assert!(fanout_error("revenue", &path)
+            .starts_with("Metric 'revenue' would count rows of 'orders' more than once"));
+
+        assert!(planner
+            .fanout_path("items", &joined(&["orders", "customers", "items"]))
+            .is_none());
+    }
+}
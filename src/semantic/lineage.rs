@@ -0,0 +1,812 @@
+//! Column-level lineage of SQL statements
+//!
+//! Every output column of a query, CTE, subquery, view or `INSERT` target is a
+//! node linked to the columns it is computed from. Plain column references give
+//! `DerivedFrom` edges, any other expression `Transform` edges. Columns used in
+//! join conditions and filters are linked to the query they restrict.
+
+use sqlparser::ast::{
+    Expr, Ident, JoinConstraint, JoinOperator, ObjectName, Query, Select, SelectItem, SetExpr,
+    Statement, TableFactor, TableWithJoins, Visit,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::str::FromStr;
+
+use super::analyzer::{LineageEdge, LineageEdgeType, LineageGraph, LineageNode, LineageNodeType};
+use super::sql::{self, alias_or, column_parts, eq_ignore_case, find_model, ExprRefs, SqlDialect};
+use super::SemanticContext;
+use crate::error::{WinxError, WinxResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineageFormat {
+    Json,
+    Dot,
+    Mermaid,
+}
+
+impl FromStr for LineageFormat {
+    type Err = WinxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "dot" | "graphviz" => Ok(Self::Dot),
+            "mermaid" => Ok(Self::Mermaid),
+            other => Err(WinxError::invalid_argument(format!(
+                "Unsupported lineage format '{}', expected json, dot or mermaid",
+                other
+            ))),
+        }
+    }
+}
+
+impl LineageGraph {
+    pub fn render(&self, format: LineageFormat) -> WinxResult<String> {
+        match format {
+            LineageFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+            LineageFormat::Dot => Ok(self.to_dot()),
+            LineageFormat::Mermaid => Ok(self.to_mermaid()),
+        }
+    }
+
+    /// Source columns of a column node: the table columns it is ultimately computed from
+    pub fn sources(&self, id: &str) -> Vec<&LineageNode> {
+        let mut sources = Vec::new();
+        let mut seen = HashSet::new();
+        let mut stack = vec![id];
+        while let Some(current) = stack.pop() {
+            if !seen.insert(current) {
+                continue;
+            }
+            let inputs: Vec<&str> = self
+                .edges
+                .iter()
+                .filter(|e| {
+                    e.to_id == current
+                        && matches!(
+                            e.edge_type,
+                            LineageEdgeType::DerivedFrom | LineageEdgeType::Transform
+                        )
+                })
+                .map(|e| e.from_id.as_str())
+                .collect();
+            if inputs.is_empty() && current != id {
+                if let Some(node) = self.node(current) {
+                    sources.push(node);
+                }
+            }
+            stack.extend(inputs);
+        }
+        sources.sort_by(|a, b| a.id.cmp(&b.id));
+        sources
+    }
+
+    /// Graphviz DOT, columns are grouped in a cluster per table or transform
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph lineage {\n  rankdir=LR;\n  node [shape=box];\n");
+        for container in self.nodes.iter().filter(|n| n.parent.is_none()) {
+            let _ = writeln!(
+                out,
+                "  subgraph \"cluster_{}\" {{\n    label=\"{}\";\n    style={};",
+                dot_escape(&container.id),
+                dot_escape(&container.name),
+                match container.node_type {
+                    LineageNodeType::Transform => "dashed",
+                    _ => "solid",
+                }
+            );
+            let _ = writeln!(
+                out,
+                "    \"{}\" [label=\"{}\", shape=plaintext];",
+                dot_escape(&container.id),
+                dot_escape(&container.name)
+            );
+            for column in self.children(&container.id) {
+                let label = column.name.rsplit('.').next().unwrap_or(&column.name);
+                let _ = writeln!(
+                    out,
+                    "    \"{}\" [label=\"{}\"];",
+                    dot_escape(&column.id),
+                    dot_escape(label)
+                );
+            }
+            out.push_str("  }\n");
+        }
+        for edge in &self.edges {
+            let style = match edge.edge_type {
+                LineageEdgeType::DerivedFrom => "",
+                LineageEdgeType::Transform => " [label=\"transform\"]",
+                LineageEdgeType::Join => " [label=\"join\", style=dashed]",
+                LineageEdgeType::Filter => " [label=\"filter\", style=dotted]",
+            };
+            let _ = writeln!(
+                out,
+                "  \"{}\" -> \"{}\"{};",
+                dot_escape(&edge.from_id),
+                dot_escape(&edge.to_id),
+                style
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Mermaid flowchart, columns are grouped in a subgraph per table or transform
+    pub fn to_mermaid(&self) -> String {
+        // Mermaid ids can't hold the dots and colons of node ids
+        let ids: HashMap<&str, String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.id.as_str(), format!("n{}", i)))
+            .collect();
+        let mut out = String::from("flowchart LR\n");
+        for container in self.nodes.iter().filter(|n| n.parent.is_none()) {
+            let _ = writeln!(
+                out,
+                "  subgraph {}[\"{}\"]",
+                ids[container.id.as_str()],
+                mermaid_escape(&container.name)
+            );
+            for column in self.children(&container.id) {
+                let label = column.name.rsplit('.').next().unwrap_or(&column.name);
+                let _ = writeln!(
+                    out,
+                    "    {}[\"{}\"]",
+                    ids[column.id.as_str()],
+                    mermaid_escape(label)
+                );
+            }
+            out.push_str("  end\n");
+        }
+        for edge in &self.edges {
+            let (Some(from), Some(to)) =
+                (ids.get(edge.from_id.as_str()), ids.get(edge.to_id.as_str()))
+            else {
+                continue;
+            };
+            let arrow = match edge.edge_type {
+                LineageEdgeType::DerivedFrom => "-->",
+                LineageEdgeType::Transform => "-- transform -->",
+                LineageEdgeType::Join => "-. join .->",
+                LineageEdgeType::Filter => "-. filter .->",
+            };
+            let _ = writeln!(out, "  {} {} {}", from, arrow, to);
+        }
+        out
+    }
+
+    fn children<'a>(&'a self, parent: &'a str) -> impl Iterator<Item = &'a LineageNode> + 'a {
+        self.nodes
+            .iter()
+            .filter(move |n| n.parent.as_deref() == Some(parent))
+    }
+}
+
+fn dot_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn mermaid_escape(value: &str) -> String {
+    value.replace('"', "#quot;")
+}
+
+/// Builds the lineage graph of every statement of `sql`
+pub fn build(
+    sql: &str,
+    dialect: SqlDialect,
+    context: &SemanticContext,
+    catalog: &str,
+    schema: &str,
+) -> WinxResult<LineageGraph> {
+    let statements = sql::parse(sql, dialect)?;
+    let mut builder = LineageBuilder {
+        context,
+        catalog,
+        schema,
+        graph: LineageGraph::new(),
+        scopes: Vec::new(),
+        ctes: Vec::new(),
+        subqueries: 0,
+    };
+    for (index, statement) in statements.iter().enumerate() {
+        builder.statement(statement, index + 1, statements.len() > 1);
+    }
+    Ok(builder.graph)
+}
+
+/// A relation of a FROM clause
+#[derive(Debug, Clone)]
+struct Relation {
+    /// Alias, or the unqualified table name
+    name: String,
+    table: Option<ObjectName>,
+    /// Node holding the relation's columns
+    container: String,
+    label: String,
+    /// Column names, `None` when they are unknown and any column is accepted
+    columns: Option<Vec<String>>,
+}
+
+/// An output column and the columns it is computed from
+struct Output {
+    name: String,
+    sources: Vec<(String, LineageEdgeType)>,
+}
+
+struct LineageBuilder<'a> {
+    context: &'a SemanticContext,
+    catalog: &'a str,
+    schema: &'a str,
+    graph: LineageGraph,
+    scopes: Vec<Vec<Relation>>,
+    /// CTEs of the enclosing queries, innermost last
+    ctes: Vec<HashMap<String, Relation>>,
+    subqueries: usize,
+}
+
+impl LineageBuilder<'_> {
+    fn statement(&mut self, statement: &Statement, index: usize, numbered: bool) {
+        match statement {
+            Statement::Query(query) => {
+                let (id, label) = if numbered {
+                    (format!("query:{}", index), format!("query {}", index))
+                } else {
+                    ("query".to_string(), "query".to_string())
+                };
+                self.container(&id, &label, LineageNodeType::Transform);
+                let outputs = self.query(query, &id);
+                self.materialize(&id, &label, outputs, &[]);
+            }
+            Statement::Insert(insert) => {
+                let target = self.table(&insert.table_name, None);
+                let Some(source) = &insert.source else {
+                    return;
+                };
+                let outputs = self.query(source, &target.container);
+                let names: Vec<String> = if insert.columns.is_empty() {
+                    target.columns.clone().unwrap_or_default()
+                } else {
+                    insert.columns.iter().map(|c| c.value.clone()).collect()
+                };
+                self.materialize(&target.container, &target.label, outputs, &names);
+            }
+            Statement::CreateView {
+                name,
+                columns,
+                query,
+                ..
+            } => {
+                let id = format!("view:{}", name);
+                let label = name.to_string();
+                self.container(&id, &label, LineageNodeType::View);
+                let outputs = self.query(query, &id);
+                let names: Vec<String> = columns.iter().map(|c| c.name.value.clone()).collect();
+                self.materialize(&id, &label, outputs, &names);
+            }
+            Statement::CreateTable(create) => {
+                let Some(query) = &create.query else {
+                    return;
+                };
+                let id = format!("table:{}", create.name);
+                let label = create.name.to_string();
+                self.container(&id, &label, LineageNodeType::Table);
+                let outputs = self.query(query, &id);
+                let names: Vec<String> = create
+                    .columns
+                    .iter()
+                    .map(|c| c.name.value.clone())
+                    .collect();
+                self.materialize(&id, &label, outputs, &names);
+            }
+            // Other statements don't move data between columns
+            _ => {}
+        }
+    }
+
+    fn container(&mut self, id: &str, label: &str, node_type: LineageNodeType) {
+        self.graph.add_node(LineageNode {
+            id: id.to_string(),
+            name: label.to_string(),
+            node_type,
+            parent: None,
+        });
+    }
+
+    fn column_node(&mut self, container: &str, label: &str, column: &str) -> String {
+        let id = format!("{}.{}", container, column);
+        self.graph.add_node(LineageNode {
+            id: id.clone(),
+            name: format!("{}.{}", label, column),
+            node_type: LineageNodeType::Column,
+            parent: Some(container.to_string()),
+        });
+        id
+    }
+
+    fn edge(&mut self, from: &str, to: &str, edge_type: LineageEdgeType) {
+        self.graph.add_edge(LineageEdge {
+            from_id: from.to_string(),
+            to_id: to.to_string(),
+            edge_type,
+        });
+    }
+
+    /// Adds the output columns to `container`, named by `names` when given
+    fn materialize(
+        &mut self,
+        container: &str,
+        label: &str,
+        outputs: Vec<Output>,
+        names: &[String],
+    ) -> Vec<String> {
+        let mut columns = Vec::new();
+        for (i, output) in outputs.into_iter().enumerate() {
+            let name = names.get(i).cloned().unwrap_or(output.name);
+            let id = self.column_node(container, label, &name);
+            for (source, edge_type) in output.sources {
+                self.edge(&source, &id, edge_type);
+            }
+            columns.push(name);
+        }
+        columns
+    }
+
+    fn query(&mut self, query: &Query, container: &str) -> Vec<Output> {
+        self.ctes.push(HashMap::new());
+        if let Some(with) = &query.with {
+            for cte in &with.cte_tables {
+                let name = cte.alias.name.value.clone();
+                let id = format!("cte:{}", name);
+                self.container(&id, &name, LineageNodeType::Transform);
+                let mut relation = Relation {
+                    name: name.clone(),
+                    table: None,
+                    container: id.clone(),
+                    label: name.clone(),
+                    columns: None,
+                };
+                // A recursive CTE sees itself with unknown columns
+                if let Some(frame) = self.ctes.last_mut() {
+                    frame.insert(name.to_lowercase(), relation.clone());
+                }
+                let outputs = self.query(&cte.query, &id);
+                let names: Vec<String> = cte
+                    .alias
+                    .columns
+                    .iter()
+                    .map(|c| c.name.value.clone())
+                    .collect();
+                relation.columns = Some(self.materialize(&id, &name, outputs, &names));
+                if let Some(frame) = self.ctes.last_mut() {
+                    frame.insert(name.to_lowercase(), relation);
+                }
+            }
+        }
+        let outputs = self.set_expr(&query.body, container);
+        self.ctes.pop();
+        outputs
+    }
+
+    fn set_expr(&mut self, body: &SetExpr, container: &str) -> Vec<Output> {
+        match body {
+            SetExpr::Select(select) => self.select(select, container),
+            SetExpr::Query(query) => self.query(query, container),
+            SetExpr::SetOperation { left, right, .. } => {
+                // Columns are matched by position, named after the left side
+                let mut outputs = self.set_expr(left, container);
+                for (output, other) in outputs.iter_mut().zip(self.set_expr(right, container)) {
+                    output.sources.extend(other.sources);
+                }
+                outputs
+            }
+            SetExpr::Values(values) => values
+                .rows
+                .first()
+                .map(|row| {
+                    (1..=row.len())
+                        .map(|i| Output {
+                            name: format!("column{}", i),
+                            sources: Vec::new(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn select(&mut self, select: &Select, container: &str) -> Vec<Output> {
+        self.scopes.push(Vec::new());
+        for from in &select.from {
+            self.table_with_joins(from, container);
+        }
+
+        let mut outputs = Vec::new();
+        for (i, item) in select.projection.iter().enumerate() {
+            match item {
+                SelectItem::UnnamedExpr(expr) => {
+                    let name = match column_parts(expr) {
+                        Some(parts) => parts.last().map(|p| p.value.clone()),
+                        None => None,
+                    };
+                    outputs.push(Output {
+                        name: name.unwrap_or_else(|| format!("_col{}", i + 1)),
+                        sources: self.expr_sources(expr),
+                    });
+                }
+                SelectItem::ExprWithAlias { expr, alias } => outputs.push(Output {
+                    name: alias.value.clone(),
+                    sources: self.expr_sources(expr),
+                }),
+                SelectItem::Wildcard(_) => {
+                    let relations = self.scopes.last().cloned().unwrap_or_default();
+                    for relation in relations {
+                        outputs.extend(self.wildcard(&relation));
+                    }
+                }
+                SelectItem::QualifiedWildcard(name, _) => {
+                    if let Some(relation) = self.find_relation(&name.0) {
+                        outputs.extend(self.wildcard(&relation));
+                    }
+                }
+            }
+        }
+
+        for expr in select
+            .selection
+            .iter()
+            .chain(&select.having)
+            .chain(&select.qualify)
+        {
+            self.link(expr, container, LineageEdgeType::Filter);
+        }
+        self.scopes.pop();
+        outputs
+    }
+
+    fn wildcard(&mut self, relation: &Relation) -> Vec<Output> {
+        relation
+            .columns
+            .iter()
+            .flatten()
+            .map(|column| Output {
+                name: column.clone(),
+                sources: vec![(
+                    self.column_node(&relation.container, &relation.label, column),
+                    LineageEdgeType::DerivedFrom,
+                )],
+            })
+            .collect()
+    }
+
+    fn table_with_joins(&mut self, from: &TableWithJoins, container: &str) {
+        self.table_factor(&from.relation, container);
+        for join in &from.joins {
+            self.table_factor(&join.relation, container);
+            let constraint = match &join.join_operator {
+                JoinOperator::Inner(c)
+                | JoinOperator::LeftOuter(c)
+                | JoinOperator::RightOuter(c)
+                | JoinOperator::FullOuter(c)
+                | JoinOperator::Semi(c)
+                | JoinOperator::LeftSemi(c)
+                | JoinOperator::RightSemi(c)
+                | JoinOperator::Anti(c)
+                | JoinOperator::LeftAnti(c)
+                | JoinOperator::RightAnti(c)
+                | JoinOperator::AsOf { constraint: c, .. } => Some(c),
+                _ => None,
+            };
+            match constraint {
+                Some(JoinConstraint::On(expr)) => self.link(expr, container, LineageEdgeType::Join),
+                Some(JoinConstraint::Using(columns)) => {
+                    let relations = self.scopes.last().cloned().unwrap_or_default();
+                    for column in columns {
+                        for relation in &relations {
+                            if let Some(id) = self.relation_column(relation, &column.value) {
+                                self.edge(&id, container, LineageEdgeType::Join);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn table_factor(&mut self, factor: &TableFactor, container: &str) {
+        let relation = match factor {
+            TableFactor::Table {
+                name, alias, args, ..
+            } if args.is_none() => self.table(name, alias.as_ref().map(|a| &a.name)),
+            TableFactor::Derived {
+                lateral,
+                subquery,
+                alias,
+            } => {
+                self.subqueries += 1;
+                let id = format!("subquery:{}", self.subqueries);
+                let label = alias
+                    .as_ref()
+                    .map(|a| a.name.value.clone())
+                    .unwrap_or_else(|| format!("subquery {}", self.subqueries));
+                self.container(&id, &label, LineageNodeType::Transform);
+                // Only LATERAL subqueries see the relations before them
+                let outer = (!lateral).then(|| std::mem::take(&mut self.scopes));
+                let outputs = self.query(subquery, &id);
+                if let Some(outer) = outer {
+                    self.scopes = outer;
+                }
+                let names: Vec<String> = alias
+                    .iter()
+                    .flat_map(|a| a.columns.iter().map(|c| c.name.value.clone()))
+                    .collect();
+                let columns = self.materialize(&id, &label, outputs, &names);
+                Relation {
+                    name: label.clone(),
+                    table: None,
+                    container: id,
+                    label,
+                    columns: Some(columns),
+                }
+            }
+            TableFactor::NestedJoin {
+                table_with_joins, ..
+            } => {
+                self.table_with_joins(table_with_joins, container);
+                return;
+            }
+            _ => {
+                // Table functions and the like, with unknown columns
+                self.subqueries += 1;
+                let id = format!("function:{}", self.subqueries);
+                let label = match factor {
+                    TableFactor::Table { name, alias, .. } => {
+                        alias_or(alias.as_ref().map(|a| &a.name), name)
+                    }
+                    _ => format!("function {}", self.subqueries),
+                };
+                self.container(&id, &label, LineageNodeType::Transform);
+                Relation {
+                    name: label.clone(),
+                    table: None,
+                    container: id,
+                    label,
+                    columns: None,
+                }
+            }
+        };
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(relation);
+        }
+    }
+
+    /// Relation of a table name: a CTE, a model or an unknown table
+    fn table(&mut self, name: &ObjectName, alias: Option<&Ident>) -> Relation {
+        let relation_name = alias_or(alias, name);
+        if name.0.len() == 1 {
+            let cte = name.0[0].value.to_lowercase();
+            if let Some(relation) = self.ctes.iter().rev().find_map(|frame| frame.get(&cte)) {
+                return Relation {
+                    name: relation_name,
+                    table: Some(name.clone()),
+                    ..relation.clone()
+                };
+            }
+        }
+
+        let (container, label, columns) =
+            match find_model(self.context, self.catalog, self.schema, name) {
+                Some(model) => (
+                    format!("table:{}", model.name),
+                    model.name.clone(),
+                    Some(
+                        model
+                            .columns
+                            .iter()
+                            .map(|c| c.name.clone())
+                            .chain(model.calculated_fields.iter().map(|f| f.name.clone()))
+                            .collect(),
+                    ),
+                ),
+                None => (format!("table:{}", name), name.to_string(), None),
+            };
+        self.container(&container, &label, LineageNodeType::Table);
+        Relation {
+            name: relation_name,
+            table: Some(name.clone()),
+            container,
+            label,
+            columns,
+        }
+    }
+
+    /// Column node of `column` in a relation, `None` when the relation doesn't have it
+    fn relation_column(&mut self, relation: &Relation, column: &str) -> Option<String> {
+        let name = match &relation.columns {
+            Some(columns) => columns.iter().find(|c| eq_ignore_case(c, column))?.clone(),
+            None => column.to_string(),
+        };
+        Some(self.column_node(&relation.container, &relation.label, &name))
+    }
+
+    fn find_relation(&self, qualifier: &[Ident]) -> Option<Relation> {
+        let last = qualifier.last()?;
+        self.scopes.iter().rev().find_map(|scope| {
+            scope
+                .iter()
+                .find(|relation| {
+                    eq_ignore_case(&relation.name, &last.value)
+                        && (qualifier.len() == 1
+                            || relation.table.as_ref().is_some_and(|table| {
+                                table.0.len() >= qualifier.len()
+                                    && table.0[table.0.len() - qualifier.len()..]
+                                        .iter()
+                                        .zip(qualifier)
+                                        .all(|(a, b)| eq_ignore_case(&a.value, &b.value))
+                            }))
+                })
+                .cloned()
+        })
+    }
+
+    /// Column node a possibly qualified column reference resolves to
+    fn resolve(&mut self, parts: &[Ident]) -> Option<String> {
+        let (column, qualifier) = parts.split_last()?;
+        if !qualifier.is_empty() {
+            let relation = self.find_relation(qualifier)?;
+            return self.relation_column(&relation, &column.value);
+        }
+
+        for scope in self.scopes.clone().iter().rev() {
+            let known: Vec<&Relation> = scope
+                .iter()
+                .filter(|r| {
+                    r.columns
+                        .as_ref()
+                        .is_some_and(|c| c.iter().any(|c| eq_ignore_case(c, &column.value)))
+                })
+                .collect();
+            let open: Vec<&Relation> = scope.iter().filter(|r| r.columns.is_none()).collect();
+            // An ambiguous column has no lineage
+            match (known.as_slice(), open.as_slice()) {
+                ([relation], _) | ([], [relation]) => {
+                    return self.relation_column(relation, &column.value)
+                }
+                ([], []) => continue,
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    /// Columns an expression is computed from, including scalar subqueries
+    fn expr_sources(&mut self, expr: &Expr) -> Vec<(String, LineageEdgeType)> {
+        let edge_type = if column_parts(expr).is_some() {
+            LineageEdgeType::DerivedFrom
+        } else {
+            LineageEdgeType::Transform
+        };
+        self.expr_columns(expr)
+            .into_iter()
+            .map(|id| (id, edge_type))
+            .collect()
+    }
+
+    fn expr_columns(&mut self, expr: &Expr) -> Vec<String> {
+        let mut refs = ExprRefs::default();
+        let _ = expr.visit(&mut refs);
+
+        let mut columns = Vec::new();
+        for parts in &refs.columns {
+            if let Some(id) = self.resolve(parts) {
+                if !columns.contains(&id) {
+                    columns.push(id);
+                }
+            }
+        }
+        for subquery in &refs.subqueries {
+            self.subqueries += 1;
+            let id = format!("subquery:{}", self.subqueries);
+            let label = format!("subquery {}", self.subqueries);
+            self.container(&id, &label, LineageNodeType::Transform);
+            let outputs = self.query(subquery, &id);
+            for name in self.materialize(&id, &label, outputs, &[]) {
+                columns.push(format!("{}.{}", id, name));
+            }
+        }
+        columns
+    }
+
+    /// Links the columns of a join condition or filter to the query it restricts
+    fn link(&mut self, expr: &Expr, container: &str, edge_type: LineageEdgeType) {
+        for id in self.expr_columns(expr) {
+            self.edge(&id, container, edge_type);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantic::mdl::DataType;
+    use crate::semantic::test_support::{model, shop};
+
+    /// The shop with a table of revenue per region
+    fn context() -> SemanticContext {
+        let mut context = shop();
+        let revenue = model(
+            "revenue_by_region",
+            "revenue_by_region",
+            &[("region", DataType::String), ("revenue", DataType::Float)],
+        );
+        context.models.insert(revenue.name.clone(), revenue);
+        context
+    }
+
+    fn names(nodes: Vec<&LineageNode>) -> Vec<&str> {
+        nodes.iter().map(|n| n.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_column_lineage_through_ctes_joins_and_insert() {
+        let context = context();
+        let sql = "INSERT INTO revenue_by_region (region, revenue)
+WITH paid AS (SELECT customer_id, amount * 0.9 AS net FROM sales.fct_orders WHERE status = 'paid')
+SELECT c.region, SUM(p.net)
+FROM paid p JOIN customers c ON c.id = p.customer_id
+GROUP BY c.region";
+        let graph = build(sql, SqlDialect::Postgres, &context, "main", "main").unwrap();
+
+        assert_eq!(
+            names(graph.sources("table:revenue_by_region.revenue")),
+            vec!["orders.amount"]
+        );
+        assert_eq!(
+            names(graph.sources("table:revenue_by_region.region")),
+            vec!["customers.region"]
+        );
+        let edge = |from: &str, to: &str| {
+            graph
+                .edges
+                .iter()
+                .find(|e| e.from_id == from && e.to_id == to)
+                .map(|e| e.edge_type)
+        };
+        assert_eq!(
+            edge("table:orders.amount", "cte:paid.net"),
+            Some(LineageEdgeType::Transform)
+        );
+        assert_eq!(
+            edge("cte:paid.customer_id", "table:revenue_by_region"),
+            Some(LineageEdgeType::Join)
+        );
+        assert_eq!(
+            edge("table:orders.status", "cte:paid"),
+            Some(LineageEdgeType::Filter)
+        );
+    }
+
+    #[test]
+    fn test_column_lineage_through_subqueries_and_unions() {
+        let context = context();
+        let sql = "SELECT id, total FROM (SELECT customer_id AS id, amount AS total FROM orders) t
+UNION ALL SELECT id, balance FROM legacy.accounts";
+        let graph = build(sql, SqlDialect::Ansi, &context, "main", "main").unwrap();
+        assert_eq!(
+            names(graph.sources("query.total")),
+            vec!["legacy.accounts.balance", "orders.amount"]
+        );
+
+        let dot = graph.render(LineageFormat::Dot).unwrap();
+        assert!(dot.contains("\"table:orders.amount\" -> \"subquery:1.total\";"));
+        let mermaid = graph.render(LineageFormat::Mermaid).unwrap();
+        assert!(mermaid.starts_with("flowchart LR\n"));
+        assert!(mermaid.contains("[\"orders\"]"));
+        let json: serde_json::Value =
+            serde_json::from_str(&graph.render(LineageFormat::Json).unwrap()).unwrap();
+        assert_eq!(json["edges"][0]["edge_type"], "derived_from");
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use super::analyzer::{ValidationIssue, ValidationSeverity};
+use super::check;
 use crate::error::{WinxError, WinxResult};
 
 /// Modeling Definition Language (MDL) representation
@@ -117,6 +119,16 @@ pub struct ViewColumn {
     pub expression: String,
 }
 
+impl Metric {
+    /// Whether rows counted more than once change the value, as they do sums and counts
+    pub fn counts_repeated_rows(&self) -> bool {
+        !matches!(
+            self.aggregation,
+            Some(Aggregation::Min | Aggregation::Max | Aggregation::DistinctCount)
+        )
+    }
+}
+
 impl WinxMDL {
     pub fn new() -> Self {
         Self {
@@ -130,10 +142,13 @@ impl WinxMDL {
     pub fn load(path: &std::path::Path) -> WinxResult<Self> {
         let content = std::fs::read_to_string(path)?;
         let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        Self::parse(&content, ext)
+    }
 
+    fn parse(content: &str, ext: &str) -> WinxResult<Self> {
         match ext {
-            "json" => Ok(serde_json::from_str(&content)?),
-            "yaml" | "yml" => Ok(serde_yaml::from_str(&content)?),
+            "json" => Ok(serde_json::from_str(content)?),
+            "yaml" | "yml" => Ok(serde_yaml::from_str(content)?),
             _ => Err(WinxError::invalid_argument(format!(
                 "Unsupported MDL format: {}",
                 ext
@@ -141,6 +156,25 @@ impl WinxMDL {
         }
     }
 
+    /// Loads an MDL file along with the issues [`Self::check`] finds
+    ///
+    /// Issues of YAML files are located at the line of their key.
+    pub fn load_checked(path: &std::path::Path) -> WinxResult<(Self, Vec<ValidationIssue>)> {
+        let content = std::fs::read_to_string(path)?;
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let mdl = Self::parse(&content, ext)?;
+        let source = matches!(ext, "yaml" | "yml").then_some(content.as_str());
+        let issues = check::check(&mdl, source);
+        Ok((mdl, issues))
+    }
+
+    /// Loads an MDL file, failing with every error it has
+    pub fn load_validated(path: &std::path::Path) -> WinxResult<Self> {
+        let (mdl, issues) = Self::load_checked(path)?;
+        errors_to_result(&issues, &format!("MDL {}", path.display()))?;
+        Ok(mdl)
+    }
+
     pub fn save(&self, path: &std::path::Path) -> WinxResult<()> {
         let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
 
@@ -159,48 +193,58 @@ impl WinxMDL {
         Ok(())
     }
 
+    /// Fails with every error [`Self::check`] finds
     pub fn validate(&self) -> WinxResult<()> {
-        let mut model_names = std::collections::HashSet::new();
-
-        // Check for duplicate model names
-        for model in &self.models {
-            if !model_names.insert(&model.name) {
-                return Err(WinxError::other(format!(
-                    "Duplicate model name: {}",
-                    model.name
-                )));
-            }
-        }
+        errors_to_result(&self.check(), "MDL")
+    }
+
+    /// Everything wrong with the models, relationships, metrics and views
+    pub fn check(&self) -> Vec<ValidationIssue> {
+        check::check(self, None)
+    }
 
-        // Validate relationships
-        for relationship in &self.relationships {
-            if !model_names.contains(&relationship.from_model) {
-                return Err(WinxError::other(format!(
-                    "Relationship '{}' references non-existent model: {}",
-                    relationship.name, relationship.from_model
-                )));
+    /// Merges an imported MDL into this one
+    ///
+    /// Imported models replace the columns of the models with the same name,
+    /// keeping the descriptions and calculated fields written here. Relationships
+    /// are added unless one already joins the same columns; metrics and views are
+    /// left as they are.
+    pub fn merge(&mut self, imported: WinxMDL) {
+        for mut model in imported.models {
+            let Some(existing) = self.models.iter_mut().find(|m| m.name == model.name) else {
+                self.models.push(model);
+                continue;
+            };
+            for column in &mut model.columns {
+                let described = existing
+                    .columns
+                    .iter()
+                    .find(|c| c.name.eq_ignore_ascii_case(&column.name))
+                    .and_then(|c| c.description.clone());
+                if described.is_some() {
+                    column.description = described;
+                }
             }
-            if !model_names.contains(&relationship.to_model) {
-                return Err(WinxError::other(format!(
-                    "Relationship '{}' references non-existent model: {}",
-                    relationship.name, relationship.to_model
-                )));
+            existing.columns = model.columns;
+            existing.table_reference = model.table_reference;
+            existing.primary_key = model.primary_key;
+            if existing.description.is_none() {
+                existing.description = model.description;
             }
         }
 
-        // Validate metrics
-        for metric in &self.metrics {
-            if let Some(base_model) = &metric.base_model {
-                if !model_names.contains(base_model) {
-                    return Err(WinxError::other(format!(
-                        "Metric '{}' references non-existent model: {}",
-                        metric.name, base_model
-                    )));
-                }
+        for relationship in imported.relationships {
+            let joins_same_columns = |r: &Relationship| {
+                r.from_model == relationship.from_model
+                    && r.from_column
+                        .eq_ignore_ascii_case(&relationship.from_column)
+                    && r.to_model == relationship.to_model
+                    && r.to_column.eq_ignore_ascii_case(&relationship.to_column)
+            };
+            if !self.relationships.iter().any(joins_same_columns) {
+                self.relationships.push(relationship);
             }
         }
-
-        Ok(())
     }
 
     pub fn get_model(&self, name: &str) -> Option<&Model> {
@@ -215,6 +259,23 @@ impl WinxMDL {
     }
 }
 
+fn errors_to_result(issues: &[ValidationIssue], subject: &str) -> WinxResult<()> {
+    let errors: Vec<String> = issues
+        .iter()
+        .filter(|i| i.severity == ValidationSeverity::Error)
+        .map(|i| format!("  {}", i))
+        .collect();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(WinxError::other(format!(
+        "{} has {} error(s):\n{}",
+        subject,
+        errors.len(),
+        errors.join("\n")
+    )))
+}
+
 impl Default for WinxMDL {
     fn default() -> Self {
         Self::new()
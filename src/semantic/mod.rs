@@ -4,10 +4,22 @@ use std::collections::HashMap;
 use crate::error::{WinxError, WinxResult};
 
 pub mod analyzer;
+pub mod check;
+pub mod execute;
+pub mod formats;
+pub mod import;
+pub mod join;
+pub mod lineage;
 pub mod mdl;
+pub mod query;
+pub mod rewrite;
+pub mod sql;
+pub mod validate;
 
 pub use analyzer::SemanticAnalyzer;
+pub use lineage::LineageFormat;
 pub use mdl::{Metric, Model, Relationship, View, WinxMDL};
+pub use sql::{SqlAnalysis, SqlDialect};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SemanticContext {
@@ -107,3 +119,176 @@ impl Default for SemanticContext {
         Self::new()
     }
 }
+
+/// MDL fixtures shared by the semantic layer's tests
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::mdl::{
+        Aggregation, CalculatedField, Column, DataType, FilterExpression, Metric, Model,
+        Relationship, RelationshipType, TableReference, WinxMDL,
+    };
+    use super::SemanticContext;
+
+    /// Model over `sales.<table>`, keyed by its `id` column when it has one
+    pub(crate) fn model(name: &str, table: &str, columns: &[(&str, DataType)]) -> Model {
+        let has_id = columns.iter().any(|(column, _)| *column == "id");
+        Model {
+            name: name.to_string(),
+            columns: columns
+                .iter()
+                .map(|(column, data_type)| Column {
+                    name: column.to_string(),
+                    data_type: data_type.clone(),
+                    description: None,
+                    nullable: true,
+                    primary_key: *column == "id",
+                })
+                .collect(),
+            table_reference: TableReference {
+                catalog: None,
+                schema: Some("sales".to_string()),
+                table: table.to_string(),
+            },
+            primary_key: has_id.then(|| "id".to_string()),
+            calculated_fields: Vec::new(),
+            description: None,
+        }
+    }
+
+    pub(crate) fn relationship(
+        name: &str,
+        (from_model, from_column): (&str, &str),
+        (to_model, to_column): (&str, &str),
+        relationship_type: RelationshipType,
+    ) -> Relationship {
+        Relationship {
+            name: name.to_string(),
+            from_model: from_model.to_string(),
+            from_column: from_column.to_string(),
+            to_model: to_model.to_string(),
+            to_column: to_column.to_string(),
+            relationship_type,
+        }
+    }
+
+    pub(crate) fn metric(
+        name: &str,
+        expression: &str,
+        base: Option<&str>,
+        aggregation: Option<Aggregation>,
+        filters: &[&str],
+    ) -> Metric {
+        Metric {
+            name: name.to_string(),
+            expression: expression.to_string(),
+            description: None,
+            base_model: base.map(str::to_string),
+            aggregation,
+            filters: filters
+                .iter()
+                .map(|f| FilterExpression {
+                    expression: f.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Customers, their orders and the order items, plus unrelated products
+    ///
+    /// Revenue sums the net amount of paid orders and units the item quantities,
+    /// so joining items repeats the rows revenue is computed over.
+    pub(crate) fn shop_mdl() -> WinxMDL {
+        use DataType::*;
+        let mut orders = model(
+            "orders",
+            "fct_orders",
+            &[
+                ("id", Integer),
+                ("customer_id", Integer),
+                ("amount", Float),
+                ("status", String),
+                ("ordered_at", Date),
+            ],
+        );
+        orders.calculated_fields.push(CalculatedField {
+            name: "net_amount".to_string(),
+            expression: "amount * 0.9".to_string(),
+            data_type: Float,
+            description: None,
+        });
+
+        let mut mdl = WinxMDL::new();
+        mdl.models = vec![
+            model(
+                "customers",
+                "dim_customers",
+                &[
+                    ("id", Integer),
+                    ("name", String),
+                    ("region", String),
+                    ("country", String),
+                    ("credit", Float),
+                ],
+            ),
+            orders,
+            model(
+                "items",
+                "fct_items",
+                &[
+                    ("id", Integer),
+                    ("order_id", Integer),
+                    ("quantity", Integer),
+                ],
+            ),
+            model(
+                "products",
+                "dim_products",
+                &[("id", Integer), ("price", Float)],
+            ),
+        ];
+        mdl.relationships = vec![
+            relationship(
+                "orders_customer",
+                ("orders", "customer_id"),
+                ("customers", "id"),
+                RelationshipType::ManyToOne,
+            ),
+            relationship(
+                "item_order",
+                ("items", "order_id"),
+                ("orders", "id"),
+                RelationshipType::ManyToOne,
+            ),
+        ];
+        mdl.metrics = vec![
+            metric(
+                "revenue",
+                "net_amount",
+                Some("orders"),
+                Some(Aggregation::Sum),
+                &["status = 'paid'"],
+            ),
+            metric(
+                "order_count",
+                "id",
+                Some("orders"),
+                Some(Aggregation::Count),
+                &[],
+            ),
+            metric("average_order", "revenue / order_count", None, None, &[]),
+            metric(
+                "units",
+                "quantity",
+                Some("items"),
+                Some(Aggregation::Sum),
+                &[],
+            ),
+        ];
+        mdl
+    }
+
+    /// Context of [`shop_mdl`]
+    pub(crate) fn shop() -> SemanticContext {
+        SemanticContext::from_mdl(&shop_mdl()).unwrap()
+    }
+}
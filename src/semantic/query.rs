@@ -0,0 +1,528 @@
+//! Metric queries compiled through the MDL
+//!
+//! A [`MetricQuery`] names metrics, the dimensions to break them down by and
+//! filters. It is written as semantic SQL over the models, joined along the
+//! declared relationships, validated, and only then rewritten into SQL over the
+//! physical tables.
+
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::{Statement, Visit};
+use sqlparser::parser::Parser;
+
+use super::analyzer::{ValidationIssue, ValidationSeverity};
+use super::join::{fanout_error, JoinPlanner};
+use super::mdl::Model;
+use super::rewrite;
+use super::sql::{self, eq_ignore_case, model_column_name, ExprRefs, SqlDialect};
+use super::validate;
+use super::SemanticContext;
+use crate::error::{WinxError, WinxResult};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetricQuery {
+    pub metrics: Vec<String>,
+    /// Columns to group by, `model.column` or a column name found in one model
+    #[serde(default)]
+    pub dimensions: Vec<String>,
+    /// SQL conditions over the models, all of which must hold
+    #[serde(default)]
+    pub filters: Vec<String>,
+    /// Output columns to sort by, each optionally followed by `ASC` or `DESC`.
+    /// Defaults to the dimensions, so that pages of the result are stable
+    #[serde(default)]
+    pub order_by: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompiledQuery {
+    /// The query over models and metrics, `None` when its parts don't resolve
+    pub semantic_sql: Option<String>,
+    /// The query over the physical tables, `None` when there are errors
+    pub sql: Option<String>,
+    pub columns: Vec<String>,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl CompiledQuery {
+    pub fn has_errors(&self) -> bool {
+        self.issues
+            .iter()
+            .any(|i| i.severity == ValidationSeverity::Error)
+    }
+}
+
+/// A dimension resolved to its model
+struct Dimension<'a> {
+    model: &'a Model,
+    column: String,
+}
+
+/// Compiles `query` into SQL over the physical tables of `context`
+pub fn compile(
+    query: &MetricQuery,
+    dialect: SqlDialect,
+    context: &SemanticContext,
+    catalog: &str,
+    schema: &str,
+) -> WinxResult<CompiledQuery> {
+    if query.metrics.is_empty() && query.dimensions.is_empty() {
+        return Err(WinxError::invalid_argument(
+            "A query needs at least one metric or dimension".to_string(),
+        ));
+    }
+    let mut issues = Vec::new();
+    let mut error = |message: String| {
+        issues.push(ValidationIssue {
+            severity: ValidationSeverity::Error,
+            message,
+            location: None,
+        })
+    };
+
+    let mut metrics = Vec::new();
+    for name in &query.metrics {
+        match context
+            .metrics
+            .iter()
+            .find(|m| eq_ignore_case(&m.name, name))
+        {
+            Some(metric) => metrics.push(metric),
+            None => error(format!("Unknown metric '{}'", name)),
+        }
+    }
+    let metric_models: Vec<&str> = metrics
+        .iter()
+        .filter_map(|m| m.base_model.as_deref())
+        .collect();
+
+    let mut models: Vec<&Model> = context.models.values().collect();
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut dimensions = Vec::new();
+    for name in &query.dimensions {
+        match resolve_dimension(&models, &metric_models, name) {
+            Ok(dimension) => dimensions.push(dimension),
+            Err(message) => error(message),
+        }
+    }
+
+    // Joins start from the metrics' models so dimensions don't repeat their rows
+    let mut needed: Vec<String> = Vec::new();
+    for model in metric_models
+        .iter()
+        .copied()
+        .chain(dimensions.iter().map(|d| d.model.name.as_str()))
+    {
+        if !needed.iter().any(|m| m == model) {
+            needed.push(model.to_string());
+        }
+    }
+    for model in filter_models(&query.filters, dialect, &models, &needed) {
+        if !needed.iter().any(|m| m == model) {
+            needed.push(model.to_string());
+        }
+    }
+    let planner = JoinPlanner::new(context);
+    let plan = planner.plan(&needed);
+    let mut notes = Vec::new();
+    let mut from = String::new();
+    if let Some(plan) = plan {
+        for model in &plan.unreachable {
+            error(format!(
+                "No relationship connects model '{}' to {}",
+                model, plan.root
+            ));
+        }
+        // A model joined on the many side of a metric's base model would inflate it
+        let joined: Vec<String> = std::iter::once(plan.root.clone())
+            .chain(plan.steps.iter().map(|s| s.to_model.clone()))
+            .collect();
+        for metric in metrics.iter().filter(|m| m.counts_repeated_rows()) {
+            if let Some(path) = metric
+                .base_model
+                .as_ref()
+                .and_then(|model| planner.fanout_path(model, &joined))
+            {
+                error(fanout_error(&metric.name, &path));
+            }
+        }
+        from = plan.to_from().to_string();
+        notes = plan.notes;
+    }
+
+    let mut columns: Vec<String> = Vec::new();
+    let mut projection = Vec::new();
+    let mut group_by = Vec::new();
+    for dimension in &dimensions {
+        let expr = format!(
+            "{}.{}",
+            ident(&dimension.model.name),
+            ident(&dimension.column)
+        );
+        let alias = if columns.iter().any(|c| eq_ignore_case(c, &dimension.column)) {
+            format!("{}_{}", dimension.model.name, dimension.column)
+        } else {
+            dimension.column.clone()
+        };
+        projection.push(format!("{} AS {}", expr, ident(&alias)));
+        group_by.push(expr);
+        columns.push(alias);
+    }
+    for metric in &metrics {
+        projection.push(ident(&metric.name));
+        columns.push(metric.name.clone());
+    }
+
+    let mut order_by = Vec::new();
+    if query.order_by.is_empty() {
+        order_by.extend(columns[..dimensions.len()].iter().map(|c| ident(c)));
+    }
+    for item in &query.order_by {
+        let mut words = item.split_whitespace();
+        let column = words.next().unwrap_or_default();
+        let direction = match words.next().map(|d| d.to_ascii_uppercase()).as_deref() {
+            None => "",
+            Some("ASC") => " ASC",
+            Some("DESC") => " DESC",
+            Some(other) => {
+                error(format!(
+                    "Invalid sort direction '{}' for '{}', expected ASC or DESC",
+                    other, column
+                ));
+                continue;
+            }
+        };
+        match columns.iter().find(|c| eq_ignore_case(c, column)) {
+            Some(column) => order_by.push(format!("{}{}", ident(column), direction)),
+            None => error(format!(
+                "Can't order by '{}', it is not one of the query's columns: {}",
+                column,
+                columns.join(", ")
+            )),
+        }
+    }
+
+    if !issues.is_empty() {
+        return Ok(CompiledQuery {
+            semantic_sql: None,
+            sql: None,
+            columns,
+            issues,
+        });
+    }
+
+    let mut semantic_sql = format!("SELECT {}", projection.join(", "));
+    // Without a FROM the rewrite adds the models derived metrics need
+    if !from.is_empty() {
+        semantic_sql.push_str(&format!(" FROM {}", from));
+    }
+    if !query.filters.is_empty() {
+        let filters: Vec<String> = query.filters.iter().map(|f| format!("({})", f)).collect();
+        semantic_sql.push_str(&format!(" WHERE {}", filters.join(" AND ")));
+    }
+    if !metrics.is_empty() && !group_by.is_empty() {
+        semantic_sql.push_str(&format!(" GROUP BY {}", group_by.join(", ")));
+    }
+    if !order_by.is_empty() {
+        semantic_sql.push_str(&format!(" ORDER BY {}", order_by.join(", ")));
+    }
+
+    // Filters are spliced in as text, they must not smuggle in other statements
+    if let Ok(statements) = sql::parse(&semantic_sql, dialect) {
+        if !matches!(statements.as_slice(), [Statement::Query(_)]) {
+            return Err(WinxError::invalid_argument(
+                "Filters must be conditions, not separate statements".to_string(),
+            ));
+        }
+    }
+
+    let mut issues = validate::validate(&semantic_sql, dialect, context, catalog, schema)?;
+    issues.extend(notes.into_iter().map(|message| ValidationIssue {
+        severity: ValidationSeverity::Warning,
+        message,
+        location: None,
+    }));
+    let errors = issues
+        .iter()
+        .any(|i| i.severity == ValidationSeverity::Error);
+    let sql = if errors {
+        None
+    } else {
+        Some(rewrite::to_physical(
+            &semantic_sql,
+            dialect,
+            context,
+            catalog,
+            schema,
+        )?)
+    };
+    Ok(CompiledQuery {
+        semantic_sql: Some(semantic_sql),
+        sql,
+        columns,
+        issues,
+    })
+}
+
+/// Finds the model of a dimension, preferring the models of the queried metrics
+fn resolve_dimension<'a>(
+    models: &[&'a Model],
+    metric_models: &[&str],
+    name: &str,
+) -> Result<Dimension<'a>, String> {
+    if let Some((model, column)) = name.split_once('.') {
+        let model = models
+            .iter()
+            .find(|m| eq_ignore_case(&m.name, model))
+            .ok_or_else(|| {
+                format!(
+                    "Dimension '{}' refers to the unknown model '{}'",
+                    name, model
+                )
+            })?;
+        let column = model_column_name(model, column).ok_or_else(|| {
+            format!(
+                "Dimension '{}': model '{}' has no column '{}'",
+                name, model.name, column
+            )
+        })?;
+        return Ok(Dimension { model, column });
+    }
+
+    let having: Vec<(&Model, String)> = models
+        .iter()
+        .filter_map(|m| model_column_name(m, name).map(|column| (*m, column)))
+        .collect();
+    let of_metrics: Vec<&(&Model, String)> = having
+        .iter()
+        .filter(|(m, _)| metric_models.contains(&m.name.as_str()))
+        .collect();
+    let candidates: Vec<&(&Model, String)> = if of_metrics.is_empty() {
+        having.iter().collect()
+    } else {
+        of_metrics
+    };
+    match candidates.as_slice() {
+        [(model, column)] => Ok(Dimension {
+            model,
+            column: column.clone(),
+        }),
+        [] => Err(format!("No model has a column named '{}'", name)),
+        several => Err(format!(
+            "Dimension '{}' is ambiguous, qualify it with one of: {}",
+            name,
+            several
+                .iter()
+                .map(|(m, _)| m.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Models the filters refer to, by qualifier or by a column of a single model
+///
+/// Unqualified columns of a model already in `needed` stay with it. Filters that
+/// don't parse are left to validation.
+fn filter_models<'a>(
+    filters: &[String],
+    dialect: SqlDialect,
+    models: &[&'a Model],
+    needed: &[String],
+) -> Vec<&'a str> {
+    let parser_dialect = dialect.parser_dialect();
+    let mut refs = ExprRefs::default();
+    for filter in filters {
+        let parsed = Parser::new(parser_dialect.as_ref())
+            .try_with_sql(filter)
+            .and_then(|mut parser| parser.parse_expr());
+        if let Ok(expr) = parsed {
+            let _ = expr.visit(&mut refs);
+        }
+    }
+
+    let mut found = Vec::new();
+    for parts in &refs.columns {
+        let model = match parts.as_slice() {
+            [column] => {
+                let of_needed = needed.iter().any(|name| {
+                    models
+                        .iter()
+                        .any(|m| m.name == *name && model_column_name(m, &column.value).is_some())
+                });
+                let having: Vec<&&Model> = models
+                    .iter()
+                    .filter(|m| model_column_name(m, &column.value).is_some())
+                    .collect();
+                match having.as_slice() {
+                    [model] if !of_needed => Some(model.name.as_str()),
+                    _ => None,
+                }
+            }
+            [.., qualifier, _] => models
+                .iter()
+                .find(|m| eq_ignore_case(&m.name, &qualifier.value))
+                .map(|m| m.name.as_str()),
+            [] => None,
+        };
+        if let Some(model) = model.filter(|m| !found.contains(m)) {
+            found.push(model);
+        }
+    }
+    found
+}
+
+/// `name` as an SQL identifier, quoted unless it's a plain word that isn't reserved
+fn ident(name: &str) -> String {
+    sql::ident(name).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantic::mdl::Aggregation;
+    use crate::semantic::test_support::{metric, shop, shop_mdl};
+
+    fn compile_query(query: &MetricQuery) -> CompiledQuery {
+        compile(query, SqlDialect::Sqlite, &shop(), "main", "main").unwrap()
+    }
+
+    fn query(metrics: &[&str], dimensions: &[&str], filters: &[&str]) -> MetricQuery {
+        let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect();
+        MetricQuery {
+            metrics: strings(metrics),
+            dimensions: strings(dimensions),
+            filters: strings(filters),
+            order_by: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_compile_metric_query() {
+        let mut query = query(&["revenue"], &["country"], &["orders.amount > 10"]);
+        query.order_by = vec!["revenue desc".to_string()];
+        let compiled = compile_query(&query);
+        assert!(compiled.issues.is_empty(), "{:?}", compiled.issues);
+        assert_eq!(compiled.columns, ["country", "revenue"]);
+        assert_eq!(
+            compiled.semantic_sql.as_deref(),
+            Some(
+                "SELECT customers.country AS country, revenue FROM orders \
+                 LEFT JOIN customers ON orders.customer_id = customers.id \
+                 WHERE (orders.amount > 10) GROUP BY customers.country ORDER BY revenue DESC"
+            )
+        );
+        let sql = compiled.sql.unwrap();
+        assert!(
+            sql.contains(
+                "SUM(CASE WHEN orders.status = 'paid' THEN (orders.amount * 0.9) END) AS revenue"
+            ),
+            "{}",
+            sql
+        );
+        assert!(sql.contains("FROM sales.fct_orders AS orders"), "{}", sql);
+    }
+
+    #[test]
+    fn test_compile_reports_unresolved_parts() {
+        let compiled = compile_query(&query(
+            &["profit"],
+            &["id", "orders.colour"],
+            &["orders.state = 'paid'"],
+        ));
+        let messages: Vec<&str> = compiled.issues.iter().map(|i| i.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Unknown metric 'profit'",
+                "Dimension 'id' is ambiguous, qualify it with one of: customers, items, orders, products",
+                "Dimension 'orders.colour': model 'orders' has no column 'colour'",
+            ]
+        );
+        assert!(compiled.sql.is_none());
+
+        // Filters are checked with the rest of the query
+        let compiled = compile_query(&query(&["revenue"], &[], &["orders.state = 'paid'"]));
+        assert!(compiled.has_errors());
+        assert!(compiled.sql.is_none());
+    }
+
+    #[test]
+    fn test_compile_quotes_reserved_words() {
+        assert_eq!(ident("order"), "\"order\"");
+        assert_eq!(ident("revenue"), "revenue");
+
+        let mut mdl = shop_mdl();
+        mdl.metrics.push(metric(
+            "order",
+            "id",
+            Some("orders"),
+            Some(Aggregation::Count),
+            &[],
+        ));
+        let context = SemanticContext::from_mdl(&mdl).unwrap();
+        let compiled = compile(
+            &query(&["order"], &["country"], &[]),
+            SqlDialect::Sqlite,
+            &context,
+            "main",
+            "main",
+        )
+        .unwrap();
+        assert!(compiled.issues.is_empty(), "{:?}", compiled.issues);
+        let sql = compiled.sql.unwrap();
+        assert!(sql.contains("AS \"order\""), "{}", sql);
+    }
+
+    #[test]
+    fn test_compile_orders_pages_by_dimensions() {
+        let compiled = compile_query(&query(&["revenue"], &["country"], &[]));
+        let semantic_sql = compiled.semantic_sql.unwrap();
+        assert!(
+            semantic_sql.ends_with("GROUP BY customers.country ORDER BY country"),
+            "{}",
+            semantic_sql
+        );
+    }
+
+    #[test]
+    fn test_compile_joins_models_only_filters_refer_to() {
+        for filter in ["customers.country = 'NL'", "country = 'NL'"] {
+            let compiled = compile_query(&query(&["revenue"], &[], &[filter]));
+            assert!(compiled.issues.is_empty(), "{:?}", compiled.issues);
+            let sql = compiled.sql.unwrap();
+            assert!(
+                sql.contains("JOIN sales.dim_customers AS customers"),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_compile_rejects_metrics_of_several_facts() {
+        // Revenue sums orders, joining items for units repeats each order per item
+        let compiled = compile_query(&query(&["revenue", "units"], &[], &[]));
+        assert!(compiled.sql.is_none());
+        assert!(
+            compiled
+                .issues
+                .iter()
+                .any(|i| i.severity == ValidationSeverity::Error
+                    && i.message
+                        .starts_with("Metric 'revenue' would count rows of 'orders'")),
+            "{:?}",
+            compiled.issues
+        );
+    }
+
+    #[test]
+    fn test_compile_rejects_filters_repeating_metric_rows() {
+        let compiled = compile_query(&query(&["revenue"], &[], &["items.quantity > 1"]));
+        assert!(compiled.has_errors());
+        assert!(compiled.sql.is_none());
+
+        // Repeated items don't matter to a sum over items
+        let compiled = compile_query(&query(&["units"], &["orders.status"], &[]));
+        assert!(compiled.issues.is_empty(), "{:?}", compiled.issues);
+    }
+}
@@ -0,0 +1,1168 @@
+//! Rewriting between the semantic vocabulary and physical SQL
+//!
+//! Semantic SQL queries models by name and uses calculated fields and metrics as
+//! columns. [`to_physical`] expands metrics into their aggregations and filters,
+//! inlines calculated fields, joins the models metrics need along declared
+//! relationships and replaces model names with their tables. [`to_semantic`] goes
+//! the other way for SQL written against the physical tables.
+
+use sqlparser::ast::{
+    visit_expressions_mut, BinaryOperator, Expr, GroupByExpr, Ident, JoinConstraint, JoinOperator,
+    ObjectName, OrderBy, Query, Select, SelectItem, SetExpr, Statement, TableAlias, TableFactor,
+    TableWithJoins, Value, Visit, VisitMut, VisitorMut,
+};
+use sqlparser::parser::Parser;
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
+
+use super::join::{fanout_error, table, JoinPlanner};
+use super::mdl::{Aggregation, CalculatedField, Metric, Model};
+use super::sql::{self, alias_or, column_parts, eq_ignore_case, find_model, ExprRefs, SqlDialect};
+use super::SemanticContext;
+use crate::error::{WinxError, WinxResult};
+
+/// Nesting limit of metrics and calculated fields referring to each other
+const MAX_EXPANSION_DEPTH: usize = 16;
+
+/// Expands metric references, leaving models and calculated fields as they are
+pub fn expand_metrics(
+    sql: &str,
+    dialect: SqlDialect,
+    context: &SemanticContext,
+    catalog: &str,
+    schema: &str,
+) -> WinxResult<String> {
+    rewrite(sql, dialect, context, catalog, schema, Mode::Metrics)
+}
+
+/// Rewrites semantic SQL into SQL over the physical tables
+pub fn to_physical(
+    sql: &str,
+    dialect: SqlDialect,
+    context: &SemanticContext,
+    catalog: &str,
+    schema: &str,
+) -> WinxResult<String> {
+    rewrite(sql, dialect, context, catalog, schema, Mode::Physical)
+}
+
+/// Rewrites SQL over the physical tables into models, calculated fields and metrics
+pub fn to_semantic(
+    sql: &str,
+    dialect: SqlDialect,
+    context: &SemanticContext,
+    catalog: &str,
+    schema: &str,
+) -> WinxResult<String> {
+    rewrite(sql, dialect, context, catalog, schema, Mode::Semantic)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Metrics,
+    Physical,
+    Semantic,
+}
+
+fn rewrite(
+    sql: &str,
+    dialect: SqlDialect,
+    context: &SemanticContext,
+    catalog: &str,
+    schema: &str,
+    mode: Mode,
+) -> WinxResult<String> {
+    let mut statements = sql::parse(sql, dialect)?;
+    let mut rewriter = Rewriter {
+        context,
+        catalog,
+        schema,
+        dialect,
+        mode,
+        scopes: Vec::new(),
+        ctes: Vec::new(),
+    };
+    for statement in &mut statements {
+        rewriter.statement(statement)?;
+    }
+    Ok(statements
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+        .join(";\n"))
+}
+
+#[derive(Debug, Clone)]
+struct Relation {
+    /// Name columns are qualified with, the alias or the table name
+    name: String,
+    model: Option<String>,
+    /// Output columns of a subquery or CTE
+    columns: Vec<String>,
+    /// Physical table name that becomes the model name, for `table.column` qualifiers
+    renamed_from: Option<ObjectName>,
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    relations: Vec<Relation>,
+    /// Filters of unaggregated metrics, added to the WHERE clause
+    filters: Vec<Expr>,
+}
+
+struct Rewriter<'a> {
+    context: &'a SemanticContext,
+    catalog: &'a str,
+    schema: &'a str,
+    dialect: SqlDialect,
+    mode: Mode,
+    scopes: Vec<Scope>,
+    /// Output columns of the CTEs of the enclosing queries, innermost last
+    ctes: Vec<HashMap<String, Vec<String>>>,
+}
+
+/// Rewrites the expressions of one scope, handing subqueries back to the rewriter
+struct ExprRewriter<'r, 'a> {
+    rewriter: &'r mut Rewriter<'a>,
+    depth: usize,
+}
+
+impl VisitorMut for ExprRewriter<'_, '_> {
+    type Break = WinxError;
+
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<WinxError> {
+        if self.depth == 0 {
+            if let Err(e) = self.rewriter.query(query) {
+                return ControlFlow::Break(e);
+            }
+        }
+        self.depth += 1;
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &mut Query) -> ControlFlow<WinxError> {
+        self.depth -= 1;
+        ControlFlow::Continue(())
+    }
+
+    // Physical names collapse top-down so the largest matching expression wins
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<WinxError> {
+        if self.depth == 0 && self.rewriter.mode == Mode::Semantic {
+            if let Err(e) = self.rewriter.collapse(expr) {
+                return ControlFlow::Break(e);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<WinxError> {
+        if self.depth == 0 && self.rewriter.mode != Mode::Semantic {
+            if let Err(e) = self.rewriter.expand(expr, 0) {
+                return ControlFlow::Break(e);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+fn break_to_result(flow: ControlFlow<WinxError>) -> WinxResult<()> {
+    match flow {
+        ControlFlow::Break(e) => Err(e),
+        ControlFlow::Continue(()) => Ok(()),
+    }
+}
+
+impl<'a> Rewriter<'a> {
+    fn statement(&mut self, statement: &mut Statement) -> WinxResult<()> {
+        match statement {
+            Statement::Query(query) => {
+                self.query(query)?;
+            }
+            Statement::Insert(insert) => {
+                if let Some(source) = &mut insert.source {
+                    self.query(source)?;
+                }
+                self.rename_table(&mut insert.table_name, None);
+            }
+            Statement::CreateView { query, .. } => {
+                self.query(query)?;
+            }
+            Statement::CreateTable(create) => {
+                if let Some(query) = &mut create.query {
+                    self.query(query)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Rewrites a query and returns its output column names
+    fn query(&mut self, query: &mut Query) -> WinxResult<Vec<String>> {
+        self.ctes.push(HashMap::new());
+        let result = self.query_in_frame(query);
+        self.ctes.pop();
+        result
+    }
+
+    fn query_in_frame(&mut self, query: &mut Query) -> WinxResult<Vec<String>> {
+        if let Some(with) = &mut query.with {
+            for cte in &mut with.cte_tables {
+                let name = cte.alias.name.value.to_lowercase();
+                if let Some(frame) = self.ctes.last_mut() {
+                    frame.insert(name.clone(), Vec::new());
+                }
+                let mut columns = self.query(&mut cte.query)?;
+                if !cte.alias.columns.is_empty() {
+                    columns = cte
+                        .alias
+                        .columns
+                        .iter()
+                        .map(|c| c.name.value.clone())
+                        .collect();
+                }
+                if let Some(frame) = self.ctes.last_mut() {
+                    frame.insert(name, columns);
+                }
+            }
+        }
+        self.set_expr(&mut query.body, query.order_by.as_mut())
+    }
+
+    fn set_expr(
+        &mut self,
+        body: &mut SetExpr,
+        order_by: Option<&mut OrderBy>,
+    ) -> WinxResult<Vec<String>> {
+        match body {
+            SetExpr::Select(select) => {
+                self.scopes.push(Scope::default());
+                let result = self.select(select, order_by);
+                self.scopes.pop();
+                result
+            }
+            SetExpr::Query(query) => self.query(query),
+            SetExpr::SetOperation { left, right, .. } => {
+                let columns = self.set_expr(left, None)?;
+                self.set_expr(right, None)?;
+                Ok(columns)
+            }
+            SetExpr::Insert(statement) | SetExpr::Update(statement) => {
+                self.statement(statement)?;
+                Ok(Vec::new())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn select(
+        &mut self,
+        select: &mut Select,
+        order_by: Option<&mut OrderBy>,
+    ) -> WinxResult<Vec<String>> {
+        for from in &mut select.from {
+            self.table_with_joins(from)?;
+        }
+        if self.mode != Mode::Semantic {
+            self.join_metric_models(select, order_by.as_deref())?;
+        }
+
+        let mut outputs = Vec::new();
+        for item in &mut select.projection {
+            match item {
+                SelectItem::UnnamedExpr(expr) => {
+                    let name = column_parts(expr).and_then(|p| p.last()).cloned();
+                    self.expr(expr)?;
+                    match name {
+                        // Keep the output name of a column that became an expression
+                        Some(name) if column_parts(expr).is_none() => {
+                            outputs.push(name.value.clone());
+                            *item = SelectItem::ExprWithAlias {
+                                expr: expr.clone(),
+                                alias: sql::ident(&name.value),
+                            };
+                        }
+                        _ => {
+                            if let Some(parts) = column_parts(expr) {
+                                outputs.extend(parts.last().map(|p| p.value.clone()));
+                            }
+                        }
+                    }
+                }
+                SelectItem::ExprWithAlias { expr, alias } => {
+                    self.expr(expr)?;
+                    outputs.push(alias.value.clone());
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(selection) = &mut select.selection {
+            self.expr(selection)?;
+        }
+        if let GroupByExpr::Expressions(exprs, _) = &mut select.group_by {
+            for expr in exprs {
+                self.expr(expr)?;
+            }
+        }
+        for expr in select.having.iter_mut().chain(select.qualify.iter_mut()) {
+            self.expr(expr)?;
+        }
+        if let Some(order_by) = order_by {
+            for order in &mut order_by.exprs {
+                // Output names are already rewritten in the projection
+                let output = matches!(&order.expr, Expr::Identifier(i)
+                    if outputs.iter().any(|o| eq_ignore_case(o, &i.value)));
+                if !output || self.mode == Mode::Semantic {
+                    self.expr(&mut order.expr)?;
+                }
+            }
+        }
+
+        let filters = self
+            .scopes
+            .last_mut()
+            .map(|s| std::mem::take(&mut s.filters))
+            .unwrap_or_default();
+        for filter in filters {
+            select.selection = Some(match select.selection.take() {
+                Some(selection) => Expr::BinaryOp {
+                    left: Box::new(selection),
+                    op: BinaryOperator::And,
+                    right: Box::new(filter),
+                },
+                None => filter,
+            });
+        }
+
+        for from in &mut select.from {
+            self.rename_tables(from);
+        }
+        Ok(outputs)
+    }
+
+    fn expr(&mut self, expr: &mut Expr) -> WinxResult<()> {
+        break_to_result(expr.visit(&mut ExprRewriter {
+            rewriter: self,
+            depth: 0,
+        }))
+    }
+
+    fn table_with_joins(&mut self, from: &mut TableWithJoins) -> WinxResult<()> {
+        self.table_factor(&mut from.relation)?;
+        for join in &mut from.joins {
+            self.table_factor(&mut join.relation)?;
+            if let Some(JoinConstraint::On(expr)) = join_constraint_mut(&mut join.join_operator) {
+                self.expr(expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn table_factor(&mut self, factor: &mut TableFactor) -> WinxResult<()> {
+        let relation = match factor {
+            TableFactor::Table {
+                name,
+                alias,
+                args: None,
+                ..
+            } => {
+                let relation_name = alias_or(alias.as_ref().map(|a| &a.name), name);
+                if let Some(columns) = self.cte_columns(name) {
+                    Relation {
+                        name: relation_name,
+                        model: None,
+                        columns,
+                        renamed_from: None,
+                    }
+                } else {
+                    match find_model(self.context, self.catalog, self.schema, name) {
+                        Some(model) => {
+                            let renamed = self.mode == Mode::Semantic
+                                && alias.is_none()
+                                && !is_model_name(name, model);
+                            Relation {
+                                name: if renamed {
+                                    model.name.clone()
+                                } else {
+                                    relation_name
+                                },
+                                model: Some(model.name.clone()),
+                                columns: Vec::new(),
+                                renamed_from: renamed.then(|| name.clone()),
+                            }
+                        }
+                        None => Relation {
+                            name: relation_name,
+                            model: None,
+                            columns: Vec::new(),
+                            renamed_from: None,
+                        },
+                    }
+                }
+            }
+            TableFactor::Derived {
+                lateral,
+                subquery,
+                alias,
+            } => {
+                // Only LATERAL subqueries see the relations before them
+                let outer = (!*lateral).then(|| std::mem::take(&mut self.scopes));
+                let result = self.query(subquery);
+                if let Some(outer) = outer {
+                    self.scopes = outer;
+                }
+                let mut columns = result?;
+                if let Some(alias) = alias {
+                    if !alias.columns.is_empty() {
+                        columns = alias.columns.iter().map(|c| c.name.value.clone()).collect();
+                    }
+                }
+                Relation {
+                    name: alias
+                        .as_ref()
+                        .map(|a| a.name.value.clone())
+                        .unwrap_or_default(),
+                    model: None,
+                    columns,
+                    renamed_from: None,
+                }
+            }
+            TableFactor::NestedJoin {
+                table_with_joins, ..
+            } => return self.table_with_joins(table_with_joins),
+            _ => return Ok(()),
+        };
+        self.push_relation(relation);
+        Ok(())
+    }
+
+    fn push_relation(&mut self, relation: Relation) {
+        if self.scopes.is_empty() {
+            self.scopes.push(Scope::default());
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.relations.push(relation);
+        }
+    }
+
+    fn cte_columns(&self, name: &ObjectName) -> Option<Vec<String>> {
+        let [single] = name.0.as_slice() else {
+            return None;
+        };
+        let key = single.value.to_lowercase();
+        self.ctes
+            .iter()
+            .rev()
+            .find_map(|frame| frame.get(&key))
+            .cloned()
+    }
+
+    /// Replaces model names with physical tables, or the reverse in semantic mode
+    fn rename_tables(&self, from: &mut TableWithJoins) {
+        let factors = std::iter::once(&mut from.relation)
+            .chain(from.joins.iter_mut().map(|j| &mut j.relation));
+        for factor in factors {
+            match factor {
+                TableFactor::Table {
+                    name,
+                    alias,
+                    args: None,
+                    ..
+                } => self.rename_table(name, Some(alias)),
+                TableFactor::NestedJoin {
+                    table_with_joins, ..
+                } => self.rename_tables(table_with_joins),
+                _ => {}
+            }
+        }
+    }
+
+    fn rename_table(&self, name: &mut ObjectName, alias: Option<&mut Option<TableAlias>>) {
+        if self.cte_columns(name).is_some() {
+            return;
+        }
+        let Some(model) = find_model(self.context, self.catalog, self.schema, name) else {
+            return;
+        };
+        match self.mode {
+            Mode::Physical if is_model_name(name, model) => {
+                let reference = &model.table_reference;
+                let physical = ObjectName(
+                    [&reference.catalog, &reference.schema]
+                        .into_iter()
+                        .flatten()
+                        .chain(std::iter::once(&reference.table))
+                        .map(Ident::new)
+                        .collect(),
+                );
+                if physical == *name {
+                    return;
+                }
+                // Qualified columns keep referring to the model name
+                if let Some(alias @ None) = alias {
+                    *alias = Some(TableAlias {
+                        name: sql::ident(&model.name),
+                        columns: Vec::new(),
+                    });
+                }
+                *name = physical;
+            }
+            Mode::Semantic if !is_model_name(name, model) => {
+                *name = ObjectName(vec![sql::ident(&model.name)]);
+            }
+            _ => {}
+        }
+    }
+
+    /// Joins the base models of the metrics the select uses but doesn't query yet
+    fn join_metric_models(
+        &mut self,
+        select: &mut Select,
+        order_by: Option<&OrderBy>,
+    ) -> WinxResult<()> {
+        let mut refs = ExprRefs::default();
+        for item in &select.projection {
+            if let SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } = item {
+                let _ = expr.visit(&mut refs);
+            }
+        }
+        for expr in select.having.iter().chain(
+            order_by
+                .iter()
+                .flat_map(|o| o.exprs.iter().map(|e| &e.expr)),
+        ) {
+            let _ = expr.visit(&mut refs);
+        }
+
+        let mut pending: Vec<&Metric> = refs
+            .columns
+            .iter()
+            .filter_map(|parts| {
+                let (name, qualifier) = parts.split_last()?;
+                self.metric(qualifier, &name.value)
+                    .map(|(metric, _)| metric)
+            })
+            .collect();
+        let mut seen = HashSet::new();
+        let mut models = Vec::new();
+        // Base models whose rows must not repeat, with the metric aggregating them
+        let mut aggregated = Vec::new();
+        while let Some(metric) = pending.pop() {
+            if !seen.insert(metric.name.clone()) {
+                continue;
+            }
+            if let Some(model) = &metric.base_model {
+                if !models.contains(model) {
+                    models.push(model.clone());
+                }
+                if metric.counts_repeated_rows() {
+                    aggregated.push((model.clone(), metric.name.clone()));
+                }
+            }
+            // Metrics built from other metrics need their models too
+            let expr = self.parse_expr(&metric.expression)?;
+            let mut inner = ExprRefs::default();
+            let _ = expr.visit(&mut inner);
+            let base = metric
+                .base_model
+                .as_ref()
+                .and_then(|m| self.context.models.get(m));
+            for parts in &inner.columns {
+                if let [ident] = parts.as_slice() {
+                    if base.is_some_and(|m| has_field(m, &ident.value)) {
+                        continue;
+                    }
+                    pending.extend(
+                        self.context
+                            .metrics
+                            .iter()
+                            .find(|m| eq_ignore_case(&m.name, &ident.value)),
+                    );
+                }
+            }
+        }
+
+        for model in models {
+            self.add_model(select, &model)?;
+        }
+
+        // A model on the many side of another one, e.g. the items of orders next to
+        // a sum over orders, repeats the rows the metric adds up
+        let present: Vec<String> = self
+            .scopes
+            .last()
+            .map(|s| s.relations.iter().filter_map(|r| r.model.clone()).collect())
+            .unwrap_or_default();
+        let planner = JoinPlanner::new(self.context);
+        for (model, metric) in aggregated {
+            if let Some(path) = planner.fanout_path(&model, &present) {
+                return Err(WinxError::invalid_argument(fanout_error(&metric, &path)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds `model` to the FROM clause, joined along the shortest relationship path
+    fn add_model(&mut self, select: &mut Select, model: &str) -> WinxResult<()> {
+        let present: Vec<String> = self
+            .scopes
+            .last()
+            .map(|s| s.relations.iter().filter_map(|r| r.model.clone()).collect())
+            .unwrap_or_default();
+        if present.iter().any(|m| m == model) {
+            return Ok(());
+        }
+
+        if select.from.is_empty() {
+            select.from.push(TableWithJoins {
+                relation: table(model),
+                joins: Vec::new(),
+            });
+            self.push_relation(model_relation(model));
+            return Ok(());
+        }
+        if present.is_empty() {
+            return Err(WinxError::invalid_argument(format!(
+                "A metric needs model '{}' but the query selects from no model to join it to",
+                model
+            )));
+        }
+
+        // Searched from the metric's model so its rows aren't repeated where avoidable
+        let path = JoinPlanner::new(self.context)
+            .path_into(&present, model)
+            .ok_or_else(|| {
+                WinxError::invalid_argument(format!(
+                    "A metric needs model '{}' but no relationship connects it to {}",
+                    model,
+                    present.join(", ")
+                ))
+            })?;
+        for step in path.steps {
+            let left = self
+                .relation_of_model(&step.from_model)
+                .unwrap_or_else(|| step.from_model.clone());
+            if let Some(from) = select.from.last_mut() {
+                from.joins.push(step.to_join(&left));
+            }
+            self.push_relation(model_relation(&step.to_model));
+        }
+        Ok(())
+    }
+
+    /// Name of the innermost relation querying `model`
+    fn relation_of_model(&self, model: &str) -> Option<String> {
+        self.scopes.iter().rev().find_map(|scope| {
+            scope
+                .relations
+                .iter()
+                .find(|r| r.model.as_deref() == Some(model))
+                .map(|r| r.name.clone())
+        })
+    }
+
+    fn find_relation(&self, qualifier: &[Ident]) -> Option<&Relation> {
+        let last = qualifier.last()?;
+        self.scopes.iter().rev().find_map(|scope| {
+            scope
+                .relations
+                .iter()
+                .find(|r| eq_ignore_case(&r.name, &last.value))
+        })
+    }
+
+    /// Whether an unqualified name is a column of a relation in scope
+    fn is_column(&self, name: &str) -> bool {
+        self.scopes.iter().flat_map(|s| &s.relations).any(|r| {
+            r.columns.iter().any(|c| eq_ignore_case(c, name))
+                || r.model
+                    .as_ref()
+                    .and_then(|m| self.context.models.get(m))
+                    .is_some_and(|m| m.columns.iter().any(|c| eq_ignore_case(&c.name, name)))
+        })
+    }
+
+    /// Calculated field a column reference names, with the relation it belongs to
+    fn calculated_field(
+        &self,
+        qualifier: &[Ident],
+        name: &str,
+    ) -> Option<(String, &'a Model, &'a CalculatedField)> {
+        let field = |relation: &Relation| {
+            let model = self.context.models.get(relation.model.as_ref()?)?;
+            let field = model
+                .calculated_fields
+                .iter()
+                .find(|f| eq_ignore_case(&f.name, name))?;
+            Some((relation.name.clone(), model, field))
+        };
+        if !qualifier.is_empty() {
+            return field(self.find_relation(qualifier)?);
+        }
+        if self.is_column(name) {
+            return None;
+        }
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|s| &s.relations)
+            .find_map(field)
+    }
+
+    /// Metric a column reference names, with the relation of its base model
+    fn metric(&self, qualifier: &[Ident], name: &str) -> Option<(&'a Metric, Option<String>)> {
+        if !qualifier.is_empty() {
+            let relation = self.find_relation(qualifier)?;
+            let model = relation.model.as_ref()?;
+            let metric =
+                self.context.metrics.iter().find(|m| {
+                    eq_ignore_case(&m.name, name) && m.base_model.as_ref() == Some(model)
+                })?;
+            return Some((metric, Some(relation.name.clone())));
+        }
+        if self.is_column(name) || self.calculated_field(&[], name).is_some() {
+            return None;
+        }
+        let metric = self
+            .context
+            .metrics
+            .iter()
+            .find(|m| eq_ignore_case(&m.name, name))?;
+        let relation = metric.base_model.as_ref().map(|model| {
+            self.relation_of_model(model)
+                .unwrap_or_else(|| model.clone())
+        });
+        Some((metric, relation))
+    }
+
+    /// Replaces a metric or calculated field reference with its definition
+    fn expand(&mut self, expr: &mut Expr, depth: usize) -> WinxResult<()> {
+        let Some(parts) = column_parts(expr) else {
+            return Ok(());
+        };
+        let Some((name, qualifier)) = parts.split_last() else {
+            return Ok(());
+        };
+
+        let mut replacement = if let (Mode::Physical, Some((relation, model, field))) =
+            (self.mode, self.calculated_field(qualifier, &name.value))
+        {
+            let parsed = self.parse_expr(&field.expression)?;
+            qualify(parsed, model, &relation)
+        } else if let Some((metric, relation)) = self.metric(qualifier, &name.value) {
+            let parsed = self.parse_expr(&metric_sql(metric))?;
+            let base = metric
+                .base_model
+                .as_ref()
+                .and_then(|m| self.context.models.get(m));
+            let qualified = match (base, &relation) {
+                (Some(model), Some(relation)) => qualify(parsed, model, relation),
+                _ => parsed,
+            };
+            if metric.aggregation.is_none() {
+                for filter in &metric.filters {
+                    let parsed = self.parse_expr(&filter.expression)?;
+                    let filter = match (base, &relation) {
+                        (Some(model), Some(relation)) => qualify(parsed, model, relation),
+                        _ => parsed,
+                    };
+                    if let Some(scope) = self.scopes.last_mut() {
+                        if !scope.filters.contains(&filter) {
+                            scope.filters.push(filter);
+                        }
+                    }
+                }
+            }
+            qualified
+        } else {
+            return Ok(());
+        };
+
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Err(WinxError::invalid_argument(format!(
+                "Expanding '{}' nests too deep, metrics or calculated fields refer to each other",
+                expr
+            )));
+        }
+        // The definition may use other metrics and calculated fields
+        break_to_result(visit_expressions_mut(&mut replacement, |e| {
+            match self.expand(e, depth + 1) {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(err) => ControlFlow::Break(err),
+            }
+        }))?;
+        *expr = parenthesize(replacement);
+        Ok(())
+    }
+
+    /// Replaces an expression matching a metric or calculated field with its name
+    fn collapse(&mut self, expr: &mut Expr) -> WinxResult<()> {
+        if let Expr::CompoundIdentifier(parts) = expr {
+            self.requalify(parts);
+            return Ok(());
+        }
+        if matches!(expr, Expr::Identifier(_) | Expr::Value(_)) {
+            return Ok(());
+        }
+
+        let target = normalize(expr.clone());
+        let relations: Vec<Relation> = self
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|s| s.relations.iter().cloned())
+            .collect();
+        let qualify_fields = relations.len() > 1;
+
+        for relation in &relations {
+            let Some(model) = relation
+                .model
+                .as_ref()
+                .and_then(|m| self.context.models.get(m))
+            else {
+                continue;
+            };
+            for field in &model.calculated_fields {
+                let definition =
+                    self.inline(self.parse_expr(&field.expression)?, Some(model), 0)?;
+                if normalize(definition) == target {
+                    *expr = if qualify_fields {
+                        Expr::CompoundIdentifier(vec![
+                            sql::ident(&relation.name),
+                            sql::ident(&field.name),
+                        ])
+                    } else {
+                        Expr::Identifier(sql::ident(&field.name))
+                    };
+                    return Ok(());
+                }
+            }
+        }
+
+        let models: Vec<&str> = relations
+            .iter()
+            .filter_map(|r| r.model.as_deref())
+            .collect();
+        for metric in &self.context.metrics {
+            if metric
+                .base_model
+                .as_ref()
+                .is_some_and(|m| !models.contains(&m.as_str()))
+            {
+                continue;
+            }
+            let base = metric
+                .base_model
+                .as_ref()
+                .and_then(|m| self.context.models.get(m));
+            let definition = self.inline(self.parse_expr(&metric_sql(metric))?, base, 0)?;
+            if normalize(definition) == target {
+                *expr = Expr::Identifier(sql::ident(&metric.name));
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Points `table.column` qualifiers of renamed tables at the model
+    fn requalify(&self, parts: &mut Vec<Ident>) {
+        let Some((_, qualifier)) = parts.split_last() else {
+            return;
+        };
+        let renamed = self
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|s| &s.relations)
+            .find(|r| {
+                r.renamed_from.as_ref().is_some_and(|table| {
+                    table.0.len() >= qualifier.len()
+                        && table.0[table.0.len() - qualifier.len()..]
+                            .iter()
+                            .zip(qualifier)
+                            .all(|(a, b)| eq_ignore_case(&a.value, &b.value))
+                })
+            });
+        if let Some(relation) = renamed {
+            let column = parts.pop();
+            *parts = std::iter::once(sql::ident(&relation.name))
+                .chain(column)
+                .collect();
+        }
+    }
+
+    /// Unqualified definition with the metrics and calculated fields it uses inlined
+    fn inline(&self, mut expr: Expr, model: Option<&Model>, depth: usize) -> WinxResult<Expr> {
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Err(WinxError::invalid_argument(format!(
+                "Inlining '{}' nests too deep, metrics or calculated fields refer to each other",
+                expr
+            )));
+        }
+        break_to_result(visit_expressions_mut(&mut expr, |e| {
+            let Expr::Identifier(ident) = e else {
+                return ControlFlow::Continue(());
+            };
+            let name = ident.value.clone();
+            let field = model.and_then(|m| {
+                m.calculated_fields
+                    .iter()
+                    .find(|f| eq_ignore_case(&f.name, &name))
+            });
+            let metric = self
+                .context
+                .metrics
+                .iter()
+                .find(|m| eq_ignore_case(&m.name, &name))
+                .filter(|_| !model.is_some_and(|m| has_field(m, &name)));
+            let inlined = match (field, metric) {
+                (Some(field), _) => self
+                    .parse_expr(&field.expression)
+                    .and_then(|parsed| self.inline(parsed, model, depth + 1)),
+                (None, Some(metric)) => {
+                    let base = metric
+                        .base_model
+                        .as_ref()
+                        .and_then(|m| self.context.models.get(m))
+                        .or(model);
+                    self.parse_expr(&metric_sql(metric))
+                        .and_then(|parsed| self.inline(parsed, base, depth + 1))
+                }
+                (None, None) => return ControlFlow::Continue(()),
+            };
+            match inlined {
+                Ok(inlined) => {
+                    *e = parenthesize(inlined);
+                    ControlFlow::Continue(())
+                }
+                Err(err) => ControlFlow::Break(err),
+            }
+        }))?;
+        Ok(expr)
+    }
+
+    fn parse_expr(&self, text: &str) -> WinxResult<Expr> {
+        let dialect = self.dialect.parser_dialect();
+        Parser::new(dialect.as_ref())
+            .try_with_sql(text)
+            .and_then(|mut parser| parser.parse_expr())
+            .map_err(|e| WinxError::parse_error(format!("Invalid expression '{}': {}", text, e)))
+    }
+}
+
+fn join_constraint_mut(operator: &mut JoinOperator) -> Option<&mut JoinConstraint> {
+    match operator {
+        JoinOperator::Inner(c)
+        | JoinOperator::LeftOuter(c)
+        | JoinOperator::RightOuter(c)
+        | JoinOperator::FullOuter(c)
+        | JoinOperator::Semi(c)
+        | JoinOperator::LeftSemi(c)
+        | JoinOperator::RightSemi(c)
+        | JoinOperator::Anti(c)
+        | JoinOperator::LeftAnti(c)
+        | JoinOperator::RightAnti(c)
+        | JoinOperator::AsOf { constraint: c, .. } => Some(c),
+        _ => None,
+    }
+}
+
+fn is_model_name(name: &ObjectName, model: &Model) -> bool {
+    matches!(name.0.as_slice(), [single] if eq_ignore_case(&single.value, &model.name))
+}
+
+fn has_field(model: &Model, name: &str) -> bool {
+    sql::model_column_name(model, name).is_some()
+}
+
+fn model_relation(model: &str) -> Relation {
+    Relation {
+        name: model.to_string(),
+        model: Some(model.to_string()),
+        columns: Vec::new(),
+        renamed_from: None,
+    }
+}
+
+/// SQL of a metric: its expression under the aggregation, restricted by its filters
+pub(crate) fn metric_sql(metric: &Metric) -> String {
+    let Some(aggregation) = &metric.aggregation else {
+        // Unaggregated metrics are complete expressions, their filters go to WHERE
+        return metric.expression.clone();
+    };
+    let value = match metric.filters.as_slice() {
+        [] => metric.expression.clone(),
+        [filter] => format!(
+            "CASE WHEN {} THEN {} END",
+            filter.expression, metric.expression
+        ),
+        filters => format!(
+            "CASE WHEN {} THEN {} END",
+            filters
+                .iter()
+                .map(|f| format!("({})", f.expression))
+                .collect::<Vec<_>>()
+                .join(" AND "),
+            metric.expression
+        ),
+    };
+    match aggregation {
+        Aggregation::Sum => format!("SUM({})", value),
+        Aggregation::Average => format!("AVG({})", value),
+        Aggregation::Count => format!("COUNT({})", value),
+        Aggregation::Min => format!("MIN({})", value),
+        Aggregation::Max => format!("MAX({})", value),
+        Aggregation::DistinctCount => format!("COUNT(DISTINCT {})", value),
+        Aggregation::Custom(function) => format!("{}({})", function, value),
+    }
+}
+
+/// Qualifies the columns and calculated fields of `model` with `relation`
+fn qualify(mut expr: Expr, model: &Model, relation: &str) -> Expr {
+    let _ = visit_expressions_mut(&mut expr, |e| {
+        if let Expr::Identifier(ident) = e {
+            if has_field(model, &ident.value) {
+                *e = Expr::CompoundIdentifier(vec![Ident::new(relation), ident.clone()]);
+            }
+        }
+        ControlFlow::<()>::Continue(())
+    });
+    expr
+}
+
+/// Wraps an inlined definition in parentheses unless it is atomic
+fn parenthesize(expr: Expr) -> Expr {
+    match expr {
+        Expr::Identifier(_)
+        | Expr::CompoundIdentifier(_)
+        | Expr::Function(_)
+        | Expr::Value(_)
+        | Expr::Nested(_)
+        | Expr::Case { .. }
+        | Expr::Cast { .. } => expr,
+        other => Expr::Nested(Box::new(other)),
+    }
+}
+
+/// Expression without qualifiers, parentheses and identifier case, for comparison
+fn normalize(mut expr: Expr) -> Expr {
+    let _ = visit_expressions_mut(&mut expr, |e| {
+        match e {
+            Expr::Nested(inner) => *e = std::mem::replace(inner.as_mut(), Expr::Value(Value::Null)),
+            Expr::Identifier(ident) => *ident = Ident::new(ident.value.to_lowercase()),
+            Expr::CompoundIdentifier(parts) => {
+                let column = parts
+                    .last()
+                    .map(|p| p.value.to_lowercase())
+                    .unwrap_or_default();
+                *e = Expr::Identifier(Ident::new(column));
+            }
+            Expr::Function(function) => {
+                for part in &mut function.name.0 {
+                    *part = Ident::new(part.value.to_lowercase());
+                }
+            }
+            _ => {}
+        }
+        ControlFlow::<()>::Continue(())
+    });
+    match expr {
+        Expr::Nested(inner) => *inner,
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantic::test_support::{metric, shop};
+
+    type Rewrite = fn(&str, SqlDialect, &SemanticContext, &str, &str) -> WinxResult<String>;
+
+    fn rewrite(f: Rewrite, sql: &str) -> String {
+        f(sql, SqlDialect::Postgres, &shop(), "warehouse", "sales").unwrap()
+    }
+
+    #[test]
+    fn test_rewrite_between_semantic_and_physical_sql() {
+        let sql = "SELECT region, revenue FROM customers GROUP BY region ORDER BY revenue DESC";
+        assert_eq!(
+            rewrite(expand_metrics, sql),
+            "SELECT region, SUM(CASE WHEN orders.status = 'paid' THEN orders.net_amount END) AS revenue \
+             FROM customers LEFT JOIN orders ON customers.id = orders.customer_id \
+             GROUP BY region ORDER BY revenue DESC"
+        );
+        let physical = rewrite(to_physical, sql);
+        assert_eq!(
+            physical,
+            "SELECT region, SUM(CASE WHEN orders.status = 'paid' THEN (orders.amount * 0.9) END) AS revenue \
+             FROM sales.dim_customers AS customers \
+             LEFT JOIN sales.fct_orders AS orders ON customers.id = orders.customer_id \
+             GROUP BY region ORDER BY revenue DESC"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_metrics_of_metrics_and_shadowed_metrics() {
+        // Metrics of metrics bring in their base model
+        assert_eq!(
+            rewrite(to_physical, "SELECT average_order"),
+            "SELECT (SUM(CASE WHEN orders.status = 'paid' THEN (orders.amount * 0.9) END) / COUNT(orders.id)) \
+             AS average_order FROM sales.fct_orders AS orders"
+        );
+        // CTE columns shadow metrics of the same name
+        assert_eq!(
+            rewrite(
+                to_physical,
+                "WITH r AS (SELECT order_count FROM orders) SELECT order_count FROM r"
+            ),
+            "WITH r AS (SELECT COUNT(orders.id) AS order_count FROM sales.fct_orders AS orders) \
+             SELECT order_count FROM r"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_physical_sql_to_semantic() {
+        // Physical SQL reads back in the semantic vocabulary
+        assert_eq!(
+            rewrite(
+                to_semantic,
+                "SELECT c.region, SUM(CASE WHEN o.status = 'paid' THEN o.amount * 0.9 END) AS rev, \
+                 COUNT(fct_orders.id) FROM sales.fct_orders JOIN dim_customers c \
+                 ON fct_orders.customer_id = c.id GROUP BY c.region"
+            ),
+            "SELECT c.region, revenue AS rev, order_count FROM orders \
+             JOIN customers AS c ON orders.customer_id = c.id GROUP BY c.region"
+        );
+    }
+
+    #[test]
+    fn test_metrics_whose_rows_a_join_repeats_are_rejected() {
+        let mut context = shop();
+        context.metrics.push(metric(
+            "largest_order",
+            "amount",
+            Some("orders"),
+            Some(Aggregation::Max),
+            &[],
+        ));
+        let physical =
+            |sql: &str| to_physical(sql, SqlDialect::Postgres, &context, "warehouse", "sales");
+
+        // Revenue sums orders, joining their items would add each order once per item
+        let error = physical("SELECT region, revenue, units FROM customers GROUP BY region")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("Metric 'revenue' would count rows of 'orders' more than once"),
+            "{}",
+            error
+        );
+        assert!(physical("SELECT revenue FROM items").is_err());
+
+        // Repeated rows don't change a maximum, nor a sum over the many side
+        assert!(physical("SELECT largest_order, units FROM items").is_ok());
+        assert!(physical("SELECT region, units FROM customers GROUP BY region").is_ok());
+    }
+}
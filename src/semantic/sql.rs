@@ -0,0 +1,1712 @@
+//! SQL parsing and resolution of tables and columns against MDL models
+//!
+//! Queries are parsed with `sqlparser` and walked scope by scope: each SELECT
+//! sees the relations of its FROM clause, the CTEs of enclosing queries and,
+//! for correlated subqueries, the relations of enclosing SELECTs. Tables map to
+//! models through the model name or its `table_reference`, qualified with the
+//! analyzer's default catalog and schema.
+
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::{
+    visit_relations, BinaryOperator, DuplicateTreatment, Expr, FunctionArg, FunctionArgExpr,
+    FunctionArguments, GroupByExpr, Ident, JoinConstraint, JoinOperator, ObjectName, Query, Select,
+    SelectItem, SetExpr, Statement, TableFactor, TableWithJoins, Visit, Visitor,
+};
+use sqlparser::dialect::{Dialect, GenericDialect, PostgreSqlDialect, SQLiteDialect};
+use sqlparser::keywords::{
+    ALL_KEYWORDS, ALL_KEYWORDS_INDEX, RESERVED_FOR_COLUMN_ALIAS, RESERVED_FOR_TABLE_ALIAS,
+};
+use sqlparser::parser::Parser;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::ControlFlow;
+use std::str::FromStr;
+
+use super::analyzer::{SourceLocation, ValidationIssue, ValidationSeverity};
+use super::mdl::{DataType, Model, RelationshipType};
+use super::validate::{literal_compatible, type_class, type_name, TypeClass};
+use super::SemanticContext;
+use crate::error::{WinxError, WinxResult};
+
+/// Identifiers that parse as columns but are SQL builtins
+const BUILTIN_IDENTIFIERS: &[&str] = &[
+    "current_date",
+    "current_time",
+    "current_timestamp",
+    "localtime",
+    "localtimestamp",
+    "current_user",
+    "session_user",
+    "user",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SqlDialect {
+    #[default]
+    Ansi,
+    Postgres,
+    Sqlite,
+}
+
+impl SqlDialect {
+    pub(crate) fn parser_dialect(&self) -> Box<dyn Dialect> {
+        match self {
+            // The generic dialect accepts ANSI SQL plus common extensions
+            Self::Ansi => Box::new(GenericDialect {}),
+            Self::Postgres => Box::new(PostgreSqlDialect {}),
+            Self::Sqlite => Box::new(SQLiteDialect {}),
+        }
+    }
+}
+
+impl FromStr for SqlDialect {
+    type Err = WinxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ansi" | "generic" => Ok(Self::Ansi),
+            "postgres" | "postgresql" => Ok(Self::Postgres),
+            "sqlite" => Ok(Self::Sqlite),
+            other => Err(WinxError::invalid_argument(format!(
+                "Unsupported SQL dialect '{}', expected ansi, postgres or sqlite",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for SqlDialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Ansi => "ansi",
+            Self::Postgres => "postgres",
+            Self::Sqlite => "sqlite",
+        })
+    }
+}
+
+pub fn parse(sql: &str, dialect: SqlDialect) -> WinxResult<Vec<Statement>> {
+    Parser::parse_sql(dialect.parser_dialect().as_ref(), sql)
+        .map_err(|e| WinxError::parse_error(format!("Invalid {} SQL: {}", dialect, e)))
+}
+
+/// A table of the query that maps to a model
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelReference {
+    pub model: String,
+    /// Table name as written in the query
+    pub table: String,
+    pub alias: Option<String>,
+    /// Fully qualified physical table of the model
+    pub physical_table: String,
+    pub location: Option<SourceLocation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColumnReference {
+    pub model: String,
+    pub column: String,
+    pub location: Option<SourceLocation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentifierKind {
+    Table,
+    Column,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UnknownIdentifier {
+    pub kind: IdentifierKind,
+    pub name: String,
+    pub reason: String,
+    pub location: Option<SourceLocation>,
+}
+
+/// Where a join between two models comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinSource {
+    On,
+    Using,
+    Where,
+    /// Both models are queried together without a join condition, the MDL
+    /// relationship says how they join
+    Relationship,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImpliedJoin {
+    pub left_model: String,
+    pub left_column: String,
+    pub right_model: String,
+    pub right_column: String,
+    /// MDL relationship matching the join, `None` when no relationship declares it
+    pub relationship: Option<String>,
+    pub source: JoinSource,
+    pub location: Option<SourceLocation>,
+}
+
+/// A metric used as a column
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricReference {
+    pub metric: String,
+    pub base_model: Option<String>,
+    /// Whether the query selects from the base model where the metric is used
+    pub base_model_queried: bool,
+    pub location: Option<SourceLocation>,
+}
+
+/// Models, columns and joins of a SQL text
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SqlAnalysis {
+    pub dialect: SqlDialect,
+    pub statement_count: usize,
+    pub models: Vec<ModelReference>,
+    pub columns: Vec<ColumnReference>,
+    pub unknown: Vec<UnknownIdentifier>,
+    pub joins: Vec<ImpliedJoin>,
+    pub metrics: Vec<MetricReference>,
+}
+
+impl SqlAnalysis {
+    fn new(dialect: SqlDialect) -> Self {
+        Self {
+            dialect,
+            statement_count: 0,
+            models: Vec::new(),
+            columns: Vec::new(),
+            unknown: Vec::new(),
+            joins: Vec::new(),
+            metrics: Vec::new(),
+        }
+    }
+
+    fn add_column(&mut self, model: &str, column: &str, location: Option<SourceLocation>) {
+        if !self
+            .columns
+            .iter()
+            .any(|c| c.model == model && c.column == column)
+        {
+            self.columns.push(ColumnReference {
+                model: model.to_string(),
+                column: column.to_string(),
+                location,
+            });
+        }
+    }
+
+    fn add_unknown(
+        &mut self,
+        kind: IdentifierKind,
+        name: String,
+        reason: String,
+        location: Option<SourceLocation>,
+    ) {
+        if !self
+            .unknown
+            .iter()
+            .any(|u| u.kind == kind && u.name == name)
+        {
+            self.unknown.push(UnknownIdentifier {
+                kind,
+                name,
+                reason,
+                location,
+            });
+        }
+    }
+
+    fn add_join(&mut self, join: ImpliedJoin) {
+        let same = |j: &ImpliedJoin| {
+            (j.left_model == join.left_model
+                && j.left_column == join.left_column
+                && j.right_model == join.right_model
+                && j.right_column == join.right_column)
+                || (j.left_model == join.right_model
+                    && j.left_column == join.right_column
+                    && j.right_model == join.left_model
+                    && j.right_column == join.left_column)
+        };
+        if !self.joins.iter().any(same) {
+            self.joins.push(join);
+        }
+    }
+
+    fn add_metric(&mut self, reference: MetricReference) {
+        if !self.metrics.contains(&reference) {
+            self.metrics.push(reference);
+        }
+    }
+}
+
+impl fmt::Display for SqlAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Dialect: {}, {} statement(s)",
+            self.dialect, self.statement_count
+        )?;
+
+        writeln!(f, "\nResolved models:")?;
+        if self.models.is_empty() {
+            writeln!(f, "  (none)")?;
+        }
+        for reference in &self.models {
+            let alias = reference
+                .alias
+                .as_ref()
+                .map(|alias| format!(" as {}", alias))
+                .unwrap_or_default();
+            writeln!(
+                f,
+                "  - {}{}: model {} ({})",
+                reference.table, alias, reference.model, reference.physical_table
+            )?;
+        }
+
+        if !self.columns.is_empty() {
+            writeln!(f, "\nResolved columns:")?;
+            for column in &self.columns {
+                writeln!(f, "  - {}.{}", column.model, column.column)?;
+            }
+        }
+
+        if !self.metrics.is_empty() {
+            writeln!(f, "\nMetrics:")?;
+            for reference in &self.metrics {
+                let base = match (&reference.base_model, reference.base_model_queried) {
+                    (Some(model), true) => format!(" (base model {})", model),
+                    (Some(model), false) => format!(" (base model {} is not queried)", model),
+                    (None, _) => String::new(),
+                };
+                writeln!(f, "  - {}{}", reference.metric, base)?;
+            }
+        }
+
+        writeln!(f, "\nUnknown identifiers:")?;
+        if self.unknown.is_empty() {
+            writeln!(f, "  (none)")?;
+        }
+        for unknown in &self.unknown {
+            let kind = match unknown.kind {
+                IdentifierKind::Table => "table",
+                IdentifierKind::Column => "column",
+            };
+            let location = unknown
+                .location
+                .as_ref()
+                .map(|l| format!(" at {}:{}", l.line, l.column))
+                .unwrap_or_default();
+            writeln!(
+                f,
+                "  - {} {}{}: {}",
+                kind, unknown.name, location, unknown.reason
+            )?;
+        }
+
+        writeln!(f, "\nImplied joins:")?;
+        if self.joins.is_empty() {
+            writeln!(f, "  (none)")?;
+        }
+        for join in &self.joins {
+            let source = match join.source {
+                JoinSource::On => "ON clause",
+                JoinSource::Using => "USING clause",
+                JoinSource::Where => "WHERE clause",
+                JoinSource::Relationship => "no join condition, implied by the relationship",
+            };
+            let relationship = match (&join.relationship, join.source) {
+                (_, JoinSource::Relationship) => String::new(),
+                (Some(name), _) => format!(", relationship {}", name),
+                (None, _) => ", no MDL relationship declares it".to_string(),
+            };
+            writeln!(
+                f,
+                "  - {}.{} = {}.{} ({}{})",
+                join.left_model,
+                join.left_column,
+                join.right_model,
+                join.right_column,
+                source,
+                relationship
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses `sql` and resolves it against the models of `context`
+pub fn analyze(
+    sql: &str,
+    dialect: SqlDialect,
+    context: &SemanticContext,
+    catalog: &str,
+    schema: &str,
+) -> WinxResult<SqlAnalysis> {
+    let statements = parse(sql, dialect)?;
+    let mut resolver = Resolver::new(context, catalog, schema, dialect);
+    for statement in &statements {
+        resolver.statement(statement);
+    }
+    resolver.analysis.statement_count = statements.len();
+    Ok(resolver.analysis)
+}
+
+pub(crate) fn location(ident: &Ident) -> Option<SourceLocation> {
+    let start = ident.span.start;
+    (start.line > 0).then_some(SourceLocation {
+        line: start.line as usize,
+        column: start.column as usize,
+    })
+}
+
+pub(crate) fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+/// What a relation in a FROM clause refers to
+#[derive(Debug, Clone)]
+enum RelationSource {
+    Model(String),
+    /// Subquery or CTE, with its output columns when they are known
+    Derived(Option<Vec<String>>),
+    /// Table function, unknown table or anything else whose columns are unknown
+    Opaque,
+}
+
+#[derive(Debug, Clone)]
+struct Relation {
+    /// Alias, or the unqualified table name
+    name: String,
+    /// Table name as written, to accept `schema.table.column`
+    table: Option<ObjectName>,
+    source: RelationSource,
+    location: Option<SourceLocation>,
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    relations: Vec<Relation>,
+    /// Projection aliases, visible from GROUP BY, HAVING and ORDER BY
+    output_aliases: HashSet<String>,
+    /// Model relations linked by a join condition
+    joined: HashSet<(usize, usize)>,
+    /// Joins following a declared relationship
+    joins: Vec<ImpliedJoin>,
+    aggregates: Vec<AggregateCall>,
+}
+
+/// Outcome of looking up a column in the visible relations
+enum Lookup {
+    Model {
+        model: String,
+        column: String,
+    },
+    /// Found in a relation whose columns aren't modelled
+    Unmodelled,
+    /// Select alias or builtin
+    NotAColumn,
+    Unknown(String),
+}
+
+/// Collects the column references of an expression, without descending into subqueries
+#[derive(Default)]
+pub(crate) struct ExprRefs {
+    depth: usize,
+    pub(crate) columns: Vec<Vec<Ident>>,
+    pub(crate) subqueries: Vec<Query>,
+}
+
+impl Visitor for ExprRefs {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        if self.depth == 0 {
+            self.subqueries.push(query.clone());
+        }
+        self.depth += 1;
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<()> {
+        self.depth -= 1;
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+        if self.depth == 0 {
+            match expr {
+                Expr::Identifier(ident) => self.columns.push(vec![ident.clone()]),
+                Expr::CompoundIdentifier(parts) => self.columns.push(parts.clone()),
+                _ => {}
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Aggregate call of a projection, HAVING or ORDER BY
+#[derive(Debug, Clone)]
+struct AggregateCall {
+    /// Uppercase function name
+    function: String,
+    distinct: bool,
+    args: Vec<Expr>,
+}
+
+const AGGREGATE_FUNCTIONS: &[&str] = &["sum", "avg", "count", "min", "max"];
+
+/// Collects the comparisons and aggregate calls of an expression, without descending into subqueries
+#[derive(Default)]
+struct TypedExprs {
+    depth: usize,
+    comparisons: Vec<(Expr, Expr)>,
+    aggregates: Vec<AggregateCall>,
+}
+
+impl Visitor for TypedExprs {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, _query: &Query) -> ControlFlow<()> {
+        self.depth += 1;
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<()> {
+        self.depth -= 1;
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+        if self.depth > 0 {
+            return ControlFlow::Continue(());
+        }
+        match expr {
+            Expr::BinaryOp {
+                left,
+                op:
+                    BinaryOperator::Eq
+                    | BinaryOperator::NotEq
+                    | BinaryOperator::Lt
+                    | BinaryOperator::LtEq
+                    | BinaryOperator::Gt
+                    | BinaryOperator::GtEq,
+                right,
+            } => self.comparisons.push(((**left).clone(), (**right).clone())),
+            Expr::InList { expr, list, .. } => {
+                for item in list {
+                    self.comparisons.push(((**expr).clone(), item.clone()));
+                }
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                self.comparisons.push(((**expr).clone(), (**low).clone()));
+                self.comparisons.push(((**expr).clone(), (**high).clone()));
+            }
+            Expr::Function(function) => {
+                let (Some(name), FunctionArguments::List(list)) =
+                    (function.name.0.last(), &function.args)
+                else {
+                    return ControlFlow::Continue(());
+                };
+                if function.name.0.len() == 1
+                    && AGGREGATE_FUNCTIONS
+                        .iter()
+                        .any(|f| eq_ignore_case(f, &name.value))
+                {
+                    self.aggregates.push(AggregateCall {
+                        function: name.value.to_uppercase(),
+                        distinct: matches!(
+                            list.duplicate_treatment,
+                            Some(DuplicateTreatment::Distinct)
+                        ),
+                        args: list
+                            .args
+                            .iter()
+                            .filter_map(|arg| match arg {
+                                FunctionArg::Unnamed(FunctionArgExpr::Expr(e)) => Some(e.clone()),
+                                _ => None,
+                            })
+                            .collect(),
+                    });
+                }
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+pub(crate) struct Resolver<'a> {
+    context: &'a SemanticContext,
+    catalog: &'a str,
+    schema: &'a str,
+    scopes: Vec<Scope>,
+    /// CTEs of the enclosing queries, innermost last
+    ctes: Vec<HashMap<String, Option<Vec<String>>>>,
+    pub(crate) analysis: SqlAnalysis,
+    /// Type mismatches and fan-out risks
+    pub(crate) issues: Vec<ValidationIssue>,
+}
+
+impl<'a> Resolver<'a> {
+    pub(crate) fn new(
+        context: &'a SemanticContext,
+        catalog: &'a str,
+        schema: &'a str,
+        dialect: SqlDialect,
+    ) -> Self {
+        Self {
+            context,
+            catalog,
+            schema,
+            scopes: Vec::new(),
+            ctes: Vec::new(),
+            analysis: SqlAnalysis::new(dialect),
+            issues: Vec::new(),
+        }
+    }
+
+    pub(crate) fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Query(query) => {
+                self.query(query);
+            }
+            Statement::Insert(insert) => {
+                let relation = self.table(&insert.table_name, None);
+                if let RelationSource::Model(model) = &relation.source {
+                    for column in &insert.columns {
+                        let model = model.clone();
+                        self.model_column(&model, column, &column.value);
+                    }
+                }
+                if let Some(source) = &insert.source {
+                    self.query(source);
+                }
+            }
+            Statement::CreateView { query, .. } => {
+                self.query(query);
+            }
+            other => {
+                // Only the tables of other statements are resolved
+                let mut names = Vec::new();
+                let _ = visit_relations(other, |name| {
+                    names.push(name.clone());
+                    ControlFlow::<()>::Continue(())
+                });
+                for name in names {
+                    self.table(&name, None);
+                }
+            }
+        }
+    }
+
+    /// Resolves a query and returns its output columns when they are known
+    fn query(&mut self, query: &Query) -> Option<Vec<String>> {
+        let mut frame = HashMap::new();
+        if let Some(with) = &query.with {
+            for cte in &with.cte_tables {
+                let name = cte.alias.name.value.to_lowercase();
+                // A recursive CTE refers to itself with unknown columns
+                frame.insert(name.clone(), None);
+                self.ctes.push(frame);
+                let mut columns = self.query(&cte.query);
+                frame = self.ctes.pop().unwrap_or_default();
+                if !cte.alias.columns.is_empty() {
+                    columns = Some(
+                        cte.alias
+                            .columns
+                            .iter()
+                            .map(|c| c.name.value.clone())
+                            .collect(),
+                    );
+                }
+                frame.insert(name, columns);
+            }
+        }
+
+        self.ctes.push(frame);
+        let order_by: Vec<&Expr> = query
+            .order_by
+            .iter()
+            .flat_map(|order_by| order_by.exprs.iter().map(|e| &e.expr))
+            .collect();
+        let columns = self.set_expr(&query.body, &order_by);
+        if let Some(limit) = &query.limit {
+            self.expr(limit, JoinSource::Where, false);
+        }
+        self.ctes.pop();
+        columns
+    }
+
+    fn set_expr(&mut self, body: &SetExpr, order_by: &[&Expr]) -> Option<Vec<String>> {
+        match body {
+            SetExpr::Select(select) => self.select(select, order_by),
+            SetExpr::Query(query) => self.query(query),
+            SetExpr::SetOperation { left, right, .. } => {
+                // ORDER BY of a set operation refers to output columns only
+                let columns = self.set_expr(left, &[]);
+                self.set_expr(right, &[]);
+                columns
+            }
+            SetExpr::Values(values) => {
+                self.scopes.push(Scope::default());
+                for row in &values.rows {
+                    for expr in row {
+                        self.expr(expr, JoinSource::Where, false);
+                    }
+                }
+                self.scopes.pop();
+                None
+            }
+            SetExpr::Insert(statement) | SetExpr::Update(statement) => {
+                self.statement(statement);
+                None
+            }
+            SetExpr::Table(_) => None,
+        }
+    }
+
+    fn select(&mut self, select: &Select, order_by: &[&Expr]) -> Option<Vec<String>> {
+        self.scopes.push(Scope::default());
+
+        for from in &select.from {
+            self.table_with_joins(from);
+        }
+
+        let mut output = Some(Vec::new());
+        for item in &select.projection {
+            match item {
+                SelectItem::UnnamedExpr(expr) => {
+                    self.expr(expr, JoinSource::Where, false);
+                    let name = match expr {
+                        Expr::Identifier(ident) => Some(ident.value.clone()),
+                        Expr::CompoundIdentifier(parts) => parts.last().map(|i| i.value.clone()),
+                        _ => None,
+                    };
+                    match (name, output.as_mut()) {
+                        (Some(name), Some(output)) => output.push(name),
+                        _ => output = None,
+                    }
+                }
+                SelectItem::ExprWithAlias { expr, alias } => {
+                    self.expr(expr, JoinSource::Where, false);
+                    if let Some(output) = output.as_mut() {
+                        output.push(alias.value.clone());
+                    }
+                    self.current_scope()
+                        .output_aliases
+                        .insert(alias.value.to_lowercase());
+                }
+                SelectItem::QualifiedWildcard(name, _) => {
+                    let columns = self.wildcard(Some(name));
+                    match (columns, output.as_mut()) {
+                        (Some(columns), Some(output)) => output.extend(columns),
+                        _ => output = None,
+                    }
+                }
+                SelectItem::Wildcard(_) => {
+                    let columns = self.wildcard(None);
+                    match (columns, output.as_mut()) {
+                        (Some(columns), Some(output)) => output.extend(columns),
+                        _ => output = None,
+                    }
+                }
+            }
+        }
+
+        if let Some(selection) = &select.selection {
+            self.expr(selection, JoinSource::Where, true);
+        }
+        if let GroupByExpr::Expressions(exprs, _) = &select.group_by {
+            for expr in exprs {
+                self.expr(expr, JoinSource::Where, false);
+            }
+        }
+        for expr in select
+            .having
+            .iter()
+            .chain(&select.qualify)
+            .chain(order_by.iter().copied())
+        {
+            self.expr(expr, JoinSource::Where, false);
+        }
+
+        self.relationship_joins();
+        self.fan_out();
+        self.scopes.pop();
+        output
+    }
+
+    fn current_scope(&mut self) -> &mut Scope {
+        if self.scopes.is_empty() {
+            self.scopes.push(Scope::default());
+        }
+        self.scopes.last_mut().expect("scope pushed above")
+    }
+
+    fn table_with_joins(&mut self, from: &TableWithJoins) {
+        self.table_factor(&from.relation);
+        for join in &from.joins {
+            let right = self.current_scope().relations.len();
+            self.table_factor(&join.relation);
+            let constraint = match &join.join_operator {
+                JoinOperator::Inner(c)
+                | JoinOperator::LeftOuter(c)
+                | JoinOperator::RightOuter(c)
+                | JoinOperator::FullOuter(c)
+                | JoinOperator::Semi(c)
+                | JoinOperator::LeftSemi(c)
+                | JoinOperator::RightSemi(c)
+                | JoinOperator::Anti(c)
+                | JoinOperator::LeftAnti(c)
+                | JoinOperator::RightAnti(c) => Some(c),
+                JoinOperator::AsOf {
+                    match_condition,
+                    constraint,
+                } => {
+                    self.expr(match_condition, JoinSource::On, false);
+                    Some(constraint)
+                }
+                JoinOperator::CrossJoin | JoinOperator::CrossApply | JoinOperator::OuterApply => {
+                    None
+                }
+            };
+            match constraint {
+                Some(JoinConstraint::On(expr)) => self.expr(expr, JoinSource::On, true),
+                Some(JoinConstraint::Using(columns)) => self.using(right, columns),
+                _ => {}
+            }
+        }
+    }
+
+    fn table_factor(&mut self, factor: &TableFactor) {
+        let relation = match factor {
+            TableFactor::Table {
+                name, alias, args, ..
+            } => {
+                if args.is_some() {
+                    Relation {
+                        name: alias_or(alias.as_ref().map(|a| &a.name), name),
+                        table: None,
+                        source: RelationSource::Opaque,
+                        location: None,
+                    }
+                } else {
+                    self.table(name, alias.as_ref().map(|a| &a.name))
+                }
+            }
+            TableFactor::Derived {
+                lateral,
+                subquery,
+                alias,
+            } => {
+                // Only LATERAL subqueries see the relations before them
+                let outer = (!lateral).then(|| std::mem::take(&mut self.scopes));
+                let mut columns = self.query(subquery);
+                if let Some(outer) = outer {
+                    self.scopes = outer;
+                }
+                if let Some(alias) = alias {
+                    if !alias.columns.is_empty() {
+                        columns =
+                            Some(alias.columns.iter().map(|c| c.name.value.clone()).collect());
+                    }
+                }
+                Relation {
+                    name: alias
+                        .as_ref()
+                        .map(|a| a.name.value.clone())
+                        .unwrap_or_default(),
+                    table: None,
+                    source: RelationSource::Derived(columns),
+                    location: alias.as_ref().and_then(|a| location(&a.name)),
+                }
+            }
+            TableFactor::NestedJoin {
+                table_with_joins, ..
+            } => {
+                self.table_with_joins(table_with_joins);
+                return;
+            }
+            other => {
+                let alias = match other {
+                    TableFactor::TableFunction { alias, .. }
+                    | TableFactor::Function { alias, .. }
+                    | TableFactor::UNNEST { alias, .. } => alias.as_ref(),
+                    _ => None,
+                };
+                Relation {
+                    name: alias.map(|a| a.name.value.clone()).unwrap_or_default(),
+                    table: None,
+                    source: RelationSource::Opaque,
+                    location: None,
+                }
+            }
+        };
+        self.current_scope().relations.push(relation);
+    }
+
+    /// Resolves a table name to a CTE or a model, recording the model reference
+    fn table(&mut self, name: &ObjectName, alias: Option<&Ident>) -> Relation {
+        let relation_name = alias_or(alias, name);
+        let last = name.0.last();
+        let location = last.and_then(location);
+
+        if name.0.len() == 1 {
+            let cte_name = name.0[0].value.to_lowercase();
+            if let Some(columns) = self
+                .ctes
+                .iter()
+                .rev()
+                .find_map(|frame| frame.get(&cte_name))
+            {
+                return Relation {
+                    name: relation_name,
+                    table: Some(name.clone()),
+                    source: RelationSource::Derived(columns.clone()),
+                    location,
+                };
+            }
+        }
+
+        match find_model(self.context, self.catalog, self.schema, name) {
+            Some(model) => {
+                self.analysis.models.push(ModelReference {
+                    model: model.name.clone(),
+                    table: name.to_string(),
+                    alias: alias.map(|a| a.value.clone()),
+                    physical_table: physical_table(model, self.catalog, self.schema),
+                    location,
+                });
+                Relation {
+                    name: relation_name,
+                    table: Some(name.clone()),
+                    source: RelationSource::Model(model.name.clone()),
+                    location,
+                }
+            }
+            None => {
+                self.analysis.add_unknown(
+                    IdentifierKind::Table,
+                    name.to_string(),
+                    "no model maps to this table".to_string(),
+                    location,
+                );
+                Relation {
+                    name: relation_name,
+                    table: Some(name.clone()),
+                    source: RelationSource::Opaque,
+                    location,
+                }
+            }
+        }
+    }
+
+    /// Output columns of `*` or `name.*`, `None` when a relation's columns are unknown
+    fn wildcard(&mut self, qualifier: Option<&ObjectName>) -> Option<Vec<String>> {
+        let relations: Vec<Relation> = match qualifier {
+            Some(name) => {
+                let parts = &name.0;
+                match self.find_relation(parts) {
+                    Some(relation) => vec![relation],
+                    None => {
+                        self.analysis.add_unknown(
+                            IdentifierKind::Table,
+                            name.to_string(),
+                            "no table or alias with this name in scope".to_string(),
+                            parts.last().and_then(location),
+                        );
+                        return None;
+                    }
+                }
+            }
+            None => self.scopes.last()?.relations.clone(),
+        };
+
+        let mut columns = Vec::new();
+        for relation in relations {
+            match relation.source {
+                RelationSource::Model(model) => {
+                    let model = self.context.models.get(&model)?;
+                    columns.extend(model.columns.iter().map(|c| c.name.clone()));
+                }
+                RelationSource::Derived(Some(derived)) => columns.extend(derived),
+                _ => return None,
+            }
+        }
+        Some(columns)
+    }
+
+    /// Records the columns of `USING (...)` and the join they imply
+    fn using(&mut self, right: usize, columns: &[Ident]) {
+        let relations = self.current_scope().relations.clone();
+        let Some(right_relation) = relations.get(right) else {
+            return;
+        };
+        for column in columns {
+            let right_side = self.model_lookup(right_relation, &column.value);
+            // The column joins the nearest relation on the left that has it
+            let left_side = relations[..right]
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(|(i, r)| self.model_lookup(r, &column.value).map(|m| (i, m)));
+
+            for (model, name) in right_side.iter().chain(left_side.as_ref().map(|(_, m)| m)) {
+                self.analysis.add_column(model, name, location(column));
+            }
+            if let (Some((right_model, right_column)), Some((left, (left_model, left_column)))) =
+                (right_side, left_side)
+            {
+                self.current_scope().joined.insert((left, right));
+                let relationship =
+                    self.find_relationship(&left_model, &left_column, &right_model, &right_column);
+                self.join(ImpliedJoin {
+                    left_model,
+                    left_column,
+                    right_model,
+                    right_column,
+                    relationship,
+                    source: JoinSource::Using,
+                    location: location(column),
+                });
+            }
+        }
+    }
+
+    /// Model and declared column name of `column` in a model relation
+    fn model_lookup(&self, relation: &Relation, column: &str) -> Option<(String, String)> {
+        match self.relation_column(relation, column)? {
+            Lookup::Model { model, column } => Some((model, column)),
+            _ => None,
+        }
+    }
+
+    /// Resolves the columns of an expression, and its equi-joins when `joins` is set
+    fn expr(&mut self, expr: &Expr, source: JoinSource, joins: bool) {
+        let mut refs = ExprRefs::default();
+        let _ = expr.visit(&mut refs);
+
+        for parts in &refs.columns {
+            self.column(parts);
+        }
+        if joins {
+            self.equi_joins(expr, source);
+        }
+
+        let mut typed = TypedExprs::default();
+        let _ = expr.visit(&mut typed);
+        for (left, right) in &typed.comparisons {
+            self.compare(left, right);
+        }
+        for call in &typed.aggregates {
+            self.aggregate_types(call);
+        }
+        self.current_scope().aggregates.extend(typed.aggregates);
+
+        for subquery in &refs.subqueries {
+            self.query(subquery);
+        }
+    }
+
+    /// Records the lookup of a column reference
+    fn column(&mut self, parts: &[Ident]) {
+        let Some(column) = parts.last() else {
+            return;
+        };
+        let name = parts
+            .iter()
+            .map(|p| p.value.as_str())
+            .collect::<Vec<_>>()
+            .join(".");
+        match self.lookup(parts) {
+            Lookup::Model { model, column: col } => {
+                self.analysis.add_column(&model, &col, location(column))
+            }
+            Lookup::Unknown(reason) => match self.metric_reference(parts) {
+                Some(reference) => self.analysis.add_metric(reference),
+                None => self.analysis.add_unknown(
+                    IdentifierKind::Column,
+                    name,
+                    reason,
+                    location(column),
+                ),
+            },
+            Lookup::Unmodelled | Lookup::NotAColumn => {}
+        }
+    }
+
+    /// Metric named by a column reference, alone or qualified by a model relation
+    fn metric_reference(&self, parts: &[Ident]) -> Option<MetricReference> {
+        let (name, qualifier) = parts.split_last()?;
+        let metric = self
+            .context
+            .metrics
+            .iter()
+            .find(|m| eq_ignore_case(&m.name, &name.value))?;
+        let is_base = |relation: &Relation| match (&relation.source, &metric.base_model) {
+            (RelationSource::Model(model), Some(base)) => model == base,
+            _ => false,
+        };
+        let base_model_queried = if qualifier.is_empty() {
+            metric.base_model.is_none()
+                || self
+                    .scopes
+                    .iter()
+                    .any(|scope| scope.relations.iter().any(is_base))
+        } else {
+            let relation = self.find_relation(qualifier)?;
+            if !matches!(relation.source, RelationSource::Model(_)) {
+                return None;
+            }
+            metric.base_model.is_none() || is_base(&relation)
+        };
+        Some(MetricReference {
+            metric: metric.name.clone(),
+            base_model: metric.base_model.clone(),
+            base_model_queried,
+            location: location(name),
+        })
+    }
+
+    /// Looks up a possibly qualified column in the visible scopes
+    fn lookup(&self, parts: &[Ident]) -> Lookup {
+        let Some((column, qualifier)) = parts.split_last() else {
+            return Lookup::NotAColumn;
+        };
+
+        if !qualifier.is_empty() {
+            return match self.find_relation(qualifier) {
+                Some(relation) => self
+                    .relation_column(&relation, &column.value)
+                    .unwrap_or_else(|| {
+                        Lookup::Unknown(match &relation.source {
+                            RelationSource::Model(model) => {
+                                format!("model '{}' has no column '{}'", model, column.value)
+                            }
+                            _ => format!("'{}' has no column '{}'", relation.name, column.value),
+                        })
+                    }),
+                None => Lookup::Unknown(format!(
+                    "no table or alias named '{}' in scope",
+                    qualifier
+                        .iter()
+                        .map(|p| p.value.as_str())
+                        .collect::<Vec<_>>()
+                        .join(".")
+                )),
+            };
+        }
+
+        if column.quote_style.is_none()
+            && BUILTIN_IDENTIFIERS
+                .iter()
+                .any(|b| eq_ignore_case(b, &column.value))
+        {
+            return Lookup::NotAColumn;
+        }
+
+        for scope in self.scopes.iter().rev() {
+            if scope.output_aliases.contains(&column.value.to_lowercase()) {
+                return Lookup::NotAColumn;
+            }
+            let mut found: Vec<(&Relation, Lookup)> = scope
+                .relations
+                .iter()
+                .filter_map(|relation| {
+                    self.relation_column(relation, &column.value)
+                        .map(|lookup| (relation, lookup))
+                })
+                .collect();
+            match found.len() {
+                0 => {}
+                1 => {
+                    return found
+                        .pop()
+                        .map(|(_, lookup)| lookup)
+                        .unwrap_or(Lookup::Unmodelled)
+                }
+                _ => {
+                    let names: Vec<&str> = found.iter().map(|(r, _)| r.name.as_str()).collect();
+                    return Lookup::Unknown(format!(
+                        "ambiguous, present in {}",
+                        names.join(" and ")
+                    ));
+                }
+            }
+            // The column may belong to a relation whose columns are unknown
+            if scope.relations.iter().any(|r| {
+                matches!(
+                    r.source,
+                    RelationSource::Opaque | RelationSource::Derived(None)
+                )
+            }) {
+                return Lookup::Unmodelled;
+            }
+        }
+
+        if self.scopes.iter().all(|s| s.relations.is_empty()) {
+            return Lookup::NotAColumn;
+        }
+        Lookup::Unknown(format!("no table in scope has a column '{}'", column.value))
+    }
+
+    /// Looks up `column` in one relation, `None` when the relation doesn't have it
+    fn relation_column(&self, relation: &Relation, column: &str) -> Option<Lookup> {
+        match &relation.source {
+            RelationSource::Model(model) => {
+                let model = self.context.models.get(model)?;
+                model_column_name(model, column).map(|name| Lookup::Model {
+                    model: model.name.clone(),
+                    column: name,
+                })
+            }
+            RelationSource::Derived(Some(columns)) => columns
+                .iter()
+                .any(|c| eq_ignore_case(c, column))
+                .then_some(Lookup::Unmodelled),
+            RelationSource::Derived(None) | RelationSource::Opaque => Some(Lookup::Unmodelled),
+        }
+    }
+
+    /// Relation named by a qualifier, the alias or the table name with its schema
+    fn find_relation(&self, qualifier: &[Ident]) -> Option<Relation> {
+        let last = qualifier.last()?;
+        self.scopes.iter().rev().find_map(|scope| {
+            scope
+                .relations
+                .iter()
+                .find(|relation| {
+                    if qualifier.len() == 1 {
+                        return eq_ignore_case(&relation.name, &last.value);
+                    }
+                    // `schema.table.column` only matches an unaliased table
+                    relation.table.as_ref().is_some_and(|table| {
+                        table.0.len() >= qualifier.len()
+                            && eq_ignore_case(&relation.name, &last.value)
+                            && table.0[table.0.len() - qualifier.len()..]
+                                .iter()
+                                .zip(qualifier)
+                                .all(|(a, b)| eq_ignore_case(&a.value, &b.value))
+                    })
+                })
+                .cloned()
+        })
+    }
+
+    fn model_column(&mut self, model: &str, ident: &Ident, column: &str) {
+        let Some(found) = self.context.models.get(model) else {
+            return;
+        };
+        match model_column_name(found, column) {
+            Some(name) => self.analysis.add_column(model, &name, location(ident)),
+            None => self.analysis.add_unknown(
+                IdentifierKind::Column,
+                format!("{}.{}", model, column),
+                format!("model '{}' has no column '{}'", model, column),
+                location(ident),
+            ),
+        }
+    }
+
+    /// Records `a.x = b.y` conjuncts between two models as joins
+    fn equi_joins(&mut self, expr: &Expr, source: JoinSource) {
+        match expr {
+            Expr::Nested(inner) => self.equi_joins(inner, source),
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::And,
+                right,
+            } => {
+                self.equi_joins(left, source);
+                self.equi_joins(right, source);
+            }
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::Eq,
+                right,
+            } => {
+                let (Some(left_parts), Some(right_parts)) =
+                    (column_parts(left), column_parts(right))
+                else {
+                    return;
+                };
+                let (
+                    Lookup::Model {
+                        model: left_model,
+                        column: left_column,
+                    },
+                    Lookup::Model {
+                        model: right_model,
+                        column: right_column,
+                    },
+                ) = (self.lookup(left_parts), self.lookup(right_parts))
+                else {
+                    return;
+                };
+                let left_index = self.relation_index(left_parts);
+                let right_index = self.relation_index(right_parts);
+                if left_index == right_index {
+                    return;
+                }
+                if let (Some(l), Some(r)) = (left_index, right_index) {
+                    self.current_scope().joined.insert((l.min(r), l.max(r)));
+                }
+                let relationship =
+                    self.find_relationship(&left_model, &left_column, &right_model, &right_column);
+                self.join(ImpliedJoin {
+                    left_model,
+                    left_column,
+                    right_model,
+                    right_column,
+                    relationship,
+                    source,
+                    location: left_parts.last().and_then(location),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Index in the current scope of the relation a column belongs to
+    fn relation_index(&self, parts: &[Ident]) -> Option<usize> {
+        let scope = self.scopes.last()?;
+        let (column, qualifier) = parts.split_last()?;
+        if qualifier.is_empty() {
+            return scope.relations.iter().position(|r| {
+                matches!(
+                    self.relation_column(r, &column.value),
+                    Some(Lookup::Model { .. })
+                )
+            });
+        }
+        let relation = self.find_relation(qualifier)?;
+        scope
+            .relations
+            .iter()
+            .position(|r| r.name == relation.name && r.table == relation.table)
+    }
+
+    /// Declared joins between models of the current scope that the query left unjoined
+    fn relationship_joins(&mut self) {
+        let Some(scope) = self.scopes.last() else {
+            return;
+        };
+        let models: Vec<(usize, String)> = scope
+            .relations
+            .iter()
+            .enumerate()
+            .filter_map(|(i, r)| match &r.source {
+                RelationSource::Model(model) => Some((i, model.clone())),
+                _ => None,
+            })
+            .collect();
+
+        let mut implied = Vec::new();
+        for (a, (i, left)) in models.iter().enumerate() {
+            for (j, right) in &models[a + 1..] {
+                if scope.joined.contains(&(*i, *j)) {
+                    continue;
+                }
+                let relationship = self.context.relationships.iter().find(|r| {
+                    (r.from_model == *left && r.to_model == *right)
+                        || (r.from_model == *right && r.to_model == *left)
+                });
+                if let Some(relationship) = relationship {
+                    implied.push(ImpliedJoin {
+                        left_model: relationship.from_model.clone(),
+                        left_column: relationship.from_column.clone(),
+                        right_model: relationship.to_model.clone(),
+                        right_column: relationship.to_column.clone(),
+                        relationship: Some(relationship.name.clone()),
+                        source: JoinSource::Relationship,
+                        location: scope.relations[*j].location,
+                    });
+                }
+            }
+        }
+        for join in implied {
+            self.join(join);
+        }
+    }
+
+    /// Records a join, keeping those that follow a relationship for the fan-out check
+    fn join(&mut self, join: ImpliedJoin) {
+        if join.relationship.is_some() {
+            self.current_scope().joins.push(join.clone());
+        }
+        self.analysis.add_join(join);
+    }
+
+    /// Warns about sums and counts of a model's columns that a join repeats rows of
+    fn fan_out(&mut self) {
+        let Some(scope) = self.scopes.last() else {
+            return;
+        };
+        let mut issues = Vec::new();
+        for call in &scope.aggregates {
+            if call.distinct || call.function == "MIN" || call.function == "MAX" {
+                continue;
+            }
+            for arg in &call.args {
+                let Some(parts) = column_parts(arg) else {
+                    continue;
+                };
+                let Lookup::Model { model, column } = self.lookup(parts) else {
+                    continue;
+                };
+                for join in &scope.joins {
+                    let Some(relationship) = self
+                        .context
+                        .relationships
+                        .iter()
+                        .find(|r| join.relationship.as_deref() == Some(r.name.as_str()))
+                    else {
+                        continue;
+                    };
+                    let (from, to) = (&relationship.from_model, &relationship.to_model);
+                    // The model on the "one" side gets a row per matching row of the other
+                    let many = match relationship.relationship_type {
+                        RelationshipType::OneToMany if *from == model => to,
+                        RelationshipType::ManyToOne if *to == model => from,
+                        RelationshipType::ManyToMany if *from == model => to,
+                        RelationshipType::ManyToMany if *to == model => from,
+                        _ => continue,
+                    };
+                    if *many == model {
+                        continue;
+                    }
+                    issues.push(ValidationIssue {
+                        severity: ValidationSeverity::Warning,
+                        message: format!(
+                            "{}({}.{}) may count {} rows more than once: the join with {} through '{}' repeats them",
+                            call.function, model, column, model, many, relationship.name
+                        ),
+                        location: parts.last().and_then(location),
+                    });
+                }
+            }
+        }
+        for issue in issues {
+            self.issue(issue);
+        }
+    }
+
+    fn issue(&mut self, issue: ValidationIssue) {
+        if !self.issues.contains(&issue) {
+            self.issues.push(issue);
+        }
+    }
+
+    /// Model column of an expression and its declared type
+    fn column_type(&self, expr: &Expr) -> Option<(String, DataType, Option<SourceLocation>)> {
+        let parts = column_parts(expr)?;
+        let Lookup::Model { model, column } = self.lookup(parts) else {
+            return None;
+        };
+        let found = self.context.models.get(&model)?;
+        let data_type = found
+            .columns
+            .iter()
+            .find(|c| c.name == column)
+            .map(|c| c.data_type.clone())
+            .or_else(|| {
+                found
+                    .calculated_fields
+                    .iter()
+                    .find(|f| f.name == column)
+                    .map(|f| f.data_type.clone())
+            })?;
+        Some((
+            format!("{}.{}", model, column),
+            data_type,
+            parts.last().and_then(location),
+        ))
+    }
+
+    /// Checks that both sides of a comparison have compatible types
+    fn compare(&mut self, left: &Expr, right: &Expr) {
+        let mismatch = match (self.column_type(left), self.column_type(right)) {
+            (Some((l, l_type, l_location)), Some((r, r_type, _))) => {
+                match (type_class(&l_type), type_class(&r_type)) {
+                    (Some(a), Some(b)) if a != b => Some((
+                        format!(
+                            "{} ({}) is compared with {} ({})",
+                            l,
+                            type_name(&l_type),
+                            r,
+                            type_name(&r_type)
+                        ),
+                        l_location,
+                    )),
+                    _ => None,
+                }
+            }
+            (Some((name, data_type, location)), None) => {
+                Self::literal_mismatch(name, data_type, location, right)
+            }
+            (None, Some((name, data_type, location))) => {
+                Self::literal_mismatch(name, data_type, location, left)
+            }
+            (None, None) => None,
+        };
+        if let Some((message, location)) = mismatch {
+            self.issue(ValidationIssue {
+                severity: ValidationSeverity::Error,
+                message,
+                location,
+            });
+        }
+    }
+
+    fn literal_mismatch(
+        name: String,
+        data_type: DataType,
+        location: Option<SourceLocation>,
+        literal: &Expr,
+    ) -> Option<(String, Option<SourceLocation>)> {
+        (!literal_compatible(&data_type, literal)).then(|| {
+            (
+                format!(
+                    "{} is a {} column but is compared with {}",
+                    name,
+                    type_name(&data_type),
+                    literal
+                ),
+                location,
+            )
+        })
+    }
+
+    /// Checks that SUM and AVG apply to numeric columns
+    fn aggregate_types(&mut self, call: &AggregateCall) {
+        if call.function != "SUM" && call.function != "AVG" {
+            return;
+        }
+        for arg in &call.args {
+            let Some((name, data_type, location)) = self.column_type(arg) else {
+                continue;
+            };
+            if type_class(&data_type).is_some_and(|class| class != TypeClass::Numeric) {
+                self.issue(ValidationIssue {
+                    severity: ValidationSeverity::Error,
+                    message: format!(
+                        "{} over {}, which is a {} column",
+                        call.function,
+                        name,
+                        type_name(&data_type)
+                    ),
+                    location,
+                });
+            }
+        }
+    }
+
+    pub(crate) fn find_relationship(
+        &self,
+        left_model: &str,
+        left_column: &str,
+        right_model: &str,
+        right_column: &str,
+    ) -> Option<String> {
+        self.context
+            .relationships
+            .iter()
+            .find(|r| {
+                (r.from_model == left_model
+                    && eq_ignore_case(&r.from_column, left_column)
+                    && r.to_model == right_model
+                    && eq_ignore_case(&r.to_column, right_column))
+                    || (r.from_model == right_model
+                        && eq_ignore_case(&r.from_column, right_column)
+                        && r.to_model == left_model
+                        && eq_ignore_case(&r.to_column, left_column))
+            })
+            .map(|r| r.name.clone())
+    }
+}
+
+/// Model named `name`, or whose physical table is `name` in the default catalog and schema
+pub(crate) fn find_model<'a>(
+    context: &'a SemanticContext,
+    default_catalog: &str,
+    default_schema: &str,
+    name: &ObjectName,
+) -> Option<&'a Model> {
+    let parts: Vec<&str> = name.0.iter().map(|i| i.value.as_str()).collect();
+    let mut models: Vec<&Model> = context.models.values().collect();
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if let [single] = parts.as_slice() {
+        if let Some(model) = models.iter().find(|m| eq_ignore_case(&m.name, single)) {
+            return Some(model);
+        }
+    }
+
+    let (catalog, schema, table) = match parts.as_slice() {
+        [table] => (None, None, *table),
+        [schema, table] => (None, Some(*schema), *table),
+        [catalog, schema, table] => (Some(*catalog), Some(*schema), *table),
+        _ => return None,
+    };
+    models.into_iter().find(|model| {
+        let reference = &model.table_reference;
+        let model_schema = reference.schema.as_deref().unwrap_or(default_schema);
+        let model_catalog = reference.catalog.as_deref().unwrap_or(default_catalog);
+        eq_ignore_case(&reference.table, table)
+            && schema.is_none_or(|s| eq_ignore_case(s, model_schema))
+            && catalog.is_none_or(|c| eq_ignore_case(c, model_catalog))
+            // An unqualified name is looked up in the default schema
+            && (schema.is_some() || eq_ignore_case(model_schema, default_schema))
+    })
+}
+
+pub(crate) fn physical_table(model: &Model, default_catalog: &str, default_schema: &str) -> String {
+    let reference = &model.table_reference;
+    format!(
+        "{}.{}.{}",
+        reference.catalog.as_deref().unwrap_or(default_catalog),
+        reference.schema.as_deref().unwrap_or(default_schema),
+        reference.table
+    )
+}
+
+pub(crate) fn alias_or(alias: Option<&Ident>, name: &ObjectName) -> String {
+    alias
+        .or(name.0.last())
+        .map(|i| i.value.clone())
+        .unwrap_or_default()
+}
+
+pub(crate) fn column_parts(expr: &Expr) -> Option<&[Ident]> {
+    match expr {
+        Expr::Identifier(ident) => Some(std::slice::from_ref(ident)),
+        Expr::CompoundIdentifier(parts) => Some(parts),
+        Expr::Nested(inner) => column_parts(inner),
+        _ => None,
+    }
+}
+
+/// Declared name of a model's column or calculated field
+pub(crate) fn model_column_name(model: &Model, column: &str) -> Option<String> {
+    model
+        .columns
+        .iter()
+        .map(|c| &c.name)
+        .chain(model.calculated_fields.iter().map(|f| &f.name))
+        .find(|name| eq_ignore_case(name, column))
+        .cloned()
+}
+
+/// `name` as an identifier, quoted unless it's a plain word that isn't reserved
+pub(crate) fn ident(name: &str) -> Ident {
+    let word = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    let reserved = ALL_KEYWORDS
+        .binary_search(&name.to_ascii_uppercase().as_str())
+        .is_ok_and(|i| {
+            let keyword = ALL_KEYWORDS_INDEX[i];
+            RESERVED_FOR_COLUMN_ALIAS.contains(&keyword)
+                || RESERVED_FOR_TABLE_ALIAS.contains(&keyword)
+        });
+    if word && !reserved {
+        Ident::new(name)
+    } else {
+        Ident::with_quote('"', name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantic::test_support::shop;
+
+    #[test]
+    fn test_analyze_resolves_models_and_columns() {
+        let context = shop();
+        let sql = "WITH big AS (SELECT id, amount FROM sales.fct_orders WHERE amount > 100)
+SELECT c.name, SUM(o.amount) AS total, o.discount
+FROM orders o JOIN dim_customers c ON o.customer_id = c.id
+LEFT JOIN audit_log a ON a.order_id = o.id
+WHERE o.id IN (SELECT id FROM big) AND c.regoin = 'EU'
+GROUP BY c.name ORDER BY total";
+        let analysis = analyze(sql, SqlDialect::Postgres, &context, "warehouse", "sales").unwrap();
+
+        let models: Vec<(&str, Option<&str>)> = analysis
+            .models
+            .iter()
+            .map(|m| (m.model.as_str(), m.alias.as_deref()))
+            .collect();
+        assert_eq!(
+            models,
+            vec![
+                ("orders", None),
+                ("orders", Some("o")),
+                ("customers", Some("c"))
+            ]
+        );
+        assert_eq!(
+            analysis.models[2].physical_table,
+            "warehouse.sales.dim_customers"
+        );
+        assert!(analysis
+            .columns
+            .iter()
+            .any(|c| c.model == "customers" && c.column == "name"));
+
+        let unknown: Vec<&str> = analysis.unknown.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(unknown, vec!["audit_log", "o.discount", "c.regoin"]);
+        let location = analysis.unknown[1].location.as_ref().unwrap();
+        assert_eq!((location.line, location.column), (2, 42));
+
+        assert_eq!(analysis.joins.len(), 1);
+        assert_eq!(
+            analysis.joins[0].relationship.as_deref(),
+            Some("orders_customer")
+        );
+        assert_eq!(analysis.joins[0].source, JoinSource::On);
+    }
+
+    #[test]
+    fn test_analyze_joins_without_conditions() {
+        let context = shop();
+        // Models queried together without a condition get the declared join
+        let analysis = analyze(
+            "SELECT name, amount FROM orders, customers",
+            SqlDialect::Sqlite,
+            &context,
+            "warehouse",
+            "sales",
+        )
+        .unwrap();
+        assert!(analysis.unknown.is_empty());
+        assert_eq!(analysis.joins[0].source, JoinSource::Relationship);
+
+        // Unqualified columns present in both models are ambiguous
+        let analysis = analyze(
+            "SELECT id FROM orders JOIN customers USING (id)",
+            SqlDialect::Ansi,
+            &context,
+            "warehouse",
+            "sales",
+        )
+        .unwrap();
+        assert!(analysis.unknown[0].reason.starts_with("ambiguous"));
+        assert_eq!(analysis.joins[0].source, JoinSource::Using);
+        assert_eq!(analysis.joins[0].relationship, None);
+    }
+
+    #[test]
+    fn test_analyze_rejects_invalid_sql() {
+        let context = shop();
+        assert!(analyze("SELEC 1", SqlDialect::Ansi, &context, "w", "s").is_err());
+    }
+}
@@ -0,0 +1,357 @@
+//! Validation of SQL against the semantic model
+//!
+//! The query is resolved like [`super::sql::analyze`] does, and what doesn't fit
+//! the model becomes a located [`ValidationIssue`]: unknown tables and columns,
+//! joins outside the declared relationships, aggregations a join fans out,
+//! metrics queried without their base model or next to models repeating its rows,
+//! and comparisons of mismatched types.
+
+use chrono::NaiveDate;
+use sqlparser::ast::{Expr, UnaryOperator, Value};
+use sqlparser::parser::Parser;
+
+use super::analyzer::{SourceLocation, ValidationIssue, ValidationSeverity};
+use super::join::{describe, fanout_error, JoinPlanner};
+use super::mdl::DataType;
+use super::sql::{IdentifierKind, JoinSource, Resolver, SqlDialect};
+use super::SemanticContext;
+use crate::error::WinxResult;
+
+/// Kind of value a column holds, columns of different kinds don't compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TypeClass {
+    Numeric,
+    Text,
+    Boolean,
+    Temporal,
+}
+
+/// Kind of a column type, `None` for types that aren't checked
+pub(crate) fn type_class(data_type: &DataType) -> Option<TypeClass> {
+    match data_type {
+        DataType::Integer | DataType::Float => Some(TypeClass::Numeric),
+        DataType::String => Some(TypeClass::Text),
+        DataType::Boolean => Some(TypeClass::Boolean),
+        DataType::Date | DataType::Timestamp => Some(TypeClass::Temporal),
+        DataType::Json | DataType::Array(_) | DataType::Custom(_) => None,
+    }
+}
+
+pub(crate) fn type_name(data_type: &DataType) -> String {
+    match data_type {
+        DataType::String => "string".to_string(),
+        DataType::Integer => "integer".to_string(),
+        DataType::Float => "float".to_string(),
+        DataType::Boolean => "boolean".to_string(),
+        DataType::Date => "date".to_string(),
+        DataType::Timestamp => "timestamp".to_string(),
+        DataType::Json => "json".to_string(),
+        DataType::Array(inner) => format!("array<{}>", type_name(inner)),
+        DataType::Custom(name) => name.clone(),
+    }
+}
+
+/// Whether a literal can be compared with a column of `data_type`
+///
+/// Expressions other than literals are accepted, their type isn't known.
+pub(crate) fn literal_compatible(data_type: &DataType, literal: &Expr) -> bool {
+    let Some(class) = type_class(data_type) else {
+        return true;
+    };
+    match literal {
+        Expr::Nested(inner) => literal_compatible(data_type, inner),
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus | UnaryOperator::Plus,
+            expr,
+        } if matches!(**expr, Expr::Value(Value::Number(..))) => class == TypeClass::Numeric,
+        Expr::Value(Value::Number(number, _)) => match class {
+            TypeClass::Numeric => true,
+            TypeClass::Boolean => number == "0" || number == "1",
+            TypeClass::Text | TypeClass::Temporal => false,
+        },
+        Expr::Value(Value::SingleQuotedString(text)) => match class {
+            TypeClass::Text => true,
+            TypeClass::Numeric => text.trim().parse::<f64>().is_ok(),
+            TypeClass::Boolean => ["t", "f", "true", "false", "0", "1"]
+                .iter()
+                .any(|b| b.eq_ignore_ascii_case(text.trim())),
+            TypeClass::Temporal => text
+                .get(..10)
+                .is_some_and(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok()),
+        },
+        Expr::Value(Value::Boolean(_)) => class == TypeClass::Boolean,
+        Expr::TypedString { data_type, .. } => {
+            let name = data_type.to_string().to_lowercase();
+            let temporal = name.starts_with("date") || name.starts_with("timestamp");
+            temporal == (class == TypeClass::Temporal)
+        }
+        _ => true,
+    }
+}
+
+/// Validates `sql` against the models, relationships and metrics of `context`
+///
+/// Unqualified tables are looked up in `catalog` and `schema`. SQL that doesn't
+/// parse yields a single error located where the parser stopped.
+pub fn validate(
+    sql: &str,
+    dialect: SqlDialect,
+    context: &SemanticContext,
+    catalog: &str,
+    schema: &str,
+) -> WinxResult<Vec<ValidationIssue>> {
+    let statements = match Parser::parse_sql(dialect.parser_dialect().as_ref(), sql) {
+        Ok(statements) => statements,
+        Err(e) => {
+            let message = e.to_string();
+            return Ok(vec![ValidationIssue {
+                severity: ValidationSeverity::Error,
+                location: parser_error_location(&message),
+                message: format!("Invalid {} SQL: {}", dialect, message),
+            }]);
+        }
+    };
+
+    let mut resolver = Resolver::new(context, catalog, schema, dialect);
+    for statement in &statements {
+        resolver.statement(statement);
+    }
+    let analysis = resolver.analysis;
+    let mut issues = resolver.issues;
+
+    for unknown in &analysis.unknown {
+        let kind = match unknown.kind {
+            IdentifierKind::Table => "table",
+            IdentifierKind::Column => "column",
+        };
+        issues.push(ValidationIssue {
+            severity: ValidationSeverity::Error,
+            message: format!("Unknown {} '{}': {}", kind, unknown.name, unknown.reason),
+            location: unknown.location,
+        });
+    }
+
+    for join in &analysis.joins {
+        let message = match (&join.relationship, join.source) {
+            (None, _) => format!(
+                "Join on {}.{} = {}.{} doesn't follow a declared relationship",
+                join.left_model, join.left_column, join.right_model, join.right_column
+            ),
+            (Some(relationship), JoinSource::Relationship) => format!(
+                "{} and {} are combined without a join condition, relationship '{}' joins them on {}.{} = {}.{}",
+                join.left_model,
+                join.right_model,
+                relationship,
+                join.left_model,
+                join.left_column,
+                join.right_model,
+                join.right_column
+            ),
+            (Some(_), _) => continue,
+        };
+        issues.push(ValidationIssue {
+            severity: ValidationSeverity::Warning,
+            message,
+            location: join.location,
+        });
+    }
+
+    let planner = JoinPlanner::new(context);
+    let queried: Vec<String> = analysis.models.iter().map(|m| m.model.clone()).collect();
+    for reference in &analysis.metrics {
+        let Some(base_model) = &reference.base_model else {
+            continue;
+        };
+
+        // The models joined once the metric is expanded must not repeat the rows it adds up
+        let counts_repeated_rows = context
+            .metrics
+            .iter()
+            .find(|m| m.name == reference.metric)
+            .is_some_and(|m| m.counts_repeated_rows());
+        let mut joined = queried.clone();
+        if !reference.base_model_queried {
+            if let Some(path) = planner.path_into(&queried, base_model) {
+                joined.extend(path.steps.iter().map(|s| s.to_model.clone()));
+            }
+        }
+        if let Some(path) = planner
+            .fanout_path(base_model, &joined)
+            .filter(|_| counts_repeated_rows)
+        {
+            issues.push(ValidationIssue {
+                severity: ValidationSeverity::Error,
+                message: fanout_error(&reference.metric, &path),
+                location: reference.location,
+            });
+        }
+
+        if reference.base_model_queried {
+            continue;
+        }
+        let path = planner.path_into(&queried, base_model);
+        let (severity, hint) = match path {
+            _ if queried.is_empty() => (
+                ValidationSeverity::Warning,
+                "expanding the metric joins it".to_string(),
+            ),
+            Some(path) => {
+                let mut hint = format!(
+                    "expanding the metric joins it along {}",
+                    describe(&path.steps)
+                );
+                for note in &path.notes {
+                    hint.push_str(". ");
+                    hint.push_str(note);
+                }
+                (ValidationSeverity::Warning, hint)
+            }
+            None => (
+                ValidationSeverity::Error,
+                "no relationship connects it to the queried models".to_string(),
+            ),
+        };
+        issues.push(ValidationIssue {
+            severity,
+            message: format!(
+                "Metric '{}' is used without its base model '{}', {}",
+                reference.metric, base_model, hint
+            ),
+            location: reference.location,
+        });
+    }
+
+    // Issues without a location come last
+    issues.sort_by_key(|issue| (issue.location.is_none(), issue.location));
+    Ok(issues)
+}
+
+/// Location of a parser error message ending in `at Line: 1, Column: 8`
+fn parser_error_location(message: &str) -> Option<SourceLocation> {
+    let (_, position) = message.rsplit_once("Line: ")?;
+    let (line, column) = position.split_once(", Column: ")?;
+    let column: String = column.chars().take_while(|c| c.is_ascii_digit()).collect();
+    Some(SourceLocation {
+        line: line.trim().parse().ok()?,
+        column: column.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantic::mdl::Aggregation;
+    use crate::semantic::test_support::{metric, shop};
+
+    /// The shop with a metric of the products no other model joins
+    fn context() -> SemanticContext {
+        let mut context = shop();
+        context.metrics.push(metric(
+            "catalog_size",
+            "price",
+            Some("products"),
+            Some(Aggregation::Sum),
+            &[],
+        ));
+        context
+    }
+
+    fn validate_sql(sql: &str) -> Vec<ValidationIssue> {
+        validate(sql, SqlDialect::Ansi, &context(), "db", "main").unwrap()
+    }
+
+    #[test]
+    fn test_validate_reports_located_issues() {
+        let issues = validate_sql(
+            "SELECT c.name, SUM(c.credit), SUM(o.status), revenue, catalog_size
+FROM customers c JOIN orders o ON o.id = c.id
+WHERE o.amount > 'cheap' AND o.ordered_at >= '2024-01-01' AND o.bogus = 1
+GROUP BY c.name",
+        );
+        let summary: Vec<(ValidationSeverity, usize, usize)> = issues
+            .iter()
+            .map(|issue| {
+                let location = issue.location.unwrap();
+                (issue.severity, location.line, location.column)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (ValidationSeverity::Error, 1, 37),
+                (ValidationSeverity::Error, 1, 55),
+                (ValidationSeverity::Warning, 2, 37),
+                (ValidationSeverity::Error, 3, 9),
+                (ValidationSeverity::Error, 3, 65),
+            ],
+            "{:#?}",
+            issues
+        );
+        assert!(issues[0].message.starts_with("SUM over orders.status"));
+        assert!(issues[1].message.contains("'catalog_size'"));
+        assert!(issues[2]
+            .message
+            .contains("doesn't follow a declared relationship"));
+        assert!(issues[3].message.contains("compared with 'cheap'"));
+        assert!(issues[4].message.starts_with("Unknown column 'o.bogus'"));
+        assert_eq!(
+            issues[2].to_string(),
+            format!("warning at 2:37: {}", issues[2].message)
+        );
+    }
+
+    #[test]
+    fn test_validate_warns_about_sums_repeated_by_joins() {
+        // Summing the "one" side of a one-to-many join counts it once per order
+        let issues = validate_sql(
+            "SELECT c.name, SUM(c.credit), COUNT(DISTINCT c.id), SUM(o.amount)
+FROM customers c JOIN orders o ON o.customer_id = c.id GROUP BY c.name",
+        );
+        assert_eq!(issues.len(), 1, "{:#?}", issues);
+        assert_eq!(issues[0].severity, ValidationSeverity::Warning);
+        assert!(issues[0].message.starts_with("SUM(customers.credit)"));
+    }
+
+    #[test]
+    fn test_validate_metrics_outside_the_queried_models() {
+        // Without any join the metric's base model is added by the expansion
+        let issues = validate_sql("SELECT revenue FROM customers");
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, ValidationSeverity::Warning);
+        assert!(validate_sql("SELECT status, revenue FROM orders").is_empty());
+    }
+
+    #[test]
+    fn test_validate_reports_syntax_errors() {
+        let issues = validate_sql("SELECT FROM WHERE");
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, ValidationSeverity::Error);
+        assert!(issues[0].location.is_some());
+    }
+
+    #[test]
+    fn test_validate_rejects_metrics_next_to_models_repeating_their_rows() {
+        // Every order of a customer repeats it, a sum over customers can't sit next to orders
+        let mut context = context();
+        context.metrics.push(metric(
+            "total_credit",
+            "credit",
+            Some("customers"),
+            Some(Aggregation::Sum),
+            &[],
+        ));
+        let validate_sql =
+            |sql: &str| validate(sql, SqlDialect::Ansi, &context, "db", "main").unwrap();
+
+        let issues = validate_sql("SELECT total_credit, revenue FROM orders");
+        assert_eq!(issues.len(), 2, "{:#?}", issues);
+        assert_eq!(issues[0].severity, ValidationSeverity::Error);
+        assert!(issues[0]
+            .message
+            .starts_with("Metric 'total_credit' would count rows of 'customers' more than once"));
+
+        // Expanding the metric onto customers alone is fine
+        let issues = validate_sql("SELECT name, total_credit FROM customers GROUP BY name");
+        assert!(issues.is_empty(), "{:#?}", issues);
+    }
+}
@@ -1,9 +1,14 @@
 use crate::reinforcement::{initialize_rl_system, AdaptiveToolSystem};
+use crate::security::audit;
+use crate::telemetry;
 use crate::tools::{
     bash_command::BashCommand,
     context_save::ContextSave,
     file_operations::{FileEdit, FileOperations, WriteIfEmpty},
     initialize::Initialize,
+    list_directory::ListDirectory,
+    search_code::SearchCode,
+    semantic_layer::SemanticLayer,
     // Temporarily commenting out LSP modules that are causing errors
     // semantic_code::{AddSymbolTool, EditSymbolTool, FindReferencesTool, FindSymbolTool},
 };
@@ -21,6 +26,9 @@ pub struct CodeAgent {
     write_if_empty: WriteIfEmpty,
     file_edit: FileEdit,
     context_save: ContextSave,
+    search_code: SearchCode,
+    list_directory: ListDirectory,
+    semantic_layer: SemanticLayer,
     // Temporarily commenting out LSP-related fields
     // find_symbol: FindSymbolTool,
     // find_references: FindReferencesTool,
@@ -48,6 +56,9 @@ impl CodeAgent {
             write_if_empty: WriteIfEmpty::new(),
             file_edit: FileEdit::new(),
             context_save: ContextSave::new(),
+            search_code: SearchCode::new(),
+            list_directory: ListDirectory::new(),
+            semantic_layer: SemanticLayer::new(),
             // Temporarily commenting out LSP-related initializations
             // find_symbol: FindSymbolTool::new(),
             // find_references: FindReferencesTool::new(),
@@ -218,6 +229,18 @@ impl Default for CodeAgent {
     }
 }
 
+/// Runs a tool call under the audit log and telemetry
+async fn observe<F>(
+    tool: &str,
+    params: serde_json::Value,
+    call: F,
+) -> Result<CallToolResult, McpError>
+where
+    F: std::future::Future<Output = Result<CallToolResult, McpError>>,
+{
+    telemetry::instrument(tool, audit::record(tool, params, call)).await
+}
+
 #[tool(tool_box)]
 impl CodeAgent {
     #[tool(
@@ -227,7 +250,12 @@ impl CodeAgent {
         &self,
         #[tool(aggr)] params: crate::tools::initialize::InitializeParams,
     ) -> Result<CallToolResult, McpError> {
-        self.initialize.initialize(params).await
+        observe(
+            "initialize",
+            audit::sanitize(&params),
+            self.initialize.initialize(params),
+        )
+        .await
     }
 
     #[tool(
@@ -237,7 +265,12 @@ impl CodeAgent {
         &self,
         #[tool(aggr)] params: crate::tools::bash_command::BashCommandParams,
     ) -> Result<CallToolResult, McpError> {
-        self.bash_command.bash_command(params).await
+        observe(
+            "bash_command",
+            audit::sanitize(&params),
+            self.bash_command.bash_command(params),
+        )
+        .await
     }
 
     #[tool(
@@ -248,25 +281,42 @@ impl CodeAgent {
         #[tool(aggr)] params: crate::tools::file_operations::ReadFilesParams,
     ) -> Result<CallToolResult, McpError> {
         log::debug!("read_files called with params: {:?}", params);
-        self.file_ops.read_files(params).await
+        observe(
+            "read_files",
+            audit::sanitize(&params),
+            self.file_ops.read_files(params),
+        )
+        .await
     }
 
-    #[tool(description = "Create new files or write to empty files only")]
+    #[tool(
+        description = "Create new files or write to empty files only.\n- Set `dry_run` to true to preview the file as a unified diff with syntax check results without writing it.\n"
+    )]
     async fn write_if_empty(
         &self,
         #[tool(aggr)] params: crate::tools::file_operations::WriteIfEmptyParams,
     ) -> Result<CallToolResult, McpError> {
-        self.write_if_empty.write_if_empty(params).await
+        observe(
+            "write_if_empty",
+            audit::sanitize(&params),
+            self.write_if_empty.write_if_empty(params),
+        )
+        .await
     }
 
     #[tool(
-        description = "\n- Edits existing files using search/replace blocks.\n- Uses Aider-like search and replace syntax.\n- File edit has spacing tolerant matching, with warning on issues like indentation mismatch.\n- If there's no match, the closest match is returned to help fix mistakes.\n"
+        description = "\n- Edits existing files using search/replace blocks.\n- Uses Aider-like search and replace syntax.\n- Also supports anchor operations: `<<<<<<< INSERT BEFORE`, `<<<<<<< INSERT AFTER`, `<<<<<<< DELETE` and `<<<<<<< REPLACE BETWEEN` (start anchor, `=======`, end anchor, `=======`, new lines). `# occurrence: N` applies to the anchor.\n- `<<<<<<< SEARCH REGEX` blocks take a multiline regex and a replacement using `$1`/`${name}` captures; every match is replaced unless `# occurrence: N` selects one, and the match count is reported.\n- File edit has spacing tolerant matching, with warning on issues like indentation mismatch.\n- If there's no match, the closest match is returned to help fix mistakes.\n- On success, returns the new line numbers of each replaced block and a compact diff of the change.\n- Set `dry_run` to true to get the unified diff and syntax check results without modifying the file. Dry runs are allowed in architect mode.\n"
     )]
     async fn file_edit(
         &self,
         #[tool(aggr)] params: crate::tools::file_operations::FileEditParams,
     ) -> Result<CallToolResult, McpError> {
-        self.file_edit.file_edit(params).await
+        observe(
+            "file_edit",
+            audit::sanitize(&params),
+            self.file_edit.file_edit(params),
+        )
+        .await
     }
 
     #[tool(description = "Read an image file and return its base64-encoded content")]
@@ -274,7 +324,12 @@ impl CodeAgent {
         &self,
         #[tool(aggr)] params: crate::tools::file_operations::ReadImageParams,
     ) -> Result<CallToolResult, McpError> {
-        self.file_ops.read_image(params).await
+        observe(
+            "read_image",
+            audit::sanitize(&params),
+            self.file_ops.read_image(params),
+        )
+        .await
     }
 
     #[tool(
@@ -284,7 +339,162 @@ impl CodeAgent {
         &self,
         #[tool(aggr)] params: crate::tools::context_save::ContextSaveParams,
     ) -> Result<CallToolResult, McpError> {
-        self.context_save.context_save(params).await
+        observe(
+            "context_save",
+            audit::sanitize(&params),
+            self.context_save.context_save(params),
+        )
+        .await
+    }
+
+    #[tool(
+        description = "\n- Search file contents across the workspace, in parallel, honoring .gitignore, .ignore and .winxignore.\n- Use this instead of running grep through bash_command.\n- `pattern` is a regex unless `literal` is true.\n- Filter with `file_types` (e.g. \"rust\", \"py\") and `globs` (prefix with ! to exclude).\n- `context_lines` adds lines around each match.\n- Results are capped by `max_results`; pass the returned `cursor` to get the next page.\n"
+    )]
+    async fn search_code(
+        &self,
+        #[tool(aggr)] params: crate::tools::search_code::SearchCodeParams,
+    ) -> Result<CallToolResult, McpError> {
+        observe(
+            "search_code",
+            audit::sanitize(&params),
+            self.search_code.search_code(params),
+        )
+        .await
+    }
+
+    #[tool(
+        description = "\n- List a directory honoring .gitignore, .ignore and .winxignore, with type, size and modification time.\n- Use this instead of running ls or find through bash_command.\n- `depth` controls how many levels are listed (default 1).\n- `pattern` filters entry names with a glob, e.g. \"*.rs\".\n- Listings come from an incrementally refreshed workspace index, so repeated calls are cheap.\n"
+    )]
+    async fn list_directory(
+        &self,
+        #[tool(aggr)] params: crate::tools::list_directory::ListDirectoryParams,
+    ) -> Result<CallToolResult, McpError> {
+        observe(
+            "list_directory",
+            audit::sanitize(&params),
+            self.list_directory.list_directory(params),
+        )
+        .await
+    }
+
+    #[tool(
+        description = "\n- Load a semantic model (MDL) file from the workspace for the other semantic_* tools.\n- `format` is winx (default, YAML or JSON), dbt (schema.yml), cube or json_schema.\n- The MDL is checked first; loading fails with every problem found, located by line for YAML.\n- Requires `semantic.enabled` in the configuration.\n"
+    )]
+    async fn semantic_load(
+        &self,
+        #[tool(aggr)] params: crate::tools::semantic_layer::SemanticLoadParams,
+    ) -> Result<CallToolResult, McpError> {
+        observe(
+            "semantic_load",
+            audit::sanitize(&params),
+            self.semantic_layer.semantic_load(params),
+        )
+        .await
+    }
+
+    #[tool(
+        description = "\n- Describe the loaded MDL: models with their tables, relationships, metrics and views.\n- Pass `model` to get its columns, types, calculated fields, relationships and metrics.\n"
+    )]
+    async fn semantic_describe(
+        &self,
+        #[tool(aggr)] params: crate::tools::semantic_layer::SemanticDescribeParams,
+    ) -> Result<CallToolResult, McpError> {
+        observe(
+            "semantic_describe",
+            audit::sanitize(&params),
+            self.semantic_layer.semantic_describe(params),
+        )
+        .await
+    }
+
+    #[tool(
+        description = "\n- Find the cheapest chain of declared relationships joining two models of the loaded MDL.\n- Steps that don't repeat rows are preferred, many-to-many relationships are used only when nothing else connects the models.\n- Each step gives the relationship, the columns it joins on and whether it repeats rows; equally cheap paths are listed.\n"
+    )]
+    async fn semantic_join_path(
+        &self,
+        #[tool(aggr)] params: crate::tools::semantic_layer::SemanticJoinPathParams,
+    ) -> Result<CallToolResult, McpError> {
+        observe(
+            "semantic_join_path",
+            audit::sanitize(&params),
+            self.semantic_layer.semantic_join_path(params),
+        )
+        .await
+    }
+
+    #[tool(
+        description = "\n- Plan the joins connecting a set of models of the loaded MDL, starting from the first model.\n- Returns the FROM clause with a LEFT JOIN per step, the steps taken, models nothing connects, and notes on joins that repeat rows or could have gone another way.\n- Put the model whose rows must not repeat, usually the one holding the measures, first.\n"
+    )]
+    async fn semantic_plan_joins(
+        &self,
+        #[tool(aggr)] params: crate::tools::semantic_layer::SemanticPlanJoinsParams,
+    ) -> Result<CallToolResult, McpError> {
+        observe(
+            "semantic_plan_joins",
+            audit::sanitize(&params),
+            self.semantic_layer.semantic_plan_joins(params),
+        )
+        .await
+    }
+
+    #[tool(
+        description = "\n- Validate SQL against the loaded MDL: unknown tables and columns, joins outside declared relationships, fan-out of aggregations, metrics without their base model and type mismatches.\n- Issues are reported with their line and column.\n"
+    )]
+    async fn semantic_validate_sql(
+        &self,
+        #[tool(aggr)] params: crate::tools::semantic_layer::SemanticSqlParams,
+    ) -> Result<CallToolResult, McpError> {
+        observe(
+            "semantic_validate_sql",
+            audit::sanitize(&params),
+            self.semantic_layer.semantic_validate_sql(params),
+        )
+        .await
+    }
+
+    #[tool(
+        description = "\n- Rewrite SQL that uses metrics as columns into their aggregations, joining the models the metrics need.\n"
+    )]
+    async fn semantic_expand_metrics(
+        &self,
+        #[tool(aggr)] params: crate::tools::semantic_layer::SemanticSqlParams,
+    ) -> Result<CallToolResult, McpError> {
+        observe(
+            "semantic_expand_metrics",
+            audit::sanitize(&params),
+            self.semantic_layer.semantic_expand_metrics(params),
+        )
+        .await
+    }
+
+    #[tool(
+        description = "\n- Build the column-level lineage of SQL over the loaded MDL, from output columns back to model columns.\n- `format` is json (default), dot or mermaid.\n"
+    )]
+    async fn semantic_lineage(
+        &self,
+        #[tool(aggr)] params: crate::tools::semantic_layer::SemanticLineageParams,
+    ) -> Result<CallToolResult, McpError> {
+        observe(
+            "semantic_lineage",
+            audit::sanitize(&params),
+            self.semantic_layer.semantic_lineage(params),
+        )
+        .await
+    }
+
+    #[tool(
+        description = "\n- Run a metric query against the SQLite database configured in `semantic.database`.\n- Give metric names, `dimensions` to group by and `filters`; they are compiled through the loaded MDL into SQL.\n- Queries that don't validate are not run, their issues are returned instead.\n- The database is opened read-only. Results are a table of at most `page_size` rows; pass the returned `cursor` to get the next page.\n"
+    )]
+    async fn semantic_query(
+        &self,
+        #[tool(aggr)] params: crate::tools::semantic_layer::SemanticQueryParams,
+    ) -> Result<CallToolResult, McpError> {
+        observe(
+            "semantic_query",
+            audit::sanitize(&params),
+            self.semantic_layer.semantic_query(params),
+        )
+        .await
     }
 
     // Temporarily commenting out LSP-related methods
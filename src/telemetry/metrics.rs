@@ -0,0 +1,249 @@
+//! Per-tool call, error, latency and output-size metrics
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Upper bounds of the latency histogram, in seconds
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+];
+
+/// Upper bounds of the output size histogram, in bytes
+pub const OUTPUT_BUCKETS: &[f64] = &[
+    256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0,
+];
+
+/// Cumulative histogram with fixed bucket bounds
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Histogram {
+    pub bounds: &'static [f64],
+    /// Observations per bucket, the last one counts those above every bound
+    pub counts: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ToolStats {
+    pub calls: u64,
+    pub errors: u64,
+    pub duration_seconds: Histogram,
+    pub output_bytes: Histogram,
+}
+
+impl Default for ToolStats {
+    fn default() -> Self {
+        Self {
+            calls: 0,
+            errors: 0,
+            duration_seconds: Histogram::new(DURATION_BUCKETS),
+            output_bytes: Histogram::new(OUTPUT_BUCKETS),
+        }
+    }
+}
+
+/// Which series are recorded, from `TelemetryConfig`
+#[derive(Debug, Clone, Copy)]
+pub struct MetricsOptions {
+    pub include_errors: bool,
+    pub include_performance: bool,
+}
+
+impl Default for MetricsOptions {
+    fn default() -> Self {
+        Self {
+            include_errors: true,
+            include_performance: true,
+        }
+    }
+}
+
+/// Metrics of every tool, keyed by tool name
+#[derive(Debug, Clone, Default)]
+pub struct ToolMetrics {
+    options: MetricsOptions,
+    tools: BTreeMap<String, ToolStats>,
+}
+
+impl ToolMetrics {
+    pub fn new(options: MetricsOptions) -> Self {
+        Self {
+            options,
+            tools: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, tool: &str, seconds: f64, output_bytes: usize, error: bool) {
+        let stats = self.tools.entry(tool.to_string()).or_default();
+        stats.calls += 1;
+        if error && self.options.include_errors {
+            stats.errors += 1;
+        }
+        if self.options.include_performance {
+            stats.duration_seconds.observe(seconds);
+            stats.output_bytes.observe(output_bytes as f64);
+        }
+    }
+
+    pub fn options(&self) -> MetricsOptions {
+        self.options
+    }
+
+    pub fn tools(&self) -> &BTreeMap<String, ToolStats> {
+        &self.tools
+    }
+
+    /// Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        self.render_counter(&mut out, "winx_tool_calls_total", "Tool calls", |s| s.calls);
+        if self.options.include_errors {
+            self.render_counter(
+                &mut out,
+                "winx_tool_errors_total",
+                "Tool calls that returned an error",
+                |s| s.errors,
+            );
+        }
+        if self.options.include_performance {
+            self.render_histogram(
+                &mut out,
+                "winx_tool_duration_seconds",
+                "Tool call latency",
+                |s| &s.duration_seconds,
+            );
+            self.render_histogram(
+                &mut out,
+                "winx_tool_output_bytes",
+                "Size of the tool output returned to the client",
+                |s| &s.output_bytes,
+            );
+        }
+        out
+    }
+
+    fn render_counter(
+        &self,
+        out: &mut String,
+        name: &str,
+        help: &str,
+        value: impl Fn(&ToolStats) -> u64,
+    ) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (tool, stats) in &self.tools {
+            let _ = writeln!(
+                out,
+                "{}{{tool=\"{}\"}} {}",
+                name,
+                escape_label(tool),
+                value(stats)
+            );
+        }
+    }
+
+    fn render_histogram(
+        &self,
+        out: &mut String,
+        name: &str,
+        help: &str,
+        histogram: impl Fn(&ToolStats) -> &Histogram,
+    ) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (tool, stats) in &self.tools {
+            let tool = escape_label(tool);
+            let histogram = histogram(stats);
+            let mut cumulative = 0;
+            for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{tool=\"{}\",le=\"{}\"}} {}",
+                    name, tool, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{tool=\"{}\",le=\"+Inf\"}} {}",
+                name, tool, histogram.count
+            );
+            let _ = writeln!(out, "{}_sum{{tool=\"{}\"}} {}", name, tool, histogram.sum);
+            let _ = writeln!(
+                out,
+                "{}_count{{tool=\"{}\"}} {}",
+                name, tool, histogram.count
+            );
+        }
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_render_prometheus() {
+        let mut metrics = ToolMetrics::new(MetricsOptions::default());
+        metrics.record("bash_command", 0.02, 100, false);
+        metrics.record("bash_command", 3.0, 5000, true);
+        metrics.record("read_files", 0.001, 300, false);
+
+        let stats = &metrics.tools()["bash_command"];
+        assert_eq!((stats.calls, stats.errors), (2, 1));
+        assert_eq!(stats.duration_seconds.count, 2);
+
+        let text = metrics.render_prometheus();
+        assert!(text.contains("winx_tool_calls_total{tool=\"bash_command\"} 2"));
+        assert!(text.contains("winx_tool_errors_total{tool=\"read_files\"} 0"));
+        // Buckets are cumulative
+        assert!(text
+            .contains("winx_tool_duration_seconds_bucket{tool=\"bash_command\",le=\"0.025\"} 1"));
+        assert!(
+            text.contains("winx_tool_duration_seconds_bucket{tool=\"bash_command\",le=\"5\"} 2")
+        );
+        assert!(
+            text.contains("winx_tool_duration_seconds_bucket{tool=\"bash_command\",le=\"+Inf\"} 2")
+        );
+        assert!(text.contains("winx_tool_output_bytes_sum{tool=\"bash_command\"} 5100"));
+
+        let mut quiet = ToolMetrics::new(MetricsOptions {
+            include_errors: false,
+            include_performance: false,
+        });
+        quiet.record("bash_command", 1.0, 10, true);
+        let text = quiet.render_prometheus();
+        assert!(text.contains("winx_tool_calls_total{tool=\"bash_command\"} 1"));
+        assert!(!text.contains("errors") && !text.contains("duration"));
+    }
+}
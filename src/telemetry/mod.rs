@@ -0,0 +1,305 @@
+//! Tool call metrics and spans
+//!
+//! Every tool call is timed and recorded in [`metrics::ToolMetrics`], which the
+//! Prometheus `/metrics` endpoint serves on localhost when it is enabled in the
+//! configuration. When telemetry
+//! is enabled, each call also becomes a span; spans and a metrics snapshot are
+//! batched and flushed to the configured [`sink::Sink`].
+
+use once_cell::sync::Lazy;
+use rmcp::{
+    model::{CallToolResult, RawContent},
+    Error as McpError,
+};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+use crate::config::config::TelemetryConfig;
+use crate::error::{WinxError, WinxResult};
+
+pub mod metrics;
+pub mod sink;
+
+use metrics::{MetricsOptions, ToolMetrics};
+use sink::{unix_nanos, Sink, Span};
+
+/// Spans kept while the sink is failing, the oldest are dropped beyond it
+const MAX_PENDING_SPANS: usize = 10_000;
+
+static TELEMETRY: Lazy<RwLock<Arc<Telemetry>>> =
+    Lazy::new(|| RwLock::new(Arc::new(Telemetry::new(MetricsOptions::default(), None))));
+
+struct Exporter {
+    sink: Sink,
+    batch_size: usize,
+    pending: Mutex<Vec<Span>>,
+    /// Wakes the flush task early when a batch is full
+    batch_full: Notify,
+}
+
+struct Telemetry {
+    metrics: Mutex<ToolMetrics>,
+    include_errors: bool,
+    include_performance: bool,
+    exporter: Option<Exporter>,
+    start_unix_nanos: u64,
+}
+
+impl Telemetry {
+    fn new(options: MetricsOptions, exporter: Option<Exporter>) -> Self {
+        Self {
+            metrics: Mutex::new(ToolMetrics::new(options)),
+            include_errors: options.include_errors,
+            include_performance: options.include_performance,
+            exporter,
+            start_unix_nanos: unix_nanos(),
+        }
+    }
+
+    async fn flush(&self) -> WinxResult<()> {
+        let Some(exporter) = &self.exporter else {
+            return Ok(());
+        };
+        let spans =
+            std::mem::take(&mut *exporter.pending.lock().unwrap_or_else(|e| e.into_inner()));
+        // Metrics only change with calls, an idle agent exports nothing
+        if spans.is_empty() {
+            return Ok(());
+        }
+        let metrics = self
+            .metrics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        if let Err(e) = exporter
+            .sink
+            .export(&spans, &metrics, self.start_unix_nanos)
+            .await
+        {
+            // Keep the spans for the next attempt
+            let mut pending = exporter.pending.lock().unwrap_or_else(|e| e.into_inner());
+            let mut retry = spans;
+            retry.append(&mut pending);
+            let excess = retry.len().saturating_sub(MAX_PENDING_SPANS);
+            retry.drain(..excess);
+            *pending = retry;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+fn current() -> Arc<Telemetry> {
+    TELEMETRY.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Applies the telemetry configuration and starts the flush task when enabled
+///
+/// Must be called within a Tokio runtime when telemetry is enabled.
+pub fn init(config: &TelemetryConfig) -> WinxResult<()> {
+    let options = MetricsOptions {
+        include_errors: config.include_errors,
+        include_performance: config.include_performance,
+    };
+    let exporter = if config.enabled {
+        let sink = Sink::from_endpoint(config.endpoint.as_deref())?;
+        log::info!("Telemetry enabled, exporting to {}", sink_name(&sink));
+        Some(Exporter {
+            sink,
+            batch_size: config.batch_size.max(1),
+            pending: Mutex::new(Vec::new()),
+            batch_full: Notify::new(),
+        })
+    } else {
+        None
+    };
+
+    let telemetry = Arc::new(Telemetry::new(options, exporter));
+    if telemetry.exporter.is_some() {
+        let runtime = tokio::runtime::Handle::try_current().map_err(|_| {
+            WinxError::other("Telemetry export requires a Tokio runtime".to_string())
+        })?;
+        runtime.spawn(flush_loop(
+            Arc::downgrade(&telemetry),
+            Duration::from_secs(config.flush_interval_secs.max(1)),
+        ));
+    }
+    *TELEMETRY.write().unwrap_or_else(|e| e.into_inner()) = telemetry;
+    Ok(())
+}
+
+fn sink_name(sink: &Sink) -> String {
+    match sink {
+        Sink::File(path) => path.display().to_string(),
+        Sink::Otlp { base_url, .. } => base_url.clone(),
+    }
+}
+
+/// Flushes every interval or when a batch fills up, until the telemetry is replaced
+async fn flush_loop(telemetry: std::sync::Weak<Telemetry>, interval: Duration) {
+    loop {
+        let Some(telemetry) = telemetry.upgrade() else {
+            return;
+        };
+        let Some(exporter) = &telemetry.exporter else {
+            return;
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = exporter.batch_full.notified() => {}
+        }
+        if !Arc::ptr_eq(&telemetry, &current()) {
+            return;
+        }
+        if let Err(e) = telemetry.flush().await {
+            log::warn!("Telemetry flush failed: {}", e);
+        }
+    }
+}
+
+/// Exports the pending spans and metrics now, e.g. before shutdown
+pub async fn flush() -> WinxResult<()> {
+    current().flush().await
+}
+
+/// Metrics in the Prometheus text exposition format
+pub fn render_prometheus() -> String {
+    current()
+        .metrics
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .render_prometheus()
+}
+
+/// Runs a tool call, recording its latency, outcome and output size
+pub async fn instrument<F>(tool: &str, call: F) -> Result<CallToolResult, McpError>
+where
+    F: Future<Output = Result<CallToolResult, McpError>>,
+{
+    let start_unix_nanos = unix_nanos();
+    let start = Instant::now();
+    let result = call.await;
+    let elapsed = start.elapsed();
+
+    let (error, message, output_bytes) = match &result {
+        Ok(outcome) => (
+            outcome.is_error == Some(true),
+            None,
+            outcome.content.iter().map(|c| content_size(&c.raw)).sum(),
+        ),
+        Err(e) => (true, Some(e.message.to_string()), 0),
+    };
+
+    let telemetry = current();
+    telemetry
+        .metrics
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .record(tool, elapsed.as_secs_f64(), output_bytes, error);
+
+    if let Some(exporter) = &telemetry.exporter {
+        let mut attributes = BTreeMap::new();
+        attributes.insert("tool".to_string(), json!(tool));
+        if telemetry.include_performance {
+            attributes.insert("output.bytes".to_string(), json!(output_bytes));
+        }
+        if let (true, Some(message)) = (telemetry.include_errors, message) {
+            attributes.insert("error.message".to_string(), json!(message));
+        }
+        let span = Span {
+            trace_id: format!("{:032x}", rand::random::<u128>()),
+            span_id: format!("{:016x}", rand::random::<u64>()),
+            name: format!("tool/{}", tool),
+            start_unix_nanos,
+            end_unix_nanos: start_unix_nanos + elapsed.as_nanos() as u64,
+            error,
+            attributes,
+        };
+        let mut pending = exporter.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending.push(span);
+        if pending.len() >= exporter.batch_size {
+            exporter.batch_full.notify_one();
+        }
+    }
+    result
+}
+
+fn content_size(content: &RawContent) -> usize {
+    match content {
+        RawContent::Text(text) => text.text.len(),
+        RawContent::Image(image) => image.data.len(),
+        other => serde_json::to_string(other).map_or(0, |s| s.len()),
+    }
+}
+
+/// Serves `GET /metrics` on `addr` until the process exits
+pub async fn serve_metrics(addr: SocketAddr) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    log::info!("Serving Prometheus metrics on http://{}/metrics", addr);
+    serve_metrics_on(listener).await
+}
+
+async fn serve_metrics_on(listener: tokio::net::TcpListener) -> std::io::Result<()> {
+    use axum::{http::header, routing::get, Router};
+
+    let app = Router::new().route(
+        "/metrics",
+        get(|| async {
+            (
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                render_prometheus(),
+            )
+        }),
+    );
+    axum::serve(listener, app).await
+}
+
+/// Starts the `/metrics` endpoint on localhost when the configuration enables it
+pub fn spawn_metrics_endpoint(config: &TelemetryConfig) {
+    if !config.metrics_endpoint {
+        return;
+    }
+    let addr = SocketAddr::from(([127, 0, 0, 1], config.metrics_port));
+    tokio::spawn(async move {
+        if let Err(e) = serve_metrics(addr).await {
+            log::warn!("Failed to serve metrics on {}: {}", addr, e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::model::Content;
+
+    #[tokio::test]
+    async fn test_metrics_endpoint_serves_tool_calls() {
+        let _ = instrument("metrics_endpoint_probe", async {
+            Ok(CallToolResult::success(vec![Content::text("ok")]))
+        })
+        .await;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_metrics_on(listener));
+
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let response = client
+            .get(format!("http://{}/metrics", addr))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        let body = response.text().await.unwrap();
+        assert!(
+            body.contains("winx_tool_calls_total{tool=\"metrics_endpoint_probe\"}"),
+            "{}",
+            body
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! Destinations of exported spans and metrics
+//!
+//! The file sink appends JSON lines, the OTLP sink posts the OTLP/HTTP JSON
+//! encoding to a collector on the local machine.
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use super::metrics::{Histogram, ToolMetrics};
+use crate::error::{WinxError, WinxResult};
+
+const SERVICE_NAME: &str = "winx-code-agent";
+
+/// A finished tool call
+#[derive(Debug, Clone, Serialize)]
+pub struct Span {
+    pub trace_id: String,
+    pub span_id: String,
+    pub name: String,
+    pub start_unix_nanos: u64,
+    pub end_unix_nanos: u64,
+    pub error: bool,
+    pub attributes: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Sink {
+    /// JSON lines appended to a file
+    File(PathBuf),
+    /// OTLP/HTTP collector, e.g. `http://localhost:4318`
+    Otlp {
+        base_url: String,
+        client: reqwest::Client,
+    },
+}
+
+impl Sink {
+    /// Sink of a `TelemetryConfig::endpoint`
+    ///
+    /// `http://` endpoints must be on the loopback interface, anything else is a
+    /// file path, optionally prefixed with `file://`. Without an endpoint the data
+    /// goes to `telemetry.jsonl` in the local data directory.
+    pub fn from_endpoint(endpoint: Option<&str>) -> WinxResult<Self> {
+        let Some(endpoint) = endpoint else {
+            return Ok(Self::File(
+                dirs::data_local_dir()
+                    .unwrap_or_else(|| PathBuf::from("."))
+                    .join("winx-code-agent")
+                    .join("telemetry.jsonl"),
+            ));
+        };
+
+        if endpoint.starts_with("http://") {
+            let url = reqwest::Url::parse(endpoint).map_err(|e| {
+                WinxError::invalid_argument(format!(
+                    "Invalid telemetry endpoint '{}': {}",
+                    endpoint, e
+                ))
+            })?;
+            let loopback = url.host_str().is_some_and(|host| {
+                host == "localhost"
+                    || host
+                        .trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .parse::<std::net::IpAddr>()
+                        .is_ok_and(|ip| ip.is_loopback())
+            });
+            if !loopback {
+                return Err(WinxError::invalid_argument(format!(
+                    "Telemetry endpoint '{}' is not on localhost, only local collectors are supported",
+                    endpoint
+                )));
+            }
+            return Ok(Self::Otlp {
+                base_url: endpoint.trim_end_matches('/').to_string(),
+                client: reqwest::Client::new(),
+            });
+        }
+
+        let path = endpoint.strip_prefix("file://").unwrap_or(endpoint);
+        Ok(Self::File(crate::security::paths::expand_tilde(path)))
+    }
+
+    pub async fn export(
+        &self,
+        spans: &[Span],
+        metrics: &ToolMetrics,
+        start_unix_nanos: u64,
+    ) -> WinxResult<()> {
+        let now = unix_nanos();
+        match self {
+            Self::File(path) => {
+                let mut lines = String::new();
+                for span in spans {
+                    let mut line = serde_json::to_value(span)?;
+                    line["type"] = json!("span");
+                    lines.push_str(&line.to_string());
+                    lines.push('\n');
+                }
+                if !metrics.tools().is_empty() {
+                    let snapshot = json!({
+                        "type": "metrics",
+                        "time_unix_nanos": now,
+                        "tools": metrics.tools(),
+                    });
+                    lines.push_str(&snapshot.to_string());
+                    lines.push('\n');
+                }
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || append(&path, &lines))
+                    .await
+                    .map_err(|e| WinxError::other(format!("Telemetry writer failed: {}", e)))?
+            }
+            Self::Otlp { base_url, client } => {
+                if !spans.is_empty() {
+                    post(
+                        client,
+                        &format!("{}/v1/traces", base_url),
+                        &otlp_traces(spans),
+                    )
+                    .await?;
+                }
+                if !metrics.tools().is_empty() {
+                    let body = otlp_metrics(metrics, start_unix_nanos, now);
+                    post(client, &format!("{}/v1/metrics", base_url), &body).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn append(path: &PathBuf, lines: &str) -> WinxResult<()> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| WinxError::io_error(e, Some(parent)))?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| WinxError::io_error(e, Some(path)))?;
+    file.write_all(lines.as_bytes())
+        .map_err(|e| WinxError::io_error(e, Some(path)))
+}
+
+async fn post(client: &reqwest::Client, url: &str, body: &Value) -> WinxResult<()> {
+    let response =
+        client.post(url).json(body).send().await.map_err(|e| {
+            WinxError::other(format!("Failed to export telemetry to {}: {}", url, e))
+        })?;
+    if !response.status().is_success() {
+        return Err(WinxError::other(format!(
+            "Telemetry collector at {} answered {}",
+            url,
+            response.status()
+        )));
+    }
+    Ok(())
+}
+
+pub fn unix_nanos() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+fn resource() -> Value {
+    json!({
+        "attributes": [attribute("service.name", &json!(SERVICE_NAME))]
+    })
+}
+
+fn attribute(key: &str, value: &Value) -> Value {
+    let value = match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_i64() || n.is_u64() => json!({ "intValue": n.to_string() }),
+        Value::Number(n) => json!({ "doubleValue": n.as_f64() }),
+        Value::String(s) => json!({ "stringValue": s }),
+        other => json!({ "stringValue": other.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+fn otlp_traces(spans: &[Span]) -> Value {
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|span| {
+            let message = span.attributes.get("error.message").and_then(Value::as_str);
+            json!({
+                "traceId": span.trace_id,
+                "spanId": span.span_id,
+                "name": span.name,
+                // SPAN_KIND_SERVER, the span covers an incoming tool call
+                "kind": 2,
+                "startTimeUnixNano": span.start_unix_nanos.to_string(),
+                "endTimeUnixNano": span.end_unix_nanos.to_string(),
+                "attributes": span
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| attribute(key, value))
+                    .collect::<Vec<_>>(),
+                "status": if span.error {
+                    json!({ "code": 2, "message": message.unwrap_or_default() })
+                } else {
+                    json!({ "code": 1 })
+                },
+            })
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": resource(),
+            "scopeSpans": [{ "scope": { "name": SERVICE_NAME }, "spans": spans }],
+        }]
+    })
+}
+
+fn otlp_metrics(metrics: &ToolMetrics, start: u64, now: u64) -> Value {
+    // Cumulative temporality, counts start with the process
+    const CUMULATIVE: u8 = 2;
+
+    let point = |tool: &str| {
+        json!({
+            "attributes": [attribute("tool", &json!(tool))],
+            "startTimeUnixNano": start.to_string(),
+            "timeUnixNano": now.to_string(),
+        })
+    };
+    let sum = |name: &str, value: fn(&super::metrics::ToolStats) -> u64| {
+        let points: Vec<Value> = metrics
+            .tools()
+            .iter()
+            .map(|(tool, stats)| {
+                let mut p = point(tool);
+                p["asInt"] = json!(value(stats).to_string());
+                p
+            })
+            .collect();
+        json!({
+            "name": name,
+            "unit": "1",
+            "sum": { "aggregationTemporality": CUMULATIVE, "isMonotonic": true, "dataPoints": points },
+        })
+    };
+    let histogram =
+        |name: &str, unit: &str, value: fn(&super::metrics::ToolStats) -> &Histogram| {
+            let points: Vec<Value> = metrics
+                .tools()
+                .iter()
+                .map(|(tool, stats)| {
+                    let histogram = value(stats);
+                    let mut p = point(tool);
+                    p["count"] = json!(histogram.count.to_string());
+                    p["sum"] = json!(histogram.sum);
+                    p["bucketCounts"] = json!(histogram
+                        .counts
+                        .iter()
+                        .map(|c| c.to_string())
+                        .collect::<Vec<_>>());
+                    p["explicitBounds"] = json!(histogram.bounds);
+                    p
+                })
+                .collect();
+            json!({
+                "name": name,
+                "unit": unit,
+                "histogram": { "aggregationTemporality": CUMULATIVE, "dataPoints": points },
+            })
+        };
+
+    let mut series = vec![sum("winx.tool.calls", |s| s.calls)];
+    let options = metrics.options();
+    if options.include_errors {
+        series.push(sum("winx.tool.errors", |s| s.errors));
+    }
+    if options.include_performance {
+        series.push(histogram("winx.tool.duration", "s", |s| {
+            &s.duration_seconds
+        }));
+        series.push(histogram("winx.tool.output_size", "By", |s| {
+            &s.output_bytes
+        }));
+    }
+    json!({
+        "resourceMetrics": [{
+            "resource": resource(),
+            "scopeMetrics": [{ "scope": { "name": SERVICE_NAME }, "metrics": series }],
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_endpoints_and_file_export() {
+        assert!(matches!(
+            Sink::from_endpoint(Some("http://localhost:4318")),
+            Ok(Sink::Otlp { .. })
+        ));
+        assert!(Sink::from_endpoint(Some("http://[::1]:4318/")).is_ok());
+        assert!(Sink::from_endpoint(Some("http://collector.example.com:4318")).is_err());
+        assert!(Sink::from_endpoint(Some("http://10.0.0.1:4318")).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out/telemetry.jsonl");
+        let sink = Sink::from_endpoint(Some(&format!("file://{}", path.display()))).unwrap();
+
+        let mut metrics = ToolMetrics::default();
+        metrics.record("bash_command", 0.5, 42, false);
+        let span = Span {
+            trace_id: "0".repeat(32),
+            span_id: "1".repeat(16),
+            name: "tool/bash_command".to_string(),
+            start_unix_nanos: 1,
+            end_unix_nanos: 2,
+            error: false,
+            attributes: BTreeMap::new(),
+        };
+        sink.export(std::slice::from_ref(&span), &metrics, 0)
+            .await
+            .unwrap();
+
+        let lines: Vec<Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["type"], "span");
+        assert_eq!(lines[1]["tools"]["bash_command"]["calls"], 1);
+
+        let traces = otlp_traces(&[span]);
+        assert_eq!(
+            traces["resourceSpans"][0]["scopeSpans"][0]["spans"][0]["status"]["code"],
+            1
+        );
+        let exported = otlp_metrics(&metrics, 0, 1);
+        let series = &exported["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        assert_eq!(series.as_array().unwrap().len(), 4);
+        assert_eq!(series[0]["sum"]["dataPoints"][0]["asInt"], "1");
+    }
+}
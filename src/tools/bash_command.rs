@@ -137,7 +137,9 @@ impl BashCommand {
                 }
             };
 
+            let sandbox = Initialize::command_sandbox()?;
             let mut cmd_runner = CommandRunner::new(&workspace_path);
+            cmd_runner.set_sandbox(sandbox.clone());
             if let Err(e) = cmd_runner.start_shell() {
                 log::error!("Failed to start shell in '{}': {}", workspace_path, e);
                 // Try again with home directory as fallback
@@ -147,6 +149,7 @@ impl BashCommand {
                     .to_string();
                 log::warn!("Falling back to home directory: {}", home_dir);
                 cmd_runner = CommandRunner::new(&home_dir);
+                cmd_runner.set_sandbox(sandbox);
                 cmd_runner.start_shell()?;
             }
             *runner = Some(cmd_runner);
@@ -165,8 +168,10 @@ impl BashCommand {
         })?;
 
         if let Some(ref runner) = *runner {
-            // Clone the CommandRunner
-            Ok(runner.clone())
+            // Clone the CommandRunner, with the limits of the current mode
+            let mut runner = runner.clone();
+            runner.set_limits(Initialize::command_limits()?);
+            Ok(runner)
         } else {
             Err(WinxError::ShellNotStarted)
         }
@@ -690,7 +695,7 @@ impl BashCommand {
         };
 
         Ok(CallToolResult::success(vec![rmcp::model::Content::text(
-            result,
+            Initialize::redact_output("bash_command", result),
         )]))
     }
 }
@@ -110,6 +110,12 @@ impl ContextSave {
         // Read and add file contents
         for file_path in all_files.iter().take(10) {
             // Limiting to 10 files for example
+            // Globs can reach outside the allowed paths, skip denied files
+            if let Err(e) = Initialize::resolve_path(Action::ReadFile, &file_path.to_string_lossy())
+            {
+                content.push_str(&format!("\n# Skipped {}: {}\n", file_path.display(), e));
+                continue;
+            }
             if let Ok(file_content) = fs::read_to_string(file_path) {
                 content.push_str(&format!(
                     "\n# File: {}\n```\n{}\n```\n",
@@ -6,13 +6,17 @@ use serde_json::json;
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::fs;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
+use crate::file::diff::{compact_unified_diff, unified_diff, DEFAULT_CONTEXT_LINES};
 use crate::file::search_replace::{
-    apply_search_replace_with_fallback, is_search_replace_content, parse_search_replace_blocks,
+    apply_search_replace_with_fallback, apply_search_replace_with_fallback_detailed,
+    is_search_replace_content, parse_search_replace_blocks,
 };
 use crate::file::syntax_checker::check_syntax;
+use crate::security::redact::introduced_placeholder;
 use crate::tools::initialize::{Action, Initialize};
 
 /// Parse file path with optional line ranges
@@ -31,6 +35,90 @@ fn parse_line_ranges(file_path: &str) -> (String, Option<usize>, Option<usize>)
     }
 }
 
+/// Error for a failed write, explicit about permission problems instead of writing elsewhere
+fn write_error(path: &Path, operation: &str, e: std::io::Error) -> McpError {
+    let message = match e.kind() {
+        std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem => format!(
+            "Failed to {} for {}: {}. The location is not writable, choose a path inside the workspace or another writable directory.",
+            operation,
+            path.display(),
+            e
+        ),
+        _ => format!("Failed to {} for {}: {}", operation, path.display(), e),
+    };
+    McpError::new(
+        ErrorCode::INTERNAL_ERROR,
+        message,
+        Some(json!({"error": e.to_string(), "path": path.display().to_string()})),
+    )
+}
+
+/// Rejects new content carrying a redaction placeholder the file doesn't already have
+///
+/// Placeholders only appear in redacted tool output, writing one back would replace
+/// the secret it stands for.
+fn check_placeholders(path: &Path, new_content: &str, original: &str) -> Result<(), McpError> {
+    match introduced_placeholder(new_content, original) {
+        Some(placeholder) => Err(McpError::new(
+            ErrorCode::INVALID_PARAMS,
+            format!(
+                "The new content of {} contains the redaction placeholder {}, which stands for a secret hidden from tool output. Leave the text it replaced untouched instead of copying the placeholder.",
+                path.display(),
+                placeholder
+            ),
+            Some(json!({"error": "redaction_placeholder", "placeholder": placeholder})),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Formats the result of a dry run, showing the diff that would have been written
+fn format_dry_run_result(path: &Path, diff: &str, warnings: &[String]) -> String {
+    let mut result = if diff.is_empty() {
+        format!(
+            "Dry run: the edit produces no changes to {}. Nothing was written.",
+            path.display()
+        )
+    } else {
+        format!(
+            "Dry run: no changes were written to {}. Proposed diff:\n```diff\n{}```",
+            path.display(),
+            diff
+        )
+    };
+
+    if !warnings.is_empty() {
+        result.push_str(&format!("\n\nWarnings: {}", warnings.join(", ")));
+    }
+
+    result
+}
+
+/// Describes where each replaced block ended up, using 1-based line numbers
+fn format_replaced_ranges(ranges: &[Range<usize>], match_counts: &[Option<usize>]) -> String {
+    let mut summary = String::from("Replaced blocks (new line numbers):");
+    for (i, range) in ranges.iter().enumerate() {
+        if range.start == range.end {
+            summary.push_str(&format!(
+                "\n- Block {}: removed at line {}",
+                i + 1,
+                range.start + 1
+            ));
+        } else {
+            summary.push_str(&format!(
+                "\n- Block {}: lines {}-{}",
+                i + 1,
+                range.start + 1,
+                range.end
+            ));
+        }
+        if let Some(Some(count)) = match_counts.get(i) {
+            summary.push_str(&format!(" ({} regex matches)", count));
+        }
+    }
+    summary
+}
+
 // Global whitelist for file access
 lazy_static::lazy_static! {
     static ref FILE_WHITELIST: Arc<Mutex<HashMap<PathBuf, FileWhitelistData>>> =
@@ -43,6 +131,8 @@ struct FileWhitelistData {
     file_hash: String,
     line_ranges_read: Vec<(usize, usize)>,
     total_lines: usize,
+    /// Set when the file changed on disk after it was read
+    stale: bool,
 }
 
 impl FileWhitelistData {
@@ -51,6 +141,7 @@ impl FileWhitelistData {
             file_hash,
             line_ranges_read,
             total_lines,
+            stale: false,
         }
     }
 
@@ -108,6 +199,29 @@ impl FileWhitelistData {
     }
 }
 
+/// Marks the whitelist entry of `path` stale if the file no longer matches what was read
+///
+/// Called by the workspace watcher. Edits made through the tools update the recorded
+/// hash, so only changes made outside the agent mark an entry.
+pub fn mark_whitelist_stale(path: &Path) {
+    let Ok(mut whitelist) = FILE_WHITELIST.lock() else {
+        return;
+    };
+    let Some(entry) = whitelist.get_mut(path) else {
+        return;
+    };
+
+    // Hash under the lock so a concurrent edit can't be mistaken for an external change
+    let current_hash = fs::read(path).ok().map(|content| {
+        let mut hasher = Sha256::new();
+        hasher.update(&content);
+        format!("{:x}", hasher.finalize())
+    });
+    if current_hash.as_deref() != Some(entry.file_hash.as_str()) {
+        entry.stale = true;
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FileOperations {
     // State is managed globally via FILE_WHITELIST
@@ -150,8 +264,10 @@ impl FileOperations {
             .entry(file_path.to_path_buf())
             .or_insert_with(|| FileWhitelistData::new(file_hash.clone(), Vec::new(), total_lines));
 
-        // Update hash if changed
-        entry.file_hash = file_hash;
+        // Ranges read from an older version of the file no longer count
+        if entry.stale || entry.file_hash != file_hash {
+            *entry = FileWhitelistData::new(file_hash, Vec::new(), total_lines);
+        }
 
         // Add new ranges
         for range in ranges {
@@ -179,7 +295,7 @@ impl FileOperations {
                 hasher.update(&content);
                 let current_hash = format!("{:x}", hasher.finalize());
 
-                return Ok(current_hash == data.file_hash && data.is_read_enough());
+                return Ok(!data.stale && current_hash == data.file_hash && data.is_read_enough());
             }
         }
 
@@ -197,7 +313,8 @@ impl FileOperations {
             )
         })?;
 
-        if let Some(data) = whitelist.get(file_path) {
+        // A changed file has to be read again entirely, not just the unread ranges
+        if let Some(data) = whitelist.get(file_path).filter(|d| !d.stale) {
             return Ok(data.get_unread_ranges());
         }
 
@@ -216,7 +333,6 @@ impl WriteIfEmpty {
     }
 
     // Check if file exists and is empty or doesn't exist
-    #[allow(dead_code)]
     fn is_file_empty_or_nonexistent(&self, path: &Path) -> Result<bool, McpError> {
         if path.exists() {
             let metadata = fs::metadata(path).map_err(|e| {
@@ -233,28 +349,21 @@ impl WriteIfEmpty {
         }
     }
 
-    // Check if it should redirect to a temporary path
-    fn check_and_redirect(&self, path: &Path) -> Option<PathBuf> {
-        // If the path is at the root but not in /tmp or in the user's home
-        if path.starts_with("/")
-            && !path.starts_with("/tmp")
-            && !path.starts_with("/Users")
-            && !path.starts_with("/home")
-        {
-            // Redirect to /tmp with the same filename
-            let file_name = path.file_name().unwrap_or_default();
-            let redirected = PathBuf::from("/tmp").join(file_name);
-
-            log::warn!(
-                "Redirecting file operation from {} to {} due to possible read-only filesystem",
-                path.display(),
-                redirected.display()
-            );
-
-            Some(redirected)
-        } else {
-            None
+    // Preview a write without creating directories or touching the file
+    fn preview_write(&self, path: &Path, content: &str) -> Result<CallToolResult, McpError> {
+        if !self.is_file_empty_or_nonexistent(path)? {
+            return Ok(CallToolResult::success(vec![rmcp::model::Content::text(
+                format!("Error: File {} already exists and is not empty. Use FileEdit to modify existing files.", path.display())
+            )]));
         }
+
+        let original = if path.exists() { Some("") } else { None };
+        let diff = unified_diff(path, original, content, DEFAULT_CONTEXT_LINES);
+        let syntax_warnings = check_syntax(path, content);
+
+        Ok(CallToolResult::success(vec![rmcp::model::Content::text(
+            format_dry_run_result(path, &diff, &syntax_warnings),
+        )]))
     }
 }
 
@@ -301,8 +410,10 @@ impl FileEdit {
             .entry(file_path.to_path_buf())
             .or_insert_with(|| FileWhitelistData::new(file_hash.clone(), Vec::new(), total_lines));
 
-        // Update hash if changed
-        entry.file_hash = file_hash;
+        // Ranges read from an older version of the file no longer count
+        if entry.stale || entry.file_hash != file_hash {
+            *entry = FileWhitelistData::new(file_hash, Vec::new(), total_lines);
+        }
 
         // Add new ranges
         for range in ranges {
@@ -330,7 +441,7 @@ impl FileEdit {
                 hasher.update(&content);
                 let current_hash = format!("{:x}", hasher.finalize());
 
-                return Ok(current_hash == data.file_hash && data.is_read_enough());
+                return Ok(!data.stale && current_hash == data.file_hash && data.is_read_enough());
             }
         }
 
@@ -348,7 +459,8 @@ impl FileEdit {
             )
         })?;
 
-        if let Some(data) = whitelist.get(file_path) {
+        // A changed file has to be read again entirely, not just the unread ranges
+        if let Some(data) = whitelist.get(file_path).filter(|d| !d.stale) {
             return Ok(data.get_unread_ranges());
         }
 
@@ -390,6 +502,11 @@ pub struct WriteIfEmptyParams {
 
     #[schemars(description = "Content to write to file")]
     pub file_content: String,
+
+    #[schemars(
+        description = "If true, only preview the file as a unified diff and run syntax checks without writing it"
+    )]
+    pub dry_run: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
@@ -399,6 +516,11 @@ pub struct FileEditParams {
 
     #[schemars(description = "Edit using search/replace blocks")]
     pub file_edit_using_search_replace_blocks: String,
+
+    #[schemars(
+        description = "If true, return the unified diff and syntax check results without modifying the file"
+    )]
+    pub dry_run: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
@@ -453,12 +575,6 @@ impl FileOperations {
         let mut file_ranges = Vec::new();
 
         for file_path in &params.file_paths {
-            let path = PathBuf::from(file_path);
-            if !path.exists() {
-                result.push_str(&format!("\n{}: File does not exist\n", file_path));
-                continue;
-            }
-
             // Parse line ranges if present in the file path
             let (parsed_path, start_line, end_line) = parse_line_ranges(file_path);
             let effective_path = if parsed_path.is_empty() {
@@ -467,7 +583,18 @@ impl FileOperations {
                 &parsed_path
             };
 
-            let range_path = PathBuf::from(effective_path);
+            // Denied paths are reported per file so the other files are still read
+            let range_path = match Initialize::resolve_path(Action::ReadFile, effective_path) {
+                Ok(path) => path,
+                Err(e) => {
+                    result.push_str(&format!("\n{}: {}\n", file_path, e));
+                    continue;
+                }
+            };
+            if !range_path.exists() {
+                result.push_str(&format!("\n{}: File does not exist\n", file_path));
+                continue;
+            }
 
             // Use a read operation that avoids cache to ensure more fresh data
             match fs::read(&range_path) {
@@ -582,7 +709,7 @@ impl FileOperations {
         }
 
         Ok(CallToolResult::success(vec![rmcp::model::Content::text(
-            result,
+            Initialize::redact_output("read_files", result),
         )]))
     }
 
@@ -603,7 +730,8 @@ impl FileOperations {
             ));
         }
 
-        let path = PathBuf::from(&params.file_path);
+        let path = Initialize::resolve_path(Action::WriteFile, &params.file_path)
+            .map_err(|e| e.to_mcp_error())?;
 
         // Create parent directories if they don't exist
         if let Some(parent) = path.parent() {
@@ -642,14 +770,14 @@ impl FileOperations {
                             .join(", ");
 
                         return Ok(CallToolResult::success(vec![rmcp::model::Content::text(
-                            format!("Error: you need to read more of the file before it can be overwritten.\nUnread line ranges: {}\n\nHere's the unread content:\n```\n{}\n```\n\nYou can now safely retry writing immediately considering the above information.",
-                                    ranges_str, file_content)
+                            Initialize::redact_output("file_write_or_edit", format!("Error: you need to read more of the file before it can be overwritten.\nUnread line ranges: {}\n\nHere's the unread content:\n```\n{}\n```\n\nYou can now safely retry writing immediately considering the above information.",
+                                    ranges_str, file_content))
                         )]));
                     } else {
                         // File hash doesn't match, file may have changed
                         return Ok(CallToolResult::success(vec![rmcp::model::Content::text(
-                            format!("Error: the file has changed since last read.\n\nHere's the existing file:\n```\n{}\n```\n\nYou can now safely retry writing immediately considering the above information.",
-                                    file_content)
+                            Initialize::redact_output("file_write_or_edit", format!("Error: the file has changed since last read.\n\nHere's the existing file:\n```\n{}\n```\n\nYou can now safely retry writing immediately considering the above information.",
+                                    file_content))
                         )]));
                     }
                 } else {
@@ -687,16 +815,20 @@ impl FileOperations {
                 log::debug!("{}", msg);
             })
                 .map_err(|e| {
-                    // Keep the original error message which includes instructions for retry
+                    // Keep the original error message which includes instructions for retry,
+                    // the closest match it quotes comes from the file
+                    let error = Initialize::redact_text(&e.to_string());
                     McpError::new(
                         ErrorCode::INVALID_PARAMS,
-                        format!("Search/Replace error: {}", e),
-                        Some(json!({"error": e.to_string(), "percentage_to_change": params.percentage_to_change})),
+                        format!("Search/Replace error: {}", error),
+                        Some(json!({"error": error, "percentage_to_change": params.percentage_to_change})),
                     )
                 });
 
             match result {
                 Ok((edited_content, warnings)) => {
+                    check_placeholders(&path, &edited_content, &original_content)?;
+
                     // Write the edited file
                     fs::write(&path, edited_content).map_err(|e| {
                         McpError::new(
@@ -723,6 +855,13 @@ impl FileOperations {
                 Err(e) => return Err(e),
             }
         } else {
+            let original_content = fs::read_to_string(&path).unwrap_or_default();
+            check_placeholders(
+                &path,
+                &params.file_content_or_search_replace_blocks,
+                &original_content,
+            )?;
+
             // Direct write
             fs::write(&path, &params.file_content_or_search_replace_blocks).map_err(|e| {
                 McpError::new(
@@ -755,16 +894,13 @@ impl FileOperations {
         // Check if initialization has been done
         crate::ensure_initialized!("You must call 'initialize' before reading images.");
 
-        // Check permission
-        // Convert WinxError to McpError for permissions
-        if let Err(e) = Initialize::check_permission(Action::ReadImage, None) {
-            return Err(e.to_mcp_error());
-        }
+        // Resolve the path and check permission
+        let path = Initialize::resolve_path(Action::ReadImage, &params.file_path)
+            .map_err(|e| e.to_mcp_error())?;
 
         use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
         use std::io::Read;
 
-        let path = PathBuf::from(&params.file_path);
         if !path.exists() {
             return Ok(CallToolResult::success(vec![rmcp::model::Content::text(
                 format!("Error: File {} does not exist", params.file_path),
@@ -802,7 +938,7 @@ impl FileOperations {
 #[tool(tool_box)]
 impl FileEdit {
     #[tool(
-        description = "\n- Edits existing files using search/replace blocks.\n- Uses intelligent search and replace with multiple tolerance levels.\n- Automatically fixes indentation issues when possible.\n- Provides detailed feedback on match failures with suggested fixes.\n- Includes fallback strategy for multiple block edits.\n"
+        description = "\n- Edits existing files using search/replace blocks.\n- Uses Aider-like search and replace syntax.\n- Also supports anchor operations: `<<<<<<< INSERT BEFORE`, `<<<<<<< INSERT AFTER`, `<<<<<<< DELETE` and `<<<<<<< REPLACE BETWEEN` (start anchor, `=======`, end anchor, `=======`, new lines). `# occurrence: N` applies to the anchor.\n- `<<<<<<< SEARCH REGEX` blocks take a multiline regex and a replacement using `$1`/`${name}` captures; every match is replaced unless `# occurrence: N` selects one, and the match count is reported.\n- File edit has spacing tolerant matching, with warning on issues like indentation mismatch.\n- If there's no match, the closest match is returned to help fix mistakes.\n- On success, returns the new line numbers of each replaced block and a compact diff of the change.\n- Set `dry_run` to true to get the unified diff and syntax check results without modifying the file. Dry runs are allowed in architect mode.\n"
     )]
     pub async fn file_edit(
         &self,
@@ -811,13 +947,17 @@ impl FileEdit {
         // Check if initialization has been done
         crate::ensure_initialized!("You must call 'initialize' before editing files.");
 
-        // Check permission
-        // Convert WinxError to McpError for permissions
-        if let Err(e) = Initialize::check_permission(Action::EditFile, Some(&params.file_path)) {
-            return Err(e.to_mcp_error());
-        }
+        // Dry runs never touch the file, so they only require read access
+        let dry_run = params.dry_run.unwrap_or(false);
+        let action = if dry_run {
+            Action::ReadFile
+        } else {
+            Action::EditFile
+        };
 
-        let path = PathBuf::from(&params.file_path);
+        // Resolve the path and check permission
+        let path =
+            Initialize::resolve_path(action, &params.file_path).map_err(|e| e.to_mcp_error())?;
 
         // Check if file exists
         if !path.exists() {
@@ -921,22 +1061,51 @@ impl FileEdit {
             };
 
         // Apply search/replace with fallback strategy
-        let result = apply_search_replace_with_fallback(&original_content, &blocks, |msg| {
-            log::debug!("{}", msg);
-        })
-        .map_err(|e| {
-            McpError::new(
-                ErrorCode::INVALID_PARAMS,
-                format!("Search/Replace error: {}", e),
-                Some(json!({"error": e.to_string()})),
-            )
-        });
+        let result =
+            apply_search_replace_with_fallback_detailed(&original_content, &blocks, |msg| {
+                log::debug!("{}", msg);
+            })
+            .map_err(|e| {
+                // The closest match quoted on a failed search comes from the file
+                let error = Initialize::redact_text(&e.to_string());
+                McpError::new(
+                    ErrorCode::INVALID_PARAMS,
+                    format!("Search/Replace error: {}", error),
+                    Some(json!({"error": error})),
+                )
+            });
 
         match result {
-            Ok((edited_content, warnings)) => {
+            Ok(outcome) => {
+                let edited_content = outcome.content;
+                check_placeholders(&path, &edited_content, &original_content)?;
+                let warnings = outcome.warnings;
+                let ranges_summary =
+                    format_replaced_ranges(&outcome.replaced_ranges, &outcome.match_counts);
+
                 // Check syntax before writing
                 let syntax_warnings = check_syntax(&path, &edited_content);
 
+                if dry_run {
+                    let diff = unified_diff(
+                        &path,
+                        Some(&original_content),
+                        &edited_content,
+                        DEFAULT_CONTEXT_LINES,
+                    );
+                    let mut all_warnings = warnings;
+                    all_warnings.extend(syntax_warnings);
+
+                    let mut result = format_dry_run_result(&path, &diff, &all_warnings);
+                    if !diff.is_empty() {
+                        result.push_str(&format!("\n\n{}", ranges_summary));
+                    }
+
+                    return Ok(CallToolResult::success(vec![rmcp::model::Content::text(
+                        Initialize::redact_output("file_edit", result),
+                    )]));
+                }
+
                 // Get a copy of edited content for hashing later
                 let edited_content_copy = edited_content.clone();
 
@@ -975,7 +1144,7 @@ impl FileEdit {
                 let mut all_warnings = warnings.clone();
                 all_warnings.extend(syntax_warnings);
 
-                let success_msg = if all_warnings.is_empty() {
+                let mut success_msg = if all_warnings.is_empty() {
                     format!("Success: File edited at {}", params.file_path)
                 } else {
                     format!(
@@ -985,8 +1154,17 @@ impl FileEdit {
                     )
                 };
 
+                // Report what actually changed so callers don't need to re-read the file
+                let diff = compact_unified_diff(&path, &original_content, &edited_content);
+                if !diff.is_empty() {
+                    success_msg.push_str(&format!(
+                        "\n\n{}\n\nDiff:\n```diff\n{}```",
+                        ranges_summary, diff
+                    ));
+                }
+
                 Ok(CallToolResult::success(vec![rmcp::model::Content::text(
-                    success_msg,
+                    Initialize::redact_output("file_edit", success_msg),
                 )]))
             }
             Err(e) => Err(e),
@@ -996,7 +1174,9 @@ impl FileEdit {
 
 #[tool(tool_box)]
 impl WriteIfEmpty {
-    #[tool(description = "Create new files or write to empty files only")]
+    #[tool(
+        description = "Create new files or write to empty files only.\n- Set `dry_run` to true to preview the file as a unified diff with syntax check results without writing it.\n"
+    )]
     pub async fn write_if_empty(
         &self,
         #[tool(aggr)] params: WriteIfEmptyParams,
@@ -1004,110 +1184,27 @@ impl WriteIfEmpty {
         // Check if initialization has been done
         crate::ensure_initialized!("You must call 'initialize' before creating files.");
 
-        // Check permission
-        // Convert WinxError to McpError for permissions
-        if let Err(e) = Initialize::check_permission(Action::WriteFile, Some(&params.file_path)) {
-            return Err(e.to_mcp_error());
-        }
-
-        let original_path = PathBuf::from(&params.file_path);
-
-        // Check if the path needs to be redirected (read-only filesystem)
-        let path = if let Some(redirected_path) = self.check_and_redirect(&original_path) {
-            // If redirected, let's use the new path
-            redirected_path
+        // Dry runs never touch the file, so they only require read access
+        let dry_run = params.dry_run.unwrap_or(false);
+        let action = if dry_run {
+            Action::ReadFile
         } else {
-            original_path.clone()
+            Action::WriteFile
         };
 
-        // Create warning message if there was a redirection
-        let warning = if path != original_path {
-            Some(format!(
-                "Warning: Using {} instead of {} because the target may be in a read-only location",
-                path.display(),
-                original_path.display()
-            ))
-        } else {
-            None
-        };
+        // Resolve the path and check permission
+        let path =
+            Initialize::resolve_path(action, &params.file_path).map_err(|e| e.to_mcp_error())?;
+
+        if dry_run {
+            return self.preview_write(&path, &params.file_content);
+        }
 
         // Create parent directories if they don't exist
         if let Some(parent) = path.parent() {
             if !parent.exists() {
-                let create_result = fs::create_dir_all(parent);
-                if let Err(ref e) = create_result {
-                    // Check if the error is a permission or read-only file system issue
-                    if e.kind() == std::io::ErrorKind::PermissionDenied
-                        || e.kind() == std::io::ErrorKind::Other
-                    {
-                        // Try to create in /tmp as a last option
-                        let tmp_path =
-                            PathBuf::from("/tmp").join(path.file_name().unwrap_or_default());
-                        let tmp_parent = tmp_path.parent().unwrap_or(&tmp_path);
-
-                        if let Err(tmp_err) = fs::create_dir_all(tmp_parent) {
-                            // If even /tmp doesn't work, return improved error
-                            return Err(McpError::new(
-                                ErrorCode::INVALID_PARAMS,
-                                format!(
-                                    "Could not create directory even in /tmp: {}. Please specify a path where you have write permissions.",
-                                    tmp_err
-                                ),
-                                Some(json!({"error": tmp_err.to_string()})),
-                            ));
-                        }
-
-                        // Use the path in /tmp directly, instead of calling recursively
-                        let warning = format!(
-                            "Warning: Could not create directory '{}' due to permission error: {}. Using '{}' instead.",
-                            path.display(), e, tmp_path.display()
-                        );
-
-                        fs::write(&tmp_path, &params.file_content).map_err(|e| {
-                            McpError::new(
-                                ErrorCode::INTERNAL_ERROR,
-                                format!("Failed to write file to /tmp path: {}", e),
-                                Some(json!({"error": e.to_string()})),
-                            )
-                        })?;
-
-                        // Add to whitelist for future edits
-                        let lines = params.file_content.lines().count();
-
-                        // Create content hash
-                        let mut hasher = Sha256::new();
-                        hasher.update(params.file_content.as_bytes());
-                        let file_hash = format!("{:x}", hasher.finalize());
-
-                        let mut whitelist = FILE_WHITELIST.lock().map_err(|e| {
-                            McpError::new(
-                                ErrorCode::INTERNAL_ERROR,
-                                format!("Failed to acquire lock: {}", e),
-                                None,
-                            )
-                        })?;
-
-                        whitelist.insert(
-                            tmp_path.clone(),
-                            FileWhitelistData::new(file_hash, vec![(1, lines)], lines),
-                        );
-
-                        return Ok(CallToolResult::success(vec![rmcp::model::Content::text(
-                            format!(
-                                "{}\n\nSuccess: New file created at {}",
-                                warning,
-                                tmp_path.display()
-                            ),
-                        )]));
-                    } else {
-                        // Other kinds of errors
-                        return Err(McpError::new(
-                            ErrorCode::INTERNAL_ERROR,
-                            format!("Failed to create directory: {}", e),
-                            Some(json!({"error": e.to_string()})),
-                        ));
-                    }
-                }
+                fs::create_dir_all(parent)
+                    .map_err(|e| write_error(&path, "create directory", e))?;
             }
         }
 
@@ -1128,77 +1225,12 @@ impl WriteIfEmpty {
             }
         }
 
+        check_placeholders(&path, &params.file_content, "")?;
+
         // Check syntax before writing
         let syntax_warnings = check_syntax(&path, &params.file_content);
 
-        // Write to the file with improved error handling
-        match fs::write(&path, &params.file_content) {
-            Ok(_) => {
-                // Success case
-            }
-            Err(e) => {
-                // Check if it's a permission or read-only file system error
-                if e.kind() == std::io::ErrorKind::PermissionDenied
-                    || e.kind() == std::io::ErrorKind::Other
-                {
-                    // Covers "Read-only file system"
-                    // Try writing to /tmp as a last resort
-                    let tmp_path = PathBuf::from("/tmp").join(path.file_name().unwrap_or_default());
-
-                    match fs::write(&tmp_path, &params.file_content) {
-                        Ok(_) => {
-                            // Add to whitelist for future edits
-                            let lines = params.file_content.lines().count();
-
-                            // Create content hash
-                            let mut hasher = Sha256::new();
-                            hasher.update(params.file_content.as_bytes());
-                            let file_hash = format!("{:x}", hasher.finalize());
-
-                            let mut whitelist = FILE_WHITELIST.lock().map_err(|e| {
-                                McpError::new(
-                                    ErrorCode::INTERNAL_ERROR,
-                                    format!("Failed to acquire lock: {}", e),
-                                    None,
-                                )
-                            })?;
-
-                            whitelist.insert(
-                                tmp_path.clone(),
-                                FileWhitelistData::new(file_hash, vec![(1, lines)], lines),
-                            );
-
-                            let result = format!(
-                                "Warning: Could not write to {} due to permission error. File created at {} instead.",
-                                path.display(),
-                                tmp_path.display()
-                            );
-
-                            return Ok(CallToolResult::success(vec![rmcp::model::Content::text(
-                                result,
-                            )]));
-                        }
-                        Err(tmp_err) => {
-                            return Err(McpError::new(
-                                ErrorCode::INTERNAL_ERROR,
-                                format!(
-                                    "Failed to write file to both original location and /tmp. Original error: {}. /tmp error: {}",
-                                    e, tmp_err
-                                ),
-                                Some(json!({"original_error": e.to_string(), "tmp_error": tmp_err.to_string()})),
-                            ));
-                        }
-                    }
-                } else {
-                    // Handle other kinds of errors
-                    return Err(McpError::new(
-                        ErrorCode::INTERNAL_ERROR,
-                        format!("Failed to write file: {}", e),
-                        Some(json!({"error": e.to_string()})),
-                    ));
-                }
-            }
-        };
+        fs::write(&path, &params.file_content).map_err(|e| write_error(&path, "write file", e))?;
 
         // Add to whitelist for future edits
         if let Ok(content) = fs::read_to_string(&path) {
@@ -1226,11 +1258,6 @@ impl WriteIfEmpty {
         // Build result message
         let mut result = String::new();
 
-        // Add warning if necessary
-        if let Some(warn_msg) = warning {
-            result.push_str(&format!("{}\n\n", warn_msg));
-        }
-
         // Adicionar mensagem de sucesso
         if syntax_warnings.is_empty() {
             result.push_str(&format!("Success: New file created at {}", path.display()));
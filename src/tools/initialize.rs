@@ -6,9 +6,13 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
+use crate::bash::limits::{ModeLimits, ResourceLimits};
+use crate::bash::sandbox::SandboxPolicy;
 use crate::bash::state::BashState;
+use crate::config::config::SecurityConfig;
 use crate::file::repository::RepositoryExplorer;
-use crate::security::SecurityManager;
+use crate::file::PathFilter;
+use crate::security::{audit, paths, SecurityManager};
 
 // Global shared state instances
 lazy_static::lazy_static! {
@@ -24,6 +28,8 @@ lazy_static::lazy_static! {
         Arc::new(std::sync::atomic::AtomicBool::new(false));
     static ref SECURITY_MANAGER: Arc<Mutex<SecurityManager>> =
         Arc::new(Mutex::new(SecurityManager::new()));
+    static ref COMMAND_LIMITS: Arc<Mutex<ModeLimits>> =
+        Arc::new(Mutex::new(ModeLimits::default()));
 }
 
 // Mode enum for different operational modes
@@ -107,16 +113,132 @@ impl Initialize {
         Ok(workspace_path.clone())
     }
 
+    /// Records a change made outside the agent, reported by the workspace watcher
+    pub fn record_file_change(path: &Path) {
+        if let Ok(mut explorer) = REPO_EXPLORER.lock() {
+            explorer.track_activity(path, "change");
+        }
+    }
+
+    /// Replaces the security manager with one built from the `security` configuration
+    pub fn apply_security_config(config: &SecurityConfig) -> WinxResult<()> {
+        let mut manager = SecurityManager::from_config(config)?;
+        let mut security_manager = SECURITY_MANAGER.lock().map_err(|e| {
+            WinxError::lock_error(format!("Failed to acquire SECURITY_MANAGER lock: {}", e))
+        })?;
+        // Keep the workspace of an already initialized session
+        if let Some(workspace) = security_manager.workspace() {
+            manager.set_workspace(workspace);
+        }
+        *security_manager = manager;
+        Ok(())
+    }
+
+    /// Filter of the paths a directory walk may read, under the role's `denied_paths`
+    /// and the sandbox confinement
+    pub fn path_filter() -> PathFilter {
+        Arc::new(|path| {
+            let security_manager = SECURITY_MANAGER.lock().unwrap_or_else(|e| e.into_inner());
+            security_manager.check_path("default", path).is_ok()
+        })
+    }
+
+    /// Text with secrets replaced by placeholders, without a summary note
+    pub fn redact_text(text: &str) -> String {
+        let security_manager = SECURITY_MANAGER.lock().unwrap_or_else(|e| e.into_inner());
+        match security_manager.redactor() {
+            Some(redactor) => redactor.redact(text).text,
+            None => text.to_string(),
+        }
+    }
+
+    /// Redacts secrets from tool output, appending how many were redacted
+    pub fn redact_output(tool: &str, output: String) -> String {
+        // A poisoned lock must not let output through unredacted
+        let security_manager = SECURITY_MANAGER.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(redactor) = security_manager.redactor() else {
+            return output;
+        };
+        let redacted = redactor.redact(&output);
+        match redacted.summary() {
+            Some(summary) => {
+                log::info!(
+                    "Redacted {} secret(s) from {} output",
+                    redacted.total(),
+                    tool
+                );
+                format!("{}\n\n{}", redacted.text, summary)
+            }
+            None => redacted.text,
+        }
+    }
+
+    /// Sandbox for shell commands, `None` unless sandboxed mode is enabled
+    pub fn command_sandbox() -> WinxResult<Option<SandboxPolicy>> {
+        let security_manager = SECURITY_MANAGER.lock().map_err(|e| {
+            WinxError::lock_error(format!("Failed to acquire SECURITY_MANAGER lock: {}", e))
+        })?;
+        if !security_manager.is_sandboxed() {
+            return Ok(None);
+        }
+        Ok(Some(SandboxPolicy::new(
+            security_manager.confinement_roots(),
+            security_manager.allowed_hosts().to_vec(),
+            security_manager.sandbox_config(),
+        )))
+    }
+
+    /// Replaces the per-mode command limits with the `limits` configuration
+    pub fn apply_command_limits(limits: &ModeLimits) -> WinxResult<()> {
+        let mut command_limits = COMMAND_LIMITS.lock().map_err(|e| {
+            WinxError::lock_error(format!("Failed to acquire COMMAND_LIMITS lock: {}", e))
+        })?;
+        *command_limits = limits.clone();
+        Ok(())
+    }
+
+    /// Limits of shell commands in the current mode
+    pub fn command_limits() -> WinxResult<ResourceLimits> {
+        let mode = Self::get_current_mode()?;
+        let command_limits = COMMAND_LIMITS.lock().map_err(|e| {
+            WinxError::lock_error(format!("Failed to acquire COMMAND_LIMITS lock: {}", e))
+        })?;
+        Ok(match mode {
+            Mode::Wcgw => command_limits.wcgw.clone(),
+            Mode::Architect => command_limits.architect.clone(),
+            Mode::CodeWriter(_) => command_limits.code_writer.clone(),
+        })
+    }
+
     // Update the workspace path
     fn update_workspace_path(&self, path: PathBuf) -> WinxResult<()> {
+        // Canonical so it compares with resolved tool paths
+        let path = fs::canonicalize(&path).unwrap_or(path);
+
+        // Released before taking WORKSPACE_PATH, check_permission locks them the other way
+        if let Ok(mut security_manager) = SECURITY_MANAGER.lock() {
+            security_manager.set_workspace(&path);
+        }
+
         let mut workspace_path = WORKSPACE_PATH.lock().map_err(|e| {
             WinxError::lock_error(format!("Failed to acquire WORKSPACE_PATH lock: {}", e))
         })?;
-
         *workspace_path = path;
         Ok(())
     }
 
+    /// Resolves a path given to a tool and checks `action` is allowed on it
+    ///
+    /// Relative paths are taken from the workspace, `~` is expanded and the result is
+    /// canonicalized, so the returned path is the one the permission check applied to.
+    pub fn resolve_path(action: Action, path: &str) -> WinxResult<PathBuf> {
+        let workspace = Self::get_workspace_path()?;
+        let resolved = paths::resolve(path, &workspace)
+            .map_err(|e| WinxError::io_error(e, Some(PathBuf::from(path))))?;
+        Self::check_permission(action, Some(&resolved.to_string_lossy()))?;
+        Ok(resolved)
+    }
+
     // Check if an action is allowed in the current mode
     /// Checks if a directory has write permissions by attempting to create a temporary file
     ///
@@ -182,14 +304,37 @@ impl Initialize {
         }
     }
 
+    /// Checks an action against path confinement, the security manager and the mode
+    ///
+    /// The decision is noted in the audit entry of the running tool call.
     pub fn check_permission(action: Action, path: Option<&str>) -> WinxResult<()> {
+        let result = Self::evaluate_permission(action.clone(), path);
+        audit::note_permission(&format!("{:?}", action), path, &result);
+        result
+    }
+
+    fn evaluate_permission(action: Action, path: Option<&str>) -> WinxResult<()> {
+        // Resolve the path so `..` and symlinks can't escape the confinement rules
+        let workspace = Self::get_workspace_path()?;
+        let resolved = match path {
+            Some(path) => Some(
+                paths::resolve(path, &workspace)
+                    .map_err(|e| WinxError::io_error(e, Some(PathBuf::from(path))))?,
+            ),
+            None => None,
+        };
+
         // Get the security manager instance
         let security_manager = SECURITY_MANAGER.lock().map_err(|e| {
             WinxError::lock_error(format!("Failed to acquire SECURITY_MANAGER lock: {}", e))
         })?;
 
-        // Convert string path to Path if provided
-        let path_buf = path.map(Path::new);
+        let path_buf = resolved.as_deref();
+
+        // Path confinement applies in every mode, denials are never relaxed below
+        if let Some(path) = path_buf {
+            security_manager.check_path("default", path)?;
+        }
 
         // For backward compatibility, check current mode as well
         let current_mode = CURRENT_MODE.lock().map_err(|e| {
@@ -236,10 +381,17 @@ impl Initialize {
                                     return Ok(());
                                 }
 
+                                // Globs may be absolute or relative to the workspace
+                                let relative =
+                                    path_buf.and_then(|p| p.strip_prefix(&workspace).ok());
+
                                 // Check if any glob matches
                                 for glob_pattern in &config.allowed_globs {
                                     if let Ok(glob) = glob::Pattern::new(glob_pattern) {
-                                        if glob.matches(file_path) {
+                                        if glob.matches(file_path)
+                                            || path_buf.is_some_and(|p| glob.matches_path(p))
+                                            || relative.is_some_and(|p| glob.matches_path(p))
+                                        {
                                             return Ok(());
                                         }
                                     }
@@ -542,6 +694,16 @@ impl Initialize {
         // Set mode in bash state
         state.set_mode(params.mode_name.clone());
 
+        // Keep caches coherent with changes made outside the agent
+        if workspace_path.exists() {
+            if let Err(e) = crate::cache::watcher::watch_workspace(&workspace_path) {
+                log::warn!(
+                    "File watcher unavailable, falling back to cache expiry: {}",
+                    e
+                );
+            }
+        }
+
         // Get repository explorer
         let repo_explorer = self.get_repo_explorer()?;
         let explorer = repo_explorer.lock().map_err(|e| {
@@ -0,0 +1,205 @@
+use chrono::{Local, TimeZone};
+use rmcp::{model::CallToolResult, model::ErrorCode, schemars, tool, Error as McpError};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::Path;
+
+use crate::file::tree_index::TreeEntry;
+use crate::tools::initialize::{Action, Initialize};
+
+/// Deepest listing allowed in one call
+const MAX_LIST_DEPTH: usize = 10;
+
+/// Maximum number of entries returned in one call
+const MAX_LIST_ENTRIES: usize = 500;
+
+#[derive(Debug, Clone)]
+pub struct ListDirectory {
+    // Listings come from the repository explorer's cached tree index
+}
+
+impl ListDirectory {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Default for ListDirectory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ListDirectoryParams {
+    #[schemars(
+        description = "Directory to list, absolute or relative to the workspace (~ allowed). Defaults to the workspace root"
+    )]
+    pub path: Option<String>,
+
+    #[schemars(description = "How many levels to descend (default 1, max 10)")]
+    pub depth: Option<usize>,
+
+    #[schemars(description = "Only show entries whose name matches this glob, e.g. \"*.rs\"")]
+    pub pattern: Option<String>,
+}
+
+/// Formats a byte count as a short human readable size
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if size < 1024 {
+        return format!("{}B", size);
+    }
+    let mut value = size as f64;
+    let mut unit = "";
+    for u in UNITS {
+        value /= 1024.0;
+        unit = u;
+        if value < 1024.0 {
+            break;
+        }
+    }
+    format!("{:.1}{}", value, unit)
+}
+
+fn format_modified(modified_nanos: u64) -> String {
+    let secs = (modified_nanos / 1_000_000_000) as i64;
+    match Local.timestamp_opt(secs, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+        None => "-".to_string(),
+    }
+}
+
+fn format_listing(dir: &Path, entries: &[(String, TreeEntry)], truncated: bool) -> String {
+    if entries.is_empty() {
+        return format!("{} is empty or all entries are ignored", dir.display());
+    }
+
+    let mut output = format!(
+        "{}\n{:<6} {:>8}  {:<16}  PATH\n",
+        dir.display(),
+        "TYPE",
+        "SIZE",
+        "MODIFIED"
+    );
+    for (rel, entry) in entries {
+        let (kind, size, name) = if entry.is_dir {
+            ("dir", "-".to_string(), format!("{}/", rel))
+        } else {
+            ("file", format_size(entry.size), rel.clone())
+        };
+        output.push_str(&format!(
+            "{:<6} {:>8}  {:<16}  {}\n",
+            kind,
+            size,
+            format_modified(entry.modified),
+            name
+        ));
+    }
+
+    if truncated {
+        output.push_str(&format!(
+            "... (output limited to {} entries, use a pattern or lower depth)\n",
+            MAX_LIST_ENTRIES
+        ));
+    }
+    output
+}
+
+#[tool(tool_box)]
+impl ListDirectory {
+    #[tool(description = "List directory contents honoring ignore files")]
+    pub async fn list_directory(
+        &self,
+        #[tool(aggr)] params: ListDirectoryParams,
+    ) -> Result<CallToolResult, McpError> {
+        // Check if initialization has been done
+        crate::ensure_initialized!("You must call 'initialize' before listing directories.");
+
+        // Resolve the directory against the workspace and check permission
+        let dir = Initialize::resolve_path(
+            Action::ReadFile,
+            params
+                .path
+                .as_deref()
+                .filter(|p| !p.trim().is_empty())
+                .unwrap_or("."),
+        )
+        .map_err(|e| e.to_mcp_error())?;
+        let workspace = Initialize::get_workspace_path().map_err(|e| e.to_mcp_error())?;
+
+        if !dir.is_dir() {
+            return Err(McpError::new(
+                ErrorCode::INVALID_PARAMS,
+                format!("Not a directory: {}", dir.display()),
+                Some(json!({"path": dir.display().to_string()})),
+            ));
+        }
+
+        let pattern = match params.pattern.as_deref().filter(|p| !p.is_empty()) {
+            Some(pattern) => Some(glob::Pattern::new(pattern).map_err(|e| {
+                McpError::new(
+                    ErrorCode::INVALID_PARAMS,
+                    format!("Invalid pattern '{}': {}", pattern, e),
+                    None,
+                )
+            })?),
+            None => None,
+        };
+        let depth = params.depth.unwrap_or(1).clamp(1, MAX_LIST_DEPTH);
+
+        // Reuse the workspace index for directories inside it
+        let index_root = if dir.starts_with(&workspace) {
+            workspace
+        } else {
+            dir.clone()
+        };
+
+        // Entries under denied paths below the directory are left out
+        let allowed = Initialize::path_filter();
+        let list_dir = dir.clone();
+        let listing = tokio::task::spawn_blocking(move || {
+            let explorer = crate::file::repository::RepositoryExplorer::new();
+            let result = explorer.list_directory(
+                &index_root,
+                &list_dir,
+                depth,
+                pattern.as_ref(),
+                MAX_LIST_ENTRIES,
+                &allowed,
+            );
+            // Ignored directories are not in the workspace index, list them directly
+            match result {
+                Ok((entries, _)) if entries.is_empty() && index_root != list_dir => explorer
+                    .list_directory(
+                        &list_dir,
+                        &list_dir,
+                        depth,
+                        pattern.as_ref(),
+                        MAX_LIST_ENTRIES,
+                        &allowed,
+                    ),
+                other => other,
+            }
+        })
+        .await
+        .map_err(|e| {
+            McpError::new(
+                ErrorCode::INTERNAL_ERROR,
+                format!("Listing task failed: {}", e),
+                None,
+            )
+        })?
+        .map_err(|e| {
+            McpError::new(
+                ErrorCode::INTERNAL_ERROR,
+                format!("Failed to list directory: {}", e),
+                Some(json!({"error": e.to_string()})),
+            )
+        })?;
+
+        Ok(CallToolResult::success(vec![rmcp::model::Content::text(
+            format_listing(&dir, &listing.0, listing.1),
+        )]))
+    }
+}
@@ -2,6 +2,9 @@ pub mod bash_command;
 pub mod context_save;
 pub mod file_operations;
 pub mod initialize;
+pub mod list_directory;
+pub mod search_code;
+pub mod semantic_layer;
 // Temporarily commenting the semantic_code module
 // pub mod semantic_code;
 
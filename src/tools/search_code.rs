@@ -0,0 +1,212 @@
+use rmcp::{model::CallToolResult, model::ErrorCode, schemars, tool, Error as McpError};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::Path;
+
+use crate::file::search::{search_workspace, SearchOptions, SearchResults, DEFAULT_MAX_RESULTS};
+use crate::tools::initialize::{Action, Initialize};
+
+/// Upper bound for `max_results` to keep responses small
+const MAX_RESULTS_LIMIT: usize = 500;
+
+/// Upper bound for `context_lines`
+const MAX_CONTEXT_LINES: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct SearchCode {
+    // Stateless, results are paginated with an offset cursor
+}
+
+impl SearchCode {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Default for SearchCode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SearchCodeParams {
+    #[schemars(description = "Regex to search for, or plain text when `literal` is true")]
+    pub pattern: String,
+
+    #[schemars(
+        description = "Directory or file to search, absolute or relative to the workspace (~ allowed). Defaults to the workspace root"
+    )]
+    pub path: Option<String>,
+
+    #[schemars(description = "Treat the pattern as literal text instead of a regex")]
+    pub literal: Option<bool>,
+
+    #[schemars(description = "Match case-insensitively")]
+    pub case_insensitive: Option<bool>,
+
+    #[schemars(description = "Only search these file types, e.g. [\"rust\", \"py\", \"ts\"]")]
+    pub file_types: Option<Vec<String>>,
+
+    #[schemars(
+        description = "Only search paths matching these globs, prefix a glob with ! to exclude"
+    )]
+    pub globs: Option<Vec<String>>,
+
+    #[schemars(description = "Lines of context before and after each match (default 0)")]
+    pub context_lines: Option<usize>,
+
+    #[schemars(description = "Maximum number of matches to return (default 100)")]
+    pub max_results: Option<usize>,
+
+    #[schemars(description = "Cursor returned by a previous call to fetch the next page")]
+    pub cursor: Option<String>,
+}
+
+/// Formats matches ripgrep-style: `path:line:text` for matches, `path-line-text` for context
+fn format_results(root: &Path, results: &SearchResults, offset: usize) -> String {
+    if results.total_matches == 0 {
+        return format!(
+            "No matches found in {} ({} files searched)",
+            root.display(),
+            results.files_searched
+        );
+    }
+
+    let mut output = String::new();
+    for m in &results.matches {
+        let display = m.path.strip_prefix(root).unwrap_or(&m.path).display();
+        let first_line = m.line_number - m.before.len();
+        for (i, line) in m.before.iter().enumerate() {
+            output.push_str(&format!("{}-{}-{}\n", display, first_line + i, line));
+        }
+        output.push_str(&format!("{}:{}:{}\n", display, m.line_number, m.line));
+        for (i, line) in m.after.iter().enumerate() {
+            output.push_str(&format!("{}-{}-{}\n", display, m.line_number + 1 + i, line));
+        }
+        if !m.before.is_empty() || !m.after.is_empty() {
+            output.push_str("--\n");
+        }
+    }
+
+    output.push_str(&format!(
+        "\nShowing matches {}-{} of {}{} ({} files searched, paths relative to {})",
+        offset + 1,
+        offset + results.matches.len(),
+        results.total_matches,
+        if results.scan_truncated {
+            " (only the first matches in path order are kept, narrow the search)"
+        } else {
+            ""
+        },
+        results.files_searched,
+        root.display()
+    ));
+
+    if let Some(next) = results.next_offset {
+        output.push_str(&format!(
+            "\nMore matches available. Call again with cursor: \"{}\"",
+            next
+        ));
+    }
+
+    output
+}
+
+#[tool(tool_box)]
+impl SearchCode {
+    #[tool(description = "Search file contents in the workspace")]
+    pub async fn search_code(
+        &self,
+        #[tool(aggr)] params: SearchCodeParams,
+    ) -> Result<CallToolResult, McpError> {
+        // Check if initialization has been done
+        crate::ensure_initialized!("You must call 'initialize' before searching code.");
+
+        // Resolve the search root against the workspace and check permission
+        let root = Initialize::resolve_path(
+            Action::ReadFile,
+            params
+                .path
+                .as_deref()
+                .filter(|p| !p.trim().is_empty())
+                .unwrap_or("."),
+        )
+        .map_err(|e| e.to_mcp_error())?;
+
+        if params.pattern.is_empty() {
+            return Err(McpError::new(
+                ErrorCode::INVALID_PARAMS,
+                "The 'pattern' parameter cannot be empty".to_string(),
+                None,
+            ));
+        }
+
+        if !root.exists() {
+            return Err(McpError::new(
+                ErrorCode::INVALID_PARAMS,
+                format!("Search path does not exist: {}", root.display()),
+                Some(json!({"path": root.display().to_string()})),
+            ));
+        }
+
+        let offset = match params.cursor.as_deref() {
+            None | Some("") => 0,
+            Some(cursor) => cursor.trim().parse::<usize>().map_err(|_| {
+                McpError::new(
+                    ErrorCode::INVALID_PARAMS,
+                    format!("Invalid cursor: {}", cursor),
+                    Some(json!({"cursor": cursor})),
+                )
+            })?,
+        };
+
+        let mut options = SearchOptions::new(params.pattern.clone());
+        options.literal = params.literal.unwrap_or(false);
+        options.case_insensitive = params.case_insensitive.unwrap_or(false);
+        options.file_types = params.file_types.clone().unwrap_or_default();
+        options.globs = params.globs.clone().unwrap_or_default();
+        options.context_lines = params.context_lines.unwrap_or(0).min(MAX_CONTEXT_LINES);
+        options.max_results = params
+            .max_results
+            .unwrap_or(DEFAULT_MAX_RESULTS)
+            .clamp(1, MAX_RESULTS_LIMIT);
+        options.offset = offset;
+
+        // The walk is CPU and IO bound, keep it off the async runtime
+        // Files under denied paths below the root are skipped, not only the root itself
+        let search_root = root.clone();
+        let allowed = Initialize::path_filter();
+        let results =
+            tokio::task::spawn_blocking(move || search_workspace(&search_root, &options, allowed))
+                .await
+                .map_err(|e| {
+                    McpError::new(
+                        ErrorCode::INTERNAL_ERROR,
+                        format!("Search task failed: {}", e),
+                        None,
+                    )
+                })?
+                .map_err(|e| {
+                    McpError::new(
+                        ErrorCode::INVALID_PARAMS,
+                        format!("Search failed: {}", e),
+                        Some(json!({"error": e.to_string()})),
+                    )
+                })?;
+
+        // Single-file searches report paths relative to the file's directory
+        let display_root = if root.is_file() {
+            root.parent().map(Path::to_path_buf).unwrap_or(root)
+        } else {
+            root
+        };
+
+        Ok(CallToolResult::success(vec![rmcp::model::Content::text(
+            Initialize::redact_output(
+                "search_code",
+                format_results(&display_root, &results, offset),
+            ),
+        )]))
+    }
+}
@@ -0,0 +1,893 @@
+use lazy_static::lazy_static;
+use rmcp::{model::CallToolResult, model::ErrorCode, schemars, tool, Error as McpError};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::cache::get_advanced_cache;
+use crate::config::config::SemanticConfig;
+use crate::error::{WinxError, WinxResult};
+use crate::semantic::analyzer::{ValidationIssue, ValidationSeverity};
+use crate::semantic::execute::{QueryRows, SqliteRunner};
+use crate::semantic::formats::{self, MdlFormat};
+use crate::semantic::join::{JoinPlanner, JoinStep};
+use crate::semantic::mdl::Model;
+use crate::semantic::query::MetricQuery;
+use crate::semantic::rewrite::metric_sql;
+use crate::semantic::sql::physical_table;
+use crate::semantic::validate::type_name;
+use crate::semantic::{LineageFormat, SemanticAnalyzer, SemanticContext, SqlDialect, WinxMDL};
+use crate::tools::initialize::{Action, Initialize};
+
+/// Rows per page of a metric query when the caller doesn't say
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Longest cell shown in a result table
+const MAX_CELL_CHARS: usize = 200;
+
+lazy_static! {
+    static ref SEMANTIC_CONFIG: Mutex<SemanticConfig> = Mutex::new(SemanticConfig::default());
+}
+
+/// An MDL loaded from the workspace with the analyzer over its context
+#[derive(Debug)]
+struct LoadedMdl {
+    path: PathBuf,
+    mdl: WinxMDL,
+    context: SemanticContext,
+    analyzer: SemanticAnalyzer,
+    catalog: String,
+    schema: String,
+    /// Digest of the MDL and defaults, keys cached results of this MDL
+    fingerprint: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SemanticLayer {
+    loaded: Arc<RwLock<Option<Arc<LoadedMdl>>>>,
+}
+
+impl SemanticLayer {
+    pub fn new() -> Self {
+        Self {
+            loaded: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Applies the `semantic` section of the configuration
+    pub fn apply_config(config: &SemanticConfig) -> WinxResult<()> {
+        let mut current = SEMANTIC_CONFIG.lock().map_err(|e| {
+            WinxError::lock_error(format!("Failed to acquire SEMANTIC_CONFIG lock: {}", e))
+        })?;
+        *current = config.clone();
+        Ok(())
+    }
+
+    /// Configuration of an enabled semantic layer
+    fn config() -> Result<SemanticConfig, McpError> {
+        let config = SEMANTIC_CONFIG
+            .lock()
+            .map_err(|e| {
+                WinxError::lock_error(format!("Failed to acquire SEMANTIC_CONFIG lock: {}", e))
+                    .to_mcp_error()
+            })?
+            .clone();
+        if !config.enabled {
+            return Err(McpError::new(
+                ErrorCode::INVALID_REQUEST,
+                "The semantic layer is disabled, set `semantic.enabled` to true in the configuration"
+                    .to_string(),
+                None,
+            ));
+        }
+        Ok(config)
+    }
+
+    async fn loaded(&self) -> Result<Arc<LoadedMdl>, McpError> {
+        self.loaded.read().await.clone().ok_or_else(|| {
+            McpError::new(
+                ErrorCode::INVALID_REQUEST,
+                "No MDL is loaded, call semantic_load first".to_string(),
+                None,
+            )
+        })
+    }
+
+    /// Output of `compute`, reused for `cache_duration_secs` across identical calls
+    async fn cached<F>(
+        config: &SemanticConfig,
+        loaded: &LoadedMdl,
+        tool: &str,
+        inputs: &[&str],
+        compute: F,
+    ) -> Result<String, McpError>
+    where
+        F: Future<Output = WinxResult<String>>,
+    {
+        let key = cache_key(&loaded.fingerprint, tool, inputs);
+        let cache = get_advanced_cache();
+        let ttl = Duration::from_secs(config.cache_duration_secs);
+        if let Some(output) = cache.get_fresh_query::<String>(&key, ttl) {
+            return Ok(output);
+        }
+        let output = compute.await.map_err(|e| e.to_mcp_error())?;
+        if !ttl.is_zero() {
+            if let Err(e) = cache.store_query(&key, &output) {
+                log::warn!("Failed to cache {} result: {}", tool, e);
+            }
+        }
+        Ok(output)
+    }
+
+    fn model<'a>(loaded: &'a LoadedMdl, name: &str) -> Result<&'a Model, McpError> {
+        loaded.mdl.get_model(name).ok_or_else(|| {
+            McpError::new(
+                ErrorCode::INVALID_PARAMS,
+                format!("Unknown model '{}'", name),
+                Some(json!({
+                    "models": loaded.mdl.models.iter().map(|m| &m.name).collect::<Vec<_>>()
+                })),
+            )
+        })
+    }
+}
+
+impl Default for SemanticLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SemanticLoadParams {
+    #[schemars(description = "MDL file, absolute or relative to the workspace (~ allowed)")]
+    pub path: String,
+
+    #[schemars(
+        description = "Format of the file: winx (default, .yaml/.yml/.json), dbt, cube or json_schema"
+    )]
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SemanticDescribeParams {
+    #[schemars(
+        description = "Model to describe in detail. Omit to list all models, relationships, metrics and views"
+    )]
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SemanticJoinPathParams {
+    #[schemars(description = "Model the path starts from")]
+    pub from_model: String,
+
+    #[schemars(description = "Model the path leads to")]
+    pub to_model: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SemanticPlanJoinsParams {
+    #[schemars(description = "Models to join, the first one is joined to")]
+    pub models: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SemanticSqlParams {
+    #[schemars(description = "SQL over the models of the loaded MDL")]
+    pub sql: String,
+
+    #[schemars(description = "SQL dialect: ansi (default), postgres or sqlite")]
+    pub dialect: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SemanticLineageParams {
+    #[schemars(description = "SQL over the models of the loaded MDL")]
+    pub sql: String,
+
+    #[schemars(description = "SQL dialect: ansi (default), postgres or sqlite")]
+    pub dialect: Option<String>,
+
+    #[schemars(description = "Output format: json (default), dot or mermaid")]
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SemanticQueryParams {
+    #[schemars(description = "Metrics to compute, by name")]
+    pub metrics: Vec<String>,
+
+    #[schemars(
+        description = "Columns to break the metrics down by, `model.column` or a column name of one model"
+    )]
+    pub dimensions: Option<Vec<String>>,
+
+    #[schemars(
+        description = "SQL conditions over the models that must all hold, e.g. \"orders.status = 'paid'\""
+    )]
+    pub filters: Option<Vec<String>>,
+
+    #[schemars(
+        description = "Output columns to sort by, optionally followed by ASC or DESC, e.g. \"revenue DESC\""
+    )]
+    pub order_by: Option<Vec<String>>,
+
+    #[schemars(description = "Rows per page (default 100, capped by `semantic.max_rows`)")]
+    pub page_size: Option<usize>,
+
+    #[schemars(description = "Cursor returned by a previous call to fetch the next page")]
+    pub cursor: Option<String>,
+}
+
+fn dialect(dialect: Option<&str>) -> Result<SqlDialect, McpError> {
+    match dialect.filter(|d| !d.trim().is_empty()) {
+        Some(dialect) => dialect.parse().map_err(|e: WinxError| e.to_mcp_error()),
+        None => Ok(SqlDialect::default()),
+    }
+}
+
+fn text(output: String) -> CallToolResult {
+    CallToolResult::success(vec![rmcp::model::Content::text(output)])
+}
+
+/// Reads the MDL at `path` along with what's wrong with it
+fn read_mdl(path: &Path, format: MdlFormat) -> WinxResult<(WinxMDL, Vec<ValidationIssue>)> {
+    if format == MdlFormat::Winx {
+        return WinxMDL::load_checked(path);
+    }
+    let content = std::fs::read_to_string(path)?;
+    let mdl = formats::import(&content, format)?;
+    let issues = mdl.check();
+    Ok((mdl, issues))
+}
+
+/// Digest of `mdl` and the defaults it is loaded with
+fn fingerprint(mdl: &WinxMDL, catalog: &str, schema: &str) -> serde_json::Result<String> {
+    let serialized = serde_json::to_string(mdl)?;
+    Ok(digest(&[&serialized, catalog, schema]))
+}
+
+/// Cache key of a `tool` result over `inputs` for the MDL of `fingerprint`
+fn cache_key(fingerprint: &str, tool: &str, inputs: &[&str]) -> String {
+    format!("semantic:{}:{}:{}", tool, fingerprint, digest(inputs))
+}
+
+fn digest(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Modification time of `path`, empty when it can't be read
+fn modified_time(path: &Path) -> String {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map(|t| format!("{:?}", t))
+        .unwrap_or_default()
+}
+
+fn format_steps(steps: &[JoinStep]) -> String {
+    let mut out = String::new();
+    for step in steps {
+        let _ = writeln!(
+            out,
+            "  {}: {}.{} = {}.{} ({})",
+            step.relationship,
+            step.from_model,
+            step.from_column,
+            step.to_model,
+            step.to_column,
+            step.fanout
+        );
+    }
+    out
+}
+
+fn format_notes(notes: &[String]) -> String {
+    let mut out = String::new();
+    if !notes.is_empty() {
+        out.push_str("\nNotes:\n");
+        for note in notes {
+            let _ = writeln!(out, "  {}", note);
+        }
+    }
+    out
+}
+
+fn format_issues(issues: &[ValidationIssue]) -> String {
+    issues.iter().map(|i| format!("  {}\n", i)).collect()
+}
+
+fn cell(value: &serde_json::Value) -> String {
+    let text = match value {
+        serde_json::Value::Null => "NULL".to_string(),
+        serde_json::Value::String(text) => text.clone(),
+        other => other.to_string(),
+    };
+    let text = text.replace('|', "\\|").replace('\n', " ");
+    if text.chars().count() > MAX_CELL_CHARS {
+        format!(
+            "{}...",
+            text.chars().take(MAX_CELL_CHARS).collect::<String>()
+        )
+    } else {
+        text
+    }
+}
+
+/// Markdown table of a page of rows
+fn format_table(rows: &QueryRows) -> String {
+    let mut out = format!("| {} |\n", rows.columns.join(" | "));
+    let _ = writeln!(out, "|{}", " --- |".repeat(rows.columns.len()));
+    for row in &rows.rows {
+        let cells: Vec<String> = row.iter().map(cell).collect();
+        let _ = writeln!(out, "| {} |", cells.join(" | "));
+    }
+    out
+}
+
+fn describe_all(loaded: &LoadedMdl) -> String {
+    let mdl = &loaded.mdl;
+    let mut out = format!("MDL {}\n", loaded.path.display());
+
+    let _ = writeln!(out, "\nModels ({}):", mdl.models.len());
+    for model in &mdl.models {
+        let _ = write!(
+            out,
+            "  {} -> {}, {} columns",
+            model.name,
+            physical_table(model, &loaded.catalog, &loaded.schema),
+            model.columns.len()
+        );
+        if !model.calculated_fields.is_empty() {
+            let _ = write!(out, ", {} calculated fields", model.calculated_fields.len());
+        }
+        match &model.description {
+            Some(description) => {
+                let _ = writeln!(out, ": {}", description);
+            }
+            None => out.push('\n'),
+        }
+    }
+
+    let _ = writeln!(out, "\nRelationships ({}):", mdl.relationships.len());
+    for relationship in &mdl.relationships {
+        let _ = writeln!(
+            out,
+            "  {}: {}.{} -> {}.{} ({:?})",
+            relationship.name,
+            relationship.from_model,
+            relationship.from_column,
+            relationship.to_model,
+            relationship.to_column,
+            relationship.relationship_type
+        );
+    }
+
+    let _ = writeln!(out, "\nMetrics ({}):", mdl.metrics.len());
+    for metric in &mdl.metrics {
+        let _ = write!(out, "  {} = {}", metric.name, metric_sql(metric));
+        if let Some(base_model) = &metric.base_model {
+            let _ = write!(out, " on {}", base_model);
+        }
+        out.push('\n');
+    }
+
+    let _ = writeln!(out, "\nViews ({}):", mdl.views.len());
+    for view in &mdl.views {
+        let _ = writeln!(out, "  {}: {}", view.name, view.base_query);
+    }
+    out
+}
+
+fn describe_model(loaded: &LoadedMdl, model: &Model) -> String {
+    let mut out = format!(
+        "Model {} (table {})\n",
+        model.name,
+        physical_table(model, &loaded.catalog, &loaded.schema)
+    );
+    if let Some(description) = &model.description {
+        let _ = writeln!(out, "{}", description);
+    }
+
+    let _ = writeln!(out, "\nColumns:");
+    for column in &model.columns {
+        let _ = write!(out, "  {} {}", column.name, type_name(&column.data_type));
+        if !column.nullable {
+            out.push_str(" not null");
+        }
+        if column.primary_key || model.primary_key.as_deref() == Some(column.name.as_str()) {
+            out.push_str(" primary key");
+        }
+        match &column.description {
+            Some(description) => {
+                let _ = writeln!(out, ": {}", description);
+            }
+            None => out.push('\n'),
+        }
+    }
+
+    if !model.calculated_fields.is_empty() {
+        let _ = writeln!(out, "\nCalculated fields:");
+        for field in &model.calculated_fields {
+            let _ = writeln!(
+                out,
+                "  {} {} = {}",
+                field.name,
+                type_name(&field.data_type),
+                field.expression
+            );
+        }
+    }
+
+    let relationships = loaded.mdl.get_relationships_for_model(&model.name);
+    if !relationships.is_empty() {
+        let _ = writeln!(out, "\nRelationships:");
+        for relationship in relationships {
+            let _ = writeln!(
+                out,
+                "  {}: {}.{} -> {}.{} ({:?})",
+                relationship.name,
+                relationship.from_model,
+                relationship.from_column,
+                relationship.to_model,
+                relationship.to_column,
+                relationship.relationship_type
+            );
+        }
+    }
+
+    let metrics: Vec<_> = loaded
+        .mdl
+        .metrics
+        .iter()
+        .filter(|m| m.base_model.as_deref() == Some(model.name.as_str()))
+        .collect();
+    if !metrics.is_empty() {
+        let _ = writeln!(out, "\nMetrics:");
+        for metric in metrics {
+            let _ = writeln!(out, "  {} = {}", metric.name, metric_sql(metric));
+        }
+    }
+    out
+}
+
+#[tool(tool_box)]
+impl SemanticLayer {
+    #[tool(description = "Load an MDL file from the workspace")]
+    pub async fn semantic_load(
+        &self,
+        #[tool(aggr)] params: SemanticLoadParams,
+    ) -> Result<CallToolResult, McpError> {
+        let config = Self::config()?;
+        crate::ensure_initialized!("You must call 'initialize' before loading an MDL.");
+
+        let path = Initialize::resolve_path(Action::ReadFile, &params.path)
+            .map_err(|e| e.to_mcp_error())?;
+        let format = match params.format.as_deref().filter(|f| !f.trim().is_empty()) {
+            Some(format) => format.parse::<MdlFormat>().map_err(|e| e.to_mcp_error())?,
+            None => MdlFormat::Winx,
+        };
+
+        let (mdl, issues) = read_mdl(&path, format).map_err(|e| e.to_mcp_error())?;
+        if !issues.is_empty() {
+            return Err(McpError::new(
+                ErrorCode::INVALID_PARAMS,
+                format!(
+                    "{} has {} problem(s):\n{}",
+                    path.display(),
+                    issues.len(),
+                    issues
+                        .iter()
+                        .map(|i| format!("  {}", i))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                ),
+                Some(json!({ "issues": issues })),
+            ));
+        }
+
+        let context = SemanticContext::from_mdl(&mdl).map_err(|e| e.to_mcp_error())?;
+        let analyzer = SemanticAnalyzer::new(config.catalog.clone(), config.schema.clone());
+        analyzer
+            .set_context(context.clone())
+            .await
+            .map_err(|e| e.to_mcp_error())?;
+
+        let fingerprint = fingerprint(&mdl, &config.catalog, &config.schema)
+            .map_err(|e| McpError::new(ErrorCode::INTERNAL_ERROR, e.to_string(), None))?;
+        let summary = format!(
+            "Loaded {} models, {} relationships, {} metrics and {} views from {}",
+            mdl.models.len(),
+            mdl.relationships.len(),
+            mdl.metrics.len(),
+            mdl.views.len(),
+            path.display()
+        );
+
+        *self.loaded.write().await = Some(Arc::new(LoadedMdl {
+            path,
+            mdl,
+            context,
+            analyzer,
+            catalog: config.catalog,
+            schema: config.schema,
+            fingerprint,
+        }));
+        Ok(text(summary))
+    }
+
+    #[tool(description = "Describe the models and relationships of the loaded MDL")]
+    pub async fn semantic_describe(
+        &self,
+        #[tool(aggr)] params: SemanticDescribeParams,
+    ) -> Result<CallToolResult, McpError> {
+        let config = Self::config()?;
+        let loaded = self.loaded().await?;
+        let model = params.model.as_deref().filter(|m| !m.trim().is_empty());
+        if let Some(name) = model {
+            Self::model(&loaded, name)?;
+        }
+
+        let output = Self::cached(
+            &config,
+            &loaded,
+            "describe",
+            &[model.unwrap_or_default()],
+            async {
+                Ok(match model.and_then(|name| loaded.mdl.get_model(name)) {
+                    Some(model) => describe_model(&loaded, model),
+                    None => describe_all(&loaded),
+                })
+            },
+        )
+        .await?;
+        Ok(text(output))
+    }
+
+    #[tool(description = "Find the relationships joining two models")]
+    pub async fn semantic_join_path(
+        &self,
+        #[tool(aggr)] params: SemanticJoinPathParams,
+    ) -> Result<CallToolResult, McpError> {
+        let config = Self::config()?;
+        let loaded = self.loaded().await?;
+        Self::model(&loaded, &params.from_model)?;
+        Self::model(&loaded, &params.to_model)?;
+
+        let output = Self::cached(
+            &config,
+            &loaded,
+            "join_path",
+            &[&params.from_model, &params.to_model],
+            async {
+                let planner = JoinPlanner::new(&loaded.context);
+                let from = [params.from_model.clone()];
+                let to = [params.to_model.clone()];
+                Ok(match planner.path(&from, &to) {
+                    Some(path) => {
+                        let mut out = format!(
+                            "{} -> {}, {} join(s)\n",
+                            params.from_model,
+                            params.to_model,
+                            path.steps.len()
+                        );
+                        out.push_str(&format_steps(&path.steps));
+                        out.push_str(&format_notes(&path.notes));
+                        out
+                    }
+                    None => format!(
+                        "No relationships connect {} to {}",
+                        params.from_model, params.to_model
+                    ),
+                })
+            },
+        )
+        .await?;
+        Ok(text(output))
+    }
+
+    #[tool(description = "Plan the joins connecting a set of models")]
+    pub async fn semantic_plan_joins(
+        &self,
+        #[tool(aggr)] params: SemanticPlanJoinsParams,
+    ) -> Result<CallToolResult, McpError> {
+        let config = Self::config()?;
+        let loaded = self.loaded().await?;
+        if params.models.is_empty() {
+            return Err(McpError::new(
+                ErrorCode::INVALID_PARAMS,
+                "Give at least one model to join".to_string(),
+                None,
+            ));
+        }
+        for model in &params.models {
+            Self::model(&loaded, model)?;
+        }
+
+        let inputs: Vec<&str> = params.models.iter().map(String::as_str).collect();
+        let output = Self::cached(&config, &loaded, "plan_joins", &inputs, async {
+            let Some(plan) = JoinPlanner::new(&loaded.context).plan(&params.models) else {
+                return Ok(String::new());
+            };
+            let mut out = format!("FROM {}\n", plan.to_from());
+            if !plan.steps.is_empty() {
+                out.push_str("\nSteps:\n");
+                out.push_str(&format_steps(&plan.steps));
+            }
+            if !plan.unreachable.is_empty() {
+                let _ = writeln!(
+                    out,
+                    "\nNo relationships connect {} to {}",
+                    plan.unreachable.join(", "),
+                    plan.root
+                );
+            }
+            out.push_str(&format_notes(&plan.notes));
+            Ok(out)
+        })
+        .await?;
+        Ok(text(output))
+    }
+
+    #[tool(description = "Validate SQL against the loaded MDL")]
+    pub async fn semantic_validate_sql(
+        &self,
+        #[tool(aggr)] params: SemanticSqlParams,
+    ) -> Result<CallToolResult, McpError> {
+        let config = Self::config()?;
+        let loaded = self.loaded().await?;
+        let dialect = dialect(params.dialect.as_deref())?;
+
+        let output = Self::cached(
+            &config,
+            &loaded,
+            "validate_sql",
+            &[&dialect.to_string(), &params.sql],
+            async {
+                let issues = loaded.analyzer.validate_query(&params.sql, dialect).await?;
+                Ok(if issues.is_empty() {
+                    "No issues found".to_string()
+                } else {
+                    issues
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                })
+            },
+        )
+        .await?;
+        Ok(text(output))
+    }
+
+    #[tool(description = "Expand the metrics used in SQL into their aggregations")]
+    pub async fn semantic_expand_metrics(
+        &self,
+        #[tool(aggr)] params: SemanticSqlParams,
+    ) -> Result<CallToolResult, McpError> {
+        let config = Self::config()?;
+        let loaded = self.loaded().await?;
+        let dialect = dialect(params.dialect.as_deref())?;
+
+        let output = Self::cached(
+            &config,
+            &loaded,
+            "expand_metrics",
+            &[&dialect.to_string(), &params.sql],
+            loaded.analyzer.expand_metrics(&params.sql, dialect),
+        )
+        .await?;
+        Ok(text(output))
+    }
+
+    #[tool(description = "Build the column-level lineage of SQL")]
+    pub async fn semantic_lineage(
+        &self,
+        #[tool(aggr)] params: SemanticLineageParams,
+    ) -> Result<CallToolResult, McpError> {
+        let config = Self::config()?;
+        let loaded = self.loaded().await?;
+        let dialect = dialect(params.dialect.as_deref())?;
+        let format = match params.format.as_deref().filter(|f| !f.trim().is_empty()) {
+            Some(format) => format
+                .parse::<LineageFormat>()
+                .map_err(|e| e.to_mcp_error())?,
+            None => LineageFormat::Json,
+        };
+
+        let output = Self::cached(
+            &config,
+            &loaded,
+            "lineage",
+            &[&dialect.to_string(), &format!("{:?}", format), &params.sql],
+            async {
+                let graph = loaded.analyzer.build_lineage(&params.sql, dialect).await?;
+                graph.render(format)
+            },
+        )
+        .await?;
+        Ok(text(output))
+    }
+
+    #[tool(description = "Run a metric query against the configured SQLite database")]
+    pub async fn semantic_query(
+        &self,
+        #[tool(aggr)] params: SemanticQueryParams,
+    ) -> Result<CallToolResult, McpError> {
+        let config = Self::config()?;
+        crate::ensure_initialized!("You must call 'initialize' before running queries.");
+        let loaded = self.loaded().await?;
+        let database = config.database.as_ref().ok_or_else(|| {
+            McpError::new(
+                ErrorCode::INVALID_REQUEST,
+                "No SQLite database is configured, set `semantic.database`".to_string(),
+                None,
+            )
+        })?;
+        let database = Initialize::resolve_path(Action::ReadFile, &database.to_string_lossy())
+            .map_err(|e| e.to_mcp_error())?;
+
+        let offset = match params.cursor.as_deref() {
+            None | Some("") => 0,
+            Some(cursor) => cursor.trim().parse::<usize>().map_err(|_| {
+                McpError::new(
+                    ErrorCode::INVALID_PARAMS,
+                    format!("Invalid cursor: {}", cursor),
+                    Some(json!({"cursor": cursor})),
+                )
+            })?,
+        };
+        let page_size = params
+            .page_size
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, config.max_rows.max(1));
+
+        let query = MetricQuery {
+            metrics: params.metrics,
+            dimensions: params.dimensions.unwrap_or_default(),
+            filters: params.filters.unwrap_or_default(),
+            order_by: params.order_by.unwrap_or_default(),
+        };
+        let compiled = loaded
+            .analyzer
+            .compile_query(&query, SqlDialect::Sqlite)
+            .await
+            .map_err(|e| e.to_mcp_error())?;
+        let Some(sql) = compiled.sql.clone().filter(|_| !compiled.has_errors()) else {
+            let mut out = format!(
+                "Query not executed, it has {} issue(s):\n{}",
+                compiled.issues.len(),
+                format_issues(&compiled.issues)
+            );
+            if let Some(semantic_sql) = &compiled.semantic_sql {
+                let _ = write!(out, "\nSemantic SQL: {}\n", semantic_sql);
+            }
+            return Ok(text(out));
+        };
+
+        // The database may change under the cache, its modification time keys the result
+        let modified = modified_time(&database);
+        let runner = SqliteRunner::new(
+            config.sqlite_binary.clone(),
+            database,
+            Duration::from_secs(config.query_timeout_secs),
+        );
+        let database_name = runner.database().display().to_string();
+        let page = format!("{}:{}", offset, page_size);
+        let output = Self::cached(
+            &config,
+            &loaded,
+            "query",
+            &[&database_name, &modified, &sql, &page],
+            async {
+                // One extra row tells whether there's another page
+                let paged = format!("{} LIMIT {} OFFSET {}", sql, page_size + 1, offset);
+                let mut rows = runner.query(&paged, &compiled.columns).await?;
+                let more = rows.rows.len() > page_size;
+                rows.rows.truncate(page_size);
+
+                let mut out = format_table(&rows);
+                if rows.rows.is_empty() {
+                    out.push_str("\nNo rows\n");
+                } else {
+                    let _ = writeln!(out, "\nRows {}-{}", offset + 1, offset + rows.rows.len());
+                }
+                if more {
+                    let _ = writeln!(
+                        out,
+                        "More rows available. Call again with cursor: \"{}\"",
+                        offset + page_size
+                    );
+                }
+                let _ = writeln!(out, "\nSQL: {}", sql);
+                let warnings: Vec<ValidationIssue> = compiled
+                    .issues
+                    .iter()
+                    .filter(|i| i.severity != ValidationSeverity::Error)
+                    .cloned()
+                    .collect();
+                if !warnings.is_empty() {
+                    let _ = write!(out, "\nWarnings:\n{}", format_issues(&warnings));
+                }
+                Ok(out)
+            },
+        )
+        .await?;
+        Ok(text(output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantic::mdl::{Aggregation, Metric};
+    use std::time::SystemTime;
+
+    #[tokio::test]
+    async fn test_tools_are_rejected_when_disabled() {
+        SemanticLayer::apply_config(&SemanticConfig::default()).unwrap();
+        let error = SemanticLayer::new()
+            .semantic_describe(SemanticDescribeParams { model: None })
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::INVALID_REQUEST);
+        assert!(
+            error.message.contains("semantic.enabled"),
+            "{}",
+            error.message
+        );
+    }
+
+    #[test]
+    fn test_cache_key_follows_mdl_and_database() {
+        let mdl = WinxMDL::new();
+        let mut changed = WinxMDL::new();
+        changed.metrics.push(Metric {
+            name: "revenue".to_string(),
+            expression: "amount".to_string(),
+            description: None,
+            base_model: None,
+            aggregation: Some(Aggregation::Sum),
+            filters: Vec::new(),
+        });
+        let before = fingerprint(&mdl, "main", "main").unwrap();
+        assert_eq!(before, fingerprint(&mdl, "main", "main").unwrap());
+        assert_ne!(before, fingerprint(&changed, "main", "main").unwrap());
+        assert_ne!(before, fingerprint(&mdl, "main", "sales").unwrap());
+
+        let dir = tempfile::tempdir().unwrap();
+        let database = dir.path().join("shop.db");
+        std::fs::write(&database, b"").unwrap();
+        let file = std::fs::File::options()
+            .append(true)
+            .open(&database)
+            .unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        let old = modified_time(&database);
+        file.set_modified(SystemTime::now()).unwrap();
+        let new = modified_time(&database);
+
+        let key = |fingerprint: &str, modified: &str| {
+            cache_key(
+                fingerprint,
+                "query",
+                &["shop.db", modified, "SELECT 1", "0:100"],
+            )
+        };
+        assert_eq!(key(&before, &old), key(&before, &old));
+        assert_ne!(key(&before, &old), key(&before, &new));
+        let other = fingerprint(&changed, "main", "main").unwrap();
+        assert_ne!(key(&before, &old), key(&other, &old));
+    }
+}